@@ -0,0 +1,176 @@
+//! SMTP AUTH credential store.
+//!
+//! When `MAIL_LASER_AUTH_CREDENTIALS` is set, the SMTP server advertises
+//! `AUTH PLAIN LOGIN` on encrypted sessions and checks submitted credentials
+//! against this store. The file holds one `username:hash` entry per line;
+//! blank lines and lines starting with `#` are ignored. Hashes are either
+//! argon2 PHC strings (`$argon2id$...`, as produced by the `argon2` CLI) or
+//! bcrypt (`$2b$...`, as produced by `htpasswd -B`).
+//!
+//! The authenticated username becomes the Cedar principal for the rest of the
+//! session; see `finalize_message` in [`crate::smtp`].
+
+use crate::config::Config;
+use anyhow::{anyhow, Context, Result};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+#[cfg(test)]
+mod tests;
+
+/// Password hash formats accepted in the credentials file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum StoredHash {
+    Argon2(String),
+    Bcrypt(String),
+}
+
+impl StoredHash {
+    fn parse(raw: &str) -> Result<Self> {
+        if raw.starts_with("$argon2") {
+            PasswordHash::new(raw).map_err(|e| anyhow!("invalid argon2 hash: {}", e))?;
+            Ok(Self::Argon2(raw.to_string()))
+        } else if ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .any(|prefix| raw.starts_with(prefix))
+        {
+            Ok(Self::Bcrypt(raw.to_string()))
+        } else {
+            Err(anyhow!(
+                "unsupported hash format (expected argon2 or bcrypt)"
+            ))
+        }
+    }
+
+    /// The algorithm and cost settings, without salt or digest: two hashes
+    /// with the same settings take the same time to verify.
+    fn settings(&self) -> &str {
+        match self {
+            // `$argon2id$v=19$m=...,t=...,p=...$<salt>$<digest>`
+            Self::Argon2(phc) => phc.rsplitn(3, '$').last().unwrap_or(phc),
+            // `$2b$<cost>$<salt+digest>`
+            Self::Bcrypt(hash) => hash.get(..7).unwrap_or(hash),
+        }
+    }
+
+    fn verify(&self, password: &str) -> bool {
+        match self {
+            Self::Argon2(phc) => PasswordHash::new(phc)
+                .map(|hash| {
+                    Argon2::default()
+                        .verify_password(password.as_bytes(), &hash)
+                        .is_ok()
+                })
+                .unwrap_or(false),
+            Self::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+        }
+    }
+}
+
+/// Username → password hash map loaded once at startup.
+#[derive(Debug)]
+pub struct Credentials {
+    users: HashMap<String, StoredHash>,
+    /// Checked in place of a stored hash when the username is unknown, so
+    /// the reply takes as long as for a known user and does not reveal which
+    /// usernames exist. When the file mixes algorithms or costs, this is the
+    /// slowest of them (see [`slowest_settings`]).
+    dummy: StoredHash,
+}
+
+impl Credentials {
+    /// Loads the file configured by `MAIL_LASER_AUTH_CREDENTIALS`, or returns
+    /// `None` when SMTP AUTH is disabled.
+    pub fn from_config(config: &Config) -> Result<Option<Arc<Self>>> {
+        match &config.auth_credentials_path {
+            Some(path) => Ok(Some(Arc::new(Self::load(path)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads and parses a credentials file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| {
+            format!("failed to read AUTH credentials file at {}", path.display())
+        })?;
+        let creds = Self::parse(&text)
+            .with_context(|| format!("failed to parse AUTH credentials at {}", path.display()))?;
+        log::info!(
+            "Loaded {} SMTP AUTH credential(s) from {}",
+            creds.users.len(),
+            path.display()
+        );
+        Ok(creds)
+    }
+
+    /// Parses credentials from `username:hash` lines.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut users = HashMap::new();
+        let mut settings: HashMap<String, StoredHash> = HashMap::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (user, hash) = line
+                .split_once(':')
+                .ok_or_else(|| anyhow!("line {}: expected 'username:hash'", idx + 1))?;
+            let user = user.trim();
+            if user.is_empty() {
+                return Err(anyhow!("line {}: empty username", idx + 1));
+            }
+            let hash =
+                StoredHash::parse(hash.trim()).map_err(|e| anyhow!("line {}: {}", idx + 1, e))?;
+            settings
+                .entry(hash.settings().to_string())
+                .or_insert_with(|| hash.clone());
+            if users.insert(user.to_string(), hash).is_some() {
+                return Err(anyhow!("line {}: duplicate username '{}'", idx + 1, user));
+            }
+        }
+        let Some(dummy) = slowest_settings(settings.into_values().collect()) else {
+            return Err(anyhow!("no credentials defined"));
+        };
+        Ok(Self { users, dummy })
+    }
+
+    /// Returns `true` when `password` matches the stored hash for `username`.
+    /// Usernames are matched exactly. An unknown username is still checked
+    /// against a dummy hash, so it costs the same time. Hash verification is
+    /// deliberately slow, so it runs on the blocking thread pool.
+    pub async fn verify(self: &Arc<Self>, username: &str, password: &str) -> bool {
+        let store = Arc::clone(self);
+        let username = username.to_string();
+        let password = password.to_string();
+        tokio::task::spawn_blocking(move || match store.users.get(&username) {
+            Some(hash) => hash.verify(&password),
+            None => {
+                store.dummy.verify(&password);
+                false
+            }
+        })
+        .await
+        .unwrap_or(false)
+    }
+}
+
+/// Picks the hash that is slowest to verify, one per distinct algorithm and
+/// cost. Argon2 and bcrypt costs are not comparable, so with more than one
+/// candidate each is timed once.
+fn slowest_settings(mut candidates: Vec<StoredHash>) -> Option<StoredHash> {
+    if candidates.len() <= 1 {
+        return candidates.pop();
+    }
+    candidates
+        .into_iter()
+        .map(|hash| {
+            let start = Instant::now();
+            hash.verify("");
+            (start.elapsed(), hash)
+        })
+        .max_by_key(|(elapsed, _)| *elapsed)
+        .map(|(_, hash)| hash)
+}
@@ -0,0 +1,90 @@
+use super::*;
+use argon2::password_hash::{PasswordHasher, SaltString};
+
+fn argon2_hash(password: &str) -> String {
+    let salt = SaltString::encode_b64(b"mail-laser-salt!").unwrap();
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .unwrap()
+        .to_string()
+}
+
+fn bcrypt_hash(password: &str) -> String {
+    // Minimum cost keeps the test fast.
+    bcrypt::hash(password, 4).unwrap()
+}
+
+#[tokio::test]
+async fn test_verify_argon2_and_bcrypt() {
+    let text = format!(
+        "# scanners\nscanner:{}\n\napp@internal:{}\n",
+        argon2_hash("s3cret"),
+        bcrypt_hash("hunter2")
+    );
+    let creds = Arc::new(Credentials::parse(&text).unwrap());
+
+    assert!(creds.verify("scanner", "s3cret").await);
+    assert!(!creds.verify("scanner", "wrong").await);
+    assert!(creds.verify("app@internal", "hunter2").await);
+    assert!(!creds.verify("app@internal", "s3cret").await);
+    assert!(!creds.verify("nobody", "s3cret").await);
+}
+
+#[test]
+fn test_parse_rejects_unknown_hash_format() {
+    let err = Credentials::parse("user:plaintext-password").unwrap_err();
+    assert!(err.to_string().contains("line 1"), "{}", err);
+}
+
+#[test]
+fn test_parse_rejects_missing_separator_and_duplicates() {
+    assert!(Credentials::parse("no-separator-here").is_err());
+
+    let hash = bcrypt_hash("pw");
+    let text = format!("dup:{}\ndup:{}\n", hash, hash);
+    let err = Credentials::parse(&text).unwrap_err();
+    assert!(err.to_string().contains("duplicate"), "{}", err);
+}
+
+#[test]
+fn test_parse_rejects_empty_file() {
+    assert!(Credentials::parse("# only comments\n\n").is_err());
+}
+
+#[test]
+fn test_unknown_users_are_checked_against_the_slowest_hash() {
+    // bcrypt at the minimum cost is far cheaper than argon2's defaults, so
+    // the dummy must not be the first entry's hash.
+    let text = format!(
+        "fast:{}\nfast2:{}\nslow:{}\n",
+        bcrypt_hash("a"),
+        bcrypt_hash("b"),
+        argon2_hash("c")
+    );
+    let creds = Credentials::parse(&text).unwrap();
+    assert!(
+        matches!(creds.dummy, StoredHash::Argon2(_)),
+        "{:?}",
+        creds.dummy
+    );
+}
+
+#[test]
+fn test_settings_ignore_salt_and_digest() {
+    let a = StoredHash::parse(&bcrypt_hash("a")).unwrap();
+    let b = StoredHash::parse(&bcrypt_hash("b")).unwrap();
+    assert_eq!(a.settings(), "$2b$04$");
+    assert_eq!(a.settings(), b.settings());
+
+    let argon = StoredHash::parse(&argon2_hash("a")).unwrap();
+    assert!(
+        argon.settings().starts_with("$argon2id$v=19$m="),
+        "{}",
+        argon.settings()
+    );
+    assert!(
+        !argon.settings().contains("bWFpbC1sYXNlci1zYWx0IQ"),
+        "{}",
+        argon.settings()
+    );
+}
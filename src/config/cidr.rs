@@ -0,0 +1,166 @@
+//! IP network ranges (`10.0.0.0/8`, `2001:db8::/32`) used by address-based
+//! settings such as the PROXY protocol trust list.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An IPv4 or IPv6 network in CIDR notation. A bare address parses as a
+/// single-host network (`/32` or `/128`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Returns `true` when `ip` falls inside this network. IPv4-mapped IPv6
+    /// addresses (`::ffff:192.0.2.1`, as reported by dual-stack sockets) are
+    /// matched against IPv4 networks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = mask_u32(self.prefix_len);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = mask_u128(self.prefix_len);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+
+    /// Parses a comma-separated list, ignoring empty entries.
+    pub fn parse_list(value: &str) -> Result<Vec<Cidr>, String> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+}
+
+fn mask_u32(prefix_len: u8) -> u32 {
+    u32::MAX
+        .checked_shl(32 - u32::from(prefix_len))
+        .unwrap_or(0)
+}
+
+fn mask_u128(prefix_len: u8) -> u128 {
+    u128::MAX
+        .checked_shl(128 - u32::from(prefix_len))
+        .unwrap_or(0)
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = addr
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid network address in '{}'", s))?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix {
+            Some(p) => p
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| format!("invalid prefix length in '{}'", s))?,
+            None => max_len,
+        };
+        Ok(Cidr {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Cidr> for String {
+    fn from(value: Cidr) -> Self {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ipv4_networks_match_by_prefix() {
+        let net: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains(ip("10.1.255.3")));
+        assert!(!net.contains(ip("10.2.0.1")));
+        assert!(!net.contains(ip("::1")));
+    }
+
+    #[test]
+    fn bare_address_is_a_single_host() {
+        let net: Cidr = "192.0.2.7".parse().unwrap();
+        assert_eq!(net.to_string(), "192.0.2.7/32");
+        assert!(net.contains(ip("192.0.2.7")));
+        assert!(!net.contains(ip("192.0.2.8")));
+    }
+
+    #[test]
+    fn zero_prefix_matches_the_whole_family() {
+        let v4: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(v4.contains(ip("203.0.113.9")));
+        let v6: Cidr = "::/0".parse().unwrap();
+        assert!(v6.contains(ip("2001:db8::1")));
+        assert!(!v6.contains(ip("203.0.113.9")));
+    }
+
+    #[test]
+    fn ipv6_networks_and_mapped_ipv4_peers() {
+        let net: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(net.contains(ip("2001:db8:ffff::1")));
+        assert!(!net.contains(ip("2001:db9::1")));
+
+        let v4: Cidr = "198.51.100.0/24".parse().unwrap();
+        assert!(v4.contains(ip("::ffff:198.51.100.20")));
+    }
+
+    #[test]
+    fn rejects_malformed_networks() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("::/129".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn parse_list_skips_blank_entries() {
+        let list = Cidr::parse_list(" 10.0.0.0/8, ,fd00::/8 ").unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(Cidr::parse_list("10.0.0.0/8,bogus").is_err());
+        assert!(Cidr::parse_list("").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,82 @@
+//! DNS blocklist zones (`MAIL_LASER_DNSBL_ZONES`).
+//!
+//! Entries are comma-separated `zone` or `zone=weight`; a bare zone weighs 1:
+//!
+//! ```text
+//! zen.spamhaus.org=3,bl.spamcop.net,b.barracudacentral.org=2
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// One blocklist zone and the weight a listing on it adds to the score.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DnsblZone {
+    /// The zone queried, without a trailing dot (`zen.spamhaus.org`).
+    pub zone: String,
+    /// Added to the peer's score when the zone lists it.
+    pub weight: u32,
+}
+
+impl DnsblZone {
+    /// Parses a comma-separated list, ignoring empty entries.
+    pub fn parse_list(value: &str) -> Result<Vec<DnsblZone>, String> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+}
+
+impl FromStr for DnsblZone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (zone, weight) = match s.split_once('=') {
+            Some((zone, weight)) => {
+                let weight = weight
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid weight in DNSBL zone '{}'", s))?;
+                (zone, weight)
+            }
+            None => (s, 1),
+        };
+        let zone = zone.trim().trim_end_matches('.').to_lowercase();
+        let valid = !zone.is_empty()
+            && zone.split('.').all(|label| {
+                !label.is_empty()
+                    && label
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            });
+        if !valid {
+            return Err(format!("invalid DNSBL zone '{}'", s));
+        }
+        Ok(DnsblZone { zone, weight })
+    }
+}
+
+impl fmt::Display for DnsblZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.zone, self.weight)
+    }
+}
+
+impl TryFrom<String> for DnsblZone {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<DnsblZone> for String {
+    fn from(value: DnsblZone) -> Self {
+        value.to_string()
+    }
+}
@@ -0,0 +1,188 @@
+//! SMTP listener endpoints (`MAIL_LASER_LISTENERS`).
+//!
+//! Each entry is an `ip:port` socket address or a `unix:/path` socket,
+//! optionally followed by `?option=value&option=value`. Entries are separated
+//! by whitespace, so CIDR lists inside an option can keep the usual comma
+//! separator:
+//!
+//! ```text
+//! 0.0.0.0:25 [::]:25 10.0.0.5:2525?require_tls=true&proxy_protocol_trusted=10.0.0.0/8
+//! unix:/run/maillaser/lmtp.sock?protocol=lmtp&mode=0660
+//! ```
+
+use super::Cidr;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// How a listener negotiates TLS.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenerTls {
+    /// Plaintext SMTP that offers `STARTTLS`.
+    Starttls,
+    /// SMTPS (RFC 8314): the TLS handshake runs right after accept.
+    Implicit,
+}
+
+/// The protocol a listener speaks.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenerProtocol {
+    /// SMTP (RFC 5321).
+    Smtp,
+    /// LMTP (RFC 2033): `LHLO` instead of `EHLO`, and one reply per
+    /// recipient after the message content.
+    Lmtp,
+}
+
+/// Where a listener accepts connections.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ListenerAddress {
+    /// A TCP socket address, written `ip:port`.
+    Tcp(SocketAddr),
+    /// A Unix domain socket, written `unix:/path`.
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenerAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("unix listener address needs a socket path".to_string());
+            }
+            return Ok(ListenerAddress::Unix(PathBuf::from(path)));
+        }
+        s.parse().map(ListenerAddress::Tcp).map_err(|_| {
+            format!(
+                "invalid listener address '{}' (expected ip:port or unix:/path)",
+                s
+            )
+        })
+    }
+}
+
+impl fmt::Display for ListenerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenerAddress::Tcp(addr) => write!(f, "{}", addr),
+            ListenerAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl From<SocketAddr> for ListenerAddress {
+    fn from(value: SocketAddr) -> Self {
+        ListenerAddress::Tcp(value)
+    }
+}
+
+impl TryFrom<String> for ListenerAddress {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ListenerAddress> for String {
+    fn from(value: ListenerAddress) -> Self {
+        value.to_string()
+    }
+}
+
+/// One address the SMTP server accepts connections on, with the settings
+/// that may differ between listeners.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListenerConfig {
+    /// Address to bind. An IPv6 wildcard (`[::]`) accepts IPv4 as well,
+    /// unless another listener binds IPv4 on the same port.
+    pub address: ListenerAddress,
+    /// `protocol=smtp|lmtp`. Default: `smtp`.
+    pub protocol: ListenerProtocol,
+    /// `tls=starttls|implicit`. Default: `starttls`.
+    pub tls: ListenerTls,
+    /// `require_tls=true|false`. Default: `MAIL_LASER_REQUIRE_TLS`.
+    pub require_tls: bool,
+    /// `proxy_protocol_trusted=<CIDRs>`. Default: `MAIL_LASER_PROXY_PROTOCOL_TRUSTED`.
+    /// Not used on Unix socket listeners.
+    pub proxy_protocol_trusted: Vec<Cidr>,
+    /// `xclient_trusted=<CIDRs>`. Default: `MAIL_LASER_XCLIENT_TRUSTED`.
+    pub xclient_trusted: Vec<Cidr>,
+    /// `mode=<octal>`: permissions applied to a Unix socket after binding.
+    /// Default: left to the process umask. Only valid on Unix socket listeners.
+    pub socket_mode: Option<u32>,
+}
+
+impl ListenerConfig {
+    /// Parses a whitespace-separated list of listener entries. Options an
+    /// entry leaves out are copied from `defaults` (whose `address` is
+    /// ignored).
+    pub fn parse_list(value: &str, defaults: &ListenerConfig) -> Result<Vec<Self>, String> {
+        value
+            .split_whitespace()
+            .map(|entry| Self::parse(entry, defaults))
+            .collect()
+    }
+
+    fn parse(entry: &str, defaults: &ListenerConfig) -> Result<Self, String> {
+        let (address, options) = entry.split_once('?').unwrap_or((entry, ""));
+        let mut listener = ListenerConfig {
+            address: address.parse()?,
+            protocol: ListenerProtocol::Smtp,
+            tls: ListenerTls::Starttls,
+            socket_mode: None,
+            ..defaults.clone()
+        };
+        for option in options.split('&').filter(|o| !o.is_empty()) {
+            let (key, value) = option
+                .split_once('=')
+                .ok_or_else(|| format!("listener option '{}' needs a value", option))?;
+            let invalid = || format!("invalid value for listener option '{}'", option);
+            match key {
+                "protocol" => {
+                    listener.protocol = match value.to_lowercase().as_str() {
+                        "smtp" => ListenerProtocol::Smtp,
+                        "lmtp" => ListenerProtocol::Lmtp,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "tls" => {
+                    listener.tls = match value.to_lowercase().as_str() {
+                        "starttls" => ListenerTls::Starttls,
+                        "implicit" => ListenerTls::Implicit,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "require_tls" => {
+                    listener.require_tls = match value.to_lowercase().as_str() {
+                        "true" | "1" | "yes" => true,
+                        "false" | "0" | "no" => false,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "proxy_protocol_trusted" => {
+                    listener.proxy_protocol_trusted = Cidr::parse_list(value)?;
+                }
+                "xclient_trusted" => listener.xclient_trusted = Cidr::parse_list(value)?,
+                "mode" => {
+                    if !matches!(listener.address, ListenerAddress::Unix(_)) {
+                        return Err("listener option 'mode' applies to unix: sockets only".into());
+                    }
+                    let mode = u32::from_str_radix(value, 8).map_err(|_| invalid())?;
+                    if mode > 0o7777 {
+                        return Err(invalid());
+                    }
+                    listener.socket_mode = Some(mode);
+                }
+                _ => return Err(format!("unknown listener option '{}'", key)),
+            }
+        }
+        Ok(listener)
+    }
+}
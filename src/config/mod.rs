@@ -8,8 +8,16 @@
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use std::env;
+use std::net::ToSocketAddrs;
 use std::path::PathBuf;
 
+mod cidr;
+mod dnsbl;
+mod listener;
+pub use cidr::Cidr;
+pub use dnsbl::DnsblZone;
+pub use listener::{ListenerAddress, ListenerConfig, ListenerProtocol, ListenerTls};
+
 const DEFAULT_MAX_MESSAGE_SIZE_BYTES: u64 = 26_214_400; // 25 MiB
 const DEFAULT_MAX_ATTACHMENT_SIZE_BYTES: u64 = 10_485_760; // 10 MiB
 
@@ -38,6 +46,53 @@ pub enum DmarcTempErrorAction {
     Accept,
 }
 
+/// What happens to a connection refused by the peer allow/deny lists.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerDenyAction {
+    /// Reply `554 5.7.1` in place of the greeting, then close.
+    Reject,
+    /// Close the connection without a word.
+    Drop,
+}
+
+/// When greylisting decides whether to defer a message.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GreylistStage {
+    /// At each `RCPT TO`, before any content is sent.
+    Rcpt,
+    /// At end-of-DATA, after DMARC, so Cedar can skip DMARC-passing senders.
+    Data,
+}
+
+/// How a message accepted for several `RCPT TO` recipients is handed to the
+/// webhook.
+///
+/// * `Combined` — one payload per message; `recipients` lists every accepted
+///   recipient and `recipient` carries the first one.
+/// * `PerRecipient` — one payload per accepted recipient (fan-out), each with
+///   its own `recipient`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecipientDelivery {
+    Combined,
+    PerRecipient,
+}
+
+/// When the SMTP `DATA` reply is sent relative to webhook delivery.
+///
+/// * `Async` — reply `250` once the message is handed to the webhook actor
+///   (or written to the spool); delivery happens afterwards.
+/// * `Sync` — wait for the webhook result, retries and timeouts included,
+///   and reply `451 4.3.0` on failure so the sending MTA keeps the message.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryMode {
+    Async,
+    Sync,
+}
+
 /// How attachments are delivered to the webhook consumer.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(tag = "mode", rename_all = "snake_case")]
@@ -81,6 +136,18 @@ pub struct Config {
     /// The network port the SMTP server should listen on. (Optional: `MAIL_LASER_PORT`, Default: 2525)
     pub smtp_port: u16,
 
+    /// Port for an additional implicit-TLS (SMTPS) listener on
+    /// `smtp_bind_address`. Sessions on it start with the TLS handshake and
+    /// use the same certificate as STARTTLS. Disabled when unset.
+    /// (Optional: `MAIL_LASER_SMTPS_PORT`, conventionally 465)
+    pub smtps_port: Option<u16>,
+
+    /// Explicit SMTP listeners, each with its own TLS mode and trust
+    /// settings. When non-empty, `smtp_bind_address`, `smtp_port` and
+    /// `smtps_port` are ignored; see [`Config::effective_listeners`].
+    /// (Optional: `MAIL_LASER_LISTENERS`, whitespace-separated, Default: empty)
+    pub listeners: Vec<ListenerConfig>,
+
     /// The IP address the health check HTTP server should listen on. (Optional: `MAIL_LASER_HEALTH_BIND_ADDRESS`, Default: "0.0.0.0")
     pub health_check_bind_address: String,
 
@@ -91,6 +158,15 @@ pub struct Config {
     /// (Optional: `MAIL_LASER_HEADER_PREFIX`, comma-separated, Default: empty)
     pub header_prefixes: Vec<String>,
 
+    /// Whether a message with several accepted recipients produces one combined
+    /// payload or one payload per recipient.
+    /// (Optional: `MAIL_LASER_RECIPIENT_DELIVERY`, Default: `combined`)
+    pub recipient_delivery: RecipientDelivery,
+
+    /// Whether the `DATA` reply waits for the webhook result.
+    /// (Optional: `MAIL_LASER_DELIVERY_MODE`, Default: `async`)
+    pub delivery_mode: DeliveryMode,
+
     /// Webhook request timeout in seconds. (Optional: `MAIL_LASER_WEBHOOK_TIMEOUT`, Default: 30)
     pub webhook_timeout_secs: u64,
 
@@ -111,12 +187,60 @@ pub struct Config {
     /// (Optional: `MAIL_LASER_WEBHOOK_SIGNING_SECRET`)
     pub webhook_signing_secret: Option<String>,
 
+    /// Directory for the durable delivery spool. When set, every payload is
+    /// written here before the SMTP `250` reply and deleted only after the
+    /// webhook answers 2xx; pending entries are re-sent after failures and
+    /// restarts. When unset, payloads live only in memory.
+    /// (Optional: `MAIL_LASER_SPOOL_DIR`)
+    pub spool_dir: Option<PathBuf>,
+
+    /// Seconds between scans of the spool for entries still awaiting delivery.
+    /// Ignored when `spool_dir` is unset.
+    /// (Optional: `MAIL_LASER_SPOOL_RETRY_INTERVAL`, Default: 30)
+    pub spool_retry_interval_secs: u64,
+
+    /// Seconds a spooled entry is retried before delivery is given up: the
+    /// entry is deleted and, when `dsn_dir` is set, a failure report written.
+    /// (Optional: `MAIL_LASER_SPOOL_MAX_AGE`, Default: 432000)
+    pub spool_max_age_secs: u64,
+
+    /// Directory that receives RFC 3464 failure reports. When set and an
+    /// asynchronous delivery is given up (for a spooled one, once it is older
+    /// than `spool_max_age_secs`), a report is written here
+    /// for every recipient that asked for `NOTIFY=FAILURE`; hand the files to
+    /// a local MTA for relay. When unset, no reports are generated.
+    /// (Optional: `MAIL_LASER_DSN_DIR`)
+    pub dsn_dir: Option<PathBuf>,
+
     /// Path to the Cedar policy file. (Required: `MAIL_LASER_CEDAR_POLICIES`)
     pub cedar_policies_path: PathBuf,
 
     /// Optional path to a Cedar entities JSON file. (Optional: `MAIL_LASER_CEDAR_ENTITIES`)
     pub cedar_entities_path: Option<PathBuf>,
 
+    /// PEM certificate chain presented on STARTTLS. Must be set together with
+    /// `tls_key_path`; when both are unset a self-signed certificate for
+    /// `server_hostname` is generated at startup. Reloaded on change or
+    /// `SIGHUP`.
+    /// (Optional: `MAIL_LASER_TLS_CERT`)
+    pub tls_cert_path: Option<PathBuf>,
+
+    /// PEM private key (PKCS#8, PKCS#1, or SEC1) matching `tls_cert_path`.
+    /// (Optional: `MAIL_LASER_TLS_KEY`)
+    pub tls_key_path: Option<PathBuf>,
+
+    /// Refuse `MAIL FROM` with `530 5.7.0` until the client has issued
+    /// STARTTLS, so no message is ever accepted in cleartext. Sessions on the
+    /// SMTPS listener are already encrypted and unaffected.
+    /// (Optional: `MAIL_LASER_REQUIRE_TLS`, Default: false)
+    pub require_tls: bool,
+
+    /// Credentials file (`username:hash` lines, argon2 or bcrypt) enabling
+    /// SMTP AUTH `PLAIN`/`LOGIN` on encrypted sessions. The authenticated
+    /// username becomes the Cedar principal. Disabled when unset.
+    /// (Optional: `MAIL_LASER_AUTH_CREDENTIALS`)
+    pub auth_credentials_path: Option<PathBuf>,
+
     /// Max total SMTP message size in bytes. (Optional: `MAIL_LASER_MAX_MESSAGE_SIZE`, Default: 26_214_400)
     pub max_message_size_bytes: u64,
 
@@ -150,6 +274,25 @@ pub struct Config {
     /// (Optional: `MAIL_LASER_MAX_CONCURRENT_PER_IP`, Default: 10)
     pub max_concurrent_per_ip: u32,
 
+    /// Maximum SMTP/LMTP sessions open at once across every listener. When
+    /// full, new connections get `421 4.3.2 Too busy` and are closed.
+    /// `0` disables the limit.
+    /// (Optional: `MAIL_LASER_MAX_SESSIONS`, Default: 1000)
+    pub max_sessions: u32,
+
+    /// Maximum new connections per minute from a single source, enforced by
+    /// a token bucket that allows a burst of this size. Over-rate connections
+    /// are dropped without a greeting, like those over the per-IP cap.
+    /// `0` disables the limit.
+    /// (Optional: `MAIL_LASER_MAX_CONNECTIONS_PER_MINUTE_PER_IP`, Default: 60)
+    pub max_connections_per_minute_per_ip: u32,
+
+    /// Prefix length that groups IPv6 clients into one "source IP" for the
+    /// per-IP concurrency cap and connection rate limit, so a client cannot
+    /// escape them by rotating through addresses in its own block.
+    /// (Optional: `MAIL_LASER_IPV6_LIMIT_PREFIX`, 0-128, Default: 64)
+    pub ipv6_limit_prefix_len: u8,
+
     /// Maximum number of unknown `RCPT TO` recipients tolerated in one SMTP
     /// session. On the Nth unknown, the server replies `421` and closes the
     /// connection, bounding recipient-address enumeration within a session.
@@ -157,6 +300,133 @@ pub struct Config {
     /// can open; this bounds how much they can learn in each one. `0` disables.
     /// (Optional: `MAIL_LASER_MAX_UNKNOWN_RCPTS_PER_SESSION`, Default: 3)
     pub max_unknown_rcpts_per_session: u32,
+
+    /// Load balancer networks allowed to send a PROXY protocol (v1 or v2)
+    /// header. When non-empty, connections from these networks must open with
+    /// the header, and the client address it carries replaces the socket peer
+    /// for the per-IP cap, SPF and Cedar `peer_ip`. Connections from any other
+    /// address are served as direct clients. Empty disables PROXY protocol.
+    /// (Optional: `MAIL_LASER_PROXY_PROTOCOL_TRUSTED`, comma-separated CIDRs, Default: empty)
+    pub proxy_protocol_trusted: Vec<Cidr>,
+
+    /// Front-end MTAs (e.g. a Postfix relay) allowed to send `XCLIENT` and
+    /// `XFORWARD`. The forwarded client address and HELO replace the relay's
+    /// own for the rest of the session, so SPF, DMARC and Cedar see the real
+    /// origin. Empty disables both commands.
+    /// (Optional: `MAIL_LASER_XCLIENT_TRUSTED`, comma-separated CIDRs, Default: empty)
+    pub xclient_trusted: Vec<Cidr>,
+
+    /// Seconds a client has, after connecting, to complete the implicit TLS
+    /// handshake and send its first command. Sessions that miss any SMTP
+    /// timeout are closed with `421 4.4.2`.
+    /// (Optional: `MAIL_LASER_GREETING_TIMEOUT`, Default: 60)
+    pub greeting_timeout_secs: u64,
+
+    /// Seconds the server waits for each subsequent command line, including
+    /// STARTTLS handshakes and AUTH responses (RFC 5321 §4.5.3.2.7).
+    /// (Optional: `MAIL_LASER_COMMAND_TIMEOUT`, Default: 300)
+    pub command_timeout_secs: u64,
+
+    /// Seconds the server waits for each line of `DATA` content or each
+    /// `BDAT` chunk (RFC 5321 §4.5.3.2.6).
+    /// (Optional: `MAIL_LASER_DATA_TIMEOUT`, Default: 180)
+    pub data_timeout_secs: u64,
+
+    /// Wall-clock cap on a whole SMTP session, however active it is, so a
+    /// client trickling bytes just fast enough cannot hold a connection slot
+    /// indefinitely. (Optional: `MAIL_LASER_SESSION_TIMEOUT`, Default: 1800)
+    pub session_timeout_secs: u64,
+
+    /// Networks allowed to connect. When this and `peer_allow_file` are both
+    /// empty, every peer not denied is allowed.
+    /// (Optional: `MAIL_LASER_PEER_ALLOW`, comma-separated CIDRs, Default: empty)
+    pub peer_allow: Vec<Cidr>,
+
+    /// File of additional allowed networks, one CIDR per line (`#` starts a
+    /// comment). Reloaded on change or `SIGHUP`.
+    /// (Optional: `MAIL_LASER_PEER_ALLOW_FILE`)
+    pub peer_allow_file: Option<PathBuf>,
+
+    /// Networks refused at connect time, even when also allowed.
+    /// (Optional: `MAIL_LASER_PEER_DENY`, comma-separated CIDRs, Default: empty)
+    pub peer_deny: Vec<Cidr>,
+
+    /// File of additional denied networks, in the same format as
+    /// `peer_allow_file`. Reloaded on change or `SIGHUP`.
+    /// (Optional: `MAIL_LASER_PEER_DENY_FILE`)
+    pub peer_deny_file: Option<PathBuf>,
+
+    /// Whether refused peers get `554 5.7.1` or are dropped silently.
+    /// (Optional: `MAIL_LASER_PEER_DENY_ACTION`, `reject` or `drop`, Default: `reject`)
+    pub peer_deny_action: PeerDenyAction,
+
+    /// DNS blocklist zones queried for each peer at connect time, with the
+    /// weight a listing adds to the peer's score. Empty disables the checks.
+    /// (Optional: `MAIL_LASER_DNSBL_ZONES`, comma-separated `zone[=weight]`, Default: empty)
+    pub dnsbl_zones: Vec<DnsblZone>,
+
+    /// Score at which a peer is refused with `554 5.7.1` before the
+    /// greeting. When unset the score is only exposed to Cedar.
+    /// (Optional: `MAIL_LASER_DNSBL_REJECT_SCORE`)
+    pub dnsbl_reject_score: Option<u32>,
+
+    /// How long to wait for each blocklist before treating it as not
+    /// listing the peer. (Optional: `MAIL_LASER_DNSBL_TIMEOUT`, Default: 2)
+    pub dnsbl_timeout_secs: u64,
+
+    /// File holding the greylisting triplets. Setting it enables greylisting.
+    /// (Optional: `MAIL_LASER_GREYLIST_DB`)
+    pub greylist_db: Option<PathBuf>,
+
+    /// Where first-seen triplets are deferred with `451 4.7.1`.
+    /// (Optional: `MAIL_LASER_GREYLIST_STAGE`, `rcpt` or `data`, Default: `rcpt`)
+    pub greylist_stage: GreylistStage,
+
+    /// Seconds a new triplet is deferred before a retry is accepted.
+    /// (Optional: `MAIL_LASER_GREYLIST_DELAY`, Default: 300)
+    pub greylist_delay_secs: u64,
+
+    /// Seconds after the delay within which the sender must retry; later
+    /// retries start over. (Optional: `MAIL_LASER_GREYLIST_RETRY_WINDOW`, Default: 172800)
+    pub greylist_retry_window_secs: u64,
+
+    /// Seconds a triplet that passed stays accepted without delay, renewed
+    /// while it keeps sending. (Optional: `MAIL_LASER_GREYLIST_PASS_TTL`, Default: 3024000)
+    pub greylist_pass_ttl_secs: u64,
+
+    /// Whether to look up the peer's reverse DNS (forward-confirmed) and the
+    /// HELO domain at end-of-DATA, for Cedar and the webhook payload.
+    /// (Optional: `MAIL_LASER_RDNS_CHECKS`, Default: false)
+    pub rdns_checks: bool,
+
+    /// How long to wait for each of those lookups before treating the name
+    /// as unconfirmed. (Optional: `MAIL_LASER_RDNS_TIMEOUT`, Default: 2)
+    pub rdns_timeout_secs: u64,
+
+    /// Seconds to hold back the `220` greeting on plaintext listeners. A
+    /// client that sends anything in the meantime is an early talker and is
+    /// refused with `554`. `0` disables.
+    /// (Optional: `MAIL_LASER_GREET_DELAY`, Default: 0)
+    pub greet_delay_secs: u64,
+
+    /// Seconds added to the `550` reply for each unknown `RCPT TO` in a
+    /// session, so a directory-harvesting client slows down as it guesses.
+    /// `0` disables. (Optional: `MAIL_LASER_RCPT_TARPIT`, Default: 0)
+    pub rcpt_tarpit_secs: u64,
+
+    /// Upper bound on that delay for any one reply.
+    /// (Optional: `MAIL_LASER_RCPT_TARPIT_MAX`, Default: 30)
+    pub rcpt_tarpit_max_secs: u64,
+
+    /// Host name the server announces in its greeting and EHLO reply, writes
+    /// in the `Received:` headers it adds, and puts in the self-signed
+    /// certificate. (Optional: `MAIL_LASER_SERVER_HOSTNAME`, Default: the
+    /// system host name, or `localhost` if that is not a valid domain name)
+    pub server_hostname: String,
+
+    /// Whether to leave the product name and version out of the greeting
+    /// and `Received:` headers. (Optional: `MAIL_LASER_HIDE_PRODUCT`, Default: false)
+    pub hide_product: bool,
 }
 
 impl Config {
@@ -263,6 +533,22 @@ impl Config {
         };
         log::info!("Config: Using smtp_port: {}", smtp_port);
 
+        let smtps_port = match env::var("MAIL_LASER_SMTPS_PORT") {
+            Ok(val) if !val.is_empty() => match val.parse::<u16>() {
+                Ok(port) => Some(port),
+                Err(e) => {
+                    let err_msg = format!(
+                        "MAIL_LASER_SMTPS_PORT ('{}') must be a valid u16 port number",
+                        val
+                    );
+                    log::error!("{}: {}", err_msg, e);
+                    return Err(anyhow!(e).context(err_msg));
+                }
+            },
+            _ => None,
+        };
+        log::info!("Config: Using smtps_port: {:?}", smtps_port);
+
         let health_check_bind_address = env::var("MAIL_LASER_HEALTH_BIND_ADDRESS")
             .map(|val| {
                 log::info!("Config: Using health_check_bind_address from env: {}", val);
@@ -303,6 +589,12 @@ impl Config {
             .unwrap_or_default();
         log::info!("Config: Using header_prefixes: {:?}", header_prefixes);
 
+        let recipient_delivery = parse_recipient_delivery()?;
+        log::info!("Config: Using recipient_delivery: {:?}", recipient_delivery);
+
+        let delivery_mode = parse_delivery_mode()?;
+        log::info!("Config: Using delivery_mode: {:?}", delivery_mode);
+
         // --- Optional: Resilience settings ---
         let webhook_timeout_secs: u64 = env::var("MAIL_LASER_WEBHOOK_TIMEOUT")
             .unwrap_or_else(|_| "30".to_string())
@@ -360,6 +652,79 @@ impl Config {
             }
         );
 
+        // --- Optional: Delivery spool ---
+        let spool_dir = env::var("MAIL_LASER_SPOOL_DIR")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from);
+        match &spool_dir {
+            Some(p) => log::info!("Config: Using spool_dir: {}", p.display()),
+            None => log::info!("Config: Using spool_dir: <not set>"),
+        }
+
+        let spool_retry_interval_secs: u64 = env::var("MAIL_LASER_SPOOL_RETRY_INTERVAL")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse()
+            .map_err(|e| anyhow!("MAIL_LASER_SPOOL_RETRY_INTERVAL must be a valid u64: {}", e))?;
+        if spool_retry_interval_secs == 0 {
+            return Err(anyhow!(
+                "MAIL_LASER_SPOOL_RETRY_INTERVAL must be greater than 0"
+            ));
+        }
+        log::info!(
+            "Config: Using spool_retry_interval_secs: {}",
+            spool_retry_interval_secs
+        );
+
+        let spool_max_age_secs = parse_timeout_secs("MAIL_LASER_SPOOL_MAX_AGE", 432_000)?;
+        log::info!("Config: Using spool_max_age_secs: {}", spool_max_age_secs);
+
+        // --- Optional: Delivery status notifications ---
+        let dsn_dir = env::var("MAIL_LASER_DSN_DIR")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from);
+        match &dsn_dir {
+            Some(p) => log::info!("Config: Using dsn_dir: {}", p.display()),
+            None => log::info!("Config: Using dsn_dir: <not set>"),
+        }
+
+        // --- Optional: TLS certificate ---
+        let tls_cert_path = env::var("MAIL_LASER_TLS_CERT")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from);
+        let tls_key_path = env::var("MAIL_LASER_TLS_KEY")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from);
+        match (&tls_cert_path, &tls_key_path) {
+            (Some(cert), Some(key)) => log::info!(
+                "Config: Using tls_cert_path: {}, tls_key_path: {}",
+                cert.display(),
+                key.display()
+            ),
+            (None, None) => log::info!("Config: Using self-signed TLS certificate"),
+            _ => {
+                return Err(anyhow!(
+                    "MAIL_LASER_TLS_CERT and MAIL_LASER_TLS_KEY must be set together"
+                ))
+            }
+        }
+
+        let require_tls = parse_bool("MAIL_LASER_REQUIRE_TLS", false)?;
+        log::info!("Config: Using require_tls: {}", require_tls);
+
+        // --- Optional: SMTP AUTH ---
+        let auth_credentials_path = env::var("MAIL_LASER_AUTH_CREDENTIALS")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from);
+        match &auth_credentials_path {
+            Some(p) => log::info!("Config: Using auth_credentials_path: {}", p.display()),
+            None => log::info!("Config: Using auth_credentials_path: <not set>"),
+        }
+
         // --- Optional: Attachment size caps ---
         let max_message_size_bytes: u64 = env::var("MAIL_LASER_MAX_MESSAGE_SIZE")
             .unwrap_or_else(|_| DEFAULT_MAX_MESSAGE_SIZE_BYTES.to_string())
@@ -444,6 +809,38 @@ impl Config {
             max_concurrent_per_ip
         );
 
+        let max_sessions: u32 = env::var("MAIL_LASER_MAX_SESSIONS")
+            .unwrap_or_else(|_| "1000".to_string())
+            .parse()
+            .map_err(|e| anyhow!("MAIL_LASER_MAX_SESSIONS must be a valid u32: {}", e))?;
+        log::info!("Config: Using max_sessions: {}", max_sessions);
+
+        let max_connections_per_minute_per_ip: u32 =
+            env::var("MAIL_LASER_MAX_CONNECTIONS_PER_MINUTE_PER_IP")
+                .unwrap_or_else(|_| "60".to_string())
+                .parse()
+                .map_err(|e| {
+                    anyhow!(
+                        "MAIL_LASER_MAX_CONNECTIONS_PER_MINUTE_PER_IP must be a valid u32: {}",
+                        e
+                    )
+                })?;
+        log::info!(
+            "Config: Using max_connections_per_minute_per_ip: {}",
+            max_connections_per_minute_per_ip
+        );
+
+        let ipv6_limit_prefix_len: u8 = env::var("MAIL_LASER_IPV6_LIMIT_PREFIX")
+            .unwrap_or_else(|_| "64".to_string())
+            .parse()
+            .ok()
+            .filter(|len| *len <= 128)
+            .ok_or_else(|| anyhow!("MAIL_LASER_IPV6_LIMIT_PREFIX must be between 0 and 128"))?;
+        log::info!(
+            "Config: Using ipv6_limit_prefix_len: {}",
+            ipv6_limit_prefix_len
+        );
+
         let max_unknown_rcpts_per_session: u32 =
             env::var("MAIL_LASER_MAX_UNKNOWN_RCPTS_PER_SESSION")
                 .unwrap_or_else(|_| "3".to_string())
@@ -459,21 +856,218 @@ impl Config {
             max_unknown_rcpts_per_session
         );
 
+        let proxy_protocol_trusted = env::var("MAIL_LASER_PROXY_PROTOCOL_TRUSTED")
+            .map(|val| Cidr::parse_list(&val))
+            .unwrap_or_else(|_| Ok(Vec::new()))
+            .map_err(|e| anyhow!("MAIL_LASER_PROXY_PROTOCOL_TRUSTED: {}", e))?;
+        log::info!(
+            "Config: Using proxy_protocol_trusted: {:?}",
+            proxy_protocol_trusted
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        );
+
+        let xclient_trusted = env::var("MAIL_LASER_XCLIENT_TRUSTED")
+            .map(|val| Cidr::parse_list(&val))
+            .unwrap_or_else(|_| Ok(Vec::new()))
+            .map_err(|e| anyhow!("MAIL_LASER_XCLIENT_TRUSTED: {}", e))?;
+        log::info!(
+            "Config: Using xclient_trusted: {:?}",
+            xclient_trusted
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        );
+
+        let peer_allow = env::var("MAIL_LASER_PEER_ALLOW")
+            .map(|val| Cidr::parse_list(&val))
+            .unwrap_or_else(|_| Ok(Vec::new()))
+            .map_err(|e| anyhow!("MAIL_LASER_PEER_ALLOW: {}", e))?;
+        log::info!(
+            "Config: Using peer_allow: {:?}",
+            peer_allow
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        );
+        let peer_allow_file = env::var("MAIL_LASER_PEER_ALLOW_FILE")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from);
+        match &peer_allow_file {
+            Some(p) => log::info!("Config: Using peer_allow_file: {}", p.display()),
+            None => log::info!("Config: Using peer_allow_file: <not set>"),
+        }
+
+        let peer_deny = env::var("MAIL_LASER_PEER_DENY")
+            .map(|val| Cidr::parse_list(&val))
+            .unwrap_or_else(|_| Ok(Vec::new()))
+            .map_err(|e| anyhow!("MAIL_LASER_PEER_DENY: {}", e))?;
+        log::info!(
+            "Config: Using peer_deny: {:?}",
+            peer_deny
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        );
+        let peer_deny_file = env::var("MAIL_LASER_PEER_DENY_FILE")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from);
+        match &peer_deny_file {
+            Some(p) => log::info!("Config: Using peer_deny_file: {}", p.display()),
+            None => log::info!("Config: Using peer_deny_file: <not set>"),
+        }
+
+        let peer_deny_action = parse_peer_deny_action()?;
+        log::info!("Config: Using peer_deny_action: {:?}", peer_deny_action);
+
+        let dnsbl_zones = env::var("MAIL_LASER_DNSBL_ZONES")
+            .map(|val| DnsblZone::parse_list(&val))
+            .unwrap_or_else(|_| Ok(Vec::new()))
+            .map_err(|e| anyhow!("MAIL_LASER_DNSBL_ZONES: {}", e))?;
+        log::info!(
+            "Config: Using dnsbl_zones: {:?}",
+            dnsbl_zones
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        );
+
+        let dnsbl_reject_score: Option<u32> = match env::var("MAIL_LASER_DNSBL_REJECT_SCORE") {
+            Ok(val) if !val.is_empty() => {
+                let score: u32 = val.parse().map_err(|e| {
+                    anyhow!("MAIL_LASER_DNSBL_REJECT_SCORE must be a valid u32: {}", e)
+                })?;
+                if score == 0 {
+                    return Err(anyhow!(
+                        "MAIL_LASER_DNSBL_REJECT_SCORE must be greater than 0"
+                    ));
+                }
+                Some(score)
+            }
+            _ => None,
+        };
+        log::info!("Config: Using dnsbl_reject_score: {:?}", dnsbl_reject_score);
+
+        let dnsbl_timeout_secs: u64 = env::var("MAIL_LASER_DNSBL_TIMEOUT")
+            .unwrap_or_else(|_| "2".to_string())
+            .parse()
+            .map_err(|e| anyhow!("MAIL_LASER_DNSBL_TIMEOUT must be a valid u64: {}", e))?;
+        if dnsbl_timeout_secs == 0 {
+            return Err(anyhow!("MAIL_LASER_DNSBL_TIMEOUT must be greater than 0"));
+        }
+        log::info!("Config: Using dnsbl_timeout_secs: {}", dnsbl_timeout_secs);
+
+        let greylist_db = env::var("MAIL_LASER_GREYLIST_DB")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from);
+        match &greylist_db {
+            Some(p) => log::info!("Config: Using greylist_db: {}", p.display()),
+            None => log::info!("Config: Using greylist_db: <not set>"),
+        }
+        let greylist_stage = parse_greylist_stage()?;
+        log::info!("Config: Using greylist_stage: {:?}", greylist_stage);
+        let greylist_delay_secs = parse_timeout_secs("MAIL_LASER_GREYLIST_DELAY", 300)?;
+        log::info!("Config: Using greylist_delay_secs: {}", greylist_delay_secs);
+        let greylist_retry_window_secs =
+            parse_timeout_secs("MAIL_LASER_GREYLIST_RETRY_WINDOW", 172_800)?;
+        log::info!(
+            "Config: Using greylist_retry_window_secs: {}",
+            greylist_retry_window_secs
+        );
+        let greylist_pass_ttl_secs = parse_timeout_secs("MAIL_LASER_GREYLIST_PASS_TTL", 3_024_000)?;
+        log::info!(
+            "Config: Using greylist_pass_ttl_secs: {}",
+            greylist_pass_ttl_secs
+        );
+
+        let rdns_checks = parse_bool("MAIL_LASER_RDNS_CHECKS", false)?;
+        log::info!("Config: Using rdns_checks: {}", rdns_checks);
+        let rdns_timeout_secs = parse_timeout_secs("MAIL_LASER_RDNS_TIMEOUT", 2)?;
+        log::info!("Config: Using rdns_timeout_secs: {}", rdns_timeout_secs);
+
+        let greet_delay_secs = parse_secs("MAIL_LASER_GREET_DELAY", 0)?;
+        log::info!("Config: Using greet_delay_secs: {}", greet_delay_secs);
+        let rcpt_tarpit_secs = parse_secs("MAIL_LASER_RCPT_TARPIT", 0)?;
+        log::info!("Config: Using rcpt_tarpit_secs: {}", rcpt_tarpit_secs);
+        let rcpt_tarpit_max_secs = parse_timeout_secs("MAIL_LASER_RCPT_TARPIT_MAX", 30)?;
+        log::info!(
+            "Config: Using rcpt_tarpit_max_secs: {}",
+            rcpt_tarpit_max_secs
+        );
+
+        let server_hostname = parse_server_hostname()?;
+        log::info!("Config: Using server_hostname: {}", server_hostname);
+        let hide_product = parse_bool("MAIL_LASER_HIDE_PRODUCT", false)?;
+        log::info!("Config: Using hide_product: {}", hide_product);
+
+        let greeting_timeout_secs = parse_timeout_secs("MAIL_LASER_GREETING_TIMEOUT", 60)?;
+        log::info!(
+            "Config: Using greeting_timeout_secs: {}",
+            greeting_timeout_secs
+        );
+        let command_timeout_secs = parse_timeout_secs("MAIL_LASER_COMMAND_TIMEOUT", 300)?;
+        log::info!(
+            "Config: Using command_timeout_secs: {}",
+            command_timeout_secs
+        );
+        let data_timeout_secs = parse_timeout_secs("MAIL_LASER_DATA_TIMEOUT", 180)?;
+        log::info!("Config: Using data_timeout_secs: {}", data_timeout_secs);
+        let session_timeout_secs = parse_timeout_secs("MAIL_LASER_SESSION_TIMEOUT", 1800)?;
+        log::info!(
+            "Config: Using session_timeout_secs: {}",
+            session_timeout_secs
+        );
+
+        // Parsed last: unset per-listener options fall back to the globals.
+        let listeners = match env::var("MAIL_LASER_LISTENERS") {
+            Ok(val) => {
+                let defaults = ListenerConfig {
+                    address: ListenerAddress::Tcp(([0, 0, 0, 0], 0).into()),
+                    protocol: ListenerProtocol::Smtp,
+                    tls: ListenerTls::Starttls,
+                    require_tls,
+                    proxy_protocol_trusted: proxy_protocol_trusted.clone(),
+                    xclient_trusted: xclient_trusted.clone(),
+                    socket_mode: None,
+                };
+                ListenerConfig::parse_list(&val, &defaults)
+                    .map_err(|e| anyhow!("MAIL_LASER_LISTENERS: {}", e))?
+            }
+            Err(_) => Vec::new(),
+        };
+        log::info!("Config: Using listeners: {:?}", listeners);
+
         Ok(Config {
             target_emails,
             webhook_url,
             smtp_bind_address,
             smtp_port,
+            smtps_port,
+            listeners,
             health_check_bind_address,
             health_check_port,
             header_prefixes,
+            recipient_delivery,
+            delivery_mode,
             webhook_timeout_secs,
             webhook_max_retries,
             circuit_breaker_threshold,
             circuit_breaker_reset_secs,
             webhook_signing_secret,
+            spool_dir,
+            spool_retry_interval_secs,
+            spool_max_age_secs,
+            dsn_dir,
             cedar_policies_path,
             cedar_entities_path,
+            tls_cert_path,
+            tls_key_path,
+            require_tls,
+            auth_credentials_path,
             max_message_size_bytes,
             max_attachment_size_bytes,
             attachment_delivery,
@@ -482,8 +1076,182 @@ impl Config {
             dmarc_dns_servers,
             dmarc_temperror_action,
             max_concurrent_per_ip,
+            max_sessions,
+            max_connections_per_minute_per_ip,
+            ipv6_limit_prefix_len,
             max_unknown_rcpts_per_session,
+            proxy_protocol_trusted,
+            xclient_trusted,
+            greeting_timeout_secs,
+            command_timeout_secs,
+            data_timeout_secs,
+            session_timeout_secs,
+            peer_allow,
+            peer_allow_file,
+            peer_deny,
+            peer_deny_file,
+            peer_deny_action,
+            dnsbl_zones,
+            dnsbl_reject_score,
+            dnsbl_timeout_secs,
+            greylist_db,
+            greylist_stage,
+            greylist_delay_secs,
+            greylist_retry_window_secs,
+            greylist_pass_ttl_secs,
+            rdns_checks,
+            rdns_timeout_secs,
+            greet_delay_secs,
+            rcpt_tarpit_secs,
+            rcpt_tarpit_max_secs,
+            server_hostname,
+            hide_product,
+        })
+    }
+
+    /// The listeners to bind: `listeners` when set, otherwise one STARTTLS
+    /// listener on `smtp_bind_address:smtp_port` plus an implicit-TLS one on
+    /// `smtps_port`, both using the global TLS and trust settings.
+    pub fn effective_listeners(&self) -> Result<Vec<ListenerConfig>> {
+        if !self.listeners.is_empty() {
+            return Ok(self.listeners.clone());
+        }
+        let mut ports = vec![(self.smtp_port, ListenerTls::Starttls)];
+        if let Some(smtps_port) = self.smtps_port {
+            ports.push((smtps_port, ListenerTls::Implicit));
+        }
+        ports
+            .into_iter()
+            .map(|(port, tls)| {
+                let address = (self.smtp_bind_address.as_str(), port)
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "MAIL_LASER_BIND_ADDRESS '{}' did not resolve",
+                            self.smtp_bind_address
+                        )
+                    })?;
+                Ok(ListenerConfig {
+                    address: address.into(),
+                    protocol: ListenerProtocol::Smtp,
+                    tls,
+                    require_tls: self.require_tls,
+                    proxy_protocol_trusted: self.proxy_protocol_trusted.clone(),
+                    xclient_trusted: self.xclient_trusted.clone(),
+                    socket_mode: None,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Reads a number of seconds; unset yields `default`.
+fn parse_secs(var: &str, default: u64) -> Result<u64> {
+    match env::var(var) {
+        Ok(val) => val
+            .trim()
+            .parse()
+            .map_err(|e| anyhow!("{} must be a valid u64: {}", var, e)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Reads a timeout in seconds; unset yields `default`, and `0` is rejected.
+fn parse_timeout_secs(var: &str, default: u64) -> Result<u64> {
+    let secs = parse_secs(var, default)?;
+    if secs == 0 {
+        return Err(anyhow!("{} must be greater than 0", var));
+    }
+    Ok(secs)
+}
+
+/// Reads a boolean flag. Accepts `true`/`false`, `1`/`0`, and `yes`/`no`
+/// (case-insensitive); unset or empty yields `default`.
+fn parse_bool(var: &str, default: bool) -> Result<bool> {
+    match env::var(var) {
+        Ok(val) if !val.trim().is_empty() => match val.trim().to_lowercase().as_str() {
+            "true" | "1" | "yes" => Ok(true),
+            "false" | "0" | "no" => Ok(false),
+            other => Err(anyhow!(
+                "{} must be 'true' or 'false' (got '{}')",
+                var,
+                other
+            )),
+        },
+        _ => Ok(default),
+    }
+}
+
+/// Reads `MAIL_LASER_SERVER_HOSTNAME`, falling back to the system host name.
+/// The name ends up in SMTP replies and headers, so it must be a plain
+/// domain name.
+fn parse_server_hostname() -> Result<String> {
+    match env::var("MAIL_LASER_SERVER_HOSTNAME") {
+        Ok(val) if !val.trim().is_empty() => {
+            let name = val.trim().trim_end_matches('.');
+            if !is_hostname(name) {
+                return Err(anyhow!(
+                    "MAIL_LASER_SERVER_HOSTNAME must be a domain name (got '{}')",
+                    val
+                ));
+            }
+            Ok(name.to_string())
+        }
+        _ => {
+            let system = gethostname::gethostname().to_string_lossy().into_owned();
+            if is_hostname(&system) {
+                Ok(system)
+            } else {
+                log::warn!(
+                    "Config: system host name '{}' is not a domain name; using localhost",
+                    system
+                );
+                Ok("localhost".to_string())
+            }
+        }
+    }
+}
+
+/// Whether `name` is a domain name: dot-separated labels of letters, digits
+/// and inner hyphens, at most 63 characters each and 253 in all.
+fn is_hostname(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
         })
+}
+
+fn parse_recipient_delivery() -> Result<RecipientDelivery> {
+    let mode = env::var("MAIL_LASER_RECIPIENT_DELIVERY")
+        .unwrap_or_else(|_| "combined".to_string())
+        .to_lowercase();
+    match mode.as_str() {
+        "combined" => Ok(RecipientDelivery::Combined),
+        "per_recipient" => Ok(RecipientDelivery::PerRecipient),
+        other => Err(anyhow!(
+            "MAIL_LASER_RECIPIENT_DELIVERY must be 'combined' or 'per_recipient' (got '{}')",
+            other
+        )),
+    }
+}
+
+fn parse_delivery_mode() -> Result<DeliveryMode> {
+    let mode = env::var("MAIL_LASER_DELIVERY_MODE")
+        .unwrap_or_else(|_| "async".to_string())
+        .to_lowercase();
+    match mode.as_str() {
+        "async" => Ok(DeliveryMode::Async),
+        "sync" => Ok(DeliveryMode::Sync),
+        other => Err(anyhow!(
+            "MAIL_LASER_DELIVERY_MODE must be 'async' or 'sync' (got '{}')",
+            other
+        )),
     }
 }
 
@@ -516,6 +1284,34 @@ fn parse_dmarc_temperror_action() -> Result<DmarcTempErrorAction> {
     }
 }
 
+fn parse_peer_deny_action() -> Result<PeerDenyAction> {
+    let action = env::var("MAIL_LASER_PEER_DENY_ACTION")
+        .unwrap_or_else(|_| "reject".to_string())
+        .to_lowercase();
+    match action.as_str() {
+        "reject" => Ok(PeerDenyAction::Reject),
+        "drop" => Ok(PeerDenyAction::Drop),
+        other => Err(anyhow!(
+            "MAIL_LASER_PEER_DENY_ACTION must be 'reject' or 'drop' (got '{}')",
+            other
+        )),
+    }
+}
+
+fn parse_greylist_stage() -> Result<GreylistStage> {
+    let stage = env::var("MAIL_LASER_GREYLIST_STAGE")
+        .unwrap_or_else(|_| "rcpt".to_string())
+        .to_lowercase();
+    match stage.as_str() {
+        "rcpt" => Ok(GreylistStage::Rcpt),
+        "data" => Ok(GreylistStage::Data),
+        other => Err(anyhow!(
+            "MAIL_LASER_GREYLIST_STAGE must be 'rcpt' or 'data' (got '{}')",
+            other
+        )),
+    }
+}
+
 fn parse_attachment_delivery() -> Result<AttachmentDelivery> {
     let mode = env::var("MAIL_LASER_ATTACHMENT_DELIVERY")
         .unwrap_or_else(|_| "inline".to_string())
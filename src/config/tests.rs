@@ -3,7 +3,11 @@
 //! or external locking (like the `ENV_LOCK` mutex previously in `mod.rs`) if run in parallel
 //! to avoid interference.
 
-use crate::config::{AttachmentDelivery, Config, DmarcMode, DmarcTempErrorAction};
+use crate::config::{
+    AttachmentDelivery, Cidr, Config, DeliveryMode, DmarcMode, DmarcTempErrorAction, DnsblZone,
+    GreylistStage, ListenerAddress, ListenerProtocol, ListenerTls, PeerDenyAction,
+    RecipientDelivery,
+};
 use once_cell::sync::Lazy;
 use std::env;
 use std::path::PathBuf;
@@ -18,12 +22,23 @@ fn clear_test_env_vars() {
     env::remove_var("MAIL_LASER_WEBHOOK_URL");
     env::remove_var("MAIL_LASER_BIND_ADDRESS");
     env::remove_var("MAIL_LASER_PORT");
+    env::remove_var("MAIL_LASER_SMTPS_PORT");
     env::remove_var("MAIL_LASER_HEALTH_BIND_ADDRESS");
     env::remove_var("MAIL_LASER_HEALTH_PORT");
     env::remove_var("MAIL_LASER_HEADER_PREFIX");
+    env::remove_var("MAIL_LASER_RECIPIENT_DELIVERY");
+    env::remove_var("MAIL_LASER_DELIVERY_MODE");
     env::remove_var("MAIL_LASER_WEBHOOK_TIMEOUT");
     env::remove_var("MAIL_LASER_WEBHOOK_MAX_RETRIES");
     env::remove_var("MAIL_LASER_WEBHOOK_SIGNING_SECRET");
+    env::remove_var("MAIL_LASER_SPOOL_DIR");
+    env::remove_var("MAIL_LASER_SPOOL_RETRY_INTERVAL");
+    env::remove_var("MAIL_LASER_SPOOL_MAX_AGE");
+    env::remove_var("MAIL_LASER_DSN_DIR");
+    env::remove_var("MAIL_LASER_TLS_CERT");
+    env::remove_var("MAIL_LASER_TLS_KEY");
+    env::remove_var("MAIL_LASER_REQUIRE_TLS");
+    env::remove_var("MAIL_LASER_AUTH_CREDENTIALS");
     env::remove_var("MAIL_LASER_CIRCUIT_BREAKER_THRESHOLD");
     env::remove_var("MAIL_LASER_CIRCUIT_BREAKER_RESET");
     env::remove_var("MAIL_LASER_CEDAR_POLICIES");
@@ -42,6 +57,36 @@ fn clear_test_env_vars() {
     env::remove_var("MAIL_LASER_DMARC_TEMPERROR_ACTION");
     env::remove_var("MAIL_LASER_MAX_CONCURRENT_PER_IP");
     env::remove_var("MAIL_LASER_MAX_UNKNOWN_RCPTS_PER_SESSION");
+    env::remove_var("MAIL_LASER_PROXY_PROTOCOL_TRUSTED");
+    env::remove_var("MAIL_LASER_XCLIENT_TRUSTED");
+    env::remove_var("MAIL_LASER_LISTENERS");
+    env::remove_var("MAIL_LASER_GREETING_TIMEOUT");
+    env::remove_var("MAIL_LASER_COMMAND_TIMEOUT");
+    env::remove_var("MAIL_LASER_DATA_TIMEOUT");
+    env::remove_var("MAIL_LASER_SESSION_TIMEOUT");
+    env::remove_var("MAIL_LASER_MAX_SESSIONS");
+    env::remove_var("MAIL_LASER_MAX_CONNECTIONS_PER_MINUTE_PER_IP");
+    env::remove_var("MAIL_LASER_IPV6_LIMIT_PREFIX");
+    env::remove_var("MAIL_LASER_PEER_ALLOW");
+    env::remove_var("MAIL_LASER_PEER_ALLOW_FILE");
+    env::remove_var("MAIL_LASER_PEER_DENY");
+    env::remove_var("MAIL_LASER_PEER_DENY_FILE");
+    env::remove_var("MAIL_LASER_PEER_DENY_ACTION");
+    env::remove_var("MAIL_LASER_DNSBL_ZONES");
+    env::remove_var("MAIL_LASER_DNSBL_REJECT_SCORE");
+    env::remove_var("MAIL_LASER_DNSBL_TIMEOUT");
+    env::remove_var("MAIL_LASER_GREYLIST_DB");
+    env::remove_var("MAIL_LASER_GREYLIST_STAGE");
+    env::remove_var("MAIL_LASER_GREYLIST_DELAY");
+    env::remove_var("MAIL_LASER_GREYLIST_RETRY_WINDOW");
+    env::remove_var("MAIL_LASER_GREYLIST_PASS_TTL");
+    env::remove_var("MAIL_LASER_RDNS_CHECKS");
+    env::remove_var("MAIL_LASER_RDNS_TIMEOUT");
+    env::remove_var("MAIL_LASER_GREET_DELAY");
+    env::remove_var("MAIL_LASER_RCPT_TARPIT");
+    env::remove_var("MAIL_LASER_RCPT_TARPIT_MAX");
+    env::remove_var("MAIL_LASER_SERVER_HOSTNAME");
+    env::remove_var("MAIL_LASER_HIDE_PRODUCT");
 }
 
 /// Sets the minimum variables required for `Config::from_env` to succeed.
@@ -158,6 +203,47 @@ async fn test_config_default_values() {
     assert!(config.dmarc_dns_servers.is_empty());
     assert_eq!(config.dmarc_temperror_action, DmarcTempErrorAction::Reject);
     assert_eq!(config.max_unknown_rcpts_per_session, 3);
+    assert_eq!(config.recipient_delivery, RecipientDelivery::Combined);
+    assert_eq!(config.delivery_mode, DeliveryMode::Async);
+    assert_eq!(config.spool_dir, None);
+    assert_eq!(config.spool_retry_interval_secs, 30);
+    assert_eq!(config.spool_max_age_secs, 432_000);
+    assert_eq!(config.dsn_dir, None);
+    assert_eq!(config.tls_cert_path, None);
+    assert_eq!(config.tls_key_path, None);
+    assert_eq!(config.smtps_port, None);
+    assert!(!config.require_tls);
+    assert_eq!(config.auth_credentials_path, None);
+    assert!(config.proxy_protocol_trusted.is_empty());
+    assert!(config.xclient_trusted.is_empty());
+    assert!(config.listeners.is_empty());
+    assert_eq!(config.greeting_timeout_secs, 60);
+    assert_eq!(config.command_timeout_secs, 300);
+    assert_eq!(config.data_timeout_secs, 180);
+    assert_eq!(config.session_timeout_secs, 1800);
+    assert_eq!(config.max_sessions, 1000);
+    assert_eq!(config.max_connections_per_minute_per_ip, 60);
+    assert_eq!(config.ipv6_limit_prefix_len, 64);
+    assert!(config.peer_allow.is_empty());
+    assert_eq!(config.peer_allow_file, None);
+    assert!(config.peer_deny.is_empty());
+    assert_eq!(config.peer_deny_file, None);
+    assert_eq!(config.peer_deny_action, PeerDenyAction::Reject);
+    assert!(config.dnsbl_zones.is_empty());
+    assert_eq!(config.dnsbl_reject_score, None);
+    assert_eq!(config.dnsbl_timeout_secs, 2);
+    assert_eq!(config.greylist_db, None);
+    assert_eq!(config.greylist_stage, GreylistStage::Rcpt);
+    assert_eq!(config.greylist_delay_secs, 300);
+    assert_eq!(config.greylist_retry_window_secs, 172_800);
+    assert_eq!(config.greylist_pass_ttl_secs, 3_024_000);
+    assert!(!config.rdns_checks);
+    assert_eq!(config.rdns_timeout_secs, 2);
+    assert_eq!(config.greet_delay_secs, 0);
+    assert_eq!(config.rcpt_tarpit_secs, 0);
+    assert_eq!(config.rcpt_tarpit_max_secs, 30);
+    assert!(!config.server_hostname.is_empty());
+    assert!(!config.hide_product);
 }
 
 #[tokio::test]
@@ -557,3 +643,665 @@ async fn test_config_max_unknown_rcpts_rejects_garbage() {
         .to_string()
         .contains("MAIL_LASER_MAX_UNKNOWN_RCPTS_PER_SESSION"));
 }
+
+#[tokio::test]
+async fn test_config_recipient_delivery_per_recipient() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_RECIPIENT_DELIVERY", "per_recipient");
+    let config = Config::from_env().expect("per_recipient must parse");
+    assert_eq!(config.recipient_delivery, RecipientDelivery::PerRecipient);
+}
+
+#[tokio::test]
+async fn test_config_recipient_delivery_invalid_errors() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_RECIPIENT_DELIVERY", "broadcast");
+    let result = Config::from_env();
+    assert!(result.is_err());
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("MAIL_LASER_RECIPIENT_DELIVERY"));
+}
+
+#[tokio::test]
+async fn test_config_spool_settings() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_SPOOL_DIR", "/var/spool/mail-laser");
+    env::set_var("MAIL_LASER_SPOOL_RETRY_INTERVAL", "5");
+    env::set_var("MAIL_LASER_SPOOL_MAX_AGE", "3600");
+    let config = Config::from_env().expect("spool settings must parse");
+    assert_eq!(
+        config.spool_dir,
+        Some(PathBuf::from("/var/spool/mail-laser"))
+    );
+    assert_eq!(config.spool_retry_interval_secs, 5);
+    assert_eq!(config.spool_max_age_secs, 3600);
+}
+
+#[tokio::test]
+async fn test_config_dsn_dir() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_DSN_DIR", "/var/spool/mail-laser-dsn");
+    let config = Config::from_env().expect("dsn dir must parse");
+    assert_eq!(
+        config.dsn_dir,
+        Some(PathBuf::from("/var/spool/mail-laser-dsn"))
+    );
+
+    env::set_var("MAIL_LASER_DSN_DIR", "");
+    let config = Config::from_env().expect("empty dsn dir must parse");
+    assert_eq!(config.dsn_dir, None);
+}
+
+#[tokio::test]
+async fn test_config_spool_retry_interval_zero_errors() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_SPOOL_RETRY_INTERVAL", "0");
+    let result = Config::from_env();
+    assert!(result.is_err());
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("MAIL_LASER_SPOOL_RETRY_INTERVAL"));
+}
+
+#[tokio::test]
+async fn test_config_spool_max_age_zero_errors() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_SPOOL_MAX_AGE", "0");
+    let result = Config::from_env();
+    assert!(result.is_err());
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("MAIL_LASER_SPOOL_MAX_AGE"));
+}
+
+#[tokio::test]
+async fn test_config_delivery_mode_sync() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_DELIVERY_MODE", "SYNC");
+    let config = Config::from_env().expect("sync must parse");
+    assert_eq!(config.delivery_mode, DeliveryMode::Sync);
+}
+
+#[tokio::test]
+async fn test_config_delivery_mode_invalid_errors() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_DELIVERY_MODE", "eventually");
+    let result = Config::from_env();
+    assert!(result.is_err());
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("MAIL_LASER_DELIVERY_MODE"));
+}
+
+#[tokio::test]
+async fn test_config_tls_paths() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_TLS_CERT", "/etc/tls/tls.crt");
+    env::set_var("MAIL_LASER_TLS_KEY", "/etc/tls/tls.key");
+    let config = Config::from_env().expect("TLS paths must parse");
+    assert_eq!(
+        config.tls_cert_path,
+        Some(PathBuf::from("/etc/tls/tls.crt"))
+    );
+    assert_eq!(config.tls_key_path, Some(PathBuf::from("/etc/tls/tls.key")));
+}
+
+#[tokio::test]
+async fn test_config_tls_cert_without_key_errors() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_TLS_CERT", "/etc/tls/tls.crt");
+    let result = Config::from_env();
+    assert!(result.is_err());
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("MAIL_LASER_TLS_KEY"));
+}
+
+#[tokio::test]
+async fn test_config_smtps_port() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_SMTPS_PORT", "465");
+    let config = Config::from_env().expect("SMTPS port must parse");
+    assert_eq!(config.smtps_port, Some(465));
+
+    env::set_var("MAIL_LASER_SMTPS_PORT", "not-a-port");
+    let result = Config::from_env();
+    assert!(result.is_err());
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("MAIL_LASER_SMTPS_PORT"));
+}
+
+#[tokio::test]
+async fn test_config_require_tls() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_REQUIRE_TLS", "true");
+    assert!(Config::from_env().unwrap().require_tls);
+
+    env::set_var("MAIL_LASER_REQUIRE_TLS", "0");
+    assert!(!Config::from_env().unwrap().require_tls);
+
+    env::set_var("MAIL_LASER_REQUIRE_TLS", "sometimes");
+    let result = Config::from_env();
+    assert!(result.is_err());
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("MAIL_LASER_REQUIRE_TLS"));
+}
+
+#[tokio::test]
+async fn test_config_auth_credentials_path() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_AUTH_CREDENTIALS", "/etc/mail-laser/users");
+    let config = Config::from_env().unwrap();
+    assert_eq!(
+        config.auth_credentials_path,
+        Some(PathBuf::from("/etc/mail-laser/users"))
+    );
+
+    env::set_var("MAIL_LASER_AUTH_CREDENTIALS", "");
+    assert_eq!(Config::from_env().unwrap().auth_credentials_path, None);
+}
+
+#[tokio::test]
+async fn test_config_proxy_protocol_trusted() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var(
+        "MAIL_LASER_PROXY_PROTOCOL_TRUSTED",
+        "10.0.0.0/8, 2001:db8::/32",
+    );
+    let config = Config::from_env().expect("CIDR list must parse");
+    assert_eq!(config.proxy_protocol_trusted.len(), 2);
+    assert!(config.proxy_protocol_trusted[0].contains("10.20.30.40".parse().unwrap()));
+
+    env::set_var("MAIL_LASER_PROXY_PROTOCOL_TRUSTED", "10.0.0.0/40");
+    let result = Config::from_env();
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("MAIL_LASER_PROXY_PROTOCOL_TRUSTED"));
+}
+
+#[tokio::test]
+async fn test_config_xclient_trusted() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_XCLIENT_TRUSTED", "192.0.2.25");
+    let config = Config::from_env().expect("single address must parse");
+    assert_eq!(config.xclient_trusted.len(), 1);
+    assert!(config.xclient_trusted[0].contains("192.0.2.25".parse().unwrap()));
+    assert!(!config.xclient_trusted[0].contains("192.0.2.26".parse().unwrap()));
+
+    env::set_var("MAIL_LASER_XCLIENT_TRUSTED", "relay.example");
+    let result = Config::from_env();
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("MAIL_LASER_XCLIENT_TRUSTED"));
+}
+
+#[tokio::test]
+async fn test_config_listeners_inherit_global_defaults() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_REQUIRE_TLS", "true");
+    env::set_var("MAIL_LASER_PROXY_PROTOCOL_TRUSTED", "10.0.0.0/8");
+    env::set_var(
+        "MAIL_LASER_LISTENERS",
+        "0.0.0.0:25 [::]:25\n  10.0.0.5:2525?require_tls=false&proxy_protocol_trusted=&xclient_trusted=10.0.5.10,10.0.5.11 \
+         0.0.0.0:465?tls=implicit",
+    );
+    let config = Config::from_env().expect("listeners must parse");
+
+    let listeners = config.effective_listeners().unwrap();
+    assert_eq!(listeners.len(), 4);
+    assert_eq!(listeners[0].address, "0.0.0.0:25".parse().unwrap());
+    assert_eq!(listeners[1].address, "[::]:25".parse().unwrap());
+    assert!(listeners[1].require_tls);
+    assert_eq!(listeners[1].tls, ListenerTls::Starttls);
+    assert_eq!(listeners[1].proxy_protocol_trusted.len(), 1);
+
+    assert!(!listeners[2].require_tls);
+    assert!(listeners[2].proxy_protocol_trusted.is_empty());
+    assert_eq!(listeners[2].xclient_trusted.len(), 2);
+
+    assert_eq!(listeners[3].tls, ListenerTls::Implicit);
+}
+
+#[tokio::test]
+async fn test_config_listeners_reject_bad_entries() {
+    let _lock = ENV_LOCK.lock().unwrap();
+
+    for bad in [
+        "localhost:25",
+        "0.0.0.0",
+        "0.0.0.0:25?tls=ssl",
+        "0.0.0.0:25?require_tls",
+        "0.0.0.0:25?proxy_protocol_trusted=10.0.0.0/99",
+        "0.0.0.0:25?backlog=5",
+        "0.0.0.0:24?protocol=esmtp",
+        "0.0.0.0:24?mode=0660",
+        "unix:",
+        "unix:/run/lmtp.sock?mode=rw",
+        "unix:/run/lmtp.sock?mode=17777",
+    ] {
+        clear_test_env_vars();
+        set_required_env();
+        env::set_var("MAIL_LASER_LISTENERS", bad);
+        let result = Config::from_env();
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("MAIL_LASER_LISTENERS"),
+            "{} must be rejected",
+            bad
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_config_listeners_lmtp_and_unix_sockets() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var(
+        "MAIL_LASER_LISTENERS",
+        "0.0.0.0:25 127.0.0.1:24?protocol=lmtp unix:/run/maillaser/lmtp.sock?protocol=LMTP&mode=0660",
+    );
+    let config = Config::from_env().expect("listeners must parse");
+
+    let listeners = config.effective_listeners().unwrap();
+    assert_eq!(listeners[0].protocol, ListenerProtocol::Smtp);
+    assert_eq!(listeners[1].protocol, ListenerProtocol::Lmtp);
+    assert_eq!(listeners[1].socket_mode, None);
+    assert_eq!(
+        listeners[2].address,
+        ListenerAddress::Unix("/run/maillaser/lmtp.sock".into())
+    );
+    assert_eq!(
+        listeners[2].address.to_string(),
+        "unix:/run/maillaser/lmtp.sock"
+    );
+    assert_eq!(listeners[2].protocol, ListenerProtocol::Lmtp);
+    assert_eq!(listeners[2].socket_mode, Some(0o660));
+}
+
+#[tokio::test]
+async fn test_config_legacy_ports_become_listeners() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_BIND_ADDRESS", "127.0.0.1");
+    env::set_var("MAIL_LASER_PORT", "2525");
+    env::set_var("MAIL_LASER_SMTPS_PORT", "4465");
+    env::set_var("MAIL_LASER_XCLIENT_TRUSTED", "127.0.0.1");
+    let config = Config::from_env().unwrap();
+    assert!(config.listeners.is_empty());
+
+    let listeners = config.effective_listeners().unwrap();
+    assert_eq!(listeners.len(), 2);
+    assert_eq!(listeners[0].address, "127.0.0.1:2525".parse().unwrap());
+    assert_eq!(listeners[0].tls, ListenerTls::Starttls);
+    assert_eq!(listeners[1].address, "127.0.0.1:4465".parse().unwrap());
+    assert_eq!(listeners[1].tls, ListenerTls::Implicit);
+    assert_eq!(listeners[1].xclient_trusted, config.xclient_trusted);
+}
+
+#[tokio::test]
+async fn test_config_session_timeouts() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_GREETING_TIMEOUT", "15");
+    env::set_var("MAIL_LASER_COMMAND_TIMEOUT", "120");
+    env::set_var("MAIL_LASER_DATA_TIMEOUT", "90");
+    env::set_var("MAIL_LASER_SESSION_TIMEOUT", "600");
+    let config = Config::from_env().unwrap();
+    assert_eq!(config.greeting_timeout_secs, 15);
+    assert_eq!(config.command_timeout_secs, 120);
+    assert_eq!(config.data_timeout_secs, 90);
+    assert_eq!(config.session_timeout_secs, 600);
+
+    for (var, value) in [
+        ("MAIL_LASER_COMMAND_TIMEOUT", "0"),
+        ("MAIL_LASER_DATA_TIMEOUT", "soon"),
+    ] {
+        clear_test_env_vars();
+        set_required_env();
+        env::set_var(var, value);
+        let result = Config::from_env();
+        assert!(
+            result.unwrap_err().to_string().contains(var),
+            "{}={} must be rejected",
+            var,
+            value
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_config_connection_limits() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_MAX_SESSIONS", "250");
+    env::set_var("MAIL_LASER_MAX_CONNECTIONS_PER_MINUTE_PER_IP", "0");
+    env::set_var("MAIL_LASER_IPV6_LIMIT_PREFIX", "48");
+    let config = Config::from_env().unwrap();
+    assert_eq!(config.max_sessions, 250);
+    assert_eq!(config.max_connections_per_minute_per_ip, 0);
+    assert_eq!(config.ipv6_limit_prefix_len, 48);
+
+    for (var, value) in [
+        ("MAIL_LASER_MAX_SESSIONS", "-1"),
+        ("MAIL_LASER_MAX_CONNECTIONS_PER_MINUTE_PER_IP", "many"),
+        ("MAIL_LASER_IPV6_LIMIT_PREFIX", "129"),
+    ] {
+        clear_test_env_vars();
+        set_required_env();
+        env::set_var(var, value);
+        let result = Config::from_env();
+        assert!(
+            result.unwrap_err().to_string().contains(var),
+            "{}={} must be rejected",
+            var,
+            value
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_config_peer_lists() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_PEER_ALLOW", "10.0.0.0/8, 2001:db8::/32");
+    env::set_var("MAIL_LASER_PEER_ALLOW_FILE", "/etc/maillaser/allow.txt");
+    env::set_var("MAIL_LASER_PEER_DENY", "10.66.0.0/16");
+    env::set_var("MAIL_LASER_PEER_DENY_FILE", "/etc/maillaser/deny.txt");
+    env::set_var("MAIL_LASER_PEER_DENY_ACTION", "DROP");
+    let config = Config::from_env().unwrap();
+    assert_eq!(
+        config.peer_allow,
+        vec![
+            "10.0.0.0/8".parse::<Cidr>().unwrap(),
+            "2001:db8::/32".parse().unwrap()
+        ]
+    );
+    assert_eq!(
+        config.peer_allow_file,
+        Some(PathBuf::from("/etc/maillaser/allow.txt"))
+    );
+    assert_eq!(config.peer_deny, vec!["10.66.0.0/16".parse().unwrap()]);
+    assert_eq!(
+        config.peer_deny_file,
+        Some(PathBuf::from("/etc/maillaser/deny.txt"))
+    );
+    assert_eq!(config.peer_deny_action, PeerDenyAction::Drop);
+
+    for (var, value) in [
+        ("MAIL_LASER_PEER_ALLOW", "10.0.0.0/33"),
+        ("MAIL_LASER_PEER_DENY", "bogus"),
+        ("MAIL_LASER_PEER_DENY_ACTION", "tarpit"),
+    ] {
+        clear_test_env_vars();
+        set_required_env();
+        env::set_var(var, value);
+        let result = Config::from_env();
+        assert!(
+            result.unwrap_err().to_string().contains(var),
+            "{}={} must be rejected",
+            var,
+            value
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_config_dnsbl() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var(
+        "MAIL_LASER_DNSBL_ZONES",
+        "zen.spamhaus.org=3, BL.SpamCop.net., b.barracudacentral.org=0",
+    );
+    env::set_var("MAIL_LASER_DNSBL_REJECT_SCORE", "4");
+    env::set_var("MAIL_LASER_DNSBL_TIMEOUT", "1");
+    let config = Config::from_env().unwrap();
+    assert_eq!(
+        config.dnsbl_zones,
+        vec![
+            DnsblZone {
+                zone: "zen.spamhaus.org".into(),
+                weight: 3
+            },
+            DnsblZone {
+                zone: "bl.spamcop.net".into(),
+                weight: 1
+            },
+            DnsblZone {
+                zone: "b.barracudacentral.org".into(),
+                weight: 0
+            },
+        ]
+    );
+    assert_eq!(config.dnsbl_reject_score, Some(4));
+    assert_eq!(config.dnsbl_timeout_secs, 1);
+
+    for (var, value) in [
+        ("MAIL_LASER_DNSBL_ZONES", "zen.spamhaus.org=-1"),
+        ("MAIL_LASER_DNSBL_ZONES", "bad zone"),
+        ("MAIL_LASER_DNSBL_ZONES", "a..b"),
+        ("MAIL_LASER_DNSBL_REJECT_SCORE", "0"),
+        ("MAIL_LASER_DNSBL_REJECT_SCORE", "high"),
+        ("MAIL_LASER_DNSBL_TIMEOUT", "0"),
+    ] {
+        clear_test_env_vars();
+        set_required_env();
+        env::set_var(var, value);
+        let result = Config::from_env();
+        assert!(
+            result.unwrap_err().to_string().contains(var),
+            "{}={} must be rejected",
+            var,
+            value
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_config_greylist() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_GREYLIST_DB", "/var/lib/maillaser/greylist.db");
+    env::set_var("MAIL_LASER_GREYLIST_STAGE", "DATA");
+    env::set_var("MAIL_LASER_GREYLIST_DELAY", "60");
+    env::set_var("MAIL_LASER_GREYLIST_RETRY_WINDOW", "3600");
+    env::set_var("MAIL_LASER_GREYLIST_PASS_TTL", "86400");
+    let config = Config::from_env().unwrap();
+    assert_eq!(
+        config.greylist_db,
+        Some(PathBuf::from("/var/lib/maillaser/greylist.db"))
+    );
+    assert_eq!(config.greylist_stage, GreylistStage::Data);
+    assert_eq!(config.greylist_delay_secs, 60);
+    assert_eq!(config.greylist_retry_window_secs, 3600);
+    assert_eq!(config.greylist_pass_ttl_secs, 86_400);
+
+    for (var, value) in [
+        ("MAIL_LASER_GREYLIST_STAGE", "helo"),
+        ("MAIL_LASER_GREYLIST_DELAY", "0"),
+        ("MAIL_LASER_GREYLIST_RETRY_WINDOW", "soon"),
+        ("MAIL_LASER_GREYLIST_PASS_TTL", "-1"),
+    ] {
+        clear_test_env_vars();
+        set_required_env();
+        env::set_var(var, value);
+        let result = Config::from_env();
+        assert!(
+            result.unwrap_err().to_string().contains(var),
+            "{}={} must be rejected",
+            var,
+            value
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_config_rdns_checks() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_RDNS_CHECKS", "yes");
+    env::set_var("MAIL_LASER_RDNS_TIMEOUT", "5");
+    let config = Config::from_env().unwrap();
+    assert!(config.rdns_checks);
+    assert_eq!(config.rdns_timeout_secs, 5);
+
+    for (var, value) in [
+        ("MAIL_LASER_RDNS_CHECKS", "sometimes"),
+        ("MAIL_LASER_RDNS_TIMEOUT", "0"),
+    ] {
+        clear_test_env_vars();
+        set_required_env();
+        env::set_var(var, value);
+        let result = Config::from_env();
+        assert!(
+            result.unwrap_err().to_string().contains(var),
+            "{}={} must be rejected",
+            var,
+            value
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_config_greet_delay_and_rcpt_tarpit() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_GREET_DELAY", "6");
+    env::set_var("MAIL_LASER_RCPT_TARPIT", "2");
+    env::set_var("MAIL_LASER_RCPT_TARPIT_MAX", "20");
+    let config = Config::from_env().unwrap();
+    assert_eq!(config.greet_delay_secs, 6);
+    assert_eq!(config.rcpt_tarpit_secs, 2);
+    assert_eq!(config.rcpt_tarpit_max_secs, 20);
+
+    for (var, value) in [
+        ("MAIL_LASER_GREET_DELAY", "-1"),
+        ("MAIL_LASER_RCPT_TARPIT", "soon"),
+        ("MAIL_LASER_RCPT_TARPIT_MAX", "0"),
+    ] {
+        clear_test_env_vars();
+        set_required_env();
+        env::set_var(var, value);
+        let result = Config::from_env();
+        assert!(
+            result.unwrap_err().to_string().contains(var),
+            "{}={} must be rejected",
+            var,
+            value
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_config_server_identity() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    clear_test_env_vars();
+    set_required_env();
+
+    env::set_var("MAIL_LASER_SERVER_HOSTNAME", "mx1.example.com.");
+    env::set_var("MAIL_LASER_HIDE_PRODUCT", "true");
+    let config = Config::from_env().unwrap();
+    assert_eq!(config.server_hostname, "mx1.example.com");
+    assert!(config.hide_product);
+
+    for (var, value) in [
+        ("MAIL_LASER_SERVER_HOSTNAME", "mx1 example.com"),
+        (
+            "MAIL_LASER_SERVER_HOSTNAME",
+            "mx1.example.com\r\nX-Injected: 1",
+        ),
+        ("MAIL_LASER_SERVER_HOSTNAME", "-mx.example.com"),
+        ("MAIL_LASER_HIDE_PRODUCT", "maybe"),
+    ] {
+        clear_test_env_vars();
+        set_required_env();
+        env::set_var(var, value);
+        let result = Config::from_env();
+        assert!(
+            result.unwrap_err().to_string().contains(var),
+            "{}={} must be rejected",
+            var,
+            value
+        );
+    }
+}
@@ -245,7 +245,7 @@ fn organizational_domain(domain: &str) -> &str {
     psl::domain_str(domain).unwrap_or(domain)
 }
 
-fn build_authenticator(servers: &[String]) -> Result<MessageAuthenticator> {
+pub(crate) fn build_authenticator(servers: &[String]) -> Result<MessageAuthenticator> {
     if servers.is_empty() {
         return MessageAuthenticator::new_system_conf()
             .map_err(|e| anyhow!("failed to read system DNS config: {}", e));
@@ -0,0 +1,242 @@
+//! DNS blocklist (DNSBL) checks for inbound SMTP peers.
+//!
+//! The checker is built once at startup from [`crate::config::Config`] and
+//! consulted by the SMTP listener for every new connection, before the
+//! greeting. [`DnsblChecker::check`] queries every configured zone
+//! concurrently and sums the weights of the zones that list the peer into a
+//! [`DnsblResult`]. The listener refuses the peer with `554` when the score
+//! reaches `dnsbl_reject_score`, and otherwise hands the result to Cedar as
+//! `context.dnsbl_score` / `context.dnsbl_listed_on`.
+//!
+//! # Listings
+//!
+//! Each zone is queried for an `A` record under the peer's reversed address
+//! (RFC 5782 §2.1 and §2.4). An answer in `127.0.0.0/8` lists the peer,
+//! except `127.255.255.0/24`, which blocklists return to refuse a query (for
+//! example from an unregistered public resolver). `NXDOMAIN` means not
+//! listed. Every other answer, error or timeout is logged and counts as not
+//! listed: a blocklist outage must not stop mail.
+//!
+//! # Caching
+//!
+//! Results are cached per address so a busy sender does not cost a round of
+//! queries per connection. A listing is kept until the shortest TTL among
+//! the listing answers (at most [`MAX_LISTED_TTL`]), a clean result for
+//! [`NOT_LISTED_TTL`]. A result with a failed zone is not cached, so the
+//! next connection asks again.
+
+use crate::config::{Config, DnsblZone};
+use crate::dmarc::build_authenticator;
+use crate::swept_map::SweptMap;
+use anyhow::{Context as _, Result};
+use mail_auth::common::resolver::ToReverseName;
+use mail_auth::{Error as DnsError, MessageAuthenticator};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+
+/// Upper bound on how long a listing is cached, whatever its DNS TTL.
+pub const MAX_LISTED_TTL: Duration = Duration::from_secs(3600);
+
+/// How long an address no zone lists is cached.
+pub const NOT_LISTED_TTL: Duration = Duration::from_secs(300);
+
+/// Aggregate blocklist verdict for one peer address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DnsblResult {
+    /// Sum of the weights of the zones listing the peer.
+    pub score: u32,
+    /// Zones listing the peer, in configuration order.
+    pub listed_on: Vec<String>,
+}
+
+/// Outcome of querying one zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lookup {
+    Listed { expires: Instant },
+    NotListed,
+    Failed,
+}
+
+/// Runtime DNSBL checker. Holds the DNS-backed mail-auth resolver, the
+/// zones, the per-query timeout and the result cache.
+pub struct DnsblChecker {
+    authenticator: Arc<MessageAuthenticator>,
+    zones: Vec<DnsblZone>,
+    timeout: Duration,
+    reject_score: Option<u32>,
+    cache: Mutex<Cache>,
+}
+
+impl DnsblChecker {
+    /// Build a checker from config. Returns `Ok(None)` when no zone is
+    /// configured — the caller should skip the checks entirely. Uses the
+    /// same resolver setup as DMARC, including `dmarc_dns_servers`.
+    pub fn load(config: &Config) -> Result<Option<Arc<Self>>> {
+        if config.dnsbl_zones.is_empty() {
+            return Ok(None);
+        }
+
+        let authenticator = build_authenticator(&config.dmarc_dns_servers)
+            .context("failed to build DNSBL DNS resolver")?;
+
+        Ok(Some(Arc::new(Self {
+            authenticator: Arc::new(authenticator),
+            zones: config.dnsbl_zones.clone(),
+            timeout: Duration::from_secs(config.dnsbl_timeout_secs),
+            reject_score: config.dnsbl_reject_score,
+            cache: Mutex::new(Cache::default()),
+        })))
+    }
+
+    /// Whether `result` reaches the configured reject score.
+    pub fn rejects(&self, result: &DnsblResult) -> bool {
+        self.reject_score.is_some_and(|limit| result.score >= limit)
+    }
+
+    /// Looks `ip` up on every zone, or returns the cached result. Loopback
+    /// peers (local relays, Unix-socket sessions) are never looked up.
+    pub async fn check(&self, ip: IpAddr) -> DnsblResult {
+        let ip = ip.to_canonical();
+        if ip.is_loopback() {
+            return DnsblResult::default();
+        }
+        if let Some(result) = self.cache().get(ip, Instant::now()) {
+            return result;
+        }
+
+        let mut queries = JoinSet::new();
+        for (index, zone) in self.zones.iter().enumerate() {
+            let authenticator = self.authenticator.clone();
+            let name = query_name(ip, &zone.zone);
+            let timeout = self.timeout;
+            queries.spawn(async move { (index, lookup(&authenticator, &name, timeout).await) });
+        }
+
+        let mut lookups = vec![Lookup::Failed; self.zones.len()];
+        while let Some(joined) = queries.join_next().await {
+            if let Ok((index, lookup)) = joined {
+                lookups[index] = lookup;
+            }
+        }
+
+        let now = Instant::now();
+        let (result, expires) = aggregate(&self.zones, &lookups, now);
+        if let Some(expires) = expires {
+            self.cache().insert(ip, result.clone(), expires, now);
+        }
+        result
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, Cache> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Queries one zone, logging anything but a clean listing or `NXDOMAIN`.
+async fn lookup(authenticator: &MessageAuthenticator, name: &str, timeout: Duration) -> Lookup {
+    let answer = match tokio::time::timeout(timeout, authenticator.ipv4_lookup_raw(name)).await {
+        Ok(Ok(answer)) => answer,
+        Ok(Err(DnsError::DnsRecordNotFound(_))) => return Lookup::NotListed,
+        Ok(Err(e)) => {
+            tracing::warn!(query = name, "DNSBL lookup failed: {}", e);
+            return Lookup::Failed;
+        }
+        Err(_elapsed) => {
+            tracing::warn!(
+                query = name,
+                timeout_secs = timeout.as_secs(),
+                "DNSBL lookup timed out"
+            );
+            return Lookup::Failed;
+        }
+    };
+    match listing(&answer.entry) {
+        Some(true) => Lookup::Listed {
+            expires: answer.expires,
+        },
+        Some(false) => Lookup::NotListed,
+        None => {
+            tracing::warn!(
+                query = name,
+                answer = ?answer.entry,
+                "DNSBL returned an error code or a non-listing answer"
+            );
+            Lookup::Failed
+        }
+    }
+}
+
+/// The name queried for `ip` on `zone`: the reversed address (dotted octets
+/// for IPv4, nibbles for IPv6) under the zone, fully qualified so the
+/// resolver's search list never applies.
+fn query_name(ip: IpAddr, zone: &str) -> String {
+    format!("{}.{}.", ip.to_reverse_name(), zone)
+}
+
+/// Classifies an `A` answer: `Some(true)` when it lists the peer,
+/// `Some(false)` when empty, `None` for error codes and answers outside
+/// `127.0.0.0/8`.
+fn listing(answer: &[Ipv4Addr]) -> Option<bool> {
+    if answer.is_empty() {
+        return Some(false);
+    }
+    let valid = answer.iter().all(|addr| {
+        let [a, b, c, _] = addr.octets();
+        a == 127 && !(b == 255 && c == 255)
+    });
+    valid.then_some(true)
+}
+
+/// Sums the listings into a result and works out how long it may be cached:
+/// `None` when any zone failed.
+fn aggregate(
+    zones: &[DnsblZone],
+    lookups: &[Lookup],
+    now: Instant,
+) -> (DnsblResult, Option<Instant>) {
+    let mut result = DnsblResult::default();
+    let mut listed_until: Option<Instant> = None;
+    let mut failed = false;
+    for (zone, lookup) in zones.iter().zip(lookups) {
+        match *lookup {
+            Lookup::Listed { expires } => {
+                result.score = result.score.saturating_add(zone.weight);
+                result.listed_on.push(zone.zone.clone());
+                let expires = expires.min(now + MAX_LISTED_TTL);
+                listed_until = Some(listed_until.map_or(expires, |until| until.min(expires)));
+            }
+            Lookup::NotListed => {}
+            Lookup::Failed => failed = true,
+        }
+    }
+    let expires = (!failed).then(|| listed_until.unwrap_or(now + NOT_LISTED_TTL));
+    (result, expires)
+}
+
+/// Results by address, swept of expired entries as it grows.
+#[derive(Default)]
+struct Cache {
+    by_ip: SweptMap<IpAddr, (DnsblResult, Instant)>,
+}
+
+impl Cache {
+    fn get(&self, ip: IpAddr, now: Instant) -> Option<DnsblResult> {
+        self.by_ip
+            .get(&ip)
+            .filter(|(_, expires)| *expires > now)
+            .map(|(result, _)| result.clone())
+    }
+
+    fn insert(&mut self, ip: IpAddr, result: DnsblResult, expires: Instant, now: Instant) {
+        self.by_ip
+            .entry(ip, |_, (_, expires)| *expires > now)
+            .insert_entry((result, expires));
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,137 @@
+use super::*;
+use crate::swept_map::SWEEP_THRESHOLD;
+
+fn zone(name: &str, weight: u32) -> DnsblZone {
+    DnsblZone {
+        zone: name.to_string(),
+        weight,
+    }
+}
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn query_name_reverses_ipv4_octets() {
+    assert_eq!(
+        query_name(ip("192.0.2.99"), "zen.spamhaus.org"),
+        "99.2.0.192.zen.spamhaus.org."
+    );
+}
+
+#[test]
+fn query_name_reverses_ipv6_nibbles() {
+    assert_eq!(
+        query_name(ip("2001:db8:1:2::3"), "bl.example"),
+        "3.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.2.0.0.0.1.0.0.0.8.b.d.0.1.0.0.2.bl.example."
+    );
+}
+
+#[test]
+fn listing_accepts_loopback_answers_only() {
+    assert_eq!(listing(&[]), Some(false));
+    assert_eq!(listing(&[Ipv4Addr::new(127, 0, 0, 2)]), Some(true));
+    assert_eq!(
+        listing(&[Ipv4Addr::new(127, 0, 0, 4), Ipv4Addr::new(127, 0, 0, 10)]),
+        Some(true)
+    );
+    // Spamhaus-style "query refused" codes are errors, not listings.
+    assert_eq!(listing(&[Ipv4Addr::new(127, 255, 255, 254)]), None);
+    // A wildcarding resolver answering outside 127/8.
+    assert_eq!(listing(&[Ipv4Addr::new(198, 51, 100, 1)]), None);
+}
+
+#[test]
+fn aggregate_sums_weights_of_listing_zones() {
+    let zones = [
+        zone("a.example", 3),
+        zone("b.example", 1),
+        zone("c.example", 2),
+    ];
+    let now = Instant::now();
+    let listed = Lookup::Listed {
+        expires: now + Duration::from_secs(600),
+    };
+    let (result, expires) = aggregate(&zones, &[listed, Lookup::NotListed, listed], now);
+    assert_eq!(result.score, 5);
+    assert_eq!(result.listed_on, vec!["a.example", "c.example"]);
+    assert_eq!(expires, Some(now + Duration::from_secs(600)));
+}
+
+#[test]
+fn aggregate_caches_clean_results_for_the_fixed_ttl() {
+    let zones = [zone("a.example", 1), zone("b.example", 1)];
+    let now = Instant::now();
+    let (result, expires) = aggregate(&zones, &[Lookup::NotListed, Lookup::NotListed], now);
+    assert_eq!(result, DnsblResult::default());
+    assert_eq!(expires, Some(now + NOT_LISTED_TTL));
+}
+
+#[test]
+fn aggregate_uses_shortest_listing_ttl_capped() {
+    let zones = [zone("a.example", 1), zone("b.example", 1)];
+    let now = Instant::now();
+    let long = Lookup::Listed {
+        expires: now + Duration::from_secs(86_400),
+    };
+    let (_, expires) = aggregate(&zones, &[long, Lookup::NotListed], now);
+    assert_eq!(expires, Some(now + MAX_LISTED_TTL));
+
+    let short = Lookup::Listed {
+        expires: now + Duration::from_secs(60),
+    };
+    let (_, expires) = aggregate(&zones, &[long, short], now);
+    assert_eq!(expires, Some(now + Duration::from_secs(60)));
+}
+
+#[test]
+fn aggregate_does_not_cache_when_a_zone_failed() {
+    let zones = [zone("a.example", 2), zone("b.example", 1)];
+    let now = Instant::now();
+    let listed = Lookup::Listed {
+        expires: now + Duration::from_secs(600),
+    };
+    let (result, expires) = aggregate(&zones, &[listed, Lookup::Failed], now);
+    assert_eq!(result.score, 2, "a failed zone counts as not listed");
+    assert_eq!(expires, None);
+}
+
+#[test]
+fn cache_expires_entries_and_sweeps_when_full() {
+    let mut cache = Cache::default();
+    let start = Instant::now();
+    let listed = DnsblResult {
+        score: 1,
+        listed_on: vec!["a.example".to_string()],
+    };
+    cache.insert(
+        ip("192.0.2.1"),
+        listed.clone(),
+        start + Duration::from_secs(10),
+        start,
+    );
+    assert_eq!(cache.get(ip("192.0.2.1"), start), Some(listed));
+    assert_eq!(cache.get(ip("192.0.2.2"), start), None);
+    assert_eq!(
+        cache.get(ip("192.0.2.1"), start + Duration::from_secs(10)),
+        None
+    );
+
+    for n in 1..SWEEP_THRESHOLD as u32 {
+        cache.insert(
+            IpAddr::from(n.to_be_bytes()),
+            DnsblResult::default(),
+            start + Duration::from_secs(10),
+            start,
+        );
+    }
+    // Everything has expired by the time the next insert trips the sweep.
+    cache.insert(
+        ip("198.51.100.1"),
+        DnsblResult::default(),
+        start + Duration::from_secs(300),
+        start + Duration::from_secs(60),
+    );
+    assert_eq!(cache.by_ip.len(), 1);
+}
@@ -0,0 +1,443 @@
+//! Delivery Status Notification support (RFC 3461 / RFC 3464).
+//!
+//! The SMTP session accepts the DSN parameters on `MAIL FROM` (`RET=`,
+//! `ENVID=`) and `RCPT TO` (`NOTIFY=`, `ORCPT=`) and carries them into the
+//! webhook payload as a [`DsnRequest`]. When `MAIL_LASER_DSN_DIR` is set and
+//! asynchronous webhook delivery of an unspooled payload is given up, or a
+//! spooled one outlives `MAIL_LASER_SPOOL_MAX_AGE`, the webhook actor hands
+//! the payload to [`DsnWriter::report_failure`], which
+//! writes an RFC 3464 `multipart/report` addressed to the original sender for
+//! every recipient that asked for `NOTIFY=FAILURE`.
+//!
+//! Reports are written one per file as `<id>.eml`, ready to be handed to a
+//! local MTA for relay (e.g. `sendmail -t -f '' < <id>.eml`). Like the spool,
+//! writes go to a temp file that is renamed into place, so a pickup job never
+//! sees a partial report.
+//!
+//! MailLaser does not keep the raw message once it has been parsed, so the
+//! returned content (`RET=FULL` or `RET=HDRS`) is rebuilt from the payload:
+//! the envelope addresses, subject, forwarded headers and, for `FULL`, the
+//! plain-text body.
+
+use crate::config::Config;
+use crate::spool;
+use crate::webhook::EmailPayload;
+use anyhow::{Context, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+
+#[cfg(test)]
+mod tests;
+
+const REPORT_EXTENSION: &str = "eml";
+const TMP_SUFFIX: &str = ".eml.tmp";
+
+/// RFC 3461 §4.4: `ENVID` values are limited to 100 characters.
+const MAX_ENVID_LEN: usize = 100;
+
+/// How much of the original message a failure report returns (`RET=`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Ret {
+    Full,
+    Hdrs,
+}
+
+impl Ret {
+    /// Parses a `RET=` value (case-insensitive).
+    pub fn parse(value: &str) -> Option<Self> {
+        if value.eq_ignore_ascii_case("FULL") {
+            Some(Self::Full)
+        } else if value.eq_ignore_ascii_case("HDRS") {
+            Some(Self::Hdrs)
+        } else {
+            None
+        }
+    }
+}
+
+/// One `NOTIFY=` keyword.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Notify {
+    Never,
+    Success,
+    Failure,
+    Delay,
+}
+
+impl Notify {
+    /// Parses a `NOTIFY=` value: either `NEVER` alone or a comma-separated
+    /// list of `SUCCESS`, `FAILURE` and `DELAY` (case-insensitive).
+    pub fn parse_list(value: &str) -> Option<Vec<Self>> {
+        let mut list = Vec::new();
+        for keyword in value.split(',') {
+            let notify = match keyword.to_ascii_uppercase().as_str() {
+                "NEVER" => Self::Never,
+                "SUCCESS" => Self::Success,
+                "FAILURE" => Self::Failure,
+                "DELAY" => Self::Delay,
+                _ => return None,
+            };
+            if !list.contains(&notify) {
+                list.push(notify);
+            }
+        }
+        if list.contains(&Self::Never) && list.len() > 1 {
+            return None;
+        }
+        Some(list)
+    }
+}
+
+/// The DSN parameters given with one accepted `RCPT TO`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DsnRecipient {
+    pub recipient: String,
+    /// `NOTIFY=` keywords; empty when the client did not send the parameter.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notify: Vec<Notify>,
+    /// `ORCPT=` as `<addr-type>;<address>`, with the address xtext-decoded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orcpt: Option<String>,
+}
+
+impl DsnRecipient {
+    /// Whether the client sent any DSN parameter for this recipient.
+    pub fn is_requested(&self) -> bool {
+        !self.notify.is_empty() || self.orcpt.is_some()
+    }
+}
+
+/// The DSN parameters of one message, as carried in the webhook payload.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DsnRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ret: Option<Ret>,
+    /// `ENVID=`, xtext-decoded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub envid: Option<String>,
+    /// Recipients that carried `NOTIFY=` or `ORCPT=`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<DsnRecipient>,
+}
+
+impl DsnRequest {
+    /// Whether the client sent any DSN parameter at all.
+    pub fn is_empty(&self) -> bool {
+        self.ret.is_none() && self.envid.is_none() && self.recipients.is_empty()
+    }
+
+    /// The request narrowed to `recipients`, or `None` when nothing is left.
+    pub fn for_recipients(&self, recipients: &[String]) -> Option<Self> {
+        let narrowed = Self {
+            ret: self.ret,
+            envid: self.envid.clone(),
+            recipients: self
+                .recipients
+                .iter()
+                .filter(|r| recipients.contains(&r.recipient))
+                .cloned()
+                .collect(),
+        };
+        (!narrowed.is_empty()).then_some(narrowed)
+    }
+
+    fn recipient(&self, address: &str) -> Option<&DsnRecipient> {
+        self.recipients.iter().find(|r| r.recipient == address)
+    }
+}
+
+/// Parses an `ENVID=` value into its decoded form.
+pub fn parse_envid(value: &str) -> Option<String> {
+    let decoded = xtext_decode(value)?;
+    let printable = decoded.bytes().all(|b| b == b' ' || b.is_ascii_graphic());
+    (printable && !decoded.is_empty() && decoded.len() <= MAX_ENVID_LEN).then_some(decoded)
+}
+
+/// Parses an `ORCPT=` value (`<addr-type>;<xtext>`) into
+/// `<addr-type>;<decoded address>`.
+pub fn parse_orcpt(value: &str) -> Option<String> {
+    let (addr_type, address) = value.split_once(';')?;
+    let valid_type = !addr_type.is_empty()
+        && addr_type
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-');
+    let address = xtext_decode(address)?;
+    (valid_type && !address.is_empty()).then(|| format!("{};{}", addr_type, address))
+}
+
+/// Decodes RFC 3461 §4 xtext: printable ASCII except `+` and `=`, with
+/// other octets written as `+XX`.
+pub fn xtext_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => {
+                let hex = value.get(i + 1..i + 3)?;
+                if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return None;
+                }
+                out.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'=' => return None,
+            b if b.is_ascii_graphic() => {
+                out.push(b);
+                i += 1;
+            }
+            _ => return None,
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Writes failure reports into the directory configured by
+/// `MAIL_LASER_DSN_DIR`.
+#[derive(Debug)]
+pub struct DsnWriter {
+    dir: PathBuf,
+    /// The server's own name (`MAIL_LASER_SERVER_HOSTNAME`), used as the
+    /// reporting MTA.
+    hostname: String,
+}
+
+impl DsnWriter {
+    /// Opens the report directory configured by `MAIL_LASER_DSN_DIR`, or
+    /// returns `None` when failure reports are disabled.
+    pub async fn from_config(config: &Config) -> Result<Option<Arc<Self>>> {
+        match &config.dsn_dir {
+            Some(dir) => Ok(Some(Arc::new(
+                Self::open(dir, &config.server_hostname).await?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Creates the directory if needed. Reports name `hostname` as the
+    /// reporting MTA.
+    pub async fn open(dir: impl AsRef<Path>, hostname: &str) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("Failed to create DSN directory {}", dir.display()))?;
+        info!("DSN: writing failure reports to {}", dir.display());
+        Ok(Self {
+            dir,
+            hostname: hostname.to_string(),
+        })
+    }
+
+    /// Writes a failure report for `payload` if any of its recipients asked
+    /// for `NOTIFY=FAILURE`, and returns the path of the new report.
+    /// `diagnostic` explains why delivery was given up.
+    pub async fn report_failure(
+        &self,
+        payload: &EmailPayload,
+        diagnostic: &str,
+    ) -> Result<Option<PathBuf>> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let Some(report) = failure_report(payload, diagnostic, &self.hostname, now) else {
+            return Ok(None);
+        };
+
+        let id = spool::new_id();
+        let tmp_path = self.dir.join(format!("{id}{TMP_SUFFIX}"));
+        let mut file = tokio::fs::File::create(&tmp_path)
+            .await
+            .with_context(|| format!("Failed to create {}", tmp_path.display()))?;
+        file.write_all(report.as_bytes()).await?;
+        file.sync_all().await?;
+        drop(file);
+
+        let path = self.dir.join(format!("{id}.{REPORT_EXTENSION}"));
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .with_context(|| format!("Failed to commit DSN {id}"))?;
+        Ok(Some(path))
+    }
+}
+
+/// Builds the RFC 3464 failure report for `payload`, or returns `None` when
+/// no recipient asked for `NOTIFY=FAILURE` or the sender is the null path.
+/// `hostname` names the reporting MTA and `now` is the report date in Unix
+/// seconds.
+pub fn failure_report(
+    payload: &EmailPayload,
+    diagnostic: &str,
+    hostname: &str,
+    now: u64,
+) -> Option<String> {
+    let dsn = payload.dsn.as_ref()?;
+    if payload.sender.is_empty() {
+        return None;
+    }
+    let recipients: Vec<String> = if payload.recipients.is_empty() {
+        vec![payload.recipient.clone()]
+    } else {
+        payload.recipients.clone()
+    };
+    let failed: Vec<(&String, &DsnRecipient)> = recipients
+        .iter()
+        .filter_map(|r| dsn.recipient(r).map(|params| (r, params)))
+        .filter(|(_, params)| params.notify.contains(&Notify::Failure))
+        .collect();
+    if failed.is_empty() {
+        return None;
+    }
+    let boundary = format!("dsn-{}", uuid::Uuid::new_v4().simple());
+    let diagnostic = one_line(diagnostic);
+
+    let mut out = String::new();
+    let mut line = |s: &str| {
+        out.push_str(s);
+        out.push_str("\r\n");
+    };
+
+    line(&format!(
+        "From: Mail Delivery System <MAILER-DAEMON@{}>",
+        hostname
+    ));
+    line(&format!("To: <{}>", one_line(&payload.sender)));
+    line("Subject: Delivery Status Notification (Failure)");
+    line(&format!("Date: {}", rfc5322_date(now)));
+    line(&format!(
+        "Message-ID: <{}@{}>",
+        uuid::Uuid::new_v4().simple(),
+        hostname
+    ));
+    line("Auto-Submitted: auto-replied");
+    line("MIME-Version: 1.0");
+    line(&format!(
+        "Content-Type: multipart/report; report-type=delivery-status; boundary=\"{}\"",
+        boundary
+    ));
+    line("");
+
+    line(&format!("--{}", boundary));
+    line("Content-Type: text/plain; charset=utf-8");
+    line("Content-Transfer-Encoding: 8bit");
+    line("");
+    line("Your message could not be delivered to the following recipient(s):");
+    line("");
+    for (recipient, _) in &failed {
+        line(&format!("  {}", recipient));
+    }
+    line("");
+    line(&format!("Reason: {}", diagnostic));
+    line("");
+
+    line(&format!("--{}", boundary));
+    line("Content-Type: message/delivery-status");
+    line("");
+    line(&format!("Reporting-MTA: dns; {}", hostname));
+    if let Some(envid) = &dsn.envid {
+        line(&format!("Original-Envelope-Id: {}", envid));
+    }
+    for (recipient, params) in &failed {
+        line("");
+        if let Some(orcpt) = &params.orcpt {
+            line(&format!("Original-Recipient: {}", one_line(orcpt)));
+        }
+        line(&format!("Final-Recipient: rfc822; {}", recipient));
+        line("Action: failed");
+        line("Status: 5.3.0");
+        line(&format!("Diagnostic-Code: X-MailLaser; {}", diagnostic));
+    }
+    line("");
+
+    line(&format!("--{}", boundary));
+    let headers = original_headers(payload, &recipients);
+    match dsn.ret {
+        Some(Ret::Full) => {
+            line("Content-Type: message/rfc822");
+            if !(headers.is_ascii() && payload.body.is_ascii()) {
+                line("Content-Transfer-Encoding: 8bit");
+            }
+            line("");
+            out.push_str(&headers);
+            out.push_str("MIME-Version: 1.0\r\n");
+            out.push_str("Content-Type: text/plain; charset=utf-8\r\n\r\n");
+            for body_line in payload.body.lines() {
+                out.push_str(body_line);
+                out.push_str("\r\n");
+            }
+        }
+        Some(Ret::Hdrs) | None => {
+            line("Content-Type: text/rfc822-headers");
+            line("");
+            out.push_str(&headers);
+        }
+    }
+    out.push_str(&format!("--{}--\r\n", boundary));
+    Some(out)
+}
+
+/// The header block of the original message, rebuilt from the payload.
+fn original_headers(payload: &EmailPayload, recipients: &[String]) -> String {
+    let mut out = String::new();
+    let from = match &payload.sender_name {
+        Some(name) => format!("{} <{}>", one_line(name), one_line(&payload.sender)),
+        None => format!("<{}>", one_line(&payload.sender)),
+    };
+    out.push_str(&format!("From: {}\r\n", from));
+    out.push_str(&format!("To: {}\r\n", recipients.join(", ")));
+    out.push_str(&format!("Subject: {}\r\n", one_line(&payload.subject)));
+    if let Some(headers) = &payload.headers {
+        let mut names: Vec<&String> = headers.keys().collect();
+        names.sort();
+        for name in names {
+            out.push_str(&format!("{}: {}\r\n", name, one_line(&headers[name])));
+        }
+    }
+    out
+}
+
+/// Replaces control characters so a value cannot break out of its header.
+pub(crate) fn one_line(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+/// Formats Unix seconds as an RFC 5322 date in UTC.
+pub(crate) fn rfc5322_date(secs: u64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+
+    // Civil-from-days (Howard Hinnant), shifted so years start in March.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{}, {} {} {} {:02}:{:02}:{:02} +0000",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
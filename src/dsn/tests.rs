@@ -0,0 +1,245 @@
+use super::*;
+
+fn temp_dsn_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("mail-laser-dsn-{}", uuid::Uuid::new_v4()))
+}
+
+fn recipient(address: &str, notify: &[Notify], orcpt: Option<&str>) -> DsnRecipient {
+    DsnRecipient {
+        recipient: address.to_string(),
+        notify: notify.to_vec(),
+        orcpt: orcpt.map(str::to_string),
+    }
+}
+
+fn sample_payload(dsn: Option<DsnRequest>) -> EmailPayload {
+    EmailPayload {
+        sender: "sender@example.com".to_string(),
+        sender_name: Some("Sam Sender".to_string()),
+        recipient: "a@target.example".to_string(),
+        recipients: vec![
+            "a@target.example".to_string(),
+            "b@target.example".to_string(),
+        ],
+        subject: "Quarterly numbers".to_string(),
+        body: "Line one\nLine two".to_string(),
+        html_body: None,
+        headers: None,
+        attachments: None,
+        dmarc_result: None,
+        authenticated_from: None,
+        authenticated: false,
+        auth_identity: None,
+        ptr_name: None,
+        fcrdns_ok: None,
+        helo_resolves: None,
+        dsn,
+    }
+}
+
+#[test]
+fn notify_parses_lists_and_rejects_never_combinations() {
+    assert_eq!(
+        Notify::parse_list("failure,Delay"),
+        Some(vec![Notify::Failure, Notify::Delay])
+    );
+    assert_eq!(Notify::parse_list("NEVER"), Some(vec![Notify::Never]));
+    assert_eq!(Notify::parse_list("NEVER,FAILURE"), None);
+    assert_eq!(Notify::parse_list("FAILURE,"), None);
+    assert_eq!(Notify::parse_list("SOMETIMES"), None);
+}
+
+#[test]
+fn ret_parses_case_insensitively() {
+    assert_eq!(Ret::parse("full"), Some(Ret::Full));
+    assert_eq!(Ret::parse("HDRS"), Some(Ret::Hdrs));
+    assert_eq!(Ret::parse("BODY"), None);
+}
+
+#[test]
+fn xtext_decodes_hex_escapes_and_rejects_malformed_input() {
+    assert_eq!(xtext_decode("a+2Bb+3Dc").as_deref(), Some("a+b=c"));
+    assert_eq!(xtext_decode("abc+2").as_deref(), None);
+    assert_eq!(xtext_decode("abc+ZZ").as_deref(), None);
+    assert_eq!(xtext_decode("a=b").as_deref(), None);
+    assert_eq!(xtext_decode("a b").as_deref(), None);
+}
+
+#[test]
+fn envid_and_orcpt_are_decoded_and_validated() {
+    assert_eq!(parse_envid("QQ314159+2B1").as_deref(), Some("QQ314159+1"));
+    assert_eq!(parse_envid(&"x".repeat(101)), None);
+    assert_eq!(parse_envid(""), None);
+    assert_eq!(
+        parse_orcpt("rfc822;user+2Btag@example.com").as_deref(),
+        Some("rfc822;user+tag@example.com")
+    );
+    assert_eq!(parse_orcpt("user@example.com"), None);
+    assert_eq!(parse_orcpt(";user@example.com"), None);
+}
+
+#[test]
+fn for_recipients_narrows_and_drops_empty_requests() {
+    let request = DsnRequest {
+        ret: None,
+        envid: None,
+        recipients: vec![recipient("a@target.example", &[Notify::Failure], None)],
+    };
+    let narrowed = request
+        .for_recipients(&["a@target.example".to_string()])
+        .expect("recipient kept");
+    assert_eq!(narrowed.recipients.len(), 1);
+    assert_eq!(
+        request.for_recipients(&["b@target.example".to_string()]),
+        None
+    );
+
+    let with_envid = DsnRequest {
+        envid: Some("env-1".to_string()),
+        ..request
+    };
+    let narrowed = with_envid
+        .for_recipients(&["b@target.example".to_string()])
+        .expect("envelope parameters kept");
+    assert!(narrowed.recipients.is_empty());
+    assert_eq!(narrowed.envid.as_deref(), Some("env-1"));
+}
+
+#[test]
+fn failure_report_lists_only_recipients_that_asked_for_failure() {
+    let payload = sample_payload(Some(DsnRequest {
+        ret: Some(Ret::Hdrs),
+        envid: Some("QQ314159".to_string()),
+        recipients: vec![
+            recipient(
+                "a@target.example",
+                &[Notify::Failure, Notify::Delay],
+                Some("rfc822;alias@target.example"),
+            ),
+            recipient("b@target.example", &[Notify::Never], None),
+        ],
+    }));
+
+    let report =
+        failure_report(&payload, "webhook returned 500", "mx.example.net", 0).expect("report");
+
+    assert!(report.contains("To: <sender@example.com>\r\n"));
+    assert!(report.contains("From: Mail Delivery System <MAILER-DAEMON@mx.example.net>\r\n"));
+    assert!(report.contains("Date: Thu, 1 Jan 1970 00:00:00 +0000\r\n"));
+    assert!(report.contains("Content-Type: multipart/report; report-type=delivery-status;"));
+    assert!(report.contains("Reporting-MTA: dns; mx.example.net\r\n"));
+    assert!(report.contains("@mx.example.net>\r\nAuto-Submitted: auto-replied\r\n"));
+    assert!(report.contains("Original-Envelope-Id: QQ314159\r\n"));
+    assert!(report.contains("Original-Recipient: rfc822;alias@target.example\r\n"));
+    assert!(report.contains("Final-Recipient: rfc822; a@target.example\r\n"));
+    assert!(!report.contains("Final-Recipient: rfc822; b@target.example"));
+    assert!(report.contains("Action: failed\r\nStatus: 5.3.0\r\n"));
+    assert!(report.contains("Diagnostic-Code: X-MailLaser; webhook returned 500\r\n"));
+    assert!(report.contains("Content-Type: text/rfc822-headers\r\n"));
+    assert!(report.contains("Subject: Quarterly numbers\r\n"));
+    assert!(
+        !report.contains("Line one"),
+        "RET=HDRS must not return the body"
+    );
+}
+
+#[test]
+fn failure_report_returns_body_for_ret_full() {
+    let payload = sample_payload(Some(DsnRequest {
+        ret: Some(Ret::Full),
+        envid: None,
+        recipients: vec![recipient("a@target.example", &[Notify::Failure], None)],
+    }));
+
+    let report = failure_report(&payload, "timed out", "mx.example.net", 0).expect("report");
+
+    assert!(report.contains("Content-Type: message/rfc822\r\n"));
+    assert!(report.contains("From: Sam Sender <sender@example.com>\r\n"));
+    assert!(report.contains("\r\n\r\nLine one\r\nLine two\r\n"));
+    assert!(!report.contains("Original-Envelope-Id"));
+}
+
+#[test]
+fn failure_report_is_skipped_without_failure_notify_or_sender() {
+    let payload = sample_payload(Some(DsnRequest {
+        ret: Some(Ret::Full),
+        envid: Some("env".to_string()),
+        recipients: vec![recipient("a@target.example", &[Notify::Success], None)],
+    }));
+    assert_eq!(failure_report(&payload, "x", "mx.example.net", 0), None);
+    assert_eq!(
+        failure_report(&sample_payload(None), "x", "mx.example.net", 0),
+        None
+    );
+
+    let mut null_sender = sample_payload(Some(DsnRequest {
+        ret: None,
+        envid: None,
+        recipients: vec![recipient("a@target.example", &[Notify::Failure], None)],
+    }));
+    null_sender.sender.clear();
+    assert_eq!(failure_report(&null_sender, "x", "mx.example.net", 0), None);
+}
+
+#[test]
+fn failure_report_keeps_header_injection_on_one_line() {
+    let mut payload = sample_payload(Some(DsnRequest {
+        ret: None,
+        envid: None,
+        recipients: vec![recipient("a@target.example", &[Notify::Failure], None)],
+    }));
+    payload.subject = "Hi\r\nBcc: victim@example.com".to_string();
+
+    let report =
+        failure_report(&payload, "bad\r\nX-Injected: 1", "mx.example.net", 0).expect("report");
+
+    assert!(!report.contains("\r\nBcc:"));
+    assert!(!report.contains("\r\nX-Injected:"));
+}
+
+#[test]
+fn rfc5322_date_formats_utc() {
+    assert_eq!(
+        rfc5322_date(951_782_400 + 3723),
+        "Tue, 29 Feb 2000 01:02:03 +0000"
+    );
+    assert_eq!(
+        rfc5322_date(1_792_108_800),
+        "Fri, 16 Oct 2026 00:00:00 +0000"
+    );
+}
+
+#[tokio::test]
+async fn writer_writes_report_only_when_requested() {
+    let dir = temp_dsn_dir();
+    let writer = DsnWriter::open(&dir, "mx.example.net").await.unwrap();
+
+    let skipped = writer
+        .report_failure(&sample_payload(None), "x")
+        .await
+        .unwrap();
+    assert_eq!(skipped, None);
+
+    let payload = sample_payload(Some(DsnRequest {
+        ret: None,
+        envid: None,
+        recipients: vec![recipient("a@target.example", &[Notify::Failure], None)],
+    }));
+    let path = writer
+        .report_failure(&payload, "webhook down")
+        .await
+        .unwrap()
+        .expect("report written");
+    assert_eq!(path.extension().and_then(|e| e.to_str()), Some("eml"));
+    let text = tokio::fs::read_to_string(&path).await.unwrap();
+    assert!(text.contains("Diagnostic-Code: X-MailLaser; webhook down\r\n"));
+
+    let mut names = Vec::new();
+    let mut entries = tokio::fs::read_dir(&dir).await.unwrap();
+    while let Some(entry) = entries.next_entry().await.unwrap() {
+        names.push(entry.file_name());
+    }
+    assert_eq!(names.len(), 1, "no temp files may be left behind");
+
+    tokio::fs::remove_dir_all(&dir).await.ok();
+}
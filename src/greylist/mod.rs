@@ -0,0 +1,447 @@
+//! Greylisting for inbound SMTP.
+//!
+//! A delivery attempt is identified by its triplet: the peer's network (the
+//! IPv4 `/24` or IPv6 `/64` around its address, since large senders retry
+//! from a neighbouring host), the envelope sender and the recipient. The
+//! first time a triplet is seen it is deferred with `451 4.7.1`. A retry
+//! after `delay` and within `retry_window` passes, and from then on the
+//! triplet passes straight away for `pass_ttl`, renewed while it keeps
+//! sending. Real MTAs queue and retry; the fire-and-forget bots behind most
+//! spam never come back.
+//!
+//! The SMTP session checks triplets at `RCPT TO` or at end-of-DATA, per
+//! [`crate::config::GreylistStage`], unless Cedar permits
+//! `Action::"SkipGreylist"`.
+//!
+//! # Storage
+//!
+//! Triplets are kept in memory and in a journal file so they survive
+//! restarts: each change appends one JSON line, and the last line for a
+//! triplet wins. On open, and whenever the journal has grown to twice the
+//! live entries, it is rewritten with only the live entries — to
+//! `<path>.tmp`, fsynced, then renamed into place. A line torn by a crash
+//! mid-append is skipped on load. Appends are not fsynced: losing the last
+//! few before a crash only means those senders are deferred once more.
+//!
+//! Checks only touch the in-memory map. The journal file is owned by a
+//! dedicated writer thread that receives appends and rewrites in order, so
+//! no SMTP session waits on disk I/O.
+
+use crate::config::{Config, GreylistStage};
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+mod tests;
+
+/// Journal size that triggers the first compaction at runtime.
+const COMPACT_THRESHOLD: usize = 4096;
+
+/// A passed triplet's expiry is only renewed when it is at least this old,
+/// so a steady sender costs one journal line an hour, not one per message.
+const RENEW_INTERVAL: u64 = 3600;
+
+/// Whether a delivery attempt may proceed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    /// Deferred; a retry is accepted after `retry_in`.
+    Defer {
+        retry_in: Duration,
+    },
+}
+
+/// Greylisting state backed by a journal file. Shared behind an `Arc`.
+pub struct Greylist {
+    stage: GreylistStage,
+    delay: u64,
+    retry_window: u64,
+    pass_ttl: u64,
+    state: Mutex<State>,
+    journal: Journal,
+}
+
+struct State {
+    triplets: HashMap<Triplet, Entry>,
+    /// Lines in the journal, live or superseded, including those still
+    /// queued for the writer.
+    journal_lines: usize,
+}
+
+/// Handle on the writer thread that owns the journal file. Dropping it
+/// waits for queued writes to finish.
+struct Journal {
+    ops: Option<mpsc::Sender<JournalOp>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+enum JournalOp {
+    Append(String),
+    /// Replace the journal with these lines.
+    Rewrite(String),
+    /// Answered once every earlier operation is done.
+    #[cfg(test)]
+    Flush(mpsc::Sender<()>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct Triplet {
+    network: IpAddr,
+    sender: String,
+    recipient: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    /// Unix seconds of the first attempt (of the current round).
+    first_seen: u64,
+    /// Unix seconds of the latest accepted attempt, once the triplet passed.
+    passed: Option<u64>,
+}
+
+/// One journal line.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    #[serde(flatten)]
+    triplet: Triplet,
+    first_seen: u64,
+    passed: Option<u64>,
+}
+
+impl Triplet {
+    fn new(ip: IpAddr, sender: &str, recipient: &str) -> Self {
+        Self {
+            network: network(ip),
+            sender: sender.to_lowercase(),
+            recipient: recipient.to_lowercase(),
+        }
+    }
+}
+
+impl Greylist {
+    /// Opens the store configured by `MAIL_LASER_GREYLIST_DB`, or returns
+    /// `None` when greylisting is disabled.
+    pub fn from_config(config: &Config) -> Result<Option<Arc<Self>>> {
+        let Some(path) = &config.greylist_db else {
+            return Ok(None);
+        };
+        let greylist = Self::open(
+            path,
+            config.greylist_stage,
+            Duration::from_secs(config.greylist_delay_secs),
+            Duration::from_secs(config.greylist_retry_window_secs),
+            Duration::from_secs(config.greylist_pass_ttl_secs),
+        )?;
+        Ok(Some(Arc::new(greylist)))
+    }
+
+    /// Loads the journal at `path` (creating it and its directory if
+    /// needed) and compacts it.
+    pub fn open(
+        path: &Path,
+        stage: GreylistStage,
+        delay: Duration,
+        retry_window: Duration,
+        pass_ttl: Duration,
+    ) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+        }
+        let mut greylist = Self {
+            stage,
+            delay: delay.as_secs(),
+            retry_window: retry_window.as_secs(),
+            pass_ttl: pass_ttl.as_secs(),
+            state: Mutex::new(State {
+                triplets: read_journal(path)?,
+                journal_lines: 0,
+            }),
+            // Started below, once the journal is compacted.
+            journal: Journal {
+                ops: None,
+                writer: None,
+            },
+        };
+        let content = {
+            let mut state = greylist.lock();
+            let content = greylist.compact(&mut state, unix_now());
+            info!(
+                "Greylist: {} triplets loaded from {}",
+                state.triplets.len(),
+                path.display()
+            );
+            content
+        };
+        let file = rewrite_journal(path, &content)?;
+        greylist.journal = Journal::start(path.to_path_buf(), file)?;
+        Ok(greylist)
+    }
+
+    /// Where in the transaction triplets are checked.
+    pub fn stage(&self) -> GreylistStage {
+        self.stage
+    }
+
+    /// Records an attempt from `ip` with this envelope and says whether it
+    /// may proceed.
+    pub fn check(&self, ip: IpAddr, sender: &str, recipient: &str) -> Verdict {
+        self.check_at(Triplet::new(ip, sender, recipient), unix_now())
+    }
+
+    fn check_at(&self, triplet: Triplet, now: u64) -> Verdict {
+        let mut state = self.lock();
+        let (verdict, update) = self.decide(state.triplets.get(&triplet).copied(), now);
+        if let Some(entry) = update {
+            self.record(&mut state, triplet, entry, now);
+        }
+        verdict
+    }
+
+    /// The verdict for a triplet in state `entry`, and its new state when
+    /// it changes.
+    fn decide(&self, entry: Option<Entry>, now: u64) -> (Verdict, Option<Entry>) {
+        let defer = |secs: u64| Verdict::Defer {
+            retry_in: Duration::from_secs(secs),
+        };
+        match entry {
+            Some(Entry {
+                first_seen,
+                passed: Some(passed),
+            }) if now < passed.saturating_add(self.pass_ttl) => {
+                let renewed = Entry {
+                    first_seen,
+                    passed: Some(now),
+                };
+                let renew = now.saturating_sub(passed) >= RENEW_INTERVAL;
+                (Verdict::Pass, renew.then_some(renewed))
+            }
+            Some(Entry {
+                first_seen,
+                passed: None,
+            }) if now < first_seen.saturating_add(self.delay) => {
+                (defer(first_seen + self.delay - now), None)
+            }
+            Some(Entry {
+                first_seen,
+                passed: None,
+            }) if now <= first_seen.saturating_add(self.delay + self.retry_window) => (
+                Verdict::Pass,
+                Some(Entry {
+                    first_seen,
+                    passed: Some(now),
+                }),
+            ),
+            // New, or expired: start a new round.
+            _ => (
+                defer(self.delay),
+                Some(Entry {
+                    first_seen: now,
+                    passed: None,
+                }),
+            ),
+        }
+    }
+
+    /// Whether `entry` can still affect a verdict.
+    fn is_live(&self, entry: &Entry, now: u64) -> bool {
+        match entry.passed {
+            Some(passed) => now < passed.saturating_add(self.pass_ttl),
+            None => {
+                now <= entry
+                    .first_seen
+                    .saturating_add(self.delay + self.retry_window)
+            }
+        }
+    }
+
+    /// Stores a new state for `triplet` and queues it for the journal. I/O
+    /// errors are logged by the writer and the in-memory state kept, so a
+    /// full disk never blocks mail.
+    fn record(&self, state: &mut State, triplet: Triplet, entry: Entry, now: u64) {
+        let line = journal_line(&triplet, &entry);
+        state.triplets.insert(triplet, entry);
+        self.journal.send(JournalOp::Append(line));
+        state.journal_lines += 1;
+        if state.journal_lines >= COMPACT_THRESHOLD.max(state.triplets.len() * 2) {
+            let content = self.compact(state, now);
+            self.journal.send(JournalOp::Rewrite(content));
+        }
+    }
+
+    /// Drops expired triplets and returns the journal content for the rest.
+    fn compact(&self, state: &mut State, now: u64) -> String {
+        state.triplets.retain(|_, entry| self.is_live(entry, now));
+        state.journal_lines = state.triplets.len();
+        let mut content = String::new();
+        for (triplet, entry) in &state.triplets {
+            content.push_str(&journal_line(triplet, entry));
+        }
+        content
+    }
+
+    /// Waits until every queued journal write is on disk.
+    #[cfg(test)]
+    fn flush(&self) {
+        let (done, wait) = mpsc::channel();
+        self.journal.send(JournalOp::Flush(done));
+        wait.recv().ok();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Journal {
+    /// Starts the writer thread, which appends to `file` and rewrites the
+    /// journal at `path` in the order operations are sent.
+    fn start(path: PathBuf, mut file: File) -> Result<Self> {
+        let (ops, queue) = mpsc::channel();
+        let writer = std::thread::Builder::new()
+            .name("greylist-journal".to_string())
+            .spawn(move || {
+                for op in queue {
+                    match op {
+                        JournalOp::Append(line) => {
+                            if let Err(e) = file.write_all(line.as_bytes()) {
+                                error!("Greylist: failed to append to {}: {}", path.display(), e);
+                            }
+                        }
+                        JournalOp::Rewrite(content) => match rewrite_journal(&path, &content) {
+                            Ok(rewritten) => file = rewritten,
+                            Err(e) => error!("Greylist: compaction failed: {:#}", e),
+                        },
+                        #[cfg(test)]
+                        JournalOp::Flush(done) => {
+                            done.send(()).ok();
+                        }
+                    }
+                }
+            })
+            .context("Failed to start the greylist journal writer")?;
+        Ok(Self {
+            ops: Some(ops),
+            writer: Some(writer),
+        })
+    }
+
+    fn send(&self, op: JournalOp) {
+        let sent = self.ops.as_ref().is_some_and(|ops| ops.send(op).is_ok());
+        if !sent {
+            error!("Greylist: journal writer has stopped; change not persisted");
+        }
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        self.ops.take();
+        if let Some(writer) = self.writer.take() {
+            writer.join().ok();
+        }
+    }
+}
+
+/// Replaces the journal at `path` with `content` — via `<path>.tmp`,
+/// fsynced, then renamed into place — and returns an append handle on it.
+fn rewrite_journal(path: &Path, content: &str) -> Result<File> {
+    let mut tmp_path = path.to_path_buf().into_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let mut file = File::create(&tmp_path)
+        .with_context(|| format!("Failed to create {}", tmp_path.display()))?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    append_handle(path)
+}
+
+/// The network a peer is greylisted as: its IPv4 `/24` or IPv6 `/64`.
+fn network(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => IpAddr::V4(Ipv4Addr::from(u32::from(v4) & 0xffff_ff00)),
+        IpAddr::V6(v6) => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & !u128::from(u64::MAX))),
+    }
+}
+
+fn journal_line(triplet: &Triplet, entry: &Entry) -> String {
+    let record = Record {
+        triplet: triplet.clone(),
+        first_seen: entry.first_seen,
+        passed: entry.passed,
+    };
+    // A struct of strings and integers always serializes.
+    let mut line = serde_json::to_string(&record).unwrap_or_default();
+    line.push('\n');
+    line
+}
+
+/// Replays the journal at `path`; a missing file is an empty store.
+fn read_journal(path: &Path) -> Result<HashMap<Triplet, Entry>> {
+    let mut triplets = HashMap::new();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(triplets),
+        Err(e) => {
+            return Err(anyhow::Error::new(e).context(format!("Failed to open {}", path.display())))
+        }
+    };
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Record>(&line) {
+            Ok(record) => {
+                triplets.insert(
+                    record.triplet,
+                    Entry {
+                        first_seen: record.first_seen,
+                        passed: record.passed,
+                    },
+                );
+            }
+            Err(e) => warn!(
+                "Greylist: skipping unreadable line {} of {}: {}",
+                n + 1,
+                path.display(),
+                e
+            ),
+        }
+    }
+    Ok(triplets)
+}
+
+fn append_handle(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
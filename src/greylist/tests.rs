@@ -0,0 +1,189 @@
+use super::*;
+
+const DELAY: u64 = 300;
+const RETRY_WINDOW: u64 = 3600;
+const PASS_TTL: u64 = 86_400;
+const T0: u64 = 1_700_000_000;
+
+fn temp_db() -> PathBuf {
+    std::env::temp_dir()
+        .join(format!("mail-laser-greylist-{}", uuid::Uuid::new_v4()))
+        .join("greylist.jsonl")
+}
+
+fn open(path: &Path) -> Greylist {
+    Greylist::open(
+        path,
+        GreylistStage::Rcpt,
+        Duration::from_secs(DELAY),
+        Duration::from_secs(RETRY_WINDOW),
+        Duration::from_secs(PASS_TTL),
+    )
+    .unwrap()
+}
+
+fn triplet(ip: &str) -> Triplet {
+    Triplet::new(
+        ip.parse().unwrap(),
+        "Sender@Example.com",
+        "rcpt@example.org",
+    )
+}
+
+fn defer(secs: u64) -> Verdict {
+    Verdict::Defer {
+        retry_in: Duration::from_secs(secs),
+    }
+}
+
+fn cleanup(path: &Path) {
+    std::fs::remove_dir_all(path.parent().unwrap()).ok();
+}
+
+#[test]
+fn network_groups_ipv4_by_24_and_ipv6_by_64() {
+    let net = |s: &str| network(s.parse().unwrap());
+    assert_eq!(net("192.0.2.17"), net("192.0.2.250"));
+    assert_ne!(net("192.0.2.17"), net("192.0.3.17"));
+    assert_eq!(net("::ffff:192.0.2.17"), net("192.0.2.1"));
+    assert_eq!(net("2001:db8:1:2::3"), net("2001:db8:1:2:ffff::1"));
+    assert_ne!(net("2001:db8:1:2::3"), net("2001:db8:1:3::3"));
+}
+
+#[test]
+fn retry_after_delay_passes_and_keeps_passing() {
+    let path = temp_db();
+    let greylist = open(&path);
+
+    assert_eq!(greylist.check_at(triplet("192.0.2.1"), T0), defer(DELAY));
+    assert_eq!(
+        greylist.check_at(triplet("192.0.2.1"), T0 + 60),
+        defer(DELAY - 60)
+    );
+    // The retry may come from a neighbouring host, with different case.
+    let retry = Triplet::new(
+        "192.0.2.2".parse().unwrap(),
+        "sender@example.com",
+        "RCPT@example.org",
+    );
+    assert_eq!(greylist.check_at(retry, T0 + DELAY), Verdict::Pass);
+    assert_eq!(
+        greylist.check_at(triplet("192.0.2.1"), T0 + PASS_TTL),
+        Verdict::Pass,
+        "a pass within the TTL of the last accepted attempt"
+    );
+
+    // Other triplets are unaffected.
+    assert_eq!(
+        greylist.check_at(triplet("198.51.100.1"), T0 + DELAY),
+        defer(DELAY)
+    );
+
+    cleanup(&path);
+}
+
+#[test]
+fn late_retry_and_expired_pass_start_a_new_round() {
+    let path = temp_db();
+    let greylist = open(&path);
+
+    greylist.check_at(triplet("192.0.2.1"), T0);
+    let late = T0 + DELAY + RETRY_WINDOW + 1;
+    assert_eq!(greylist.check_at(triplet("192.0.2.1"), late), defer(DELAY));
+    assert_eq!(
+        greylist.check_at(triplet("192.0.2.1"), late + DELAY),
+        Verdict::Pass
+    );
+
+    let expired = late + DELAY + PASS_TTL;
+    assert_eq!(
+        greylist.check_at(triplet("192.0.2.1"), expired),
+        defer(DELAY)
+    );
+
+    cleanup(&path);
+}
+
+#[test]
+fn pass_is_renewed_at_most_hourly() {
+    let path = temp_db();
+    let greylist = open(&path);
+    greylist.check_at(triplet("192.0.2.1"), T0);
+    greylist.check_at(triplet("192.0.2.1"), T0 + DELAY);
+    let lines = greylist.lock().journal_lines;
+
+    greylist.check_at(triplet("192.0.2.1"), T0 + DELAY + 60);
+    assert_eq!(greylist.lock().journal_lines, lines);
+
+    greylist.check_at(triplet("192.0.2.1"), T0 + DELAY + RENEW_INTERVAL);
+    assert_eq!(greylist.lock().journal_lines, lines + 1);
+
+    cleanup(&path);
+}
+
+#[test]
+fn triplets_survive_reopening() {
+    let path = temp_db();
+    let now = unix_now();
+    {
+        let greylist = open(&path);
+        greylist.check_at(triplet("192.0.2.1"), now - DELAY);
+        greylist.check_at(triplet("198.51.100.1"), now - 10);
+    }
+
+    let greylist = open(&path);
+    assert_eq!(greylist.check_at(triplet("192.0.2.1"), now), Verdict::Pass);
+    assert_eq!(
+        greylist.check_at(triplet("198.51.100.1"), now),
+        defer(DELAY - 10)
+    );
+
+    cleanup(&path);
+}
+
+#[test]
+fn open_skips_torn_lines_and_drops_expired_triplets() {
+    let path = temp_db();
+    let now = unix_now();
+    {
+        let greylist = open(&path);
+        greylist.check_at(triplet("192.0.2.1"), now - DELAY);
+        greylist.check_at(triplet("203.0.113.1"), now - DELAY - RETRY_WINDOW - 1);
+    }
+    let mut journal = OpenOptions::new().append(true).open(&path).unwrap();
+    journal.write_all(b"{\"network\":\"198.51").unwrap();
+    drop(journal);
+
+    let greylist = open(&path);
+    assert_eq!(greylist.lock().triplets.len(), 1);
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(content.lines().count(), 1, "journal compacted on open");
+    assert!(content.contains("192.0.2.0"));
+    assert_eq!(greylist.check_at(triplet("192.0.2.1"), now), Verdict::Pass);
+
+    cleanup(&path);
+}
+
+#[test]
+fn journal_is_compacted_as_it_grows() {
+    let path = temp_db();
+    let greylist = open(&path);
+    for n in 0..COMPACT_THRESHOLD as u32 {
+        let ip = IpAddr::from((0x0a00_0000 + (n << 8)).to_be_bytes());
+        greylist.check_at(Triplet::new(ip, "a@example.com", "b@example.com"), T0);
+    }
+    // Each late retry restarts the round and appends a line. By the time the
+    // journal reaches twice the live triplets, those above have expired.
+    let round = DELAY + RETRY_WINDOW + 1;
+    for n in 1..=COMPACT_THRESHOLD as u64 + 2 {
+        greylist.check_at(triplet("192.0.2.1"), T0 + n * round);
+    }
+
+    assert_eq!(greylist.lock().triplets.len(), 1);
+    assert_eq!(greylist.lock().journal_lines, 1);
+    greylist.flush();
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(content.lines().count(), 1);
+
+    cleanup(&path);
+}
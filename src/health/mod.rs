@@ -20,6 +20,12 @@ where
             .status(StatusCode::OK)
             .body(Full::new(Bytes::from("")))
             .unwrap())
+    } else if req.uri().path() == "/metrics" {
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(Full::new(Bytes::from(crate::metrics::render())))
+            .unwrap())
     } else {
         Ok(Response::builder()
             .status(StatusCode::NOT_FOUND)
@@ -142,6 +148,20 @@ mod tests {
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let req = Request::builder()
+            .uri("/metrics")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = health_check_handler(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; version=0.0.4"
+        );
+    }
+
     #[tokio::test]
     async fn test_health_check_post_method() {
         let req = Request::builder()
@@ -1,9 +1,17 @@
 pub mod attachment;
+pub mod auth;
 pub mod config;
 pub mod dmarc;
+pub mod dnsbl;
+pub mod dsn;
+pub mod greylist;
 pub mod health;
+pub mod metrics;
 pub mod policy;
+pub mod rdns;
 pub mod smtp;
+pub mod spool;
+mod swept_map;
 pub mod webhook;
 
 use acton_reactive::prelude::*;
@@ -18,6 +26,11 @@ pub async fn run() -> Result<()> {
         env!("CARGO_PKG_VERSION")
     );
 
+    // Both `ring` and `aws-lc-rs` end up in the dependency graph, so rustls
+    // cannot pick a provider on its own. The STARTTLS server config is built
+    // at startup and needs one selected before the listeners come up.
+    let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+
     let config = match config::Config::from_env() {
         Ok(config) => config,
         Err(e) => {
@@ -0,0 +1,184 @@
+//! Process-wide counters, served in the Prometheus text exposition format at
+//! `/metrics` on the health check listener.
+//!
+//! Counters are plain statics so any task can bump them without threading a
+//! handle through; they reset when the process restarts.
+
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A monotonically increasing count.
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A single counter with its own name and no labels.
+pub struct NamedCounter {
+    name: &'static str,
+    help: &'static str,
+    counter: Counter,
+}
+
+impl NamedCounter {
+    pub const fn new(name: &'static str, help: &'static str) -> Self {
+        NamedCounter {
+            name,
+            help,
+            counter: Counter::new(),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.counter.inc();
+    }
+
+    pub fn get(&self) -> u64 {
+        self.counter.get()
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} counter", self.name);
+        let _ = writeln!(out, "{} {}", self.name, self.counter.get());
+    }
+}
+
+/// A family of counters sharing a name, split by the value of one label.
+/// The label values are fixed up front.
+pub struct CounterVec<const N: usize> {
+    name: &'static str,
+    help: &'static str,
+    label: &'static str,
+    values: [(&'static str, Counter); N],
+}
+
+impl<const N: usize> CounterVec<N> {
+    /// Increments the counter for `label_value`. Unknown values are ignored.
+    pub fn inc(&self, label_value: &str) {
+        if let Some((_, counter)) = self.values.iter().find(|(v, _)| *v == label_value) {
+            counter.inc();
+        }
+    }
+
+    /// The current count for `label_value`, or `0` if it is not a known value.
+    pub fn get(&self, label_value: &str) -> u64 {
+        self.values
+            .iter()
+            .find(|(v, _)| *v == label_value)
+            .map_or(0, |(_, counter)| counter.get())
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} counter", self.name);
+        for (value, counter) in &self.values {
+            let _ = writeln!(
+                out,
+                "{}{{{}=\"{}\"}} {}",
+                self.name,
+                self.label,
+                value,
+                counter.get()
+            );
+        }
+    }
+}
+
+/// SMTP sessions ended because the client was too slow, by the phase it
+/// stalled in: `greeting`, `command`, `data` or `session` (wall-clock cap).
+pub static SMTP_TIMEOUTS: CounterVec<4> = CounterVec {
+    name: "maillaser_smtp_timeouts_total",
+    help: "SMTP sessions closed after a client timeout.",
+    label: "phase",
+    values: [
+        ("greeting", Counter::new()),
+        ("command", Counter::new()),
+        ("data", Counter::new()),
+        ("session", Counter::new()),
+    ],
+};
+
+/// SMTP clients refused for sending data before the greeting.
+pub static SMTP_EARLY_TALKERS: NamedCounter = NamedCounter::new(
+    "maillaser_smtp_early_talkers_total",
+    "SMTP clients refused for talking before the greeting.",
+);
+
+/// Renders every metric in the Prometheus text format.
+pub fn render() -> String {
+    let mut out = String::new();
+    SMTP_TIMEOUTS.render(&mut out);
+    SMTP_EARLY_TALKERS.render(&mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_vec_counts_known_labels_only() {
+        let family = CounterVec {
+            name: "test_total",
+            help: "Test counter.",
+            label: "kind",
+            values: [("a", Counter::new()), ("b", Counter::new())],
+        };
+        family.inc("a");
+        family.inc("a");
+        family.inc("c");
+        assert_eq!(family.get("a"), 2);
+        assert_eq!(family.get("b"), 0);
+        assert_eq!(family.get("c"), 0);
+
+        let mut out = String::new();
+        family.render(&mut out);
+        assert_eq!(
+            out,
+            "# HELP test_total Test counter.\n\
+             # TYPE test_total counter\n\
+             test_total{kind=\"a\"} 2\n\
+             test_total{kind=\"b\"} 0\n"
+        );
+    }
+
+    #[test]
+    fn named_counter_renders_without_labels() {
+        let counter = NamedCounter::new("test_single_total", "Test counter.");
+        counter.inc();
+        assert_eq!(counter.get(), 1);
+
+        let mut out = String::new();
+        counter.render(&mut out);
+        assert_eq!(
+            out,
+            "# HELP test_single_total Test counter.\n\
+             # TYPE test_single_total counter\n\
+             test_single_total 1\n"
+        );
+    }
+
+    #[test]
+    fn render_includes_every_metric() {
+        assert!(render().contains("maillaser_smtp_timeouts_total{phase=\"data\"}"));
+        assert!(render().contains("maillaser_smtp_early_talkers_total "));
+    }
+}
@@ -1,13 +1,21 @@
 //! Cedar-based authorization for mail-laser.
 //!
-//! Two decisions are expressed as Cedar authorization requests:
+//! Three decisions are expressed as Cedar authorization requests:
 //!
+//! * [`PolicyEngine::can_mail_from`] — may this principal open a transaction
+//!   with the declared message size? Invoked at `MAIL FROM`, before any
+//!   content is transferred, so per-sender size limits cost no bandwidth.
+//!   Opt-in: evaluated only when some policy names `Action::"MailFrom"`.
 //! * [`PolicyEngine::can_send`] — may this principal deliver a message to this
 //!   recipient? Invoked at end-of-DATA, *after* DMARC has run, so the DMARC
 //!   outcome and the aligned From address are available as context.
 //! * [`PolicyEngine::can_attach`] — may the principal attach *this* file (by MIME
 //!   type, size, filename)? Invoked once per attachment after parsing, with the
 //!   same DMARC context mirrored in.
+//! * [`PolicyEngine::can_skip_greylist`] — may this principal's mail to this
+//!   recipient bypass greylisting? Invoked wherever greylisting runs (`RCPT TO`
+//!   or end-of-DATA) with the facts known there. Opt-in: evaluated only when
+//!   some policy names `Action::"SkipGreylist"`.
 //!
 //! Policies and optional entities are loaded once at startup from paths supplied
 //! in [`crate::config::Config`]. The engine is cheap to clone via `Arc` and safe
@@ -15,7 +23,8 @@
 
 use anyhow::{anyhow, Context as _, Result};
 use cedar_policy::{
-    Authorizer, Context, Decision, Entities, EntityUid, PolicySet, Request, RestrictedExpression,
+    ActionConstraint, Authorizer, Context, Decision, Entities, EntityUid, PolicySet, Request,
+    RestrictedExpression,
 };
 use std::collections::HashMap;
 use std::fs;
@@ -31,11 +40,33 @@ pub struct AttachmentCheck<'a> {
     pub size_bytes: u64,
 }
 
-/// Per-request DMARC facts surfaced to Cedar as context attributes.
+/// Envelope facts known at `MAIL FROM`, surfaced to Cedar as context for the
+/// `MailFrom` action. DMARC has not run yet, so none of its fields exist here.
+#[derive(Debug, Clone)]
+pub struct EnvelopeContext {
+    /// Envelope MAIL FROM.
+    pub envelope_from: String,
+    /// HELO/EHLO domain the client announced.
+    pub helo: String,
+    /// Peer IP address of the sending MTA.
+    pub peer_ip: IpAddr,
+    /// Username accepted by SMTP AUTH, if the session authenticated.
+    pub auth_identity: Option<String>,
+    /// `SIZE=` from `MAIL FROM` (RFC 1870). Emitted as `size_declared: Bool`
+    /// plus `declared_size: Long` (0 when absent).
+    pub declared_size: Option<u64>,
+    /// Summed weight of the DNS blocklists listing the peer (0 when none do
+    /// or no zones are configured).
+    pub dnsbl_score: u32,
+    /// Blocklist zones listing the peer. Emitted as a `Set` of `String`.
+    pub dnsbl_listed_on: Vec<String>,
+}
+
+/// Per-request DMARC and session facts surfaced to Cedar as context attributes.
 ///
 /// Constructed once in `finalize_message()` after DMARC runs and reused for
-/// both the `SendMail` and `Attach` evaluations so policies see a consistent
-/// view of the message's authentication state.
+/// the `SendMail`, `Attach` and (at end-of-DATA) `SkipGreylist` evaluations
+/// so policies see a consistent view of the message's authentication state.
 #[derive(Debug, Clone)]
 pub struct DmarcContext {
     /// `"pass" | "fail" | "none" | "temperror" | "off"` — matches the
@@ -55,6 +86,30 @@ pub struct DmarcContext {
     pub helo: String,
     /// Peer IP address of the sending MTA.
     pub peer_ip: IpAddr,
+    /// Username accepted by SMTP AUTH, if the session authenticated. Emitted
+    /// as `authenticated: Bool` plus `auth_identity: String` (empty when
+    /// absent), mirroring `authenticated_from`.
+    pub auth_identity: Option<String>,
+    /// Summed weight of the DNS blocklists listing the peer.
+    pub dnsbl_score: u32,
+    /// Blocklist zones listing the peer. Emitted as a `Set` of `String`.
+    pub dnsbl_listed_on: Vec<String>,
+    /// The peer's reverse DNS name, forward-confirmed when `fcrdns_ok`.
+    /// Emitted as the empty string when absent or reverse DNS checks are off.
+    pub ptr_name: Option<String>,
+    /// Whether `ptr_name` resolves back to `peer_ip`.
+    pub fcrdns_ok: bool,
+    /// Whether `helo` resolves to `peer_ip`. Makes `helo` usable for trust
+    /// decisions; `false` when reverse DNS checks are off.
+    pub helo_resolves: bool,
+}
+
+/// The facts a `SkipGreylist` decision sees, depending on where greylisting
+/// runs: the envelope context at `RCPT TO`, the DMARC context at end-of-DATA.
+#[derive(Debug, Clone, Copy)]
+pub enum GreylistFacts<'a> {
+    Envelope(&'a EnvelopeContext),
+    Dmarc(&'a DmarcContext),
 }
 
 /// Cedar authorization engine.
@@ -62,6 +117,14 @@ pub struct PolicyEngine {
     policies: PolicySet,
     entities: Entities,
     authorizer: Authorizer,
+    /// Whether any policy names `Action::"MailFrom"` in its scope. Policy
+    /// sets written before the action existed never permit it, so it is
+    /// only evaluated when a policy opts in.
+    gates_mail_from: bool,
+    /// Whether any policy names `Action::"SkipGreylist"`. Unlike `MailFrom`,
+    /// a permit here relaxes a check, so a policy permitting every action
+    /// must not skip greylisting by accident.
+    gates_skip_greylist: bool,
 }
 
 impl PolicyEngine {
@@ -101,11 +164,7 @@ impl PolicyEngine {
             None => Entities::empty(),
         };
 
-        Ok(Self {
-            policies,
-            entities,
-            authorizer: Authorizer::new(),
-        })
+        Ok(Self::new(policies, entities))
     }
 
     /// Builds an engine directly from in-memory strings. Useful for tests.
@@ -117,18 +176,69 @@ impl PolicyEngine {
                 .map_err(|e| anyhow!("failed to parse Cedar entities: {}", e))?,
             None => Entities::empty(),
         };
-        Ok(Self {
+        Ok(Self::new(policies, entities))
+    }
+
+    fn new(policies: PolicySet, entities: Entities) -> Self {
+        Self {
+            gates_mail_from: names_action(&policies, "MailFrom"),
+            gates_skip_greylist: names_action(&policies, "SkipGreylist"),
             policies,
             entities,
             authorizer: Authorizer::new(),
-        })
+        }
+    }
+
+    /// Returns `true` when the `MailFrom` action is permitted for `principal`
+    /// with the envelope facts known at `MAIL FROM`, notably the declared
+    /// message size. Always `true` when no policy names `Action::"MailFrom"`.
+    ///
+    /// The principal is the SMTP AUTH username when the session
+    /// authenticated, otherwise the envelope sender.
+    pub fn can_mail_from(&self, principal: &str, envelope: &EnvelopeContext) -> bool {
+        if !self.gates_mail_from {
+            return true;
+        }
+        let principal_uid = match user_uid(principal) {
+            Ok(uid) => uid,
+            Err(e) => {
+                tracing::warn!(principal = principal, error = %e, "rejecting sender — failed to build principal UID");
+                return false;
+            }
+        };
+        let action = match action_uid("MailFrom") {
+            Ok(uid) => uid,
+            Err(e) => {
+                tracing::error!(error = %e, "failed to build MailFrom action UID — denying");
+                return false;
+            }
+        };
+        let resource = match envelope_resource_uid() {
+            Ok(uid) => uid,
+            Err(e) => {
+                tracing::error!(error = %e, "failed to build Envelope resource UID — denying");
+                return false;
+            }
+        };
+
+        let pairs = envelope_context_pairs(envelope);
+        let context = match Context::from_pairs(pairs) {
+            Ok(ctx) => ctx,
+            Err(e) => {
+                tracing::error!(error = %e, "failed to build Cedar context — denying MailFrom");
+                return false;
+            }
+        };
+
+        self.decide(principal_uid, action, resource, context)
     }
 
     /// Returns `true` when the `SendMail` action is permitted for `principal`
     /// delivering to `recipient`, with DMARC authentication facts in context.
     ///
-    /// The principal is the identity selected by the caller: the DMARC-aligned
-    /// From address when DMARC passed in `enforce` mode, otherwise the envelope
+    /// The principal is the identity selected by the caller: the SMTP AUTH
+    /// username when the session authenticated, else the DMARC-aligned From
+    /// address when DMARC passed in `enforce` mode, otherwise the envelope
     /// `MAIL FROM` sender. The envelope sender is always available in context
     /// so policies can cross-check claimed vs. authenticated identity.
     pub fn can_send(&self, principal: &str, recipient: &str, dmarc: &DmarcContext) -> bool {
@@ -227,6 +337,59 @@ impl PolicyEngine {
         self.decide(principal_uid, action, resource, context)
     }
 
+    /// Returns `true` when the `SkipGreylist` action is permitted for
+    /// `principal` mailing `recipient`, exempting the delivery from
+    /// greylisting. Always `false` when no policy names
+    /// `Action::"SkipGreylist"`, and on any evaluation error.
+    ///
+    /// The principal is chosen as for the action evaluated at the same
+    /// stage: `MailFrom` at `RCPT TO`, `SendMail` at end-of-DATA.
+    pub fn can_skip_greylist(
+        &self,
+        principal: &str,
+        recipient: &str,
+        facts: GreylistFacts<'_>,
+    ) -> bool {
+        if !self.gates_skip_greylist {
+            return false;
+        }
+        let principal_uid = match user_uid(principal) {
+            Ok(uid) => uid,
+            Err(e) => {
+                tracing::warn!(principal = principal, error = %e, "greylisting — failed to build principal UID");
+                return false;
+            }
+        };
+        let action = match action_uid("SkipGreylist") {
+            Ok(uid) => uid,
+            Err(e) => {
+                tracing::error!(error = %e, "failed to build SkipGreylist action UID — greylisting");
+                return false;
+            }
+        };
+        let resource = match recipient_uid(recipient) {
+            Ok(uid) => uid,
+            Err(e) => {
+                tracing::error!(error = %e, recipient = recipient, "failed to build Recipient UID — greylisting");
+                return false;
+            }
+        };
+
+        let pairs = match facts {
+            GreylistFacts::Envelope(envelope) => envelope_context_pairs(envelope),
+            GreylistFacts::Dmarc(dmarc) => dmarc_context_pairs(dmarc),
+        };
+        let context = match Context::from_pairs(pairs) {
+            Ok(ctx) => ctx,
+            Err(e) => {
+                tracing::error!(error = %e, "failed to build Cedar context — greylisting");
+                return false;
+            }
+        };
+
+        self.decide(principal_uid, action, resource, context)
+    }
+
     fn decide(
         &self,
         principal: EntityUid,
@@ -274,6 +437,62 @@ fn attachment_resource_uid() -> Result<EntityUid> {
         .map_err(|e| anyhow!("invalid Attachment UID: {}", e))
 }
 
+fn envelope_resource_uid() -> Result<EntityUid> {
+    EntityUid::from_str(r#"Envelope::"inbound""#)
+        .map_err(|e| anyhow!("invalid Envelope UID: {}", e))
+}
+
+/// Whether any policy names `Action::"<name>"` in its scope.
+fn names_action(policies: &PolicySet, name: &str) -> bool {
+    action_uid(name).is_ok_and(|action| {
+        policies
+            .policies()
+            .any(|policy| match policy.action_constraint() {
+                ActionConstraint::Any => false,
+                ActionConstraint::Eq(uid) => uid == action,
+                ActionConstraint::In(uids) => uids.contains(&action),
+            })
+    })
+}
+
+/// Builds the envelope HashMap shared between `can_mail_from` and
+/// `can_skip_greylist` at `RCPT TO`.
+fn envelope_context_pairs(envelope: &EnvelopeContext) -> HashMap<String, RestrictedExpression> {
+    let mut pairs: HashMap<String, RestrictedExpression> = HashMap::new();
+    pairs.insert(
+        "envelope_from".to_string(),
+        RestrictedExpression::new_string(envelope.envelope_from.clone()),
+    );
+    pairs.insert(
+        "helo".to_string(),
+        RestrictedExpression::new_string(envelope.helo.clone()),
+    );
+    pairs.insert(
+        "peer_ip".to_string(),
+        RestrictedExpression::new_string(envelope.peer_ip.to_string()),
+    );
+    pairs.insert(
+        "authenticated".to_string(),
+        RestrictedExpression::new_bool(envelope.auth_identity.is_some()),
+    );
+    pairs.insert(
+        "auth_identity".to_string(),
+        RestrictedExpression::new_string(envelope.auth_identity.clone().unwrap_or_default()),
+    );
+    pairs.insert(
+        "size_declared".to_string(),
+        RestrictedExpression::new_bool(envelope.declared_size.is_some()),
+    );
+    // Cedar longs are i64; a larger declaration saturates.
+    let declared_size = envelope.declared_size.unwrap_or(0).min(i64::MAX as u64) as i64;
+    pairs.insert(
+        "declared_size".to_string(),
+        RestrictedExpression::new_long(declared_size),
+    );
+    insert_dnsbl_pairs(&mut pairs, envelope.dnsbl_score, &envelope.dnsbl_listed_on);
+    pairs
+}
+
 /// Builds the DMARC-only HashMap shared between `can_send` and `can_attach`.
 /// Attachment-specific keys (`content_type`, `size_bytes`, `filename`) are
 /// merged in on top at the `Attach` call site.
@@ -303,9 +522,51 @@ fn dmarc_context_pairs(dmarc: &DmarcContext) -> HashMap<String, RestrictedExpres
         "peer_ip".to_string(),
         RestrictedExpression::new_string(dmarc.peer_ip.to_string()),
     );
+    pairs.insert(
+        "authenticated".to_string(),
+        RestrictedExpression::new_bool(dmarc.auth_identity.is_some()),
+    );
+    pairs.insert(
+        "auth_identity".to_string(),
+        RestrictedExpression::new_string(dmarc.auth_identity.clone().unwrap_or_default()),
+    );
+    insert_dnsbl_pairs(&mut pairs, dmarc.dnsbl_score, &dmarc.dnsbl_listed_on);
+    pairs.insert(
+        "ptr_name".to_string(),
+        RestrictedExpression::new_string(dmarc.ptr_name.clone().unwrap_or_default()),
+    );
+    pairs.insert(
+        "fcrdns_ok".to_string(),
+        RestrictedExpression::new_bool(dmarc.fcrdns_ok),
+    );
+    pairs.insert(
+        "helo_resolves".to_string(),
+        RestrictedExpression::new_bool(dmarc.helo_resolves),
+    );
     pairs
 }
 
+/// Adds `dnsbl_score: Long` and `dnsbl_listed_on: Set<String>`, shared by
+/// the envelope and DMARC contexts.
+fn insert_dnsbl_pairs(
+    pairs: &mut HashMap<String, RestrictedExpression>,
+    score: u32,
+    listed_on: &[String],
+) {
+    pairs.insert(
+        "dnsbl_score".to_string(),
+        RestrictedExpression::new_long(i64::from(score)),
+    );
+    pairs.insert(
+        "dnsbl_listed_on".to_string(),
+        RestrictedExpression::new_set(
+            listed_on
+                .iter()
+                .map(|zone| RestrictedExpression::new_string(zone.clone())),
+        ),
+    );
+}
+
 fn build_dmarc_context(dmarc: &DmarcContext) -> Result<Context> {
     Context::from_pairs(dmarc_context_pairs(dmarc))
         .map_err(|e| anyhow!("failed to build Cedar context: {}", e))
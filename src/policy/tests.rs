@@ -1,4 +1,4 @@
-use crate::policy::{AttachmentCheck, DmarcContext, PolicyEngine};
+use crate::policy::{AttachmentCheck, DmarcContext, EnvelopeContext, GreylistFacts, PolicyEngine};
 use std::net::{IpAddr, Ipv4Addr};
 
 const POLICIES: &str = r#"
@@ -44,6 +44,12 @@ fn dmarc_off(envelope_from: &str) -> DmarcContext {
         envelope_from: envelope_from.to_string(),
         helo: "test.example".to_string(),
         peer_ip: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+        auth_identity: None,
+        dnsbl_score: 0,
+        dnsbl_listed_on: Vec::new(),
+        ptr_name: None,
+        fcrdns_ok: false,
+        helo_resolves: false,
     }
 }
 
@@ -55,6 +61,12 @@ fn dmarc_pass(envelope_from: &str, authenticated_from: &str) -> DmarcContext {
         envelope_from: envelope_from.to_string(),
         helo: "test.example".to_string(),
         peer_ip: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+        auth_identity: None,
+        dnsbl_score: 0,
+        dnsbl_listed_on: Vec::new(),
+        ptr_name: None,
+        fcrdns_ok: false,
+        helo_resolves: false,
     }
 }
 
@@ -349,3 +361,210 @@ fn can_attach_carries_dmarc_context_alongside_attachment_fields() {
     ));
     assert!(!e.can_attach("alice@agency.gov", &att, &dmarc_off("alice@agency.gov"),));
 }
+
+// --- SMTP AUTH context tests ---
+
+#[test]
+fn can_send_sees_smtp_auth_identity() {
+    let policies = r#"
+        permit(principal == User::"scanner", action == Action::"SendMail", resource)
+          when { context.authenticated && context.auth_identity == "scanner" };
+    "#;
+    let e = PolicyEngine::from_strings(policies, None).expect("policies parse");
+    let authed = DmarcContext {
+        auth_identity: Some("scanner".to_string()),
+        ..dmarc_off("noreply@scanner.internal")
+    };
+    assert!(e.can_send("scanner", recipient(), &authed));
+    // Same principal without an authenticated session is denied.
+    assert!(!e.can_send(
+        "scanner",
+        recipient(),
+        &dmarc_off("noreply@scanner.internal")
+    ));
+}
+
+// --- MailFrom (declared SIZE) tests ---
+
+fn envelope(envelope_from: &str, declared_size: Option<u64>) -> EnvelopeContext {
+    EnvelopeContext {
+        envelope_from: envelope_from.to_string(),
+        helo: "test.example".to_string(),
+        peer_ip: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+        auth_identity: None,
+        declared_size,
+        dnsbl_score: 0,
+        dnsbl_listed_on: Vec::new(),
+    }
+}
+
+#[test]
+fn can_mail_from_is_permitted_when_no_policy_names_the_action() {
+    // POLICIES only covers SendMail and Attach; existing policy sets must
+    // keep accepting every MAIL FROM.
+    assert!(engine().can_mail_from(
+        "mallory@evil.example",
+        &envelope("mallory@evil.example", Some(1))
+    ));
+}
+
+#[test]
+fn can_mail_from_enforces_per_sender_declared_size() {
+    let policies = r#"
+        permit(principal, action == Action::"MailFrom", resource);
+        forbid(principal == User::"small@agency.gov", action == Action::"MailFrom", resource)
+          when { context.size_declared && context.declared_size > 1000 };
+    "#;
+    let e = PolicyEngine::from_strings(policies, None).expect("policies parse");
+    assert!(e.can_mail_from(
+        "small@agency.gov",
+        &envelope("small@agency.gov", Some(1000))
+    ));
+    assert!(!e.can_mail_from(
+        "small@agency.gov",
+        &envelope("small@agency.gov", Some(1001))
+    ));
+    assert!(e.can_mail_from("small@agency.gov", &envelope("small@agency.gov", None)));
+    assert!(e.can_mail_from(
+        "big@agency.gov",
+        &envelope("big@agency.gov", Some(1_000_000))
+    ));
+}
+
+#[test]
+fn can_mail_from_saturates_oversized_declarations() {
+    let policies = r#"
+        permit(principal, action in [Action::"MailFrom"], resource)
+          when { context.declared_size < 9223372036854775807 };
+    "#;
+    let e = PolicyEngine::from_strings(policies, None).expect("policies parse");
+    assert!(e.can_mail_from("a@agency.gov", &envelope("a@agency.gov", Some(10))));
+    assert!(!e.can_mail_from("a@agency.gov", &envelope("a@agency.gov", Some(u64::MAX))));
+}
+
+// --- DNSBL context tests ---
+
+#[test]
+fn policies_see_dnsbl_score_and_listing_zones() {
+    let policies = r#"
+        permit(principal, action == Action::"MailFrom", resource)
+          when { context.dnsbl_score < 3 };
+        permit(principal, action == Action::"SendMail", resource);
+        forbid(principal, action == Action::"SendMail", resource)
+          when { context.dnsbl_listed_on.contains("zen.spamhaus.org") };
+    "#;
+    let e = PolicyEngine::from_strings(policies, None).expect("policies parse");
+    let sender = "alice@example.com";
+
+    let listed_lightly = EnvelopeContext {
+        dnsbl_score: 2,
+        dnsbl_listed_on: vec!["bl.spamcop.net".to_string()],
+        ..envelope(sender, None)
+    };
+    assert!(e.can_mail_from(sender, &listed_lightly));
+    let listed_heavily = EnvelopeContext {
+        dnsbl_score: 3,
+        ..listed_lightly
+    };
+    assert!(!e.can_mail_from(sender, &listed_heavily));
+
+    assert!(e.can_send(sender, recipient(), &dmarc_off(sender)));
+    let on_zen = DmarcContext {
+        dnsbl_score: 1,
+        dnsbl_listed_on: vec!["zen.spamhaus.org".to_string()],
+        ..dmarc_off(sender)
+    };
+    assert!(!e.can_send(sender, recipient(), &on_zen));
+}
+
+// --- SkipGreylist tests ---
+
+#[test]
+fn can_skip_greylist_is_denied_unless_a_policy_names_the_action() {
+    // A blanket permit must not disable greylisting by accident.
+    let e = PolicyEngine::from_strings("permit(principal, action, resource);", None)
+        .expect("policies parse");
+    let sender = "alice@example.com";
+    assert!(!e.can_skip_greylist(
+        sender,
+        recipient(),
+        GreylistFacts::Envelope(&envelope(sender, None))
+    ));
+    assert!(!e.can_skip_greylist(
+        sender,
+        recipient(),
+        GreylistFacts::Dmarc(&dmarc_pass(sender, sender))
+    ));
+}
+
+#[test]
+fn can_skip_greylist_sees_the_facts_of_its_stage() {
+    let policies = r#"
+        permit(principal, action == Action::"SkipGreylist", resource)
+          when { context has dmarc_aligned && context.dmarc_aligned };
+        permit(principal, action == Action::"SkipGreylist", resource)
+          when { ip(context.peer_ip).isInRange(ip("10.0.0.0/8")) };
+    "#;
+    let e = PolicyEngine::from_strings(policies, None).expect("policies parse");
+    let sender = "alice@example.com";
+
+    let internal = EnvelopeContext {
+        peer_ip: IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3)),
+        ..envelope(sender, None)
+    };
+    assert!(e.can_skip_greylist(sender, recipient(), GreylistFacts::Envelope(&internal)));
+    assert!(!e.can_skip_greylist(
+        sender,
+        recipient(),
+        GreylistFacts::Envelope(&envelope(sender, None))
+    ));
+
+    assert!(e.can_skip_greylist(
+        sender,
+        recipient(),
+        GreylistFacts::Dmarc(&dmarc_pass(sender, sender))
+    ));
+    assert!(!e.can_skip_greylist(
+        sender,
+        recipient(),
+        GreylistFacts::Dmarc(&dmarc_off(sender))
+    ));
+}
+
+// --- Reverse DNS context tests ---
+
+#[test]
+fn can_send_sees_verified_reverse_dns_and_helo() {
+    let policies = r#"
+        permit(principal, action == Action::"SendMail", resource)
+          when { context.fcrdns_ok && context.ptr_name like "*.example.net" };
+        permit(principal, action == Action::"SendMail", resource)
+          when { context.helo_resolves && context.helo == "mx.partner.example" };
+    "#;
+    let e = PolicyEngine::from_strings(policies, None).expect("policies parse");
+    let sender = "alice@example.com";
+
+    assert!(!e.can_send(sender, recipient(), &dmarc_off(sender)));
+    let confirmed = DmarcContext {
+        ptr_name: Some("mail.example.net".to_string()),
+        fcrdns_ok: true,
+        ..dmarc_off(sender)
+    };
+    assert!(e.can_send(sender, recipient(), &confirmed));
+    let unconfirmed = DmarcContext {
+        fcrdns_ok: false,
+        ..confirmed
+    };
+    assert!(!e.can_send(sender, recipient(), &unconfirmed));
+
+    let claimed_helo = DmarcContext {
+        helo: "mx.partner.example".to_string(),
+        ..dmarc_off(sender)
+    };
+    assert!(!e.can_send(sender, recipient(), &claimed_helo));
+    let verified_helo = DmarcContext {
+        helo_resolves: true,
+        ..claimed_helo
+    };
+    assert!(e.can_send(sender, recipient(), &verified_helo));
+}
@@ -0,0 +1,195 @@
+//! Reverse DNS and HELO checks for inbound SMTP peers.
+//!
+//! The checker is built once at startup from [`crate::config::Config`] when
+//! `rdns_checks` is on, and consulted at end-of-DATA for the peer address and
+//! HELO the message arrived with (after any `XCLIENT` rewrite). The
+//! [`PeerDns`] result reaches Cedar as `context.ptr_name`,
+//! `context.fcrdns_ok` and `context.helo_resolves`, and the webhook payload
+//! as the fields of the same names.
+//!
+//! # Checks
+//!
+//! * **Forward-confirmed reverse DNS (FCrDNS):** the peer's `PTR` names are
+//!   looked up, and each (at most [`MAX_PTR_NAMES`]) is resolved back to
+//!   `A`/`AAAA` records. The first name that resolves to the peer address is
+//!   confirmed. A `PTR` name alone proves nothing: whoever controls the
+//!   address's reverse zone can claim any name.
+//! * **HELO:** the announced domain resolves to the peer address. An address
+//!   literal (`[192.0.2.1]`, `[IPv6:2001:db8::1]`) must be the peer address
+//!   itself. A bare word such as `localhost` or `tester` never matches.
+//!
+//! Every lookup is bounded by `rdns_timeout_secs`. Errors and timeouts are
+//! logged and leave the name unconfirmed; they never fail the message. The
+//! resolver caches answers for their TTL, so repeat senders cost no queries.
+
+use crate::config::Config;
+use crate::dmarc::build_authenticator;
+use anyhow::{Context as _, Result};
+use mail_auth::common::cache::NoCache;
+use mail_auth::{Error as DnsError, MessageAuthenticator};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Upper bound on the `PTR` names forward-confirmed per peer, as for the
+/// SPF `ptr` mechanism (RFC 7208 §5.5).
+pub const MAX_PTR_NAMES: usize = 10;
+
+/// Verified DNS identity of one peer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerDns {
+    /// The forward-confirmed `PTR` name, or the first `PTR` name when none
+    /// confirms. `None` when the address has no `PTR` record.
+    pub ptr_name: Option<String>,
+    /// Whether `ptr_name` resolves back to the peer address.
+    pub fcrdns_ok: bool,
+    /// Whether the HELO domain resolves to the peer address.
+    pub helo_resolves: bool,
+}
+
+/// Runtime reverse DNS checker. Holds the DNS-backed mail-auth resolver and
+/// the per-lookup timeout.
+pub struct RdnsChecker {
+    authenticator: MessageAuthenticator,
+    timeout: Duration,
+}
+
+impl RdnsChecker {
+    /// Build a checker from config. Returns `Ok(None)` when `rdns_checks` is
+    /// off. Uses the same resolver setup as DMARC, including
+    /// `dmarc_dns_servers`.
+    pub fn load(config: &Config) -> Result<Option<Arc<Self>>> {
+        if !config.rdns_checks {
+            return Ok(None);
+        }
+
+        let authenticator = build_authenticator(&config.dmarc_dns_servers)
+            .context("failed to build reverse DNS resolver")?;
+
+        Ok(Some(Arc::new(Self {
+            authenticator,
+            timeout: Duration::from_secs(config.rdns_timeout_secs),
+        })))
+    }
+
+    /// Runs the FCrDNS and HELO checks for `ip` concurrently.
+    pub async fn check(&self, ip: IpAddr, helo: &str) -> PeerDns {
+        let ip = ip.to_canonical();
+        let ((ptr_name, fcrdns_ok), helo_resolves) =
+            tokio::join!(self.confirm_ptr(ip), self.helo_resolves(ip, helo));
+        PeerDns {
+            ptr_name,
+            fcrdns_ok,
+            helo_resolves,
+        }
+    }
+
+    async fn confirm_ptr(&self, ip: IpAddr) -> (Option<String>, bool) {
+        let lookup = self
+            .authenticator
+            .ptr_lookup(ip, None::<&NoCache<IpAddr, Arc<[Box<str>]>>>);
+        let names = match tokio::time::timeout(self.timeout, lookup).await {
+            Ok(Ok(names)) => names,
+            Ok(Err(DnsError::DnsRecordNotFound(_))) => return (None, false),
+            Ok(Err(e)) => {
+                tracing::warn!(peer = %ip, "PTR lookup failed: {}", e);
+                return (None, false);
+            }
+            Err(_elapsed) => {
+                tracing::warn!(
+                    peer = %ip,
+                    timeout_secs = self.timeout.as_secs(),
+                    "PTR lookup timed out"
+                );
+                return (None, false);
+            }
+        };
+
+        let names: Vec<String> = names
+            .iter()
+            .take(MAX_PTR_NAMES)
+            .map(|name| name.trim_end_matches('.').to_string())
+            .collect();
+        for name in &names {
+            if self.resolves_to(name, ip).await {
+                return (Some(name.clone()), true);
+            }
+        }
+        (names.into_iter().next(), false)
+    }
+
+    async fn helo_resolves(&self, ip: IpAddr, helo: &str) -> bool {
+        if let Some(literal) = address_literal(helo) {
+            return literal == ip;
+        }
+        is_domain(helo) && self.resolves_to(helo, ip).await
+    }
+
+    /// Whether `name` has an `A` (for IPv4 peers) or `AAAA` (for IPv6)
+    /// record equal to `ip`.
+    async fn resolves_to(&self, name: &str, ip: IpAddr) -> bool {
+        let name = fqdn(name);
+        let lookup = async {
+            match ip {
+                IpAddr::V4(v4) => self
+                    .authenticator
+                    .ipv4_lookup_raw(&name)
+                    .await
+                    .map(|answer| answer.entry.contains(&v4)),
+                IpAddr::V6(v6) => self
+                    .authenticator
+                    .ipv6_lookup_raw(&name)
+                    .await
+                    .map(|answer| answer.entry.contains(&v6)),
+            }
+        };
+        match tokio::time::timeout(self.timeout, lookup).await {
+            Ok(Ok(found)) => found,
+            Ok(Err(DnsError::DnsRecordNotFound(_))) => false,
+            Ok(Err(e)) => {
+                tracing::warn!(query = name, "address lookup failed: {}", e);
+                false
+            }
+            Err(_elapsed) => {
+                tracing::warn!(
+                    query = name,
+                    timeout_secs = self.timeout.as_secs(),
+                    "address lookup timed out"
+                );
+                false
+            }
+        }
+    }
+}
+
+/// The address in a HELO address literal (RFC 5321 §4.1.3), if it is one.
+fn address_literal(helo: &str) -> Option<IpAddr> {
+    let inner = helo.strip_prefix('[')?.strip_suffix(']')?;
+    let addr = match inner.get(..5) {
+        Some(tag) if tag.eq_ignore_ascii_case("IPv6:") => inner[5..].parse().ok()?,
+        _ => IpAddr::V4(inner.parse().ok()?),
+    };
+    Some(addr.to_canonical())
+}
+
+/// Whether `helo` is worth resolving: a dotted name of letters, digits,
+/// hyphens and underscores.
+fn is_domain(helo: &str) -> bool {
+    let name = helo.trim_end_matches('.');
+    name.contains('.')
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+/// `name` fully qualified, so the resolver's search list never applies.
+fn fqdn(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+#[cfg(test)]
+mod tests;
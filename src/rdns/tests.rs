@@ -0,0 +1,39 @@
+use super::*;
+
+#[test]
+fn address_literals_are_parsed_and_canonicalized() {
+    assert_eq!(
+        address_literal("[192.0.2.1]"),
+        Some("192.0.2.1".parse().unwrap())
+    );
+    assert_eq!(
+        address_literal("[IPv6:2001:db8::1]"),
+        Some("2001:db8::1".parse().unwrap())
+    );
+    assert_eq!(
+        address_literal("[ipv6:::ffff:192.0.2.1]"),
+        Some("192.0.2.1".parse().unwrap())
+    );
+    assert_eq!(address_literal("[2001:db8::1]"), None);
+    assert_eq!(address_literal("192.0.2.1"), None);
+    assert_eq!(address_literal("[mail.example.com]"), None);
+}
+
+#[test]
+fn only_dotted_names_are_resolved() {
+    assert!(is_domain("mail.example.com"));
+    assert!(is_domain("mail.example.com."));
+    assert!(is_domain("mx-1.example.co.uk"));
+    assert!(!is_domain("localhost"));
+    assert!(!is_domain("tester"));
+    assert!(!is_domain(""));
+    assert!(!is_domain("mail..example.com"));
+    assert!(!is_domain("mail example.com"));
+    assert!(!is_domain(&format!("{}.example.com", "a".repeat(64))));
+}
+
+#[test]
+fn fqdn_adds_a_single_trailing_dot() {
+    assert_eq!(fqdn("mail.example.com"), "mail.example.com.");
+    assert_eq!(fqdn("mail.example.com."), "mail.example.com.");
+}
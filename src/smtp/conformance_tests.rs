@@ -0,0 +1,286 @@
+//! RFC 5321 conformance suite for [`SmtpProtocol`].
+//!
+//! Each script drives a fresh protocol instance through a sequence of
+//! commands using only its public API, checking the reply code written for
+//! every command and the state it leaves behind. Commands answered by the
+//! session loop rather than the protocol (`MAIL FROM`, `RCPT TO`, end of
+//! data) expect no reply here.
+
+use super::smtp_protocol::{SmtpCommandResult, SmtpProtocol, SmtpState};
+use std::io::Cursor;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncWrite, BufReader};
+
+use SmtpState::*;
+
+/// Writer that keeps everything written in a buffer the test can inspect
+/// while the protocol owns the writer.
+#[derive(Clone, Default)]
+struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuf {
+    fn take(&self) -> String {
+        String::from_utf8(std::mem::take(&mut *self.0.lock().unwrap())).unwrap()
+    }
+}
+
+impl AsyncWrite for SharedBuf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+type TestProtocol = SmtpProtocol<BufReader<Cursor<Vec<u8>>>, SharedBuf>;
+
+fn protocol(input: &[u8]) -> (TestProtocol, SharedBuf) {
+    let out = SharedBuf::default();
+    let reader = BufReader::new(Cursor::new(input.to_vec()));
+    (SmtpProtocol::new(reader, out.clone(), 26_214_400), out)
+}
+
+/// One step of a script: the command sent, the reply code the protocol must
+/// write (`""` when the session loop replies instead), and the state after.
+type Step<'a> = (&'a str, &'a str, SmtpState);
+
+async fn run(protocol: &mut TestProtocol, out: &SharedBuf, script: &[Step<'_>]) {
+    for &(command, code, state) in script {
+        protocol.process_command(command).await.unwrap();
+        let reply = out.take();
+        // The last line of a (possibly multiline) reply carries the final code.
+        let last = reply.lines().last().unwrap_or("");
+        if code.is_empty() {
+            assert_eq!(
+                reply, "",
+                "{:?} should leave the reply to the caller",
+                command
+            );
+        } else {
+            assert!(
+                last.starts_with(&format!("{} ", code)),
+                "{:?} expected {}, got {:?}",
+                command,
+                code,
+                reply
+            );
+        }
+        assert_eq!(protocol.get_state(), state, "state after {:?}", command);
+    }
+}
+
+async fn run_script(script: &[Step<'_>]) {
+    let (mut p, out) = protocol(b"");
+    run(&mut p, &out, script).await;
+}
+
+#[tokio::test]
+async fn initial_state_accepts_only_session_commands() {
+    run_script(&[
+        ("NOOP", "250", Initial),
+        ("RSET", "250", Initial),
+        ("VRFY postmaster", "252", Initial),
+        ("HELP", "214", Initial),
+        ("MAIL FROM:<a@example.com>", "503", Initial),
+        ("RCPT TO:<b@example.com>", "503", Initial),
+        ("DATA", "503", Initial),
+        ("STARTTLS", "503", Initial),
+        ("XYZZY", "500", Initial),
+        ("EHLO client.example", "250", Greeted),
+    ])
+    .await;
+}
+
+#[tokio::test]
+async fn helo_is_accepted_as_well_as_ehlo() {
+    run_script(&[
+        ("HELO client.example", "250", Greeted),
+        ("MAIL FROM:<a@example.com>", "", MailFrom),
+    ])
+    .await;
+}
+
+#[tokio::test]
+async fn full_transaction_transitions() {
+    run_script(&[
+        ("EHLO client.example", "250", Greeted),
+        ("RCPT TO:<b@example.com>", "503", Greeted),
+        ("DATA", "503", Greeted),
+        ("MAIL FROM:<a@example.com>", "", MailFrom),
+        ("MAIL FROM:<a@example.com>", "503", MailFrom),
+        ("DATA", "503", MailFrom),
+        ("NOOP", "250", MailFrom),
+        ("RCPT TO:<b@example.com>", "", RcptTo),
+        ("RCPT TO:<c@example.com>", "", RcptTo),
+        ("VRFY b@example.com", "252", RcptTo),
+        ("DATA", "354", Data),
+        ("Subject: test", "", Data),
+        ("", "", Data),
+        (".", "", Greeted),
+        ("MAIL FROM:<a@example.com>", "", MailFrom),
+    ])
+    .await;
+}
+
+#[tokio::test]
+async fn commands_inside_data_are_content() {
+    let (mut p, out) = protocol(b"");
+    run(
+        &mut p,
+        &out,
+        &[
+            ("EHLO client.example", "250", Greeted),
+            ("MAIL FROM:<a@example.com>", "", MailFrom),
+            ("RCPT TO:<b@example.com>", "", RcptTo),
+            ("DATA", "354", Data),
+        ],
+    )
+    .await;
+    for line in ["RSET", "NOOP", "QUIT", "EHLO x", "HELP"] {
+        let result = p.process_command(line).await.unwrap();
+        assert!(
+            matches!(result, SmtpCommandResult::DataLine(ref l) if l == line.as_bytes()),
+            "{:?} in DATA gave {:?}",
+            line,
+            result
+        );
+        assert_eq!(out.take(), "");
+        assert_eq!(p.get_state(), Data);
+    }
+}
+
+#[tokio::test]
+async fn rset_aborts_transaction_from_every_state() {
+    for setup in [
+        &[("EHLO c", "250", Greeted)][..],
+        &[
+            ("EHLO c", "250", Greeted),
+            ("MAIL FROM:<a@example.com>", "", MailFrom),
+        ],
+        &[
+            ("EHLO c", "250", Greeted),
+            ("MAIL FROM:<a@example.com>", "", MailFrom),
+            ("RCPT TO:<b@example.com>", "", RcptTo),
+        ],
+    ] {
+        let (mut p, out) = protocol(b"");
+        run(&mut p, &out, setup).await;
+        let result = p.process_command("RSET").await.unwrap();
+        assert!(
+            matches!(result, SmtpCommandResult::Reset),
+            "got {:?}",
+            result
+        );
+        assert!(out.take().starts_with("250 2.0.0"));
+        assert_eq!(p.get_state(), Greeted);
+    }
+}
+
+#[tokio::test]
+async fn ehlo_mid_transaction_resets_it() {
+    run_script(&[
+        ("EHLO c", "250", Greeted),
+        ("MAIL FROM:<a@example.com>", "", MailFrom),
+        ("RCPT TO:<b@example.com>", "", RcptTo),
+        ("EHLO c", "250", Greeted),
+        ("RCPT TO:<b@example.com>", "503", Greeted),
+    ])
+    .await;
+}
+
+#[tokio::test]
+async fn re_ehlo_after_starttls() {
+    // The TLS session starts a fresh protocol instance; RFC 3207 §4.2
+    // requires the client to EHLO again, and a second EHLO is also fine.
+    let out = SharedBuf::default();
+    let reader = BufReader::new(Cursor::new(Vec::new()));
+    let mut p = SmtpProtocol::new(reader, out.clone(), 26_214_400).with_tls_active(true);
+    run(
+        &mut p,
+        &out,
+        &[
+            ("EHLO client.example", "250", Greeted),
+            ("STARTTLS", "503", Greeted),
+            ("EHLO client.example", "250", Greeted),
+            ("MAIL FROM:<a@example.com>", "", MailFrom),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn quit_is_accepted_in_every_command_state() {
+    let setups: [&[Step<'_>]; 4] = [
+        &[],
+        &[("EHLO c", "250", Greeted)],
+        &[
+            ("EHLO c", "250", Greeted),
+            ("MAIL FROM:<a@example.com>", "", MailFrom),
+        ],
+        &[
+            ("EHLO c", "250", Greeted),
+            ("MAIL FROM:<a@example.com>", "", MailFrom),
+            ("RCPT TO:<b@example.com>", "", RcptTo),
+        ],
+    ];
+    for setup in setups {
+        let (mut p, out) = protocol(b"");
+        run(&mut p, &out, setup).await;
+        let result = p.process_command("QUIT").await.unwrap();
+        assert!(matches!(result, SmtpCommandResult::Quit));
+        assert_eq!(out.take(), "221 2.0.0 Bye\r\n");
+    }
+}
+
+#[tokio::test]
+async fn bdat_transitions() {
+    let (mut p, out) = protocol(b"Subject: x\r\n\r\nbody\r\n");
+    // The chunk payloads are buffered from the start, which EHLO would take
+    // as pipelining past a synchronization point; start greeted instead.
+    p.reset_transaction();
+    run(
+        &mut p,
+        &out,
+        &[
+            ("MAIL FROM:<a@example.com>", "", MailFrom),
+            ("BDAT 4", "503", MailFrom),
+            ("RCPT TO:<b@example.com>", "", RcptTo),
+            ("BDAT 8", "", Bdat),
+            ("DATA", "503", Bdat),
+            ("HELP", "214", Bdat),
+            ("BDAT 6 LAST", "", Greeted),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn rset_discards_partial_bdat_transaction() {
+    let (mut p, out) = protocol(b"Subject: x\r\n");
+    p.reset_transaction();
+    run(
+        &mut p,
+        &out,
+        &[
+            ("MAIL FROM:<a@example.com>", "", MailFrom),
+            ("RCPT TO:<b@example.com>", "", RcptTo),
+            ("BDAT 12", "", Bdat),
+            ("RSET", "250", Greeted),
+            ("BDAT 0 LAST", "503", Greeted),
+        ],
+    )
+    .await;
+}
@@ -309,6 +309,20 @@ Content-Type: text/html; charset=\"UTF-8\"\r\n\
         assert!(parsed.attachments.is_empty());
     }
 
+    #[test]
+    fn parse_decodes_8bit_latin1_body() {
+        // Raw 8-bit body as sent under 8BITMIME; 0xE9 is "é" in Latin-1 and
+        // not valid UTF-8 on its own.
+        let mut raw = b"Subject: Caf\xe9\r\n\
+Content-Type: text/plain; charset=iso-8859-1\r\n\
+Content-Transfer-Encoding: 8bit\r\n\
+\r\n"
+            .to_vec();
+        raw.extend_from_slice(b"Un caf\xe9, s'il vous pla\xeet.\r\n");
+        let parsed = EmailParser::parse(&raw, &[]).expect("parse failed");
+        assert_eq!(parsed.text_body.trim_end(), "Un café, s'il vous plaît.");
+    }
+
     #[test]
     fn parse_headers_match_prefixes_case_insensitively() {
         let email = "From: sender@example.com\r\n\
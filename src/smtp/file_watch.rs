@@ -0,0 +1,87 @@
+//! Reloading file-backed configuration on change or `SIGHUP`.
+//!
+//! Shared by the STARTTLS certificate ([`super::tls`]) and the peer lists
+//! ([`super::peer_filter`]): both poll their files' modification stamps and
+//! re-read them when a stamp moves or the process receives `SIGHUP`. The
+//! stamp check and the reload touch the filesystem, so they run on the
+//! blocking pool rather than on the runtime worker driving the watcher.
+
+use anyhow::Result;
+use log::{error, info};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Calls `reload` whenever `changed` reports a file change (checked every
+/// `poll_interval`) or `SIGHUP` arrives, until `cancel` fires.
+///
+/// `name` prefixes the log lines and `kept` names what stays in effect when
+/// a reload fails.
+pub(crate) async fn watch_reload<T>(
+    target: Arc<T>,
+    name: &'static str,
+    kept: &'static str,
+    poll_interval: Duration,
+    changed: fn(&T) -> bool,
+    reload: fn(&T) -> Result<()>,
+    cancel: CancellationToken,
+) where
+    T: Send + Sync + 'static,
+{
+    #[cfg(unix)]
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(s) => Some(s),
+        Err(e) => {
+            error!(
+                "{}: cannot listen for SIGHUP, relying on file polling: {}",
+                name, e
+            );
+            None
+        }
+    };
+
+    let mut ticker = tokio::time::interval(poll_interval);
+    ticker.tick().await; // the first tick completes immediately
+    loop {
+        #[cfg(unix)]
+        let sighup = async {
+            match hangup.as_mut() {
+                Some(s) => {
+                    s.recv().await;
+                }
+                None => std::future::pending::<()>().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let sighup = std::future::pending::<()>();
+
+        let forced = tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = ticker.tick() => false,
+            _ = sighup => true,
+        };
+        if forced {
+            info!("{}: SIGHUP received, reloading {}", name, kept);
+        } else {
+            let target = target.clone();
+            // A panicked check counts as a change, so the reload reports it.
+            let changed = tokio::task::spawn_blocking(move || changed(&target))
+                .await
+                .unwrap_or(true);
+            if !changed {
+                continue;
+            }
+        }
+        let target = target.clone();
+        let result = match tokio::task::spawn_blocking(move || reload(&target)).await {
+            Ok(result) => result,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            error!(
+                "{}: reload failed, keeping previous {}: {:#}",
+                name, kept, e
+            );
+        }
+    }
+}
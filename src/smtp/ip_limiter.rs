@@ -11,11 +11,30 @@
 //! authorization flow: without it, a single abusive client could keep many
 //! sessions open streaming up to `max_message_size_bytes` each before the
 //! end-of-DATA Cedar check rejects them.
+//!
+//! IPv6 clients are counted per network rather than per address (see
+//! [`source_key`]): a single host usually controls a whole /64, and would
+//! otherwise get a fresh cap for every address it rotates through.
 
 use std::collections::HashMap;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv6Addr};
 use std::sync::{Arc, Mutex};
 
+/// The key a client is limited under: the IPv4 address itself, or the
+/// first `ipv6_prefix_len` bits of an IPv6 address. IPv4-mapped IPv6
+/// addresses count as their IPv4 form.
+pub fn source_key(ip: IpAddr, ipv6_prefix_len: u8) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => IpAddr::V4(v4),
+        IpAddr::V6(v6) => {
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(ipv6_prefix_len.min(128)))
+                .unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask))
+        }
+    }
+}
+
 /// Tracks live connection counts per peer IP.
 ///
 /// Cheap to clone — internal state is one `Arc<Mutex<…>>`.
@@ -23,21 +42,25 @@ use std::sync::{Arc, Mutex};
 pub struct IpLimiter {
     inner: Arc<Mutex<HashMap<IpAddr, u32>>>,
     max_per_ip: u32,
+    ipv6_prefix_len: u8,
 }
 
 impl IpLimiter {
-    /// Creates a limiter with the given cap. `max_per_ip == 0` disables the
-    /// limiter entirely — every `try_acquire` returns a no-op guard.
-    pub fn new(max_per_ip: u32) -> Self {
+    /// Creates a limiter with the given cap, grouping IPv6 clients by
+    /// `ipv6_prefix_len`. `max_per_ip == 0` disables the limiter entirely —
+    /// every `try_acquire` returns a no-op guard.
+    pub fn new(max_per_ip: u32, ipv6_prefix_len: u8) -> Self {
         Self {
             inner: Arc::new(Mutex::new(HashMap::new())),
             max_per_ip,
+            ipv6_prefix_len,
         }
     }
 
     /// Attempts to reserve a connection slot for `ip`. Returns `None` when
     /// the cap is already reached.
     pub fn try_acquire(&self, ip: IpAddr) -> Option<IpConnGuard> {
+        let ip = source_key(ip, self.ipv6_prefix_len);
         if self.max_per_ip == 0 {
             return Some(IpConnGuard { limiter: None, ip });
         }
@@ -92,7 +115,7 @@ mod tests {
 
     #[test]
     fn cap_zero_disables_limiter() {
-        let lim = IpLimiter::new(0);
+        let lim = IpLimiter::new(0, 64);
         let peer = ip(127, 0, 0, 1);
         let guards: Vec<_> = (0..1000)
             .map(|_| lim.try_acquire(peer).expect("disabled"))
@@ -102,7 +125,7 @@ mod tests {
 
     #[test]
     fn refuses_beyond_cap() {
-        let lim = IpLimiter::new(2);
+        let lim = IpLimiter::new(2, 64);
         let peer = ip(10, 0, 0, 1);
         let g1 = lim.try_acquire(peer).unwrap();
         let g2 = lim.try_acquire(peer).unwrap();
@@ -114,7 +137,7 @@ mod tests {
 
     #[test]
     fn separate_ips_have_independent_counts() {
-        let lim = IpLimiter::new(1);
+        let lim = IpLimiter::new(1, 64);
         let a = ip(10, 0, 0, 1);
         let b = ip(10, 0, 0, 2);
         let _ga = lim.try_acquire(a).unwrap();
@@ -125,7 +148,7 @@ mod tests {
 
     #[test]
     fn guard_removes_map_entry_when_count_returns_to_zero() {
-        let lim = IpLimiter::new(3);
+        let lim = IpLimiter::new(3, 64);
         let peer = ip(192, 168, 0, 1);
         {
             let _g = lim.try_acquire(peer).unwrap();
@@ -136,4 +159,28 @@ mod tests {
             "zeroed entries must be removed to bound memory"
         );
     }
+
+    #[test]
+    fn ipv6_clients_share_a_slot_per_prefix() {
+        let lim = IpLimiter::new(1, 64);
+        let a: IpAddr = "2001:db8:1:2::a".parse().unwrap();
+        let b: IpAddr = "2001:db8:1:2:ffff::b".parse().unwrap();
+        let other: IpAddr = "2001:db8:1:3::a".parse().unwrap();
+        let _ga = lim.try_acquire(a).unwrap();
+        assert!(lim.try_acquire(b).is_none(), "same /64 must share the cap");
+        assert!(lim.try_acquire(other).is_some());
+    }
+
+    #[test]
+    fn source_key_masks_ipv6_and_canonicalizes_mapped_ipv4() {
+        let key = |s: &str, len| source_key(s.parse().unwrap(), len).to_string();
+        assert_eq!(
+            key("2001:db8:aaaa:bbbb:1:2:3:4", 64),
+            "2001:db8:aaaa:bbbb::"
+        );
+        assert_eq!(key("2001:db8:aaaa:bbbb:1:2:3:4", 48), "2001:db8:aaaa::");
+        assert_eq!(key("2001:db8::1", 128), "2001:db8::1");
+        assert_eq!(key("2001:db8::1", 0), "::");
+        assert_eq!(key("::ffff:192.0.2.7", 64), "192.0.2.7");
+    }
 }
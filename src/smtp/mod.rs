@@ -1,28 +1,52 @@
 pub mod email_parser;
+mod file_watch;
 mod ip_limiter;
+mod peer_filter;
+mod proxy_protocol;
+mod rate_limiter;
 mod smtp_protocol;
+mod tls;
+
+#[cfg(test)]
+mod conformance_tests;
+#[cfg(test)]
+mod tests;
 
 use crate::attachment::AttachmentBackend;
-use crate::config::{Config, DmarcMode, DmarcTempErrorAction};
+use crate::auth::Credentials;
+use crate::config::{
+    Cidr, Config, DeliveryMode, DmarcMode, DmarcTempErrorAction, GreylistStage, ListenerAddress,
+    ListenerConfig, ListenerProtocol, ListenerTls, PeerDenyAction, RecipientDelivery,
+};
 use crate::dmarc::{decide, DmarcDecision, DmarcValidator};
-use crate::policy::{AttachmentCheck, DmarcContext, PolicyEngine};
-use crate::webhook::{EmailPayload, ForwardEmail};
+use crate::dnsbl::{DnsblChecker, DnsblResult};
+use crate::dsn::DsnRequest;
+use crate::greylist::{Greylist, Verdict};
+use crate::policy::{AttachmentCheck, DmarcContext, EnvelopeContext, GreylistFacts, PolicyEngine};
+use crate::rdns::{PeerDns, RdnsChecker};
+use crate::spool::Spool;
+use crate::webhook::{DeliveryNotifier, EmailPayload, ForwardEmail};
 use acton_reactive::prelude::*;
-use anyhow::{Context, Result};
+use anyhow::Result;
 use email_parser::EmailParser;
-use ip_limiter::IpLimiter;
+use ip_limiter::{IpConnGuard, IpLimiter};
 use log::{error, info, trace, warn};
-use smtp_protocol::{SmtpCommandResult, SmtpProtocol, SmtpState};
-use tokio::io::{AsyncRead, AsyncWrite};
+use peer_filter::PeerFilter;
+use rate_limiter::RateLimiter;
+use smtp_protocol::{
+    redact_auth, ReadTimeout, ReadTimeouts, ServerIdentity, SmtpCommandResult, SmtpProtocol,
+    TimeoutPhase, TIMEOUT_REPLY_GRACE,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use tls::ServerTls;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio_util::sync::CancellationToken;
 
-use rcgen::generate_simple_self_signed;
-use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
-use rustls::ServerConfig as RustlsServerConfig;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
-use tokio_rustls::TlsAcceptor;
+use std::time::Duration;
 
 // --- SmtpListenerActor ---
 
@@ -36,6 +60,8 @@ struct SessionContext {
     webhook_handle: ActorHandle,
     target_emails: Vec<String>,
     header_prefixes: Vec<String>,
+    recipient_delivery: RecipientDelivery,
+    delivery_mode: DeliveryMode,
     policy: Arc<PolicyEngine>,
     backend: Arc<dyn AttachmentBackend>,
     max_message_size_bytes: u64,
@@ -45,6 +71,42 @@ struct SessionContext {
     dmarc_mode: DmarcMode,
     dmarc_temperror_action: DmarcTempErrorAction,
     max_unknown_rcpts_per_session: u32,
+    spool: Option<Arc<Spool>>,
+    tls: Arc<ServerTls>,
+    require_tls: bool,
+    credentials: Option<Arc<Credentials>>,
+    /// Networks whose peers may send `XCLIENT`/`XFORWARD`.
+    xclient_trusted: Arc<[Cidr]>,
+    /// Whether this connection's peer is in `xclient_trusted`. Decided once at
+    /// accept, so a forwarded `ADDR` cannot grant or revoke the privilege.
+    xclient_allowed: bool,
+    /// Whether the listener speaks LMTP, which answers each recipient
+    /// separately once the message content is in.
+    lmtp: bool,
+    /// Read deadlines; `session_deadline` is set per connection from
+    /// `session_timeout`.
+    timeouts: ReadTimeouts,
+    session_timeout: Duration,
+    /// Blocklist checker, when zones are configured.
+    dnsbl: Option<Arc<DnsblChecker>>,
+    /// The peer's blocklist result, looked up at accept and again when
+    /// `XCLIENT` forwards a client address.
+    dnsbl_result: DnsblResult,
+    /// Greylisting store, when `greylist_db` is configured.
+    greylist: Option<Arc<Greylist>>,
+    /// Reverse DNS and HELO checker, when `rdns_checks` is on.
+    rdns: Option<Arc<RdnsChecker>>,
+    /// How long plaintext sessions hold back the greeting to catch early
+    /// talkers; zero disables.
+    greet_delay: Duration,
+    /// Delay added to the `550` reply per unknown recipient, up to
+    /// `rcpt_tarpit_max`; zero disables.
+    rcpt_tarpit: Duration,
+    rcpt_tarpit_max: Duration,
+    /// How the server names itself in replies and `Received:` headers.
+    identity: Arc<ServerIdentity>,
+    /// Whether the session runs over TLS, for the `Received:` protocol.
+    tls_active: bool,
 }
 
 impl SmtpListenerState {
@@ -61,82 +123,97 @@ impl SmtpListenerState {
 
         let mut builder = runtime.new_actor_with_config::<Self>(actor_config);
 
+        let spool = Spool::from_config(config).await?;
+        let credentials = Credentials::from_config(config)?;
+        let tls = ServerTls::load(
+            config.tls_cert_path.as_deref(),
+            config.tls_key_path.as_deref(),
+            &config.server_hostname,
+        )?;
+        let identity = Arc::new(ServerIdentity::new(
+            &config.server_hostname,
+            config.hide_product,
+        ));
+        let listeners = config.effective_listeners()?;
+        let peer_filter = PeerFilter::load(
+            &config.peer_allow,
+            config.peer_allow_file.as_deref(),
+            &config.peer_deny,
+            config.peer_deny_file.as_deref(),
+            config.peer_deny_action,
+        )?;
+        let dnsbl = DnsblChecker::load(config)?;
+        let greylist = Greylist::from_config(config)?;
+        let rdns = RdnsChecker::load(config)?;
+
         let cancel = CancellationToken::new();
         let cancel_for_loop = cancel.clone();
         let cancel_for_stop = cancel.clone();
 
         let smtp_config = config.clone();
-        let wh = webhook_handle.clone();
+        let wh = webhook_handle;
 
         builder.after_start(move |_actor| {
             let config = smtp_config.clone();
-            let webhook_handle = wh.clone();
             let cancel = cancel_for_loop.clone();
-            let policy = policy.clone();
-            let backend = backend.clone();
-            let dmarc = dmarc.clone();
-            let ip_limiter = IpLimiter::new(config.max_concurrent_per_ip);
-
-            tokio::spawn(async move {
-                let addr = format!("{}:{}", config.smtp_bind_address, config.smtp_port);
-                let listener = match TcpListener::bind(&addr).await {
-                    Ok(l) => {
-                        tracing::info!("SMTP server listening on {}", addr);
-                        l
-                    }
-                    Err(e) => {
-                        tracing::error!("Failed to bind SMTP: {}", e);
-                        return;
-                    }
-                };
+            let limits = ConnectionLimits::new(&config, peer_filter.clone());
 
-                loop {
-                    tokio::select! {
-                        result = listener.accept() => {
-                            match result {
-                                Ok((stream, remote_addr)) => {
-                                    let peer_ip = remote_addr.ip();
-                                    let Some(conn_guard) = ip_limiter.try_acquire(peer_ip) else {
-                                        tracing::warn!(
-                                            peer = %remote_addr,
-                                            cap = config.max_concurrent_per_ip,
-                                            "per-IP concurrent connection cap reached — dropping"
-                                        );
-                                        drop(stream);
-                                        continue;
-                                    };
-                                    tracing::info!("New connection from: {}", remote_addr);
-                                    let ctx = SessionContext {
-                                        webhook_handle: webhook_handle.clone(),
-                                        target_emails: config.target_emails.clone(),
-                                        header_prefixes: config.header_prefixes.clone(),
-                                        policy: policy.clone(),
-                                        backend: backend.clone(),
-                                        max_message_size_bytes: config.max_message_size_bytes,
-                                        max_attachment_size_bytes: config.max_attachment_size_bytes,
-                                        peer_addr: peer_ip,
-                                        dmarc: dmarc.clone(),
-                                        dmarc_mode: config.dmarc_mode,
-                                        dmarc_temperror_action: config.dmarc_temperror_action,
-                                        max_unknown_rcpts_per_session: config.max_unknown_rcpts_per_session,
-                                    };
-                                    tokio::spawn(async move {
-                                        let _guard = conn_guard; // RAII release at session end
-                                        if let Err(e) = handle_connection(stream, ctx).await {
-                                            tracing::error!("Error handling SMTP connection from {}: {:#?}", remote_addr, e);
-                                        }
-                                    });
-                                }
-                                Err(e) => tracing::error!("Error accepting connection: {:?}", e),
-                            }
-                        }
-                        _ = cancel.cancelled() => {
-                            tracing::info!("SMTP listener shutting down gracefully");
-                            break;
-                        }
-                    }
-                }
-            });
+            // Per-listener fields (`require_tls`, `xclient_trusted`, `lmtp`)
+            // are set by `accept_loop`; per-connection ones (`peer_addr`,
+            // `xclient_allowed`, `timeouts.session_deadline`, `dnsbl_result`)
+            // at accept.
+            let base_ctx = SessionContext {
+                webhook_handle: wh.clone(),
+                target_emails: config.target_emails.clone(),
+                header_prefixes: config.header_prefixes.clone(),
+                recipient_delivery: config.recipient_delivery,
+                delivery_mode: config.delivery_mode,
+                policy: policy.clone(),
+                backend: backend.clone(),
+                max_message_size_bytes: config.max_message_size_bytes,
+                max_attachment_size_bytes: config.max_attachment_size_bytes,
+                peer_addr: IpAddr::from([0, 0, 0, 0]),
+                dmarc: dmarc.clone(),
+                dmarc_mode: config.dmarc_mode,
+                dmarc_temperror_action: config.dmarc_temperror_action,
+                max_unknown_rcpts_per_session: config.max_unknown_rcpts_per_session,
+                spool: spool.clone(),
+                tls: tls.clone(),
+                require_tls: config.require_tls,
+                credentials: credentials.clone(),
+                xclient_trusted: config.xclient_trusted.clone().into(),
+                xclient_allowed: false,
+                lmtp: false,
+                timeouts: ReadTimeouts {
+                    greeting: Duration::from_secs(config.greeting_timeout_secs),
+                    command: Duration::from_secs(config.command_timeout_secs),
+                    data: Duration::from_secs(config.data_timeout_secs),
+                    session_deadline: tokio::time::Instant::now(),
+                },
+                session_timeout: Duration::from_secs(config.session_timeout_secs),
+                dnsbl: dnsbl.clone(),
+                dnsbl_result: DnsblResult::default(),
+                greylist: greylist.clone(),
+                rdns: rdns.clone(),
+                greet_delay: Duration::from_secs(config.greet_delay_secs),
+                rcpt_tarpit: Duration::from_secs(config.rcpt_tarpit_secs),
+                rcpt_tarpit_max: Duration::from_secs(config.rcpt_tarpit_max_secs),
+                identity: identity.clone(),
+                tls_active: false,
+            };
+
+            tokio::spawn(tls.clone().watch(cancel.clone()));
+            tokio::spawn(peer_filter.clone().watch(cancel.clone()));
+
+            for listener in &listeners {
+                tokio::spawn(accept_loop(
+                    listener.clone(),
+                    needs_v6only(listener, &listeners),
+                    base_ctx.clone(),
+                    limits.clone(),
+                    cancel.clone(),
+                ));
+            }
 
             Reply::ready()
         });
@@ -150,61 +227,535 @@ impl SmtpListenerState {
     }
 }
 
-// --- Certificate generation (unchanged) ---
+/// An IPv6 wildcard listener normally accepts IPv4 too (dual-stack). When
+/// another listener binds IPv4 on the same port, it must be IPv6-only or the
+/// two binds would collide.
+fn needs_v6only(listener: &ListenerConfig, all: &[ListenerConfig]) -> bool {
+    let ListenerAddress::Tcp(address) = listener.address else {
+        return false;
+    };
+    address.is_ipv6()
+        && all.iter().any(|other| {
+            matches!(other.address, ListenerAddress::Tcp(o) if o.is_ipv4() && o.port() == address.port())
+        })
+}
+
+/// Binds a listening socket with an explicit `IPV6_V6ONLY` setting, so
+/// dual-stack behavior does not depend on the host's `bindv6only` default.
+fn bind_listener(address: SocketAddr, v6only: bool) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if address.is_ipv6() {
+        socket.set_only_v6(v6only)?;
+    }
+    // Matches `TcpListener::bind`: allow quick restarts over TIME_WAIT.
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Binds one configured listener and serves sessions until `cancel` fires.
+async fn accept_loop(
+    config: ListenerConfig,
+    v6only: bool,
+    base_ctx: SessionContext,
+    limits: ConnectionLimits,
+    cancel: CancellationToken,
+) {
+    let base_ctx = SessionContext {
+        require_tls: config.require_tls,
+        xclient_trusted: config.xclient_trusted.clone().into(),
+        lmtp: config.protocol == ListenerProtocol::Lmtp,
+        ..base_ctx
+    };
+    match config.address.clone() {
+        ListenerAddress::Tcp(addr) => {
+            accept_tcp(addr, config, v6only, base_ctx, limits, cancel).await
+        }
+        ListenerAddress::Unix(path) => accept_unix(path, config, base_ctx, limits, cancel).await,
+    }
+}
+
+/// Human-readable listener kind for log lines, e.g. `SMTP (implicit TLS)`.
+fn listener_label(config: &ListenerConfig) -> String {
+    let protocol = match config.protocol {
+        ListenerProtocol::Smtp => "SMTP",
+        ListenerProtocol::Lmtp => "LMTP",
+    };
+    match config.tls {
+        ListenerTls::Starttls => protocol.to_string(),
+        ListenerTls::Implicit => format!("{} (implicit TLS)", protocol),
+    }
+}
+
+/// Accepts TCP connections. Every listener shares the same
+/// `ConnectionLimits`, so the caps span all endpoints.
+///
+/// Peers inside the listener's `proxy_protocol_trusted` must open with a
+/// PROXY protocol header; the client address it carries becomes the
+/// session's `peer_addr` and the key for the per-IP limits.
+async fn accept_tcp(
+    addr: SocketAddr,
+    config: ListenerConfig,
+    v6only: bool,
+    base_ctx: SessionContext,
+    limits: ConnectionLimits,
+    cancel: CancellationToken,
+) {
+    let kind = config.tls;
+    let listener = match bind_listener(addr, v6only) {
+        Ok(l) => {
+            tracing::info!("{} server listening on {}", listener_label(&config), addr);
+            l
+        }
+        Err(e) => {
+            tracing::error!(
+                "Failed to bind {} on {}: {}",
+                listener_label(&config),
+                addr,
+                e
+            );
+            return;
+        }
+    };
+    let proxy_trusted: Arc<[Cidr]> = config.proxy_protocol_trusted.into();
+
+    loop {
+        tokio::select! {
+            result = listener.accept() => {
+                match result {
+                    Ok((stream, remote_addr)) => {
+                        // Dual-stack sockets report IPv4 clients as
+                        // `::ffff:a.b.c.d`; per-IP logic wants the IPv4 form.
+                        let remote_addr = SocketAddr::new(remote_addr.ip().to_canonical(), remote_addr.port());
+                        if proxy_trusted.iter().any(|net| net.contains(remote_addr.ip())) {
+                            // The real client is only known once the header
+                            // arrives, so the limits are applied in the task.
+                            tokio::spawn(serve_proxied(
+                                stream,
+                                remote_addr,
+                                kind,
+                                base_ctx.clone(),
+                                limits.clone(),
+                            ));
+                            continue;
+                        }
+                        let guard = match limits.admit(Some(remote_addr.ip())) {
+                            Ok(guard) => guard,
+                            Err(refusal) => {
+                                refusal.log(remote_addr, None);
+                                tokio::spawn(refusal.send(stream, kind));
+                                continue;
+                            }
+                        };
+                        tracing::info!("New connection from: {}", remote_addr);
+                        tokio::spawn(serve(stream, remote_addr, kind, base_ctx.clone(), guard));
+                    }
+                    Err(e) => tracing::error!("Error accepting connection: {:?}", e),
+                }
+            }
+            _ = cancel.cancelled() => {
+                tracing::info!("SMTP listener on {} shutting down gracefully", addr);
+                break;
+            }
+        }
+    }
+}
+
+/// Accepts connections on a Unix domain socket, typically from a local MTA
+/// delivering over LMTP. A socket file left over from an unclean shutdown is
+/// replaced, and the file is removed again on shutdown.
+///
+/// The peer has no IP address: sessions report `127.0.0.1` to policy and to
+/// `xclient_trusted`, and only the global session cap applies — not the
+/// per-IP limits or PROXY protocol. Access is controlled by the socket's
+/// file permissions (`mode`).
+#[cfg(unix)]
+async fn accept_unix(
+    path: std::path::PathBuf,
+    config: ListenerConfig,
+    base_ctx: SessionContext,
+    limits: ConnectionLimits,
+    cancel: CancellationToken,
+) {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    let label = listener_label(&config);
+    if std::fs::symlink_metadata(&path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        if let Err(e) = std::fs::remove_file(&path) {
+            tracing::warn!("Could not remove stale socket {}: {}", path.display(), e);
+        }
+    }
+    let listener = match tokio::net::UnixListener::bind(&path) {
+        Ok(l) => l,
+        Err(e) => {
+            tracing::error!("Failed to bind {} on {}: {}", label, path.display(), e);
+            return;
+        }
+    };
+    if let Some(mode) = config.socket_mode {
+        if let Err(e) = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)) {
+            tracing::error!("Failed to set mode {:o} on {}: {}", mode, path.display(), e);
+            let _ = std::fs::remove_file(&path);
+            return;
+        }
+    }
+    tracing::info!("{} server listening on {}", label, path.display());
+
+    let peer = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    loop {
+        tokio::select! {
+            result = listener.accept() => {
+                match result {
+                    Ok((stream, _)) => {
+                        let guard = match limits.admit(None) {
+                            Ok(guard) => guard,
+                            Err(refusal) => {
+                                refusal.log(peer, None);
+                                tokio::spawn(refusal.send(stream, config.tls));
+                                continue;
+                            }
+                        };
+                        tracing::info!("New connection on {}", path.display());
+                        tokio::spawn(serve(stream, peer, config.tls, base_ctx.clone(), guard));
+                    }
+                    Err(e) => tracing::error!("Error accepting connection: {:?}", e),
+                }
+            }
+            _ = cancel.cancelled() => {
+                tracing::info!("{} listener on {} shutting down gracefully", label, path.display());
+                let _ = std::fs::remove_file(&path);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn accept_unix(
+    path: std::path::PathBuf,
+    config: ListenerConfig,
+    _base_ctx: SessionContext,
+    _limits: ConnectionLimits,
+    _cancel: CancellationToken,
+) {
+    tracing::error!(
+        "Failed to bind {} on {}: Unix domain sockets are not supported on this platform",
+        listener_label(&config),
+        path.display()
+    );
+}
+
+/// Reads the PROXY header from a trusted load balancer, then applies the
+/// connection limits to the client it names. A missing or malformed header
+/// closes the connection before the greeting.
+async fn serve_proxied(
+    mut stream: TcpStream,
+    proxy_addr: SocketAddr,
+    kind: ListenerTls,
+    base_ctx: SessionContext,
+    limits: ConnectionLimits,
+) {
+    let client_addr = match proxy_protocol::read_header(&mut stream).await {
+        Ok(Some(client_addr)) => client_addr,
+        // Health checks and other proxy-originated connections.
+        Ok(None) => proxy_addr,
+        Err(e) => {
+            tracing::warn!(proxy = %proxy_addr, "rejecting connection: {:#}", e);
+            return;
+        }
+    };
+    let guard = match limits.admit(Some(client_addr.ip())) {
+        Ok(guard) => guard,
+        Err(refusal) => {
+            refusal.log(client_addr, Some(proxy_addr));
+            refusal.send(stream, kind).await;
+            return;
+        }
+    };
+    tracing::info!("New connection from: {} (via {})", client_addr, proxy_addr);
+    serve(stream, client_addr, kind, base_ctx, guard).await;
+}
+
+/// Connection admission shared by every listener: the peer allow/deny
+/// lists, the per-IP rate limit and concurrency cap, then the global session
+/// cap. Cheap to clone.
+#[derive(Clone)]
+struct ConnectionLimits {
+    peer_filter: Arc<PeerFilter>,
+    rate_limiter: RateLimiter,
+    ip_limiter: IpLimiter,
+    sessions: Arc<Semaphore>,
+    max_connections_per_minute_per_ip: u32,
+    max_concurrent_per_ip: u32,
+    max_sessions: u32,
+}
+
+/// Slots held for the life of one session; dropping it releases them.
+struct SessionGuard {
+    _ip: Option<IpConnGuard>,
+    _session: OwnedSemaphorePermit,
+}
+
+/// Why a connection was turned away before the greeting: by
+/// [`ConnectionLimits::admit`], or by the blocklist check in [`serve`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Refusal {
+    /// The peer is outside the allowlist or inside the denylist.
+    Denied { action: PeerDenyAction },
+    /// The source exceeded its connection rate.
+    Rate { per_minute: u32 },
+    /// The source already has its share of concurrent sessions.
+    PerIp { cap: u32 },
+    /// Every session slot is in use.
+    Busy { cap: u32 },
+    /// The peer's DNSBL score reached `dnsbl_reject_score`.
+    Blocklisted { score: u32, listed_on: Vec<String> },
+}
 
-fn generate_self_signed_cert() -> Result<(CertificateDer<'static>, PrivateKeyDer<'static>)> {
-    let subject_alt_names = vec!["localhost".to_string()];
+impl ConnectionLimits {
+    fn new(config: &Config, peer_filter: Arc<PeerFilter>) -> Self {
+        let max_sessions = match config.max_sessions {
+            0 => Semaphore::MAX_PERMITS,
+            n => n as usize,
+        };
+        Self {
+            peer_filter,
+            rate_limiter: RateLimiter::new(
+                config.max_connections_per_minute_per_ip,
+                config.ipv6_limit_prefix_len,
+            ),
+            ip_limiter: IpLimiter::new(config.max_concurrent_per_ip, config.ipv6_limit_prefix_len),
+            sessions: Arc::new(Semaphore::new(max_sessions)),
+            max_connections_per_minute_per_ip: config.max_connections_per_minute_per_ip,
+            max_concurrent_per_ip: config.max_concurrent_per_ip,
+            max_sessions: config.max_sessions,
+        }
+    }
 
-    let certified_key = generate_simple_self_signed(subject_alt_names)
-        .context("Failed to generate self-signed certificate using rcgen")?;
+    /// Admits a new session from `client`, or from a local socket peer when
+    /// `None` (which only counts against the global cap). Every attempt past
+    /// the peer lists spends a rate-limit token, including ones refused by
+    /// the later caps.
+    fn admit(&self, client: Option<IpAddr>) -> Result<SessionGuard, Refusal> {
+        let ip_guard = match client {
+            Some(ip) => {
+                if !self.peer_filter.admits(ip) {
+                    return Err(Refusal::Denied {
+                        action: self.peer_filter.action(),
+                    });
+                }
+                if !self.rate_limiter.try_acquire(ip) {
+                    return Err(Refusal::Rate {
+                        per_minute: self.max_connections_per_minute_per_ip,
+                    });
+                }
+                let guard = self.ip_limiter.try_acquire(ip).ok_or(Refusal::PerIp {
+                    cap: self.max_concurrent_per_ip,
+                })?;
+                Some(guard)
+            }
+            None => None,
+        };
+        let session = self
+            .sessions
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| Refusal::Busy {
+                cap: self.max_sessions,
+            })?;
+        Ok(SessionGuard {
+            _ip: ip_guard,
+            _session: session,
+        })
+    }
+}
 
-    let cert_der = certified_key.cert.der().to_vec();
-    let key_der = certified_key.signing_key.serialize_der();
+impl Refusal {
+    fn log(&self, peer: SocketAddr, proxy: Option<SocketAddr>) {
+        let proxy = proxy.map(|p| p.to_string());
+        match self {
+            Refusal::Denied { action } => tracing::warn!(
+                peer = %peer,
+                proxy,
+                ?action,
+                "peer refused by allow/deny lists"
+            ),
+            Refusal::Rate { per_minute } => tracing::warn!(
+                peer = %peer,
+                proxy,
+                per_minute,
+                "per-IP connection rate exceeded — dropping"
+            ),
+            Refusal::PerIp { cap } => tracing::warn!(
+                peer = %peer,
+                proxy,
+                cap,
+                "per-IP concurrent connection cap reached — dropping"
+            ),
+            Refusal::Busy { cap } => tracing::warn!(
+                peer = %peer,
+                proxy,
+                cap,
+                "global session cap reached — replying 421"
+            ),
+            Refusal::Blocklisted { score, listed_on } => tracing::warn!(
+                peer = %peer,
+                proxy,
+                score,
+                ?listed_on,
+                "peer listed on DNS blocklists — replying 554"
+            ),
+        }
+    }
 
-    Ok((
-        CertificateDer::from(cert_der),
-        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_der)),
-    ))
+    /// Closes a refused connection. Per-IP refusals are dropped without a
+    /// word, so an abusive client gets nothing for its effort; a busy server
+    /// tells well-behaved MTAs to retry later, and denied peers get a
+    /// permanent `554` unless configured to be dropped, and blocklisted
+    /// peers always get one. Implicit-TLS clients would not understand a
+    /// plaintext reply, so they are always just dropped.
+    async fn send<S>(self, mut stream: S, kind: ListenerTls)
+    where
+        S: AsyncWrite + Unpin,
+    {
+        let reply = match self {
+            Refusal::Busy { .. } => "421 4.3.2 Too busy, try again later\r\n".to_string(),
+            Refusal::Denied {
+                action: PeerDenyAction::Reject,
+            } => "554 5.7.1 Access denied\r\n".to_string(),
+            Refusal::Blocklisted { listed_on, .. } => {
+                format!(
+                    "554 5.7.1 Client host listed on {}\r\n",
+                    listed_on.join(", ")
+                )
+            }
+            _ => return,
+        };
+        if kind == ListenerTls::Implicit {
+            return;
+        }
+        let _ = tokio::time::timeout(TIMEOUT_REPLY_GRACE, async {
+            stream.write_all(reply.as_bytes()).await?;
+            stream.shutdown().await
+        })
+        .await;
+    }
+}
+
+/// Runs one session for `peer`, holding its connection-limit slots until it
+/// ends. A peer whose DNSBL score reaches the reject score is refused first.
+async fn serve<S>(
+    stream: S,
+    peer: SocketAddr,
+    kind: ListenerTls,
+    base_ctx: SessionContext,
+    _guard: SessionGuard, // RAII release at session end
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let dnsbl_result = match &base_ctx.dnsbl {
+        Some(dnsbl) => {
+            let result = dnsbl.check(peer.ip()).await;
+            if dnsbl.rejects(&result) {
+                let refusal = Refusal::Blocklisted {
+                    score: result.score,
+                    listed_on: result.listed_on,
+                };
+                refusal.log(peer, None);
+                refusal.send(stream, kind).await;
+                return;
+            }
+            result
+        }
+        None => DnsblResult::default(),
+    };
+    let ctx = SessionContext {
+        peer_addr: peer.ip(),
+        dnsbl_result,
+        xclient_allowed: base_ctx
+            .xclient_trusted
+            .iter()
+            .any(|net| net.contains(peer.ip())),
+        timeouts: ReadTimeouts {
+            session_deadline: tokio::time::Instant::now() + base_ctx.session_timeout,
+            ..base_ctx.timeouts
+        },
+        ..base_ctx
+    };
+    let result = match kind {
+        ListenerTls::Starttls => handle_connection(stream, ctx).await,
+        ListenerTls::Implicit => handle_implicit_tls(stream, ctx).await,
+    };
+    match result {
+        Ok(()) => {}
+        Err(e) => match e.downcast_ref::<ReadTimeout>() {
+            Some(ReadTimeout(phase)) => {
+                crate::metrics::SMTP_TIMEOUTS.inc(phase.as_str());
+                tracing::warn!(peer = %peer, "closing SMTP session: {}", e);
+            }
+            None => tracing::error!("Error handling SMTP connection from {}: {:#?}", peer, e),
+        },
+    }
 }
 
 // --- Connection handlers ---
 
-async fn handle_connection(mut stream: TcpStream, ctx: SessionContext) -> Result<()> {
+async fn handle_connection<S>(mut stream: S, mut ctx: SessionContext) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    if !greet_delay(&mut stream, &ctx).await? {
+        return Ok(());
+    }
+
     let mut session = MessageSession::default();
 
     let protocol_result = async {
         let (read_half, write_half) = tokio::io::split(&mut stream);
         let reader = tokio::io::BufReader::new(read_half);
         let writer = tokio::io::BufWriter::new(write_half);
-        let mut protocol = SmtpProtocol::new(reader, writer, ctx.max_message_size_bytes);
+        let mut protocol = SmtpProtocol::new(reader, writer, ctx.max_message_size_bytes)
+            .with_require_tls(ctx.require_tls)
+            .with_auth(ctx.credentials.is_some())
+            .with_xclient(ctx.xclient_allowed)
+            .with_lmtp(ctx.lmtp)
+            .with_timeouts(ctx.timeouts)
+            .with_identity(ctx.identity.clone());
 
         protocol.send_greeting().await?;
 
         loop {
             trace!("SMTP({:?}): Waiting for command...", protocol.get_state());
-            let line = protocol.read_line().await?;
-            trace!(
-                "SMTP({:?}): Received line (len {}): {:?}",
-                protocol.get_state(),
-                line.len(),
-                line
-            );
-
-            if protocol.get_state() != SmtpState::Data && line.is_empty() {
+            let Some(line) = protocol.read_raw_line().await? else {
                 info!(
                     "Connection closed by client (EOF). State: {:?}",
                     protocol.get_state()
                 );
                 return Ok(());
-            }
+            };
+            trace!(
+                "SMTP({:?}): Received line (len {}): {:?}",
+                protocol.get_state(),
+                line.len(),
+                redact_auth(&String::from_utf8_lossy(&line))
+            );
 
-            let result = protocol.process_command(&line).await?;
+            let result = protocol.process_line(&line).await?;
 
-            match step(&mut protocol, &ctx, &mut session, result).await? {
+            match step(&mut protocol, &mut ctx, &mut session, result).await? {
                 StepOutcome::Continue => {}
-                StepOutcome::Quit => return Ok(()),
+                StepOutcome::Quit | StepOutcome::CloseConnection => {
+                    protocol.flush().await?;
+                    return Ok(());
+                }
                 StepOutcome::StartTls => return Err(anyhow::anyhow!("STARTTLS")),
-                StepOutcome::CloseConnection => return Ok(()),
             }
         }
     }
@@ -217,21 +768,56 @@ async fn handle_connection(mut stream: TcpStream, ctx: SessionContext) -> Result
     }
 }
 
-async fn handle_starttls(stream: TcpStream, ctx: SessionContext) -> Result<()> {
-    let (cert, key) = generate_self_signed_cert()
-        .context("Failed to generate self-signed certificate for STARTTLS")?;
+/// Reply to a client that spoke before the greeting.
+const EARLY_TALKER_REPLY: &[u8] =
+    b"554 5.5.0 SMTP synchronization error, data sent before greeting\r\n";
 
-    let tls_config = RustlsServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(vec![cert], key)
-        .map_err(|e| anyhow::anyhow!("Failed to create rustls config: {}", e))?;
+/// Waits out `greet_delay` before the greeting. A client that sends anything
+/// meanwhile is not waiting for the server as RFC 5321 §4.3.1 requires —
+/// typically a bot replaying a canned dialogue — and is refused with `554`.
+/// Returns whether the session may go on.
+async fn greet_delay<S>(stream: &mut S, ctx: &SessionContext) -> Result<bool>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if ctx.greet_delay.is_zero() {
+        return Ok(true);
+    }
+    let mut byte = [0u8; 1];
+    match tokio::time::timeout(ctx.greet_delay, stream.read(&mut byte)).await {
+        Err(_elapsed) => Ok(true),
+        Ok(Ok(0)) => {
+            info!("Connection closed by client before the greeting.");
+            Ok(false)
+        }
+        Ok(Ok(_)) => {
+            crate::metrics::SMTP_EARLY_TALKERS.inc();
+            tracing::warn!(
+                peer = %ctx.peer_addr,
+                "client sent data before the greeting — replying 554"
+            );
+            let _ = tokio::time::timeout(TIMEOUT_REPLY_GRACE, async {
+                stream.write_all(EARLY_TALKER_REPLY).await?;
+                stream.shutdown().await
+            })
+            .await;
+            Ok(false)
+        }
+        Ok(Err(e)) => Err(anyhow::Error::new(e).context("read failed before the greeting")),
+    }
+}
 
-    let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+async fn handle_starttls<S>(stream: S, ctx: SessionContext) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let acceptor = ctx.tls.acceptor();
+    let handshake = tls_handshake(&ctx, TimeoutPhase::Command, acceptor.accept(stream));
 
-    match acceptor.accept(stream).await {
+    match handshake.await? {
         Ok(tls_stream) => {
             info!("STARTTLS handshake successful.");
-            handle_secure_session(tls_stream, ctx).await
+            handle_secure_session(tls_stream, ctx, false).await
         }
         Err(e) => {
             error!("STARTTLS handshake failed: {:?}", e);
@@ -240,14 +826,61 @@ async fn handle_starttls(stream: TcpStream, ctx: SessionContext) -> Result<()> {
     }
 }
 
-async fn handle_secure_session<T>(tls_stream: T, ctx: SessionContext) -> Result<()>
+/// SMTPS: the TLS handshake happens before any SMTP traffic, and the
+/// greeting is sent over the encrypted channel.
+async fn handle_implicit_tls<S>(stream: S, ctx: SessionContext) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let acceptor = ctx.tls.acceptor();
+    let handshake = tls_handshake(&ctx, TimeoutPhase::Greeting, acceptor.accept(stream));
+
+    match handshake.await? {
+        Ok(tls_stream) => {
+            info!("Implicit TLS handshake successful.");
+            handle_secure_session(tls_stream, ctx, true).await
+        }
+        Err(e) => {
+            warn!("Implicit TLS handshake failed: {:?}", e);
+            Err(anyhow::Error::new(e).context("Implicit TLS handshake failed"))
+        }
+    }
+}
+
+/// Bounds a TLS handshake by the read deadline for `phase`. No `421` can be
+/// sent mid-handshake, so a timeout simply ends the session.
+async fn tls_handshake<F: std::future::Future>(
+    ctx: &SessionContext,
+    phase: TimeoutPhase,
+    handshake: F,
+) -> Result<F::Output> {
+    let (deadline, phase) = ctx.timeouts.deadline(phase);
+    tokio::time::timeout_at(deadline, handshake)
+        .await
+        .map_err(|_| ReadTimeout(phase).into())
+}
+
+/// Runs the SMTP session over an established TLS stream. `greet` is set for
+/// implicit TLS; after STARTTLS the client speaks first (RFC 3207 §4.2).
+async fn handle_secure_session<T>(tls_stream: T, mut ctx: SessionContext, greet: bool) -> Result<()>
 where
     T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
+    ctx.tls_active = true;
     let (read_half, write_half) = tokio::io::split(tls_stream);
     let reader = tokio::io::BufReader::new(read_half);
     let writer = tokio::io::BufWriter::new(write_half);
-    let mut protocol = SmtpProtocol::new(reader, writer, ctx.max_message_size_bytes);
+    let mut protocol = SmtpProtocol::new(reader, writer, ctx.max_message_size_bytes)
+        .with_tls_active(true)
+        .with_auth(ctx.credentials.is_some())
+        .with_xclient(ctx.xclient_allowed)
+        .with_lmtp(ctx.lmtp)
+        .with_timeouts(ctx.timeouts)
+        .with_identity(ctx.identity.clone());
+
+    if greet {
+        protocol.send_greeting().await?;
+    }
 
     let mut session = MessageSession::default();
 
@@ -256,29 +889,29 @@ where
             "SMTP(TLS/{:?}): Waiting for command...",
             protocol.get_state()
         );
-        let line = protocol.read_line().await?;
+        let Some(line) = protocol.read_raw_line().await? else {
+            info!("Connection closed by client (EOF) during secure session.");
+            break;
+        };
         trace!(
             "SMTP(TLS/{:?}): Received line (len {}): {:?}",
             protocol.get_state(),
             line.len(),
-            line
+            redact_auth(&String::from_utf8_lossy(&line))
         );
 
-        if protocol.get_state() != SmtpState::Data && line.is_empty() {
-            info!("Connection closed by client (EOF) during secure session.");
-            break;
-        }
-
-        let result = protocol.process_command(&line).await?;
+        let result = protocol.process_line(&line).await?;
 
-        match step(&mut protocol, &ctx, &mut session, result).await? {
+        match step(&mut protocol, &mut ctx, &mut session, result).await? {
             StepOutcome::Continue => {}
-            StepOutcome::Quit => break,
+            StepOutcome::Quit | StepOutcome::CloseConnection => {
+                protocol.flush().await?;
+                break;
+            }
             StepOutcome::StartTls => {
-                warn!("Received STARTTLS command within secure session. Sending error.");
-                protocol.write_line("503 STARTTLS already active").await?;
+                // `SmtpProtocol` answers STARTTLS with 503 once TLS is active.
+                warn!("Unexpected STARTTLS outcome within secure session; ignoring.");
             }
-            StepOutcome::CloseConnection => break,
         }
     }
     Ok(())
@@ -288,8 +921,19 @@ where
 #[derive(Default)]
 struct MessageSession {
     sender: String,
-    accepted_recipient: String,
-    email_data: String,
+    /// Every recipient accepted by `RCPT TO` in this transaction, in the order
+    /// the client sent them, without duplicates.
+    accepted_recipients: Vec<String>,
+    /// Index into `accepted_recipients` of every `RCPT TO` answered `250`,
+    /// repeats included: LMTP owes one reply per entry after the message
+    /// content (RFC 2033 §4.2).
+    rcpt_indices: Vec<usize>,
+    /// Raw DATA bytes, dot-unstuffed, with CRLF line endings. Not decoded:
+    /// 8-bit content must reach the parser and DKIM verifier unchanged.
+    email_data: Vec<u8>,
+    /// DSN parameters (RFC 3461) of this transaction: `RET`/`ENVID` from
+    /// `MAIL FROM` plus one entry per accepted recipient that sent any.
+    dsn: DsnRequest,
     collecting_data: bool,
     size_exceeded: bool,
     data_size_bytes: u64,
@@ -297,23 +941,37 @@ struct MessageSession {
     /// messages within the same connection (a client may send multiple
     /// transactions without resending HELO).
     helo: String,
+    /// Set once a trusted relay supplied the client's HELO via `XCLIENT` or
+    /// `XFORWARD`; the relay's own EHLO must not replace it afterwards.
+    helo_forwarded: bool,
     /// Count of unknown RCPT TO addresses seen in this session. Persists
     /// across messages — the cap bounds enumeration over the whole connection.
     unknown_rcpt_count: u32,
+    /// Username accepted by SMTP AUTH. Session-wide, like `helo`.
+    auth_identity: Option<String>,
+    /// Rejected AUTH attempts in this session; see [`MAX_AUTH_FAILURES`].
+    auth_failures: u32,
 }
 
 impl MessageSession {
     fn reset_message(&mut self) {
         self.sender.clear();
-        self.accepted_recipient.clear();
+        self.accepted_recipients.clear();
+        self.rcpt_indices.clear();
         self.email_data.clear();
+        self.dsn = DsnRequest::default();
         self.collecting_data = false;
         self.size_exceeded = false;
         self.data_size_bytes = 0;
-        // Deliberately do not clear `helo` — it's a session-wide fact.
+        // Deliberately do not clear `helo` or `auth_identity` — they are
+        // session-wide facts.
     }
 }
 
+/// Rejected AUTH attempts after which the session is closed, bounding
+/// password guessing over one connection.
+const MAX_AUTH_FAILURES: u32 = 3;
+
 enum StepOutcome {
     Continue,
     Quit,
@@ -326,7 +984,7 @@ enum StepOutcome {
 /// lockstep.
 async fn step<R, W>(
     protocol: &mut SmtpProtocol<R, W>,
-    ctx: &SessionContext,
+    ctx: &mut SessionContext,
     session: &mut MessageSession,
     result: SmtpCommandResult,
 ) -> Result<StepOutcome>
@@ -344,16 +1002,99 @@ where
             info!("Client initiated STARTTLS.");
             Ok(StepOutcome::StartTls)
         }
+        SmtpCommandResult::Close => Ok(StepOutcome::CloseConnection),
+        SmtpCommandResult::Auth { username, password } => {
+            let verified = match ctx.credentials.as_ref() {
+                Some(credentials) => credentials.verify(&username, &password).await,
+                None => false,
+            };
+            if verified {
+                info!(
+                    "SMTP AUTH succeeded for '{}' from {}",
+                    username, ctx.peer_addr
+                );
+                protocol.mark_authenticated();
+                session.auth_identity = Some(username);
+                protocol
+                    .write_line("235 2.7.0 Authentication successful")
+                    .await?;
+            } else {
+                warn!("SMTP AUTH failed for '{}' from {}", username, ctx.peer_addr);
+                session.auth_failures = session.auth_failures.saturating_add(1);
+                if session.auth_failures >= MAX_AUTH_FAILURES {
+                    warn!(
+                        "Peer {} hit AUTH failure cap ({}); closing session",
+                        ctx.peer_addr, MAX_AUTH_FAILURES
+                    );
+                    protocol
+                        .write_line(
+                            "421 4.7.0 Too many authentication failures, closing connection",
+                        )
+                        .await?;
+                    return Ok(StepOutcome::CloseConnection);
+                }
+                protocol
+                    .write_line("535 5.7.8 Authentication credentials invalid")
+                    .await?;
+            }
+            Ok(StepOutcome::Continue)
+        }
         SmtpCommandResult::Helo(domain) => {
-            session.helo = domain;
+            // A HELO/EHLO mid-session also aborts any open transaction.
+            if !session.helo_forwarded {
+                session.helo = domain;
+            }
+            session.reset_message();
+            Ok(StepOutcome::Continue)
+        }
+        SmtpCommandResult::Forwarded(client) => {
+            info!(
+                "Relay {} forwarded client: addr={:?} name={:?} helo={:?} proto={:?}",
+                ctx.peer_addr, client.addr, client.name, client.helo, client.proto
+            );
+            // Everything keyed on the peer (SPF, Cedar `peer_ip` and
+            // `dnsbl_score`, logs) now describes the original client rather
+            // than the relay. A listed client is not refused here: the relay
+            // already accepted it, so its score only feeds Cedar.
+            if let Some(addr) = client.addr {
+                ctx.peer_addr = addr;
+                if let Some(dnsbl) = &ctx.dnsbl {
+                    ctx.dnsbl_result = dnsbl.check(addr).await;
+                }
+            }
+            if let Some(helo) = client.helo {
+                session.helo = helo;
+                session.helo_forwarded = true;
+            }
+            session.reset_message();
+            Ok(StepOutcome::Continue)
+        }
+        SmtpCommandResult::Reset => {
+            session.reset_message();
             Ok(StepOutcome::Continue)
         }
         SmtpCommandResult::MailFrom(email) => {
-            // Cedar `SendMail` evaluation is deferred to end-of-DATA so the
-            // DMARC outcome can feed policy context and principal selection
-            // (see `finalize_message`). Accept the envelope sender provisionally.
+            // Cedar `MailFrom` sees only the envelope (notably the declared
+            // SIZE), so per-sender limits apply before any content is sent.
+            // `SendMail` is deferred to end-of-DATA so the DMARC outcome can
+            // feed policy context and principal selection (see
+            // `finalize_message`).
+            let envelope = envelope_context(ctx, session, &email, protocol.declared_size());
+            let principal = session.auth_identity.as_deref().unwrap_or(&email);
+            if !ctx.policy.can_mail_from(principal, &envelope) {
+                warn!(
+                    "Cedar denied MailFrom: principal={} envelope_from={} declared_size={:?} peer={}",
+                    principal, email, envelope.declared_size, ctx.peer_addr
+                );
+                protocol.reset_transaction();
+                protocol
+                    .write_line("550 5.7.1 Sender not authorized")
+                    .await?;
+                return Ok(StepOutcome::Continue);
+            }
             session.sender = email;
-            protocol.write_line("250 OK").await?;
+            session.dsn = protocol.mail_dsn().clone();
+            protocol.write_line("250 2.1.0 OK").await?;
             Ok(StepOutcome::Continue)
         }
         SmtpCommandResult::RcptTo(email) => {
@@ -363,11 +1104,34 @@ where
                 .iter()
                 .any(|t| t.to_lowercase() == received_email_lower);
             if is_known {
-                session.accepted_recipient = email;
-                protocol.write_line("250 OK").await?;
+                let envelope =
+                    envelope_context(ctx, session, &session.sender, protocol.declared_size());
+                let principal = session.auth_identity.as_deref().unwrap_or(&session.sender);
+                let facts = GreylistFacts::Envelope(&envelope);
+                if let Some(retry_in) =
+                    greylist_delay(ctx, session, GreylistStage::Rcpt, principal, &email, facts)
+                {
+                    protocol.write_line(&greylisted_reply(retry_in)).await?;
+                    return Ok(StepOutcome::Continue);
+                }
+                let index = match session
+                    .accepted_recipients
+                    .iter()
+                    .position(|r| r.to_lowercase() == received_email_lower)
+                {
+                    Some(index) => index,
+                    None => {
+                        session.accepted_recipients.push(email);
+                        if protocol.rcpt_dsn().is_requested() {
+                            session.dsn.recipients.push(protocol.rcpt_dsn().clone());
+                        }
+                        session.accepted_recipients.len() - 1
+                    }
+                };
+                session.rcpt_indices.push(index);
+                protocol.write_line("250 2.1.5 OK").await?;
                 Ok(StepOutcome::Continue)
             } else {
-                session.accepted_recipient.clear();
                 session.unknown_rcpt_count = session.unknown_rcpt_count.saturating_add(1);
                 let cap = ctx.max_unknown_rcpts_per_session;
                 if cap > 0 && session.unknown_rcpt_count >= cap {
@@ -380,7 +1144,19 @@ where
                         .await?;
                     Ok(StepOutcome::CloseConnection)
                 } else {
-                    protocol.write_line("550 No such user here").await?;
+                    let delay = rcpt_tarpit_delay(ctx, session.unknown_rcpt_count);
+                    if !delay.is_zero() {
+                        trace!(
+                            "Tarpitting unknown RCPT #{} from {} for {:?}",
+                            session.unknown_rcpt_count,
+                            ctx.peer_addr,
+                            delay
+                        );
+                        let until = (tokio::time::Instant::now() + delay)
+                            .min(ctx.timeouts.session_deadline);
+                        tokio::time::sleep_until(until).await;
+                    }
+                    protocol.write_line("550 5.1.1 No such user here").await?;
                     Ok(StepOutcome::Continue)
                 }
             }
@@ -402,11 +1178,7 @@ where
             // RFC 5321 §4.5.2: a receiving MTA strips a single leading dot
             // from each DATA line. Not doing so breaks DKIM body-hash
             // verification for any body line that starts with `.`.
-            let unstuffed: &str = if let Some(rest) = line_content.strip_prefix('.') {
-                rest
-            } else {
-                line_content.as_str()
-            };
+            let unstuffed: &[u8] = line_content.strip_prefix(b".").unwrap_or(&line_content);
             let added = (unstuffed.len() as u64).saturating_add(2); // include CRLF
             let next_total = session.data_size_bytes.saturating_add(added);
             if next_total > ctx.max_message_size_bytes {
@@ -418,42 +1190,257 @@ where
                     session.size_exceeded = true;
                 }
             } else {
-                session.email_data.push_str(unstuffed);
-                session.email_data.push_str("\r\n");
+                session.email_data.extend_from_slice(unstuffed);
+                session.email_data.extend_from_slice(b"\r\n");
                 session.data_size_bytes = next_total;
             }
             Ok(StepOutcome::Continue)
         }
         SmtpCommandResult::DataEnd => {
             session.collecting_data = false;
-            let response = finalize_message(ctx, session).await;
-            protocol.write_line(&response).await?;
+            let reply = finalize_message(ctx, session).await;
+            write_message_reply(protocol, ctx, session, &reply).await?;
             session.reset_message();
             Ok(StepOutcome::Continue)
         }
+        SmtpCommandResult::BdatChunk { data: None, last } => {
+            // The protocol layer drained the chunk and ended the transaction.
+            warn!(
+                "Message from '{}' exceeds max_message_size_bytes ({}) during BDAT; rejecting.",
+                session.sender, ctx.max_message_size_bytes
+            );
+            let reply = MessageReply::from("552 5.3.4 Message size exceeds fixed limit");
+            if last {
+                write_message_reply(protocol, ctx, session, &reply).await?;
+            } else {
+                protocol.write_line(&reply.summary).await?;
+            }
+            session.reset_message();
+            Ok(StepOutcome::Continue)
+        }
+        SmtpCommandResult::BdatChunk {
+            data: Some(chunk),
+            last,
+        } => {
+            // BDAT content is neither dot-stuffed nor line-oriented; it is
+            // appended verbatim.
+            session.email_data.extend_from_slice(&chunk);
+            session.data_size_bytes = session.data_size_bytes.saturating_add(chunk.len() as u64);
+            if last {
+                let reply = finalize_message(ctx, session).await;
+                write_message_reply(protocol, ctx, session, &reply).await?;
+                session.reset_message();
+            } else {
+                protocol
+                    .write_line(&format!("250 2.0.0 {} octets received", chunk.len()))
+                    .await?;
+            }
+            Ok(StepOutcome::Continue)
+        }
+    }
+}
+
+/// Envelope facts for Cedar, as known from `MAIL FROM` onwards.
+fn envelope_context(
+    ctx: &SessionContext,
+    session: &MessageSession,
+    envelope_from: &str,
+    declared_size: Option<u64>,
+) -> EnvelopeContext {
+    EnvelopeContext {
+        envelope_from: envelope_from.to_string(),
+        helo: session.helo.clone(),
+        peer_ip: ctx.peer_addr,
+        auth_identity: session.auth_identity.clone(),
+        declared_size,
+        dnsbl_score: ctx.dnsbl_result.score,
+        dnsbl_listed_on: ctx.dnsbl_result.listed_on.clone(),
+    }
+}
+
+/// Checks the delivery to `recipient` against the greylist when it runs at
+/// `stage`, and returns how long until a retry passes when deferred.
+/// Authenticated sessions, and deliveries Cedar permits to
+/// `SkipGreylist`, are never greylisted.
+fn greylist_delay(
+    ctx: &SessionContext,
+    session: &MessageSession,
+    stage: GreylistStage,
+    principal: &str,
+    recipient: &str,
+    facts: GreylistFacts<'_>,
+) -> Option<Duration> {
+    let greylist = ctx.greylist.as_ref().filter(|g| g.stage() == stage)?;
+    if session.auth_identity.is_some() || ctx.policy.can_skip_greylist(principal, recipient, facts)
+    {
+        return None;
+    }
+    match greylist.check(ctx.peer_addr, &session.sender, recipient) {
+        Verdict::Pass => None,
+        Verdict::Defer { retry_in } => {
+            info!(
+                "Greylisted: sender={} recipient={} peer={} retry_in={}s",
+                session.sender,
+                recipient,
+                ctx.peer_addr,
+                retry_in.as_secs()
+            );
+            Some(retry_in)
+        }
+    }
+}
+
+/// The value of the `Received:` trace header (RFC 5321 §4.4) for this
+/// message: the client's HELO, verified `PTR` name and address, this server,
+/// and the protocol keyword of RFC 3848. Names the recipient only when there
+/// is one.
+fn received_value(
+    ctx: &SessionContext,
+    session: &MessageSession,
+    peer_dns: &PeerDns,
+    permitted: &[String],
+) -> String {
+    let helo = if session.helo.is_empty() {
+        "unknown".to_string()
+    } else {
+        crate::dsn::one_line(&session.helo).replace(' ', "")
+    };
+    let ptr = match &peer_dns.ptr_name {
+        Some(name) if peer_dns.fcrdns_ok => format!("{} ", name),
+        _ => String::new(),
+    };
+    let by = match ctx.identity.software {
+        Some(software) => format!("{} ({})", ctx.identity.hostname, software),
+        None => ctx.identity.hostname.clone(),
+    };
+    let protocol = format!(
+        "{}{}{}",
+        if ctx.lmtp { "LMTP" } else { "ESMTP" },
+        if ctx.tls_active { "S" } else { "" },
+        if session.auth_identity.is_some() {
+            "A"
+        } else {
+            ""
+        }
+    );
+    let recipient = match permitted {
+        [only] => format!(" for <{}>", crate::dsn::one_line(only)),
+        _ => String::new(),
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    format!(
+        "from {} ({}{}) by {} with {}{}; {}",
+        helo,
+        ptr,
+        address_literal(ctx.peer_addr),
+        by,
+        protocol,
+        recipient,
+        crate::dsn::rfc5322_date(now)
+    )
+}
+
+/// `ip` as an RFC 5321 §4.1.3 address literal: `[192.0.2.1]` or
+/// `[IPv6:2001:db8::1]`.
+fn address_literal(ip: IpAddr) -> String {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => format!("[{}]", v4),
+        IpAddr::V6(v6) => format!("[IPv6:{}]", v6),
+    }
+}
+
+/// How long to hold the reply to the `count`th unknown recipient of a
+/// session: `rcpt_tarpit` per unknown so far, capped at `rcpt_tarpit_max`.
+fn rcpt_tarpit_delay(ctx: &SessionContext, count: u32) -> Duration {
+    ctx.rcpt_tarpit
+        .saturating_mul(count)
+        .min(ctx.rcpt_tarpit_max)
+}
+
+fn greylisted_reply(retry_in: Duration) -> String {
+    format!(
+        "451 4.7.1 Greylisted, please try again in {} seconds",
+        retry_in.as_secs()
+    )
+}
+
+/// Reply to a policy-denied recipient, or to a message with none permitted.
+const SENDER_NOT_AUTHORIZED: &str = "550 5.7.1 Sender not authorized";
+
+/// The outcome of a transaction: one `summary` for SMTP, and for LMTP a
+/// reply per accepted recipient when their outcomes differ.
+struct MessageReply {
+    summary: String,
+    /// Parallel to `MessageSession::accepted_recipients`. Empty when
+    /// `summary` applies to every recipient.
+    per_recipient: Vec<String>,
+}
+
+impl From<String> for MessageReply {
+    fn from(summary: String) -> Self {
+        MessageReply {
+            summary,
+            per_recipient: Vec::new(),
+        }
     }
 }
 
-/// Parses, authorizes, and forwards the collected message. Returns the SMTP
+impl From<&str> for MessageReply {
+    fn from(summary: &str) -> Self {
+        summary.to_string().into()
+    }
+}
+
+/// Writes the reply to the end of the message content: the summary over
+/// SMTP, or one line per successful `RCPT TO` over LMTP.
+async fn write_message_reply<R, W>(
+    protocol: &mut SmtpProtocol<R, W>,
+    ctx: &SessionContext,
+    session: &MessageSession,
+    reply: &MessageReply,
+) -> Result<()>
+where
+    R: tokio::io::AsyncBufReadExt + Unpin,
+    W: tokio::io::AsyncWriteExt + Unpin,
+{
+    if !ctx.lmtp || session.rcpt_indices.is_empty() {
+        return protocol.write_line(&reply.summary).await;
+    }
+    for &index in &session.rcpt_indices {
+        let line = reply.per_recipient.get(index).unwrap_or(&reply.summary);
+        protocol.write_line(line).await?;
+    }
+    Ok(())
+}
+
+/// Parses, authorizes, and forwards the collected message. Returns the
 /// reply to write back to the client.
-async fn finalize_message(ctx: &SessionContext, session: &MessageSession) -> String {
+async fn finalize_message(ctx: &SessionContext, session: &MessageSession) -> MessageReply {
     if session.size_exceeded {
-        return "552 5.3.4 Message size exceeds fixed limit".to_string();
+        return "552 5.3.4 Message size exceeds fixed limit".into();
     }
-    if session.sender.is_empty() || session.accepted_recipient.is_empty() {
-        return "503 5.5.1 Bad sequence: no MAIL FROM or RCPT TO".to_string();
+    if session.sender.is_empty() || session.accepted_recipients.is_empty() {
+        return "503 5.5.1 Bad sequence: no MAIL FROM or RCPT TO".into();
     }
 
     // DMARC gate — runs before parse so we can 550/451 on fail without burning
     // the parse + policy budget. When ctx.dmarc is None (mode=off) this is a
     // no-op returning an "off" accept decision.
-    let (dmarc_result, authenticated_from, dmarc_ctx) = match run_dmarc(ctx, session).await {
+    // Reverse DNS runs alongside, for Cedar context and the payload.
+    let (dmarc_decision, peer_dns) =
+        tokio::join!(run_dmarc(ctx, session), check_peer_dns(ctx, session));
+    let rdns_checked = peer_dns.is_some();
+    let peer_dns = peer_dns.unwrap_or_default();
+    let (dmarc_result, authenticated_from, dmarc_ctx) = match dmarc_decision {
         DmarcDecision::Reject { code, status } => {
             warn!(
                 "DMARC {}: sender={} helo={} peer={}",
                 status, session.sender, session.helo, ctx.peer_addr
             );
-            return format!("{} {}", code, status);
+            return format!("{} {}", code, status).into();
         }
         DmarcDecision::Accept {
             dmarc_result,
@@ -466,6 +1453,12 @@ async fn finalize_message(ctx: &SessionContext, session: &MessageSession) -> Str
                 envelope_from: session.sender.clone(),
                 helo: session.helo.clone(),
                 peer_ip: ctx.peer_addr,
+                auth_identity: session.auth_identity.clone(),
+                dnsbl_score: ctx.dnsbl_result.score,
+                dnsbl_listed_on: ctx.dnsbl_result.listed_on.clone(),
+                ptr_name: peer_dns.ptr_name.clone(),
+                fcrdns_ok: peer_dns.fcrdns_ok,
+                helo_resolves: peer_dns.helo_resolves,
             };
             if dmarc_result == "off" {
                 // DMARC disabled — omit the payload fields entirely.
@@ -484,39 +1477,82 @@ async fn finalize_message(ctx: &SessionContext, session: &MessageSession) -> Str
         }
     };
 
-    // Cedar `SendMail` — principal substitution: DMARC-aligned From in enforce
-    // mode when DMARC passed, otherwise envelope sender. Monitor mode stays
+    // Cedar `SendMail` — principal substitution: the SMTP AUTH username when
+    // the session authenticated, else the DMARC-aligned From in enforce mode
+    // when DMARC passed, otherwise envelope sender. Monitor mode stays
     // strictly observational (envelope sender always).
-    let principal = match (ctx.dmarc_mode, dmarc_ctx.authenticated_from.as_ref()) {
-        (DmarcMode::Enforce, Some(aligned)) => aligned.as_str(),
+    let principal = match (
+        session.auth_identity.as_ref(),
+        ctx.dmarc_mode,
+        dmarc_ctx.authenticated_from.as_ref(),
+    ) {
+        (Some(identity), _, _) => identity.as_str(),
+        (None, DmarcMode::Enforce, Some(aligned)) => aligned.as_str(),
         _ => session.sender.as_str(),
     };
-    if !ctx
-        .policy
-        .can_send(principal, &session.accepted_recipient, &dmarc_ctx)
-    {
-        warn!(
-            "Cedar denied SendMail: principal={} envelope_from={} recipient={} dmarc_result={}",
-            principal, session.sender, session.accepted_recipient, dmarc_ctx.result
-        );
-        return "550 5.7.1 Sender not authorized".to_string();
+    // Evaluated per recipient. SMTP has a single end-of-DATA reply, so any
+    // denial rejects the whole message rather than accepting it for a
+    // recipient it will never reach; LMTP refuses just the denied ones.
+    let allowed: Vec<bool> = session
+        .accepted_recipients
+        .iter()
+        .map(|recipient| {
+            let allowed = ctx.policy.can_send(principal, recipient, &dmarc_ctx);
+            if !allowed {
+                warn!(
+                    "Cedar denied SendMail: principal={} envelope_from={} recipient={} dmarc_result={}",
+                    principal, session.sender, recipient, dmarc_ctx.result
+                );
+            }
+            allowed
+        })
+        .collect();
+    let permitted: Vec<String> = session
+        .accepted_recipients
+        .iter()
+        .zip(&allowed)
+        .filter(|(_, allowed)| **allowed)
+        .map(|(recipient, _)| recipient.clone())
+        .collect();
+    if permitted.is_empty() || (!ctx.lmtp && permitted.len() < allowed.len()) {
+        return SENDER_NOT_AUTHORIZED.into();
     }
 
-    let parsed = match EmailParser::parse(session.email_data.as_bytes(), &ctx.header_prefixes) {
+    // Greylisting at end-of-DATA: every permitted recipient's triplet is
+    // recorded, so a retry of the whole message passes for all of them.
+    let facts = GreylistFacts::Dmarc(&dmarc_ctx);
+    let retry_in = permitted
+        .iter()
+        .filter_map(|recipient| {
+            greylist_delay(
+                ctx,
+                session,
+                GreylistStage::Data,
+                principal,
+                recipient,
+                facts,
+            )
+        })
+        .max();
+    if let Some(retry_in) = retry_in {
+        return greylisted_reply(retry_in).into();
+    }
+
+    let mut parsed = match EmailParser::parse(&session.email_data, &ctx.header_prefixes) {
         Ok(p) => p,
         Err(e) => {
             error!(
                 "Failed to parse email data from {}: {:#}",
                 session.sender, e
             );
-            return "451 4.3.0 Could not parse message".to_string();
+            return "451 4.3.0 Could not parse message".into();
         }
     };
 
     info!(
-        "Received email from {} to {} (Subject: '{}') with {} attachment(s)",
+        "Received email from {} to {:?} (Subject: '{}') with {} attachment(s)",
         session.sender,
-        session.accepted_recipient,
+        permitted,
         parsed.subject,
         parsed.attachments.len()
     );
@@ -531,7 +1567,8 @@ async fn finalize_message(ctx: &SessionContext, session: &MessageSession) -> Str
             return format!(
                 "552 5.3.4 Attachment '{}' exceeds size limit",
                 att.filename.as_deref().unwrap_or("(unnamed)")
-            );
+            )
+            .into();
         }
         let check = AttachmentCheck {
             filename: att.filename.as_deref(),
@@ -543,7 +1580,7 @@ async fn finalize_message(ctx: &SessionContext, session: &MessageSession) -> Str
                 "Cedar denied Attach (type={}, name={:?}) for principal {}",
                 att.content_type, att.filename, principal
             );
-            return "550 5.7.1 Attachment not permitted by policy".to_string();
+            return "550 5.7.1 Attachment not permitted by policy".into();
         }
     }
 
@@ -557,11 +1594,23 @@ async fn finalize_message(ctx: &SessionContext, session: &MessageSession) -> Str
                     "Attachment backend prepare failed for {}: {:#}",
                     session.sender, e
                 );
-                return "451 4.7.0 Attachment delivery backend failed".to_string();
+                return "451 4.7.0 Attachment delivery backend failed".into();
             }
         }
     }
 
+    // Our own trace header is never written into the message bytes; it is
+    // forwarded like a received one when a prefix selects `Received`.
+    if ctx
+        .header_prefixes
+        .iter()
+        .any(|prefix| "received".starts_with(&prefix.to_lowercase()))
+    {
+        parsed.matched_headers.insert(
+            "Received".to_string(),
+            received_value(ctx, session, &peer_dns, &permitted),
+        );
+    }
     let headers = if parsed.matched_headers.is_empty() {
         None
     } else {
@@ -575,7 +1624,8 @@ async fn finalize_message(ctx: &SessionContext, session: &MessageSession) -> Str
     let email_payload = EmailPayload {
         sender: session.sender.clone(),
         sender_name: parsed.from_name,
-        recipient: session.accepted_recipient.clone(),
+        recipient: permitted[0].clone(),
+        recipients: permitted.clone(),
         subject: parsed.subject,
         body: parsed.text_body,
         html_body: parsed.html_body,
@@ -583,14 +1633,145 @@ async fn finalize_message(ctx: &SessionContext, session: &MessageSession) -> Str
         attachments,
         dmarc_result,
         authenticated_from,
+        authenticated: session.auth_identity.is_some(),
+        auth_identity: session.auth_identity.clone(),
+        ptr_name: peer_dns.ptr_name,
+        fcrdns_ok: rdns_checked.then_some(peer_dns.fcrdns_ok),
+        helo_resolves: rdns_checked.then_some(peer_dns.helo_resolves),
+        dsn: session.dsn.for_recipients(&permitted),
+    };
+    let payloads = match ctx.recipient_delivery {
+        RecipientDelivery::Combined => vec![email_payload],
+        RecipientDelivery::PerRecipient => permitted
+            .iter()
+            .map(|recipient| EmailPayload {
+                recipient: recipient.clone(),
+                recipients: vec![recipient.clone()],
+                dsn: session.dsn.for_recipients(std::slice::from_ref(recipient)),
+                ..email_payload.clone()
+            })
+            .collect(),
     };
-    ctx.webhook_handle
-        .send(ForwardEmail {
-            payload: email_payload,
+
+    if ctx.delivery_mode == DeliveryMode::Sync {
+        let delivered = deliver_sync(ctx, payloads).await;
+        let summary = if delivered.iter().all(|ok| *ok) {
+            SYNC_DELIVERED
+        } else {
+            warn!(
+                "Synchronous webhook delivery failed for message from {}; deferring to sender",
+                session.sender
+            );
+            SYNC_DEFERRED
+        };
+        // Per-recipient payloads succeed or fail independently; a combined
+        // payload decides for every recipient.
+        let permitted_replies: Vec<&str> = match ctx.recipient_delivery {
+            RecipientDelivery::Combined => vec![summary; permitted.len()],
+            RecipientDelivery::PerRecipient => delivered
+                .iter()
+                .map(|ok| if *ok { SYNC_DELIVERED } else { SYNC_DEFERRED })
+                .collect(),
+        };
+        return per_recipient_reply(summary, &allowed, permitted_replies);
+    }
+
+    // With a spool configured, every payload must be on disk before the 250
+    // goes out; if any write fails, undo the others and ask the client to
+    // retry rather than accept a message that could be lost.
+    let mut spool_ids: Vec<Option<String>> = Vec::with_capacity(payloads.len());
+    if let Some(spool) = ctx.spool.as_ref() {
+        for payload in &payloads {
+            match spool.enqueue(payload).await {
+                Ok(id) => spool_ids.push(Some(id)),
+                Err(e) => {
+                    error!("Failed to spool message from {}: {:#}", session.sender, e);
+                    for id in spool_ids.iter().flatten() {
+                        let _ = spool.remove(id).await;
+                    }
+                    return "451 4.3.0 Could not queue message".into();
+                }
+            }
+        }
+    } else {
+        spool_ids.resize(payloads.len(), None);
+    }
+
+    for (payload, spool_id) in payloads.into_iter().zip(spool_ids) {
+        ctx.webhook_handle
+            .send(ForwardEmail {
+                payload,
+                spool_id,
+                notify: None,
+            })
+            .await;
+    }
+
+    let accepted = "250 2.0.0 OK: Message accepted for delivery";
+    per_recipient_reply(accepted, &allowed, vec![accepted; permitted.len()])
+}
+
+/// Builds a [`MessageReply`] from the replies for the permitted recipients
+/// (in order), refusing the ones Cedar denied.
+fn per_recipient_reply(
+    summary: &str,
+    allowed: &[bool],
+    permitted_replies: Vec<&str>,
+) -> MessageReply {
+    let mut permitted_replies = permitted_replies.into_iter();
+    let per_recipient = allowed
+        .iter()
+        .map(|allowed| {
+            let reply = if *allowed {
+                permitted_replies.next().unwrap_or(summary)
+            } else {
+                SENDER_NOT_AUTHORIZED
+            };
+            reply.to_string()
         })
-        .await;
+        .collect();
+    MessageReply {
+        summary: summary.to_string(),
+        per_recipient,
+    }
+}
+
+/// Reply to a message the webhook accepted in `DeliveryMode::Sync`.
+const SYNC_DELIVERED: &str = "250 2.0.0 OK: Message delivered";
+/// Reply when synchronous delivery failed; the sender keeps the message.
+const SYNC_DEFERRED: &str = "451 4.3.0 Webhook delivery failed, try again later";
+
+/// `DeliveryMode::Sync`: hands every payload to the webhook actor and holds
+/// the `DATA` reply until each delivery has finished, returning whether each
+/// payload was delivered. Any failure answers `451` so the sending MTA keeps
+/// the message and retries on its own schedule. The spool is bypassed — the
+/// sender's queue is the durable copy.
+async fn deliver_sync(ctx: &SessionContext, payloads: Vec<EmailPayload>) -> Vec<bool> {
+    let mut receivers = Vec::with_capacity(payloads.len());
+    for payload in payloads {
+        let (notify, rx) = DeliveryNotifier::new();
+        ctx.webhook_handle
+            .send(ForwardEmail {
+                payload,
+                spool_id: None,
+                notify: Some(notify),
+            })
+            .await;
+        receivers.push(rx);
+    }
+
+    let mut delivered = Vec::with_capacity(receivers.len());
+    for rx in receivers {
+        // A dropped notifier (actor stopped mid-delivery) counts as failure.
+        delivered.push(rx.await.unwrap_or(false));
+    }
+    delivered
+}
 
-    "250 OK: Message accepted for delivery".to_string()
+/// Runs the reverse DNS and HELO checks for the message's peer, when on.
+async fn check_peer_dns(ctx: &SessionContext, session: &MessageSession) -> Option<PeerDns> {
+    let rdns = ctx.rdns.as_ref()?;
+    Some(rdns.check(ctx.peer_addr, &session.helo).await)
 }
 
 /// Runs the DMARC check when the validator is configured, otherwise returns an
@@ -611,12 +1792,7 @@ async fn run_dmarc(ctx: &SessionContext, session: &MessageSession) -> DmarcDecis
     };
 
     let outcome = validator
-        .validate(
-            session.email_data.as_bytes(),
-            ctx.peer_addr,
-            helo,
-            &session.sender,
-        )
+        .validate(&session.email_data, ctx.peer_addr, helo, &session.sender)
         .await;
 
     decide(&outcome, ctx.dmarc_mode, ctx.dmarc_temperror_action)
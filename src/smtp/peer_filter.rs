@@ -0,0 +1,316 @@
+//! Connect-time peer allow/deny lists.
+//!
+//! `MAIL_LASER_PEER_ALLOW` / `MAIL_LASER_PEER_DENY` give CIDRs inline, and
+//! the `_FILE` variants name files with one CIDR per line (blank lines and
+//! `#` comments ignored). A peer is refused when it matches any denied
+//! network, or when allowed networks exist and it matches none of them.
+//!
+//! The check runs before the greeting, so a refused client never gets to
+//! stream a message that Cedar would only reject at end-of-DATA.
+//!
+//! List files are reloaded when they change (checked every
+//! [`LIST_POLL_INTERVAL`]) or when the process receives `SIGHUP`. A reload
+//! that fails keeps the previous lists. An allow file that lists no networks
+//! is an error rather than "allow everyone", so a file caught empty or
+//! truncated mid-rewrite never opens the server to every peer.
+
+use super::file_watch::watch_reload;
+use crate::config::{Cidr, PeerDenyAction};
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio_util::sync::CancellationToken;
+
+/// How often list files are checked for changes.
+pub(crate) const LIST_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Shared, reloadable allow/deny lists.
+pub(crate) struct PeerFilter {
+    current: RwLock<Arc<Lists>>,
+    inline: Lists,
+    allow_file: Option<ListFile>,
+    deny_file: Option<ListFile>,
+    action: PeerDenyAction,
+}
+
+#[derive(Default)]
+struct Lists {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+struct ListFile {
+    path: PathBuf,
+    /// Modification stamp at the last (re)load.
+    stamp: RwLock<Option<SystemTime>>,
+}
+
+impl ListFile {
+    fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            stamp: RwLock::new(modified_stamp(path)),
+        }
+    }
+
+    fn changed(&self) -> bool {
+        let now = modified_stamp(&self.path);
+        self.stamp.read().map(|last| *last != now).unwrap_or(true)
+    }
+}
+
+impl PeerFilter {
+    /// Builds the filter from the inline lists and reads any list files.
+    pub(crate) fn load(
+        allow: &[Cidr],
+        allow_file: Option<&Path>,
+        deny: &[Cidr],
+        deny_file: Option<&Path>,
+        action: PeerDenyAction,
+    ) -> Result<Arc<Self>> {
+        let filter = Self {
+            current: RwLock::default(),
+            inline: Lists {
+                allow: allow.to_vec(),
+                deny: deny.to_vec(),
+            },
+            allow_file: allow_file.map(ListFile::new),
+            deny_file: deny_file.map(ListFile::new),
+            action,
+        };
+        filter.reload()?;
+        Ok(Arc::new(filter))
+    }
+
+    /// Whether `ip` may connect.
+    pub(crate) fn admits(&self, ip: IpAddr) -> bool {
+        let lists = self
+            .current
+            .read()
+            .map(|l| l.clone())
+            .unwrap_or_else(|poisoned| poisoned.into_inner().clone());
+        if lists.deny.iter().any(|net| net.contains(ip)) {
+            return false;
+        }
+        lists.allow.is_empty() || lists.allow.iter().any(|net| net.contains(ip))
+    }
+
+    /// How refused peers are turned away.
+    pub(crate) fn action(&self) -> PeerDenyAction {
+        self.action
+    }
+
+    /// Re-reads the list files and swaps in the combined lists.
+    pub(crate) fn reload(&self) -> Result<()> {
+        let mut lists = Lists {
+            allow: self.inline.allow.clone(),
+            deny: self.inline.deny.clone(),
+        };
+        let mut stamps = Vec::new();
+        for (file, target, must_list) in [
+            (&self.allow_file, &mut lists.allow, true),
+            (&self.deny_file, &mut lists.deny, false),
+        ] {
+            let Some(file) = file else {
+                continue;
+            };
+            let stamp = modified_stamp(&file.path);
+            let networks = read_list_file(&file.path)?;
+            if must_list && networks.is_empty() {
+                return Err(anyhow!(
+                    "peer allow list {} lists no networks",
+                    file.path.display()
+                ));
+            }
+            target.extend(networks);
+            stamps.push((file, stamp));
+        }
+        // Only a reload that succeeded counts as having seen the files.
+        for (file, stamp) in stamps {
+            if let Ok(mut last) = file.stamp.write() {
+                *last = stamp;
+            }
+        }
+        info!(
+            "Peer filter: {} allowed and {} denied networks",
+            lists.allow.len(),
+            lists.deny.len()
+        );
+        if let Ok(mut current) = self.current.write() {
+            *current = Arc::new(lists);
+        }
+        Ok(())
+    }
+
+    fn files_changed(&self) -> bool {
+        [&self.allow_file, &self.deny_file]
+            .into_iter()
+            .flatten()
+            .any(ListFile::changed)
+    }
+
+    /// Reloads on file changes and `SIGHUP` until `cancel` fires. Returns
+    /// immediately when no list file is configured.
+    pub(crate) async fn watch(self: Arc<Self>, cancel: CancellationToken) {
+        if self.allow_file.is_none() && self.deny_file.is_none() {
+            return;
+        }
+        watch_reload(
+            self,
+            "Peer filter",
+            "lists",
+            LIST_POLL_INTERVAL,
+            Self::files_changed,
+            Self::reload,
+            cancel,
+        )
+        .await;
+    }
+}
+
+fn modified_stamp(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Parses a list file: one CIDR per line, `#` to end of line is a comment.
+fn read_list_file(path: &Path) -> Result<Vec<Cidr>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read peer list {}", path.display()))?;
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(n, line)| {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            (!entry.is_empty()).then_some((n, entry))
+        })
+        .map(|(n, entry)| {
+            entry
+                .parse()
+                .map_err(|e| anyhow!("{}:{}: {}", path.display(), n + 1, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mail-laser-peers-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn filter(allow: &str, deny: &str) -> Arc<PeerFilter> {
+        PeerFilter::load(
+            &Cidr::parse_list(allow).unwrap(),
+            None,
+            &Cidr::parse_list(deny).unwrap(),
+            None,
+            PeerDenyAction::Reject,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn empty_lists_admit_everyone() {
+        let f = filter("", "");
+        assert!(f.admits(ip("203.0.113.9")));
+        assert!(f.admits(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn allowlist_admits_only_its_networks_and_deny_wins() {
+        let f = filter("10.0.0.0/8,2001:db8::/32", "10.66.0.0/16");
+        assert!(f.admits(ip("10.1.2.3")));
+        assert!(f.admits(ip("::ffff:10.1.2.3")));
+        assert!(f.admits(ip("2001:db8::25")));
+        assert!(!f.admits(ip("192.0.2.1")));
+        assert!(!f.admits(ip("10.66.0.1")), "deny overrides allow");
+    }
+
+    #[test]
+    fn denylist_alone_refuses_only_its_networks() {
+        let f = filter("", "198.51.100.0/24");
+        assert!(!f.admits(ip("198.51.100.7")));
+        assert!(f.admits(ip("198.51.101.7")));
+    }
+
+    #[test]
+    fn list_files_merge_with_inline_lists_and_reload() {
+        let dir = temp_dir();
+        let deny_path = dir.join("deny.txt");
+        std::fs::write(&deny_path, "# abusive\n192.0.2.0/24  # test-net\n\n").unwrap();
+
+        let f = PeerFilter::load(
+            &[],
+            None,
+            &Cidr::parse_list("198.51.100.1").unwrap(),
+            Some(&deny_path),
+            PeerDenyAction::Drop,
+        )
+        .unwrap();
+        assert!(!f.admits(ip("192.0.2.50")));
+        assert!(!f.admits(ip("198.51.100.1")));
+        assert!(f.admits(ip("203.0.113.1")));
+
+        std::fs::write(&deny_path, "203.0.113.0/24\n").unwrap();
+        f.reload().unwrap();
+        assert!(f.admits(ip("192.0.2.50")));
+        assert!(!f.admits(ip("203.0.113.1")));
+        assert!(
+            !f.admits(ip("198.51.100.1")),
+            "inline entries survive reload"
+        );
+
+        std::fs::write(&deny_path, "203.0.113.0/24\nnot-a-network\n").unwrap();
+        let err = f.reload().unwrap_err();
+        assert!(err.to_string().contains(":2:"), "{}", err);
+        assert!(
+            !f.admits(ip("203.0.113.1")),
+            "failed reload must keep the previous lists"
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn empty_allow_file_keeps_refusing_outside_peers() {
+        let dir = temp_dir();
+        let allow_path = dir.join("allow.txt");
+        std::fs::write(&allow_path, "192.0.2.0/24\n").unwrap();
+
+        let f =
+            PeerFilter::load(&[], Some(&allow_path), &[], None, PeerDenyAction::Reject).unwrap();
+        assert!(f.admits(ip("192.0.2.7")));
+        assert!(!f.admits(ip("203.0.113.1")));
+
+        for emptied in ["", "# being rewritten\n"] {
+            std::fs::write(&allow_path, emptied).unwrap();
+            let err = f.reload().unwrap_err();
+            assert!(err.to_string().contains("lists no networks"), "{}", err);
+            assert!(
+                !f.admits(ip("203.0.113.1")),
+                "an emptied allow file must not admit everyone"
+            );
+            assert!(f.admits(ip("192.0.2.7")));
+        }
+
+        let result = PeerFilter::load(&[], Some(&allow_path), &[], None, PeerDenyAction::Reject);
+        assert!(result.is_err(), "an empty allow file is refused at startup");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn missing_list_file_is_an_error() {
+        let missing = Path::new("/nonexistent/allow.txt");
+        let result = PeerFilter::load(&[], Some(missing), &[], None, PeerDenyAction::Reject);
+        assert!(result.is_err());
+    }
+}
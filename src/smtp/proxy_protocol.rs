@@ -0,0 +1,255 @@
+//! HAProxy PROXY protocol (v1 text and v2 binary) header parsing.
+//!
+//! A load balancer in front of the listener prepends one header to the TCP
+//! stream, carrying the original client address, before any SMTP traffic.
+//! [`read_header`] consumes exactly that header and nothing more, so the
+//! stream is left positioned at the first byte the client sent — the SMTP
+//! greeting (or, on SMTPS listeners, the TLS handshake) follows normally.
+//!
+//! Only peers in `MAIL_LASER_PROXY_PROTOCOL_TRUSTED` are asked for a header;
+//! the caller decides that before calling in here.
+
+use anyhow::{anyhow, bail, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// How long a trusted proxy has to deliver the header after connecting.
+pub const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The 12-byte signature that opens every v2 header.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// v1 lines are at most 107 bytes including the trailing CRLF.
+const V1_MAX_LEN: usize = 107;
+
+/// Reads one PROXY header, v1 or v2, within [`HEADER_TIMEOUT`].
+///
+/// Returns the client's address, or `None` when the proxy reports a
+/// connection of its own (v1 `UNKNOWN`, v2 `LOCAL`, or a non-TCP address
+/// family) — typically a health check — in which case the socket peer
+/// should be used. Any malformed, truncated or missing header is an error.
+pub async fn read_header<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<SocketAddr>> {
+    tokio::time::timeout(HEADER_TIMEOUT, read_header_inner(reader))
+        .await
+        .map_err(|_| anyhow!("timed out waiting for PROXY header"))?
+}
+
+async fn read_header_inner<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<SocketAddr>> {
+    // Both the v2 signature and the shortest v1 line ("PROXY UNKNOWN\r\n",
+    // 15 bytes) are at least 12 bytes, so this never reads past the header.
+    let mut prefix = [0u8; 12];
+    reader.read_exact(&mut prefix).await?;
+
+    if prefix == V2_SIGNATURE {
+        read_v2(reader).await
+    } else if prefix.starts_with(b"PROXY ") {
+        read_v1(reader, &prefix).await
+    } else {
+        bail!("connection did not start with a PROXY header")
+    }
+}
+
+async fn read_v1<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    prefix: &[u8],
+) -> Result<Option<SocketAddr>> {
+    let mut line = prefix.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            bail!("PROXY v1 header exceeds {} bytes", V1_MAX_LEN);
+        }
+        line.push(reader.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| anyhow!("PROXY v1 header is not ASCII"))?;
+    parse_v1(line)
+}
+
+fn parse_v1(line: &str) -> Result<Option<SocketAddr>> {
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", family @ ("TCP4" | "TCP6"), src, dst, src_port, dst_port] => {
+            let parse_ip = |s: &str| -> Result<IpAddr> {
+                let ip = if *family == "TCP4" {
+                    s.parse::<Ipv4Addr>().map(IpAddr::V4)
+                } else {
+                    s.parse::<Ipv6Addr>().map(IpAddr::V6)
+                };
+                ip.map_err(|_| anyhow!("invalid {} address '{}' in PROXY header", family, s))
+            };
+            let parse_port = |s: &str| -> Result<u16> {
+                s.parse()
+                    .map_err(|_| anyhow!("invalid port '{}' in PROXY header", s))
+            };
+            let src = parse_ip(src)?;
+            parse_ip(dst)?;
+            let src_port = parse_port(src_port)?;
+            parse_port(dst_port)?;
+            Ok(Some(SocketAddr::new(src, src_port)))
+        }
+        _ => bail!("malformed PROXY v1 header"),
+    }
+}
+
+async fn read_v2<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<SocketAddr>> {
+    let mut fixed = [0u8; 4];
+    reader.read_exact(&mut fixed).await?;
+    let [version_command, family, len_hi, len_lo] = fixed;
+
+    if version_command >> 4 != 2 {
+        bail!(
+            "unsupported PROXY protocol version {}",
+            version_command >> 4
+        );
+    }
+    // Read the whole address block (including any TLVs) so the stream is
+    // left at the start of the client's data.
+    let mut block = vec![0u8; usize::from(u16::from_be_bytes([len_hi, len_lo]))];
+    reader.read_exact(&mut block).await?;
+
+    match version_command & 0x0F {
+        0x0 => return Ok(None), // LOCAL
+        0x1 => {}               // PROXY
+        other => bail!("unsupported PROXY v2 command {:#x}", other),
+    }
+
+    match family >> 4 {
+        // AF_INET: src(4) dst(4) src_port(2) dst_port(2)
+        0x1 => {
+            let Some(addr) = block.get(..12) else {
+                bail!("PROXY v2 IPv4 address block too short");
+            };
+            let ip = Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]);
+            let port = u16::from_be_bytes([addr[8], addr[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        // AF_INET6: src(16) dst(16) src_port(2) dst_port(2)
+        0x2 => {
+            let Some(addr) = block.get(..36) else {
+                bail!("PROXY v2 IPv6 address block too short");
+            };
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addr[..16]);
+            let port = u16::from_be_bytes([addr[32], addr[33]]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(octets)),
+                port,
+            )))
+        }
+        // AF_UNSPEC / AF_UNIX carry no usable client IP.
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(bytes: &[u8]) -> (Result<Option<SocketAddr>>, Vec<u8>) {
+        let mut reader = bytes;
+        let result = read_header(&mut reader).await;
+        (result, reader.to_vec())
+    }
+
+    fn v2(command: u8, family: u8, block: &[u8]) -> Vec<u8> {
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.push(0x20 | command);
+        bytes.push(family);
+        bytes.extend_from_slice(&(block.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(block);
+        bytes
+    }
+
+    #[tokio::test]
+    async fn v1_tcp4_yields_client_address_and_leaves_the_rest() {
+        let (result, rest) = parse(b"PROXY TCP4 203.0.113.9 10.0.0.5 51234 25\r\nEHLO x\r\n").await;
+        assert_eq!(result.unwrap(), Some("203.0.113.9:51234".parse().unwrap()));
+        assert_eq!(rest, b"EHLO x\r\n");
+    }
+
+    #[tokio::test]
+    async fn v1_tcp6_and_unknown() {
+        let (result, _) = parse(b"PROXY TCP6 2001:db8::9 2001:db8::1 4000 25\r\n").await;
+        assert_eq!(result.unwrap(), Some("[2001:db8::9]:4000".parse().unwrap()));
+
+        let (result, rest) = parse(b"PROXY UNKNOWN\r\n").await;
+        assert_eq!(result.unwrap(), None);
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn v1_rejects_malformed_headers() {
+        for bad in [
+            &b"PROXY TCP4 2001:db8::9 10.0.0.5 1 25\r\n"[..],
+            b"PROXY TCP4 203.0.113.9 10.0.0.5 70000 25\r\n",
+            b"PROXY TCP4 203.0.113.9 10.0.0.5 1\r\n",
+            b"PROXY UDP4 203.0.113.9 10.0.0.5 1 25\r\n",
+        ] {
+            assert!(parse(bad).await.0.is_err(), "{:?}", bad);
+        }
+
+        let mut long = b"PROXY UNKNOWN ".to_vec();
+        long.extend(std::iter::repeat_n(b'x', 200));
+        assert!(parse(&long).await.0.is_err());
+    }
+
+    #[tokio::test]
+    async fn missing_header_is_an_error() {
+        assert!(parse(b"EHLO client.example\r\n").await.0.is_err());
+        assert!(parse(b"PROXY").await.0.is_err());
+    }
+
+    #[tokio::test]
+    async fn v2_inet_with_tlvs() {
+        let mut block = vec![198, 51, 100, 20, 10, 0, 0, 5];
+        block.extend_from_slice(&4321u16.to_be_bytes());
+        block.extend_from_slice(&25u16.to_be_bytes());
+        block.extend_from_slice(&[0xEA, 0x00, 0x02, b'v', b'p']); // AWS TLV
+        let mut bytes = v2(0x1, 0x11, &block);
+        bytes.extend_from_slice(b"\x16\x03\x01");
+
+        let (result, rest) = parse(&bytes).await;
+        assert_eq!(result.unwrap(), Some("198.51.100.20:4321".parse().unwrap()));
+        assert_eq!(rest, b"\x16\x03\x01");
+    }
+
+    #[tokio::test]
+    async fn v2_inet6() {
+        let src: Ipv6Addr = "2001:db8::42".parse().unwrap();
+        let mut block = src.octets().to_vec();
+        block.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        block.extend_from_slice(&[0x13, 0x88, 0x00, 0x19]);
+
+        let (result, _) = parse(&v2(0x1, 0x21, &block)).await;
+        assert_eq!(
+            result.unwrap(),
+            Some("[2001:db8::42]:5000".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn v2_local_and_unspec_fall_back_to_the_socket_peer() {
+        let (result, rest) = parse(&v2(0x0, 0x00, &[])).await;
+        assert_eq!(result.unwrap(), None);
+        assert!(rest.is_empty());
+
+        let (result, _) = parse(&v2(0x1, 0x00, &[])).await;
+        assert_eq!(result.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn v2_rejects_bad_version_and_short_blocks() {
+        let mut wrong_version = v2(0x1, 0x11, &[0; 12]);
+        wrong_version[12] = 0x11;
+        assert!(parse(&wrong_version).await.0.is_err());
+
+        assert!(parse(&v2(0x1, 0x11, &[0; 8])).await.0.is_err());
+        assert!(parse(&v2(0x1, 0x21, &[0; 12])).await.0.is_err());
+
+        let mut truncated = v2(0x1, 0x11, &[0; 12]);
+        truncated.truncate(20);
+        assert!(parse(&truncated).await.0.is_err());
+    }
+}
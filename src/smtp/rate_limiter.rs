@@ -0,0 +1,144 @@
+//! Per-source connection rate limit for the SMTP listener.
+//!
+//! The concurrency cap in `ip_limiter` does nothing against a client that
+//! closes and reconnects as fast as it can. This limiter keeps a token
+//! bucket per source (keyed like the cap, see [`source_key`]) that holds up
+//! to `per_minute` tokens and refills at `per_minute` tokens a minute. Each
+//! connection takes a token; a source whose bucket is empty is refused until
+//! it refills.
+//!
+//! A full bucket behaves exactly like a missing one, so once the map grows
+//! past a watermark, full buckets are dropped to bound memory.
+
+use super::ip_limiter::source_key;
+use crate::swept_map::SweptMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Token-bucket connection rate limiter keyed by source.
+///
+/// Cheap to clone — internal state is one `Arc<Mutex<…>>`.
+#[derive(Clone)]
+pub struct RateLimiter {
+    inner: Arc<Mutex<SweptMap<IpAddr, Bucket>>>,
+    per_minute: u32,
+    ipv6_prefix_len: u8,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn level(&self, now: Instant, capacity: f64) -> f64 {
+        let refill = now.saturating_duration_since(self.updated).as_secs_f64() * capacity / 60.0;
+        (self.tokens + refill).min(capacity)
+    }
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing `per_minute` connections per source, with
+    /// bursts of the same size. `per_minute == 0` disables the limiter.
+    pub fn new(per_minute: u32, ipv6_prefix_len: u8) -> Self {
+        Self {
+            inner: Arc::default(),
+            per_minute,
+            ipv6_prefix_len,
+        }
+    }
+
+    /// Takes a token for a new connection from `ip`. Returns `false` when
+    /// the source has used up its allowance.
+    pub fn try_acquire(&self, ip: IpAddr) -> bool {
+        self.try_acquire_at(ip, Instant::now())
+    }
+
+    fn try_acquire_at(&self, ip: IpAddr, now: Instant) -> bool {
+        if self.per_minute == 0 {
+            return true;
+        }
+        let capacity = f64::from(self.per_minute);
+        let mut buckets = self.inner.lock().expect("rate-limiter mutex poisoned");
+        let bucket = buckets
+            .entry(source_key(ip, self.ipv6_prefix_len), |_, bucket| {
+                bucket.level(now, capacity) < capacity
+            })
+            .or_insert(Bucket {
+                tokens: capacity,
+                updated: now,
+            });
+        bucket.tokens = bucket.level(now, capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swept_map::SWEEP_THRESHOLD;
+    use std::time::Duration;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn zero_disables_limiter() {
+        let lim = RateLimiter::new(0, 64);
+        assert!((0..1000).all(|_| lim.try_acquire(ip("10.0.0.1"))));
+    }
+
+    #[test]
+    fn allows_a_burst_then_refills_over_time() {
+        let lim = RateLimiter::new(6, 64);
+        let peer = ip("10.0.0.1");
+        let start = Instant::now();
+        assert!((0..6).all(|_| lim.try_acquire_at(peer, start)));
+        assert!(!lim.try_acquire_at(peer, start), "burst exhausted");
+
+        // Six a minute is one token every ten seconds.
+        assert!(!lim.try_acquire_at(peer, start + Duration::from_secs(9)));
+        assert!(lim.try_acquire_at(peer, start + Duration::from_secs(10)));
+        assert!(!lim.try_acquire_at(peer, start + Duration::from_secs(10)));
+
+        // Refill never exceeds the burst size.
+        let later = start + Duration::from_secs(3600);
+        assert!((0..6).all(|_| lim.try_acquire_at(peer, later)));
+        assert!(!lim.try_acquire_at(peer, later));
+    }
+
+    #[test]
+    fn sources_are_independent_but_ipv6_groups_by_prefix() {
+        let lim = RateLimiter::new(1, 64);
+        let now = Instant::now();
+        assert!(lim.try_acquire_at(ip("10.0.0.1"), now));
+        assert!(lim.try_acquire_at(ip("10.0.0.2"), now));
+        assert!(!lim.try_acquire_at(ip("10.0.0.1"), now));
+
+        assert!(lim.try_acquire_at(ip("2001:db8:0:1::1"), now));
+        assert!(!lim.try_acquire_at(ip("2001:db8:0:1::2"), now));
+        assert!(lim.try_acquire_at(ip("2001:db8:0:2::1"), now));
+    }
+
+    #[test]
+    fn full_buckets_are_pruned() {
+        let lim = RateLimiter::new(1, 128);
+        let start = Instant::now();
+        for n in 0..SWEEP_THRESHOLD as u32 {
+            assert!(lim.try_acquire_at(IpAddr::from(n.to_be_bytes()), start));
+        }
+        // A minute later every bucket has refilled, so the next connection
+        // sweeps them all away and only the new source remains.
+        let later = start + Duration::from_secs(60);
+        assert!(lim.try_acquire_at(ip("192.0.2.1"), later));
+        assert_eq!(lim.inner.lock().unwrap().len(), 1);
+    }
+}
@@ -4,13 +4,113 @@
 //! manages reading commands and writing responses over a `TcpStream`,
 //! and parses basic SMTP commands, transitioning the state accordingly.
 
+use crate::dsn::{self, DsnRecipient, DsnRequest, Notify, Ret};
 use anyhow::Result;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
 use log::{debug, warn}; // Add warn
 use mailparse::{addrparse, MailAddr}; // Add mailparse imports
                                       // Keep only used IO traits/types
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use std::fmt;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio::time::Instant;
 // Remove unused TcpStream import
 
+/// Command verbs this server implements, used to tell an out-of-sequence
+/// command (`503`) from an unknown one (`500`).
+const KNOWN_COMMANDS: &[&str] = &[
+    "HELO", "EHLO", "LHLO", "MAIL", "RCPT", "DATA", "BDAT", "RSET", "NOOP", "VRFY", "HELP", "QUIT",
+    "STARTTLS", "AUTH", "XCLIENT", "XFORWARD",
+];
+
+/// How long a client that stopped reading gets to take the `421` reply
+/// before the connection is dropped anyway.
+pub const TIMEOUT_REPLY_GRACE: Duration = Duration::from_secs(5);
+
+/// The read deadline a client missed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// Waiting for the first command (or the implicit TLS handshake).
+    Greeting,
+    /// Waiting for a command line, a STARTTLS handshake or an AUTH response.
+    Command,
+    /// Waiting for a line of `DATA` content or the bytes of a `BDAT` chunk.
+    Data,
+    /// The session's wall-clock allowance ran out.
+    Session,
+}
+
+impl TimeoutPhase {
+    /// Lower-case name, as used in metrics labels.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TimeoutPhase::Greeting => "greeting",
+            TimeoutPhase::Command => "command",
+            TimeoutPhase::Data => "data",
+            TimeoutPhase::Session => "session",
+        }
+    }
+}
+
+/// How long the server waits for client input (RFC 5321 §4.5.3.2). Each
+/// read gets the limit for its phase, cut short by `session_deadline`.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadTimeouts {
+    pub greeting: Duration,
+    pub command: Duration,
+    pub data: Duration,
+    pub session_deadline: Instant,
+}
+
+impl ReadTimeouts {
+    /// When a read in `phase` starting now must finish, and the phase to
+    /// blame if it does not.
+    pub fn deadline(&self, phase: TimeoutPhase) -> (Instant, TimeoutPhase) {
+        let limit = match phase {
+            TimeoutPhase::Greeting => self.greeting,
+            TimeoutPhase::Command => self.command,
+            TimeoutPhase::Data => self.data,
+            TimeoutPhase::Session => return (self.session_deadline, phase),
+        };
+        let phase_deadline = Instant::now() + limit;
+        if self.session_deadline < phase_deadline {
+            (self.session_deadline, TimeoutPhase::Session)
+        } else {
+            (phase_deadline, phase)
+        }
+    }
+}
+
+/// Error returned by reads once the client misses a deadline. The protocol
+/// has already answered `421 4.4.2`; the caller only has to close.
+#[derive(Debug)]
+pub struct ReadTimeout(pub TimeoutPhase);
+
+impl fmt::Display for ReadTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "client {} timeout", self.0.as_str())
+    }
+}
+
+impl std::error::Error for ReadTimeout {}
+
+/// Awaits `read`, giving up at `deadline` (if any) with the phase to blame.
+async fn with_deadline<F: Future>(
+    deadline: Option<(Instant, TimeoutPhase)>,
+    read: F,
+) -> Result<F::Output, TimeoutPhase> {
+    match deadline {
+        Some((at, phase)) => tokio::time::timeout_at(at, read).await.map_err(|_| phase),
+        None => Ok(read.await),
+    }
+}
+
 /// Represents the possible states during an SMTP session.
 ///
 /// The protocol handler transitions between these states based on the commands received.
@@ -26,6 +126,36 @@ pub enum SmtpState {
     RcptTo,
     /// State after a `DATA` command has been received and acknowledged (354). Client sends email content.
     Data,
+    /// State after a `BDAT` chunk without `LAST` (RFC 3030). Only further `BDAT` chunks are accepted.
+    Bdat,
+}
+
+/// Product name and version announced unless the identity hides them.
+pub const SOFTWARE: &str = concat!("MailLaser ", env!("CARGO_PKG_VERSION"));
+
+/// How the server names itself in the greeting, the EHLO reply and the
+/// `Received:` headers it adds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerIdentity {
+    /// The server's host name (`MAIL_LASER_SERVER_HOSTNAME`).
+    pub hostname: String,
+    /// [`SOFTWARE`], or `None` when `MAIL_LASER_HIDE_PRODUCT` is set.
+    pub software: Option<&'static str>,
+}
+
+impl ServerIdentity {
+    pub fn new(hostname: &str, hide_product: bool) -> Self {
+        ServerIdentity {
+            hostname: hostname.to_string(),
+            software: (!hide_product).then_some(SOFTWARE),
+        }
+    }
+}
+
+impl Default for ServerIdentity {
+    fn default() -> Self {
+        Self::new("localhost", false)
+    }
 }
 
 /// Manages the state and I/O for a single SMTP client connection.
@@ -44,6 +174,40 @@ where
     writer: W, // Use the generic writer type
     state: SmtpState,
     max_message_size_bytes: u64,
+    /// Whether the session runs over TLS (after STARTTLS or on the SMTPS
+    /// listener). Suppresses the `STARTTLS` capability and command.
+    tls_active: bool,
+    /// Whether `MAIL FROM` is refused until the session runs over TLS.
+    require_tls: bool,
+    /// Whether SMTP AUTH is configured. Only offered once `tls_active`.
+    auth_enabled: bool,
+    /// Set after a successful AUTH; a second AUTH is refused (RFC 4954 §4).
+    authenticated: bool,
+    /// Whether the peer is a trusted front-end MTA allowed to send `XCLIENT`
+    /// and `XFORWARD`.
+    xclient_enabled: bool,
+    /// Whether the session speaks LMTP (RFC 2033): `LHLO` replaces
+    /// `HELO`/`EHLO` and the greeting names the protocol.
+    lmtp: bool,
+    /// Whether the current transaction's `MAIL FROM` carried `SMTPUTF8`
+    /// (RFC 6531), permitting non-ASCII addresses.
+    smtputf8: bool,
+    /// Bytes received via `BDAT` in the current transaction.
+    bdat_received: u64,
+    /// `SIZE=` declared on the current transaction's `MAIL FROM` (RFC 1870).
+    declared_size: Option<u64>,
+    /// `RET=` and `ENVID=` from the current transaction's `MAIL FROM`
+    /// (RFC 3461). `recipients` is always empty here.
+    mail_dsn: DsnRequest,
+    /// `NOTIFY=` and `ORCPT=` from the most recent accepted `RCPT TO`.
+    rcpt_dsn: DsnRecipient,
+    /// Read deadlines; `None` waits indefinitely.
+    timeouts: Option<ReadTimeouts>,
+    /// Set until the first command line arrives, which is read under the
+    /// greeting timeout.
+    awaiting_first_command: bool,
+    /// Names the server in the greeting and EHLO reply.
+    identity: Arc<ServerIdentity>,
 }
 
 // Implementation block now needs the generic parameters and bounds.
@@ -62,15 +226,111 @@ where
             writer,
             state: SmtpState::Initial,
             max_message_size_bytes,
+            tls_active: false,
+            require_tls: false,
+            auth_enabled: false,
+            authenticated: false,
+            xclient_enabled: false,
+            lmtp: false,
+            smtputf8: false,
+            bdat_received: 0,
+            declared_size: None,
+            mail_dsn: DsnRequest::default(),
+            rcpt_dsn: DsnRecipient::default(),
+            timeouts: None,
+            awaiting_first_command: true,
+            identity: Arc::new(ServerIdentity::default()),
         }
     }
 
-    /// Sends the initial SMTP greeting (220) to the client.
+    /// Marks the session as already encrypted. EHLO then omits `STARTTLS`,
+    /// and a `STARTTLS` command is answered with `503`.
+    pub fn with_tls_active(mut self, tls_active: bool) -> Self {
+        self.tls_active = tls_active;
+        self
+    }
+
+    /// When set, `MAIL FROM` on a plaintext session is answered with
+    /// `530 5.7.0` (RFC 3207 §4) until the client upgrades via STARTTLS.
+    pub fn with_require_tls(mut self, require_tls: bool) -> Self {
+        self.require_tls = require_tls;
+        self
+    }
+
+    /// Enables SMTP AUTH (`PLAIN` and `LOGIN`). The mechanisms are advertised
+    /// and accepted only on encrypted sessions; plaintext attempts get `538`.
+    pub fn with_auth(mut self, auth_enabled: bool) -> Self {
+        self.auth_enabled = auth_enabled;
+        self
+    }
+
+    /// Accepts Postfix `XCLIENT` and `XFORWARD` from this peer and
+    /// advertises them in EHLO. Other peers get `550` for both commands.
+    pub fn with_xclient(mut self, xclient_enabled: bool) -> Self {
+        self.xclient_enabled = xclient_enabled;
+        self
+    }
+
+    /// Switches the session to LMTP. The per-recipient replies after `DATA`
+    /// are written by the session loop, which knows the recipients.
+    pub fn with_lmtp(mut self, lmtp: bool) -> Self {
+        self.lmtp = lmtp;
+        self
+    }
+
+    /// Bounds every read from the client. A missed deadline is answered with
+    /// `421 4.4.2` and surfaces as a [`ReadTimeout`] error.
+    pub fn with_timeouts(mut self, timeouts: ReadTimeouts) -> Self {
+        self.timeouts = Some(timeouts);
+        self
+    }
+
+    /// Names the server as `identity` in the greeting and EHLO reply.
+    pub fn with_identity(mut self, identity: Arc<ServerIdentity>) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Records that the credentials returned in [`SmtpCommandResult::Auth`]
+    /// were accepted. Called by the session loop after verification.
+    pub fn mark_authenticated(&mut self) {
+        self.authenticated = true;
+    }
+
+    /// The `SIZE=` declared on the current transaction's `MAIL FROM`; valid
+    /// after [`SmtpCommandResult::MailFrom`].
+    pub fn declared_size(&self) -> Option<u64> {
+        self.declared_size
+    }
+
+    /// The DSN parameters of the current transaction's `MAIL FROM`; valid
+    /// after [`SmtpCommandResult::MailFrom`].
+    pub fn mail_dsn(&self) -> &DsnRequest {
+        &self.mail_dsn
+    }
+
+    /// The DSN parameters of the `RCPT TO` just returned as
+    /// [`SmtpCommandResult::RcptTo`].
+    pub fn rcpt_dsn(&self) -> &DsnRecipient {
+        &self.rcpt_dsn
+    }
+
+    /// Sends the initial SMTP greeting (220) to the client: the host name
+    /// first (RFC 5321 §4.3.1), then the protocol and, unless hidden, the
+    /// product name and version.
     ///
     /// This should be called immediately after establishing a connection.
     /// Transitions the state implicitly (caller should expect `Greeted` state next).
     pub async fn send_greeting(&mut self) -> Result<()> {
-        self.write_line("220 MailLaser SMTP Server Ready").await // Informative greeting.
+        let protocol = if self.lmtp { "LMTP" } else { "ESMTP" };
+        let greeting = match self.identity.software {
+            Some(software) => format!(
+                "220 {} {} {} ready",
+                self.identity.hostname, protocol, software
+            ),
+            None => format!("220 {} {} ready", self.identity.hostname, protocol),
+        };
+        self.write_line(&greeting).await
     }
 
     /// Processes a single command line received from the client.
@@ -89,159 +349,739 @@ where
     /// writing the response fails.
     pub async fn process_command(&mut self, line: &str) -> Result<SmtpCommandResult> {
         // Log the command being processed and the state *before* processing.
-        debug!("SMTP({:?}): Processing command: {:?}", self.state, line);
+        debug!(
+            "SMTP({:?}): Processing command: {:?}",
+            self.state,
+            redact_auth(line)
+        );
 
-        match self.state {
-            SmtpState::Initial => {
-                // Expect HELO or EHLO after connection.
-                let upper_line = line.to_uppercase(); // Avoid repeated conversions
-                if upper_line.starts_with("HELO") {
-                    let domain = line.split_whitespace().nth(1).unwrap_or("client");
-                    let domain_owned = domain.to_string();
-                    self.write_line("250 MailLaser").await?;
-                    self.state = SmtpState::Greeted;
-                    Ok(SmtpCommandResult::Helo(domain_owned))
-                } else if upper_line.starts_with("EHLO") {
-                    // Respond to EHLO, advertising SIZE and STARTTLS.
-                    let domain = line.split_whitespace().nth(1).unwrap_or("client");
-                    let domain_owned = domain.to_string();
-                    self.write_line(&format!("250-MailLaser greets {}", domain))
-                        .await?;
-                    self.write_line(&format!("250-SIZE {}", self.max_message_size_bytes))
-                        .await?;
-                    self.write_line("250 STARTTLS").await?;
-                    self.state = SmtpState::Greeted;
-                    Ok(SmtpCommandResult::Helo(domain_owned))
-                } else if line.to_uppercase().starts_with("QUIT") {
-                    self.write_line("221 Bye").await?;
-                    Ok(SmtpCommandResult::Quit)
-                } else {
-                    // Command out of sequence or unrecognized.
-                    self.write_line("500 Command not recognized or out of sequence")
-                        .await?;
-                    Ok(SmtpCommandResult::Continue)
-                }
+        if self.state != SmtpState::Data {
+            if let Some(result) = self.process_session_command(line).await? {
+                return Ok(result);
             }
+        }
+
+        let upper_line = line.to_uppercase(); // Avoid repeated conversions
+        match self.state {
+            // Only the session-wide commands above are valid before HELO/EHLO.
+            SmtpState::Initial if self.lmtp => self.reject_command(line, "LHLO").await,
+            SmtpState::Initial => self.reject_command(line, "EHLO or HELO").await,
             SmtpState::Greeted => {
-                // Expect MAIL FROM or STARTTLS after greeting.
-                let upper_line = line.to_uppercase(); // Avoid repeated conversions
+                // Expect MAIL FROM, AUTH or STARTTLS after greeting.
                 if upper_line.starts_with("MAIL FROM:") {
-                    if let Some(email) = self.extract_email(line) {
-                        // Caller responds (250 OK or 550) after running authorization.
-                        self.state = SmtpState::MailFrom;
-                        Ok(SmtpCommandResult::MailFrom(email))
-                    } else {
-                        self.write_line("501 Syntax error in MAIL FROM parameters")
+                    if self.require_tls && !self.tls_active {
+                        self.write_line("530 5.7.0 Must issue a STARTTLS command first")
                             .await?;
-                        Ok(SmtpCommandResult::Continue)
+                        return Ok(SmtpCommandResult::Continue);
                     }
+                    match self.mail_from_path(line).await? {
+                        Some(email) => {
+                            // Caller responds (250 OK or 550) after running authorization.
+                            self.state = SmtpState::MailFrom;
+                            Ok(SmtpCommandResult::MailFrom(email))
+                        }
+                        None => Ok(SmtpCommandResult::Continue),
+                    }
+                } else if upper_line.starts_with("AUTH") {
+                    self.process_auth(line).await
                 } else if upper_line.starts_with("STARTTLS") {
-                    // Handle STARTTLS command
-                    self.write_line("220 Go ahead").await?;
+                    if self.tls_active {
+                        self.write_line("503 5.5.1 STARTTLS already active").await?;
+                        return Ok(SmtpCommandResult::Continue);
+                    }
+                    if self.has_pending_input().await {
+                        // Anything queued behind STARTTLS would otherwise be
+                        // read as plaintext and acted on inside the TLS session.
+                        return self.reject_pipelining().await;
+                    }
+                    self.write_line("220 2.0.0 Ready to start TLS").await?;
+                    // The handshake takes over the raw stream, so the reply
+                    // must be on the wire first.
+                    self.flush().await?;
                     // State remains Greeted; the caller handles the TLS upgrade.
                     Ok(SmtpCommandResult::StartTls)
-                } else if upper_line.starts_with("QUIT") {
-                    self.write_line("221 Bye").await?;
-                    Ok(SmtpCommandResult::Quit)
                 } else {
-                    self.write_line(
-                        "503 Bad sequence of commands (expected MAIL FROM or STARTTLS)",
-                    )
-                    .await?;
-                    Ok(SmtpCommandResult::Continue)
+                    self.reject_command(line, "MAIL FROM or STARTTLS").await
                 }
             }
             SmtpState::MailFrom => {
                 // Expect RCPT TO after MAIL FROM.
-                if line.to_uppercase().starts_with("RCPT TO:") {
-                    if let Some(email) = self.extract_email(line) {
-                        // Response (250 or 550) is handled by the caller based on validation.
-                        self.state = SmtpState::RcptTo;
-                        Ok(SmtpCommandResult::RcptTo(email))
-                    } else {
-                        self.write_line("501 Syntax error in RCPT TO parameters")
-                            .await?;
-                        Ok(SmtpCommandResult::Continue)
+                if upper_line.starts_with("RCPT TO:") {
+                    match self.rcpt_to_path(line).await? {
+                        Some(email) => {
+                            // Response (250 or 550) is handled by the caller based on validation.
+                            self.state = SmtpState::RcptTo;
+                            Ok(SmtpCommandResult::RcptTo(email))
+                        }
+                        None => Ok(SmtpCommandResult::Continue),
                     }
-                } else if line.to_uppercase().starts_with("QUIT") {
-                    self.write_line("221 Bye").await?;
-                    Ok(SmtpCommandResult::Quit)
                 } else {
-                    self.write_line("503 Bad sequence of commands (expected RCPT TO)")
-                        .await?;
-                    Ok(SmtpCommandResult::Continue)
+                    self.reject_command(line, "RCPT TO").await
                 }
             }
             SmtpState::RcptTo => {
                 // Expect DATA or another RCPT TO after RCPT TO.
-                if line.to_uppercase().starts_with("DATA") {
+                if upper_line.starts_with("DATA") {
+                    if self.has_pending_input().await {
+                        return self.reject_pipelining().await;
+                    }
                     self.write_line("354 Start mail input; end with <CRLF>.<CRLF>")
                         .await?;
                     self.state = SmtpState::Data;
                     Ok(SmtpCommandResult::DataStart)
-                } else if line.to_uppercase().starts_with("RCPT TO:") {
+                } else if upper_line.starts_with("RCPT TO:") {
                     // Allow multiple recipients.
-                    if let Some(email) = self.extract_email(line) {
+                    match self.rcpt_to_path(line).await? {
                         // Response handled by caller. State remains RcptTo.
-                        Ok(SmtpCommandResult::RcptTo(email))
-                    } else {
-                        self.write_line("501 Syntax error in RCPT TO parameters")
-                            .await?;
-                        Ok(SmtpCommandResult::Continue)
+                        Some(email) => Ok(SmtpCommandResult::RcptTo(email)),
+                        None => Ok(SmtpCommandResult::Continue),
                     }
-                } else if line.to_uppercase().starts_with("QUIT") {
-                    self.write_line("221 Bye").await?;
-                    Ok(SmtpCommandResult::Quit)
                 } else {
-                    self.write_line("503 Bad sequence of commands (expected DATA or RCPT TO)")
-                        .await?;
-                    Ok(SmtpCommandResult::Continue)
+                    self.reject_command(line, "DATA or RCPT TO").await
                 }
             }
-            SmtpState::Data => {
-                // Expect email content lines or the end-of-data marker ".".
-                if line == "." {
-                    // Caller responds (250 OK, 550, 552, etc.) based on parse + policy.
-                    self.state = SmtpState::Greeted;
-                    Ok(SmtpCommandResult::DataEnd)
-                } else {
-                    // Pass the line content up to the caller.
-                    Ok(SmtpCommandResult::DataLine(line.to_string()))
+            SmtpState::Data => Ok(self.process_data_line(line.as_bytes())),
+            // Only BDAT (handled above) may follow a non-final chunk.
+            SmtpState::Bdat => self.reject_command(line, "BDAT").await,
+        }
+    }
+
+    /// Handles the commands RFC 5321 §4.1.4 allows outside a fixed point of
+    /// the transaction: HELO/EHLO or LHLO (which also reset it), RSET, NOOP, VRFY,
+    /// HELP and QUIT, plus BDAT, whose chunk must be consumed in every state
+    /// or its payload would be read as commands. Returns `None` for anything
+    /// else.
+    async fn process_session_command(&mut self, line: &str) -> Result<Option<SmtpCommandResult>> {
+        let verb = line.split_whitespace().next().unwrap_or("").to_uppercase();
+        // RFC 2920 §3.1 also ends a pipelined group at the greeting, NOOP and
+        // VRFY. QUIT is left out: it closes the session either way, and
+        // whatever follows it is simply never read.
+        if matches!(verb.as_str(), "HELO" | "EHLO" | "LHLO" | "NOOP" | "VRFY")
+            && self.has_pending_input().await
+        {
+            return self.reject_pipelining().await.map(Some);
+        }
+        let result = match verb.as_str() {
+            "HELO" | "EHLO" if self.lmtp => {
+                self.write_line("500 5.5.1 This is an LMTP server; use LHLO")
+                    .await?;
+                SmtpCommandResult::Continue
+            }
+            "LHLO" if self.lmtp => self.ehlo(line).await?,
+            "HELO" => {
+                let domain = line.split_whitespace().nth(1).unwrap_or("client");
+                let reply = format!("250 {}", self.identity.hostname);
+                self.write_line(&reply).await?;
+                self.reset_transaction();
+                SmtpCommandResult::Helo(domain.to_string())
+            }
+            "EHLO" => self.ehlo(line).await?,
+            "RSET" => {
+                self.write_line("250 2.0.0 OK").await?;
+                // RSET before HELO/EHLO leaves the session un-greeted.
+                if self.state != SmtpState::Initial {
+                    self.reset_transaction();
+                }
+                SmtpCommandResult::Reset
+            }
+            "NOOP" => {
+                self.write_line("250 2.0.0 OK").await?;
+                SmtpCommandResult::Continue
+            }
+            "VRFY" => {
+                // Never confirm or deny mailboxes (RFC 5321 §7.3).
+                self.write_line(
+                    "252 2.1.5 Cannot VRFY user, but will accept message and attempt delivery",
+                )
+                .await?;
+                SmtpCommandResult::Continue
+            }
+            "HELP" => {
+                // Lists what this session would accept, as `ehlo` advertises.
+                let mut commands = vec![
+                    if self.lmtp { "LHLO" } else { "EHLO HELO" },
+                    "MAIL RCPT DATA BDAT RSET NOOP VRFY HELP QUIT",
+                ];
+                if self.xclient_enabled {
+                    commands.push("XCLIENT XFORWARD");
+                }
+                if !self.tls_active {
+                    commands.push("STARTTLS");
+                } else if self.auth_enabled {
+                    commands.push("AUTH");
+                }
+                self.write_line(&format!("214 2.0.0 Commands: {}", commands.join(" ")))
+                    .await?;
+                SmtpCommandResult::Continue
+            }
+            "QUIT" => {
+                self.write_line("221 2.0.0 Bye").await?;
+                SmtpCommandResult::Quit
+            }
+            "BDAT" => return self.process_bdat(line).await.map(Some),
+            "XCLIENT" | "XFORWARD" => return self.process_forwarded(&verb, line).await.map(Some),
+            _ => return Ok(None),
+        };
+        Ok(Some(result))
+    }
+
+    /// Answers EHLO (or LHLO), advertising SIZE, PIPELINING, 8BITMIME,
+    /// SMTPUTF8, DSN, CHUNKING, ENHANCEDSTATUSCODES, XCLIENT/XFORWARD to
+    /// trusted relays and either STARTTLS (before TLS) or AUTH (after).
+    async fn ehlo(&mut self, line: &str) -> Result<SmtpCommandResult> {
+        let domain = line.split_whitespace().nth(1).unwrap_or("client");
+        let mut lines = vec![
+            format!("{} greets {}", self.identity.hostname, domain),
+            format!("SIZE {}", self.max_message_size_bytes),
+            "PIPELINING".to_string(),
+            "8BITMIME".to_string(),
+            "SMTPUTF8".to_string(),
+            "DSN".to_string(),
+            "CHUNKING".to_string(),
+            "ENHANCEDSTATUSCODES".to_string(),
+        ];
+        if self.xclient_enabled {
+            lines.push(format!("XCLIENT {}", FORWARDED_ATTRIBUTES));
+            lines.push(format!("XFORWARD {}", FORWARDED_ATTRIBUTES));
+        }
+        if !self.tls_active {
+            lines.push("STARTTLS".to_string());
+        } else if self.auth_enabled {
+            lines.push("AUTH PLAIN LOGIN".to_string());
+        }
+        self.write_multiline(250, &lines).await?;
+        self.reset_transaction();
+        Ok(SmtpCommandResult::Helo(domain.to_string()))
+    }
+
+    /// Abandons the current transaction and returns to `Greeted`. Also used
+    /// by the session loop when it refuses a `MAIL FROM` the protocol layer
+    /// had accepted.
+    pub fn reset_transaction(&mut self) {
+        self.state = SmtpState::Greeted;
+        self.smtputf8 = false;
+        self.bdat_received = 0;
+        self.declared_size = None;
+        self.mail_dsn = DsnRequest::default();
+    }
+
+    /// Handles Postfix `XCLIENT` and `XFORWARD` from a trusted front-end.
+    /// Both are refused inside a mail transaction. `XCLIENT` restarts the
+    /// session as if the named client had connected: the greeting is sent
+    /// again and the relay must repeat EHLO. `XFORWARD` is answered `250`.
+    async fn process_forwarded(&mut self, verb: &str, line: &str) -> Result<SmtpCommandResult> {
+        if !self.xclient_enabled {
+            self.write_line("550 5.7.0 Insufficient authorization")
+                .await?;
+            return Ok(SmtpCommandResult::Continue);
+        }
+        if !matches!(self.state, SmtpState::Initial | SmtpState::Greeted) {
+            self.write_line(&format!(
+                "503 5.5.1 {} not allowed in a mail transaction",
+                verb
+            ))
+            .await?;
+            return Ok(SmtpCommandResult::Continue);
+        }
+        let args = line.split_once(char::is_whitespace).map_or("", |(_, a)| a);
+        let Some(client) = ForwardedClient::parse(args) else {
+            self.write_line(&format!("501 5.5.4 Syntax: {} attribute=value ...", verb))
+                .await?;
+            return Ok(SmtpCommandResult::Continue);
+        };
+        if verb == "XCLIENT" {
+            self.reset_transaction();
+            self.state = SmtpState::Initial;
+            self.send_greeting().await?;
+        } else {
+            self.write_line("250 2.0.0 OK").await?;
+        }
+        Ok(SmtpCommandResult::Forwarded(client))
+    }
+
+    /// Answers a command the current state does not accept: `503` for a known
+    /// command sent out of order, `500` for anything else.
+    async fn reject_command(&mut self, line: &str, expected: &str) -> Result<SmtpCommandResult> {
+        let verb = line.split([' ', ':']).next().unwrap_or("").to_uppercase();
+        if KNOWN_COMMANDS.contains(&verb.as_str()) {
+            self.write_line(&format!(
+                "503 5.5.1 Bad sequence of commands (expected {})",
+                expected
+            ))
+            .await?;
+        } else {
+            self.write_line("500 5.5.2 Command not recognized").await?;
+        }
+        Ok(SmtpCommandResult::Continue)
+    }
+
+    /// Handles `BDAT <size> [LAST]` (RFC 3030): reads exactly `size` bytes of
+    /// message content following the command. Chunks are only accepted after
+    /// `RCPT TO`; otherwise the bytes are discarded and `503` returned. A
+    /// chunk that takes the message past `max_message_size_bytes` is drained
+    /// without buffering and ends the transaction.
+    async fn process_bdat(&mut self, line: &str) -> Result<SmtpCommandResult> {
+        let mut args = line.split_whitespace().skip(1);
+        let size = args.next().and_then(|n| n.parse::<u64>().ok());
+        let last = match args.next() {
+            None => Some(false),
+            Some(arg) if arg.eq_ignore_ascii_case("LAST") => Some(true),
+            Some(_) => None,
+        };
+        let (Some(size), Some(last), None) = (size, last, args.next()) else {
+            self.write_line("501 5.5.4 Syntax: BDAT <size> [LAST]")
+                .await?;
+            return Ok(SmtpCommandResult::Continue);
+        };
+
+        if !matches!(self.state, SmtpState::RcptTo | SmtpState::Bdat) {
+            self.discard_bytes(size).await?;
+            self.write_line("503 5.5.1 Bad sequence of commands (BDAT requires RCPT TO)")
+                .await?;
+            return Ok(SmtpCommandResult::Continue);
+        }
+
+        let total = self.bdat_received.saturating_add(size);
+        if total > self.max_message_size_bytes {
+            warn!(
+                "BDAT chunk takes message past max_message_size_bytes ({} > {}); discarding.",
+                total, self.max_message_size_bytes
+            );
+            self.discard_bytes(size).await?;
+            self.bdat_received = 0;
+            self.state = SmtpState::Greeted;
+            return Ok(SmtpCommandResult::BdatChunk { data: None, last });
+        }
+
+        let mut data = Vec::with_capacity(size as usize);
+        let deadline = self.deadline(TimeoutPhase::Data);
+        let read = match with_deadline(
+            deadline,
+            (&mut self.reader).take(size).read_to_end(&mut data),
+        )
+        .await
+        {
+            Ok(read) => read?,
+            Err(phase) => return Err(self.time_out(phase).await),
+        };
+        if (read as u64) < size {
+            return Err(anyhow::anyhow!(
+                "Connection closed after {} of {} BDAT bytes",
+                read,
+                size
+            ));
+        }
+        debug!("SMTP Read: BDAT chunk of {} bytes (last: {})", size, last);
+
+        if last {
+            self.bdat_received = 0;
+            self.state = SmtpState::Greeted;
+        } else {
+            self.bdat_received = total;
+            self.state = SmtpState::Bdat;
+        }
+        Ok(SmtpCommandResult::BdatChunk {
+            data: Some(data),
+            last,
+        })
+    }
+
+    /// Reads and drops `size` bytes of a rejected BDAT chunk to stay in sync
+    /// with the client.
+    async fn discard_bytes(&mut self, size: u64) -> Result<()> {
+        let deadline = self.deadline(TimeoutPhase::Data);
+        let drained = match with_deadline(
+            deadline,
+            tokio::io::copy(&mut (&mut self.reader).take(size), &mut tokio::io::sink()),
+        )
+        .await
+        {
+            Ok(drained) => drained?,
+            Err(phase) => return Err(self.time_out(phase).await),
+        };
+        if drained < size {
+            return Err(anyhow::anyhow!(
+                "Connection closed after {} of {} BDAT bytes",
+                drained,
+                size
+            ));
+        }
+        Ok(())
+    }
+
+    /// Processes a raw line as read by [`Self::read_raw_line`]. DATA content
+    /// is passed through byte for byte (8BITMIME, RFC 6152); command lines
+    /// are decoded as UTF-8 (SMTPUTF8, RFC 6531) and handed to
+    /// [`Self::process_command`].
+    pub async fn process_line(&mut self, line: &[u8]) -> Result<SmtpCommandResult> {
+        if self.state == SmtpState::Data {
+            return Ok(self.process_data_line(line));
+        }
+        self.process_command(&String::from_utf8_lossy(line)).await
+    }
+
+    fn process_data_line(&mut self, line: &[u8]) -> SmtpCommandResult {
+        // Expect email content lines or the end-of-data marker ".".
+        if line == b"." {
+            // Caller responds (250 OK, 550, 552, etc.) based on parse + policy.
+            self.state = SmtpState::Greeted;
+            SmtpCommandResult::DataEnd
+        } else {
+            // Pass the line content up to the caller.
+            SmtpCommandResult::DataLine(line.to_vec())
+        }
+    }
+
+    /// Validates a `MAIL FROM` command and returns the sender address, or
+    /// answers the error and returns `None`.
+    async fn mail_from_path(&mut self, line: &str) -> Result<Option<String>> {
+        let Some((_path, params)) = split_path(line) else {
+            self.write_line("501 5.5.4 Syntax error in MAIL FROM parameters")
+                .await?;
+            return Ok(None);
+        };
+        self.smtputf8 = false;
+        self.bdat_received = 0;
+        self.declared_size = None;
+        self.mail_dsn = DsnRequest::default();
+        for param in params.split_whitespace() {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let accepted = match key.to_uppercase().as_str() {
+                "BODY" => {
+                    value.eq_ignore_ascii_case("7BIT") || value.eq_ignore_ascii_case("8BITMIME")
+                }
+                "SMTPUTF8" if value.is_empty() => {
+                    self.smtputf8 = true;
+                    true
+                }
+                "SIZE" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                    // Too many digits for a u64 is over any limit.
+                    self.declared_size = Some(value.parse().unwrap_or(u64::MAX));
+                    true
+                }
+                "AUTH" => !value.is_empty(),
+                "RET" => {
+                    self.mail_dsn.ret = Ret::parse(value);
+                    self.mail_dsn.ret.is_some()
+                }
+                "ENVID" => {
+                    self.mail_dsn.envid = dsn::parse_envid(value);
+                    self.mail_dsn.envid.is_some()
+                }
+                _ => false,
+            };
+            if !accepted {
+                self.write_line(&format!(
+                    "555 5.5.4 MAIL FROM parameter not recognized: {}",
+                    param
+                ))
+                .await?;
+                return Ok(None);
+            }
+        }
+        let address = self.checked_address(line, "MAIL FROM").await?;
+        if address.is_some()
+            && self
+                .declared_size
+                .is_some_and(|size| size > self.max_message_size_bytes)
+        {
+            // RFC 1870 §6.1: refuse now rather than after the whole message
+            // has been streamed in.
+            self.write_line("552 5.3.4 Message size exceeds fixed limit")
+                .await?;
+            return Ok(None);
+        }
+        Ok(address)
+    }
+
+    /// Validates a `RCPT TO` command and returns the recipient address, or
+    /// answers the error and returns `None`.
+    async fn rcpt_to_path(&mut self, line: &str) -> Result<Option<String>> {
+        let Some((_path, params)) = split_path(line) else {
+            self.write_line("501 5.5.4 Syntax error in RCPT TO parameters")
+                .await?;
+            return Ok(None);
+        };
+        let mut rcpt_dsn = DsnRecipient::default();
+        for param in params.split_whitespace() {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let accepted = match key.to_uppercase().as_str() {
+                "NOTIFY" => {
+                    rcpt_dsn.notify = Notify::parse_list(value).unwrap_or_default();
+                    !rcpt_dsn.notify.is_empty()
                 }
+                "ORCPT" => {
+                    rcpt_dsn.orcpt = dsn::parse_orcpt(value);
+                    rcpt_dsn.orcpt.is_some()
+                }
+                _ => false,
+            };
+            if !accepted {
+                self.write_line(&format!(
+                    "555 5.5.4 RCPT TO parameter not recognized: {}",
+                    param
+                ))
+                .await?;
+                return Ok(None);
+            }
+        }
+        let address = self.checked_address(line, "RCPT TO").await?;
+        if let Some(recipient) = &address {
+            rcpt_dsn.recipient = recipient.clone();
+            self.rcpt_dsn = rcpt_dsn;
+        }
+        Ok(address)
+    }
+
+    /// Extracts the address from `line`. Non-ASCII addresses are accepted
+    /// only when the transaction declared `SMTPUTF8` (RFC 6531 §3.5).
+    async fn checked_address(&mut self, line: &str, command: &str) -> Result<Option<String>> {
+        match self.extract_email(line) {
+            Some(email) if !email.is_ascii() && !self.smtputf8 => {
+                self.write_line("553 5.6.7 Non-ASCII address requires SMTPUTF8")
+                    .await?;
+                Ok(None)
+            }
+            Some(email) => Ok(Some(email)),
+            None => {
+                self.write_line(&format!("501 5.5.4 Syntax error in {} parameters", command))
+                    .await?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Runs the SASL exchange for `AUTH PLAIN` / `AUTH LOGIN` (RFC 4954,
+    /// RFC 4616) and returns the decoded credentials. Failures of the exchange
+    /// itself are answered here; the caller verifies the credentials and
+    /// sends `235` or `535`.
+    async fn process_auth(&mut self, line: &str) -> Result<SmtpCommandResult> {
+        if !self.auth_enabled {
+            self.write_line("502 5.5.1 AUTH not supported").await?;
+            return Ok(SmtpCommandResult::Continue);
+        }
+        if !self.tls_active {
+            self.write_line(
+                "538 5.7.11 Encryption required for requested authentication mechanism",
+            )
+            .await?;
+            return Ok(SmtpCommandResult::Continue);
+        }
+        if self.authenticated {
+            self.write_line("503 5.5.1 Already authenticated").await?;
+            return Ok(SmtpCommandResult::Continue);
+        }
+
+        let mut parts = line.split_whitespace().skip(1);
+        let mechanism = parts.next().unwrap_or("").to_uppercase();
+        let initial = parts.next().map(str::to_string);
+
+        let credentials = match mechanism.as_str() {
+            "PLAIN" => {
+                let response = match initial {
+                    Some(r) => r,
+                    None => match self.auth_challenge("").await? {
+                        Some(r) => r,
+                        None => return self.auth_cancelled().await,
+                    },
+                };
+                decode_b64(&response).and_then(|decoded| parse_plain(&decoded))
+            }
+            "LOGIN" => {
+                let username = match initial {
+                    Some(r) => r,
+                    // "Username:"
+                    None => match self.auth_challenge("VXNlcm5hbWU6").await? {
+                        Some(r) => r,
+                        None => return self.auth_cancelled().await,
+                    },
+                };
+                // "Password:"
+                let password = match self.auth_challenge("UGFzc3dvcmQ6").await? {
+                    Some(r) => r,
+                    None => return self.auth_cancelled().await,
+                };
+                decode_b64(&username).zip(decode_b64(&password))
+            }
+            _ => {
+                self.write_line("504 5.5.4 Unrecognized authentication type")
+                    .await?;
+                return Ok(SmtpCommandResult::Continue);
+            }
+        };
+
+        match credentials {
+            Some((username, password)) => Ok(SmtpCommandResult::Auth { username, password }),
+            None => {
+                self.write_line("501 5.5.2 Cannot decode authentication response")
+                    .await?;
+                Ok(SmtpCommandResult::Continue)
             }
         }
     }
 
-    /// Reads a single line (terminated by CRLF) from the client stream.
+    /// Answers a command that must end a pipelined group (RFC 2920 §3.1) but
+    /// arrived with more input already queued behind it. The session is closed:
+    /// the queued bytes cannot be interpreted safely.
+    async fn reject_pipelining(&mut self) -> Result<SmtpCommandResult> {
+        warn!("Client sent commands after a pipelining synchronization point; closing.");
+        self.write_line("554 5.5.0 Improper use of SMTP command pipelining")
+            .await?;
+        Ok(SmtpCommandResult::Close)
+    }
+
+    async fn auth_cancelled(&mut self) -> Result<SmtpCommandResult> {
+        self.write_line("501 5.0.0 Authentication cancelled")
+            .await?;
+        Ok(SmtpCommandResult::Continue)
+    }
+
+    /// Sends a `334` challenge and reads the client's response. Returns `None`
+    /// when the client cancels with `*`. The response is not logged.
+    async fn auth_challenge(&mut self, challenge: &str) -> Result<Option<String>> {
+        self.write_line(&format!("334 {}", challenge)).await?;
+        let mut buffer = Vec::new();
+        let deadline = self.deadline(TimeoutPhase::Command);
+        match with_deadline(deadline, async {
+            self.flush_if_idle().await?;
+            Ok::<_, anyhow::Error>(self.reader.read_until(b'\n', &mut buffer).await?)
+        })
+        .await
+        {
+            Ok(read) => read?,
+            Err(phase) => return Err(self.time_out(phase).await),
+        };
+        let response = String::from_utf8_lossy(&buffer);
+        let response = response.trim_end_matches(['\r', '\n']);
+        if response == "*" {
+            Ok(None)
+        } else {
+            Ok(Some(response.to_string()))
+        }
+    }
+
+    /// Reads a single line from the client stream without decoding it, so
+    /// 8-bit DATA content survives unchanged. Strips the line terminator
+    /// (CRLF, or a bare LF) and returns `None` if the connection is closed
+    /// (EOF).
     ///
-    /// Returns an empty string if the connection is closed (EOF).
-    /// Trims the trailing CRLF from the returned string.
-    pub async fn read_line(&mut self) -> Result<String> {
-        let mut buffer = String::new();
-        // Read until \n, including the delimiter.
-        let bytes_read = self.reader.read_line(&mut buffer).await?;
+    /// Queued replies are flushed first unless the client has already sent
+    /// the next command, so a pipelined group is answered in one write.
+    pub async fn read_raw_line(&mut self) -> Result<Option<Vec<u8>>> {
+        let phase = if self.awaiting_first_command {
+            TimeoutPhase::Greeting
+        } else if matches!(self.state, SmtpState::Data | SmtpState::Bdat) {
+            TimeoutPhase::Data
+        } else {
+            TimeoutPhase::Command
+        };
+        let mut buffer = Vec::new();
+        let deadline = self.deadline(phase);
+        // Read until \n, including the delimiter. The flush is covered too:
+        // a client that stops reading must not stall the session either.
+        let bytes_read = match with_deadline(deadline, async {
+            self.flush_if_idle().await?;
+            Ok::<_, anyhow::Error>(self.reader.read_until(b'\n', &mut buffer).await?)
+        })
+        .await
+        {
+            Ok(read) => read?,
+            Err(phase) => return Err(self.time_out(phase).await),
+        };
+        self.awaiting_first_command = false;
 
         if bytes_read == 0 {
             // Connection closed by peer.
-            Ok(String::new())
-        } else {
-            // Trim trailing CRLF or LF before returning.
-            // Use array pattern suggested by clippy for conciseness
-            let line = buffer.trim_end_matches(['\r', '\n']).to_string();
-            debug!("SMTP Read: {}", line);
-            Ok(line)
+            return Ok(None);
+        }
+        if buffer.last() == Some(&b'\n') {
+            buffer.pop();
+            if buffer.last() == Some(&b'\r') {
+                buffer.pop();
+            }
         }
+        debug!(
+            "SMTP Read: {}",
+            redact_auth(&String::from_utf8_lossy(&buffer))
+        );
+        Ok(Some(buffer))
+    }
+
+    /// The deadline for a read in `phase`, if timeouts are configured.
+    fn deadline(&self, phase: TimeoutPhase) -> Option<(Instant, TimeoutPhase)> {
+        self.timeouts.map(|t| t.deadline(phase))
+    }
+
+    /// Answers a missed deadline with `421 4.4.2` and returns the
+    /// [`ReadTimeout`] error that ends the session. The reply is best effort:
+    /// a client that stopped reading is not waited on for long.
+    async fn time_out(&mut self, phase: TimeoutPhase) -> anyhow::Error {
+        let reply = format!(
+            "421 4.4.2 {} timeout, closing connection",
+            match phase {
+                TimeoutPhase::Greeting => "Greeting",
+                TimeoutPhase::Command => "Command",
+                TimeoutPhase::Data => "Data",
+                TimeoutPhase::Session => "Session",
+            }
+        );
+        let _ = tokio::time::timeout(TIMEOUT_REPLY_GRACE, async {
+            self.write_line(&reply).await?;
+            self.flush().await
+        })
+        .await;
+        ReadTimeout(phase).into()
     }
 
     /// Writes a single line (appending CRLF) to the client stream.
     ///
-    /// Flushes the write buffer to ensure the line is sent immediately.
+    /// The line is only queued; it goes out on the next [`Self::flush`],
+    /// which `read_raw_line` performs before it would block (RFC 2920 §3.2).
     pub async fn write_line(&mut self, line: &str) -> Result<()> {
         debug!("SMTP Write: {}", line);
         self.writer
             .write_all(format!("{}\r\n", line).as_bytes())
             .await?;
-        self.writer.flush().await?; // Ensure data is sent over the network.
+        Ok(())
+    }
+
+    /// Sends all queued replies. Callers must flush before closing the
+    /// connection or handing the stream to a TLS handshake.
+    pub async fn flush(&mut self) -> Result<()> {
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    /// Flushes queued replies unless the next command is already buffered.
+    async fn flush_if_idle(&mut self) -> Result<()> {
+        if !self.has_pending_input().await {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` when client input is available without waiting, i.e.
+    /// the client pipelined further commands behind the current one.
+    ///
+    /// Best effort: only bytes that have already arrived are seen, so a
+    /// command still in flight behind a synchronization point goes unnoticed.
+    async fn has_pending_input(&mut self) -> bool {
+        std::future::poll_fn(|cx| {
+            Poll::Ready(matches!(
+                Pin::new(&mut self.reader).poll_fill_buf(cx),
+                Poll::Ready(Ok(buf)) if !buf.is_empty()
+            ))
+        })
+        .await
+    }
+
+    /// Writes a multiline reply: every line but the last carries `<code>-`,
+    /// the last `<code> ` (RFC 5321 §4.2.1).
+    pub async fn write_multiline(&mut self, code: u16, lines: &[String]) -> Result<()> {
+        for (i, text) in lines.iter().enumerate() {
+            let sep = if i + 1 == lines.len() { ' ' } else { '-' };
+            self.write_line(&format!("{}{}{}", code, sep, text)).await?;
+        }
         Ok(())
     }
 
@@ -249,10 +1089,11 @@ where
     ///
     /// Uses `mailparse::addrparse` to robustly handle addresses with or without
     /// display names, enclosed in angle brackets or not (within the command syntax).
-    /// Expects input like "MAIL FROM:<user@example.com>" or "RCPT TO:<Name <user@example.com>>".
+    /// Expects input like "MAIL FROM:<user@example.com>" or "RCPT TO:<Name <user@example.com>>";
+    /// ESMTP parameters after the path are ignored.
     fn extract_email(&self, line: &str) -> Option<String> {
-        // Find the colon separating the command verb from the address part.
-        let addr_part = line.split_once(':').map(|(_cmd, addr)| addr.trim());
+        // Separate the path from the command verb and any ESMTP parameters.
+        let addr_part = split_path(line).map(|(path, _params)| path);
 
         addr_part.and_then(|addr_spec| {
             // Remove outer angle brackets if present, as addrparse expects the raw address spec.
@@ -296,6 +1137,135 @@ where
     }
 }
 
+/// Splits a `MAIL FROM:` / `RCPT TO:` command into the path and the ESMTP
+/// parameters that follow it (RFC 5321 §4.1.2). Angle brackets are matched
+/// so a display name inside the path (`<Name <user@host>>`) stays intact.
+fn split_path(line: &str) -> Option<(&str, &str)> {
+    let (_cmd, rest) = line.split_once(':')?;
+    let rest = rest.trim();
+    if rest.starts_with('<') {
+        let mut depth = 0usize;
+        for (i, c) in rest.char_indices() {
+            match c {
+                '<' => depth += 1,
+                '>' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some((&rest[..=i], rest[i + 1..].trim()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Unbalanced brackets: let the address parser reject it.
+        Some((rest, ""))
+    } else {
+        Some(match rest.split_once(char::is_whitespace) {
+            Some((path, params)) => (path, params.trim()),
+            None => (rest, ""),
+        })
+    }
+}
+
+/// Decodes a base64 SASL response into UTF-8. `=` is the empty response.
+fn decode_b64(response: &str) -> Option<String> {
+    if response == "=" {
+        return Some(String::new());
+    }
+    BASE64
+        .decode(response)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+}
+
+/// Splits a decoded `PLAIN` message (`authzid NUL authcid NUL passwd`,
+/// RFC 4616) into username and password. A non-empty authzid must equal the
+/// authcid — acting on behalf of another identity is not supported.
+fn parse_plain(decoded: &str) -> Option<(String, String)> {
+    let mut fields = decoded.split('\0');
+    let authzid = fields.next()?;
+    let authcid = fields.next()?;
+    let passwd = fields.next()?;
+    if fields.next().is_some() || authcid.is_empty() {
+        return None;
+    }
+    if !authzid.is_empty() && authzid != authcid {
+        return None;
+    }
+    Some((authcid.to_string(), passwd.to_string()))
+}
+
+/// Masks the SASL initial response in an `AUTH` line so credentials never
+/// reach the logs. Other lines are returned unchanged.
+pub fn redact_auth(line: &str) -> String {
+    let mut parts = line.splitn(3, ' ');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(verb), Some(mechanism), Some(_)) if verb.eq_ignore_ascii_case("AUTH") => {
+            format!("{} {} <redacted>", verb, mechanism)
+        }
+        _ => line.to_string(),
+    }
+}
+
+/// Attributes accepted in `XCLIENT` and `XFORWARD`, as advertised in EHLO.
+const FORWARDED_ATTRIBUTES: &str = "ADDR NAME HELO PROTO";
+
+/// The original client described by a trusted front-end MTA through
+/// `XCLIENT` or `XFORWARD`. Attributes that were omitted, or sent as
+/// `[UNAVAILABLE]` / `[TEMPUNAVAIL]`, are `None` and leave the current
+/// value in place.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForwardedClient {
+    /// `ADDR`: the client's IP address (`IPV6:` prefix accepted).
+    pub addr: Option<IpAddr>,
+    /// `NAME`: the client's verified reverse DNS name.
+    pub name: Option<String>,
+    /// `HELO`: the HELO/EHLO domain the client sent.
+    pub helo: Option<String>,
+    /// `PROTO`: `SMTP` or `ESMTP`.
+    pub proto: Option<String>,
+}
+
+impl ForwardedClient {
+    /// Parses space-separated `NAME=xtext` attributes. Returns `None` when
+    /// the list is empty, an attribute is unknown, or a value is malformed.
+    fn parse(args: &str) -> Option<Self> {
+        let mut client = ForwardedClient::default();
+        let mut any = false;
+        for attr in args.split_whitespace() {
+            let (name, value) = attr.split_once('=')?;
+            let value = dsn::xtext_decode(value)?;
+            any = true;
+            let value = match value.as_str() {
+                "[UNAVAILABLE]" | "[TEMPUNAVAIL]" => None,
+                _ => Some(value),
+            };
+            match name.to_uppercase().as_str() {
+                "ADDR" => {
+                    client.addr = match value {
+                        Some(v) => Some(parse_forwarded_addr(&v)?),
+                        None => None,
+                    }
+                }
+                "NAME" => client.name = value,
+                "HELO" => client.helo = value,
+                "PROTO" => client.proto = value,
+                _ => return None,
+            }
+        }
+        any.then_some(client)
+    }
+}
+
+/// Parses an `ADDR` value; Postfix writes IPv6 addresses as `IPV6:2001:db8::1`.
+fn parse_forwarded_addr(value: &str) -> Option<IpAddr> {
+    let bare = match value.get(..5) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("IPV6:") => &value[5..],
+        _ => value,
+    };
+    bare.parse().ok()
+}
+
 /// Represents the outcome of processing a single SMTP command line.
 ///
 /// This enum signals to the connection handler what action resulted from
@@ -306,6 +1276,8 @@ pub enum SmtpCommandResult {
     Continue,
     /// QUIT command received, connection should be closed.
     Quit,
+    /// RSET command received; the caller discards the current transaction.
+    Reset,
     /// HELO/EHLO command processed, contains the domain claimed by the client
     /// (or `"client"` when the domain was omitted, matching the EHLO reply fallback).
     /// Needed by SPF verification, which signs over the HELO identity.
@@ -316,12 +1288,26 @@ pub enum SmtpCommandResult {
     RcptTo(String),
     /// DATA command received, client will start sending email content.
     DataStart,
-    /// A line of email content received during the DATA state.
-    DataLine(String),
+    /// A line of email content received during the DATA state, exactly as
+    /// received minus the line terminator.
+    DataLine(Vec<u8>),
     /// End-of-data marker (`.`) received, email content finished.
     DataEnd,
+    /// A `BDAT` chunk was read. `data` is `None` when the chunk took the
+    /// message past `max_message_size_bytes`; the transaction is then over.
+    /// With `last` set the message is complete.
+    BdatChunk { data: Option<Vec<u8>>, last: bool },
     /// STARTTLS command received, server should initiate TLS handshake.
     StartTls,
+    /// The reply has been written and the connection must be closed (e.g.
+    /// a pipelining synchronization violation).
+    Close,
+    /// AUTH exchange completed; the caller verifies the credentials, replies
+    /// `235` or `535`, and calls [`SmtpProtocol::mark_authenticated`] on success.
+    Auth { username: String, password: String },
+    /// A trusted front-end sent `XCLIENT` or `XFORWARD`; the caller replaces
+    /// the session's peer address and HELO with the forwarded values.
+    Forwarded(ForwardedClient),
 }
 #[cfg(test)]
 mod tests {
@@ -334,128 +1320,929 @@ mod tests {
         let reader = BufReader::new(io::empty());
         let writer = BufWriter::new(io::sink());
 
-        // Now calling the generic `new` function
-        SmtpProtocol::new(reader, writer, 26_214_400)
+        // Now calling the generic `new` function
+        SmtpProtocol::new(reader, writer, 26_214_400)
+    }
+
+    // Test existing HELO behavior for baseline
+    #[tokio::test]
+    async fn test_initial_helo_sets_greeted() {
+        let mut protocol = create_test_protocol();
+        assert_eq!(protocol.get_state(), SmtpState::Initial);
+        // We assume write_line succeeds internally for state tests
+        let result = protocol.process_command("HELO example.com").await.unwrap();
+        assert!(matches!(result, SmtpCommandResult::Helo(ref d) if d == "example.com"));
+        assert_eq!(protocol.get_state(), SmtpState::Greeted);
+    }
+
+    // Test existing EHLO behavior for baseline
+    #[tokio::test]
+    async fn test_initial_ehlo_sets_greeted() {
+        let mut protocol = create_test_protocol();
+        assert_eq!(protocol.get_state(), SmtpState::Initial);
+        // The actual response lines for EHLO will be modified later to include STARTTLS
+        let result = protocol.process_command("EHLO example.com").await.unwrap();
+        assert!(matches!(result, SmtpCommandResult::Helo(ref d) if d == "example.com"));
+        assert_eq!(protocol.get_state(), SmtpState::Greeted);
+    }
+
+    // Test STARTTLS command in the correct state (Greeted)
+    #[tokio::test]
+    async fn test_greeted_starttls_accepted() {
+        let mut protocol = create_test_protocol();
+        // Manually set the state to Greeted for this test scenario
+        protocol.state = SmtpState::Greeted;
+        assert_eq!(protocol.get_state(), SmtpState::Greeted);
+
+        let result = protocol.process_command("STARTTLS").await.unwrap();
+
+        // Expect the StartTls command result
+        assert!(
+            matches!(result, SmtpCommandResult::StartTls),
+            "Expected StartTls result, got {:?}",
+            result
+        );
+        // The state should remain Greeted, as the handshake happens *after* this command response.
+        // The connection handler will take over for the TLS part.
+        assert_eq!(
+            protocol.get_state(),
+            SmtpState::Greeted,
+            "State should remain Greeted after STARTTLS command"
+        );
+    }
+
+    // Test STARTTLS command in an incorrect state (e.g., MailFrom)
+    #[tokio::test]
+    async fn test_mailfrom_starttls_rejected() {
+        let mut protocol = create_test_protocol();
+        protocol.state = SmtpState::MailFrom; // Set state manually
+        assert_eq!(protocol.get_state(), SmtpState::MailFrom);
+
+        let result = protocol.process_command("STARTTLS").await.unwrap();
+
+        // Expect a rejection (Continue means an error response was sent, loop continues)
+        assert!(
+            matches!(result, SmtpCommandResult::Continue),
+            "Expected Continue result for rejected STARTTLS, got {:?}",
+            result
+        );
+        // State should not change due to the invalid command sequence
+        assert_eq!(
+            protocol.get_state(),
+            SmtpState::MailFrom,
+            "State should remain MailFrom after rejected STARTTLS"
+        );
+    }
+
+    // Test STARTTLS command in another incorrect state (e.g., RcptTo)
+    #[tokio::test]
+    async fn test_rcptto_starttls_rejected() {
+        let mut protocol = create_test_protocol();
+        protocol.state = SmtpState::RcptTo; // Set state manually
+        assert_eq!(protocol.get_state(), SmtpState::RcptTo);
+
+        let result = protocol.process_command("STARTTLS").await.unwrap();
+
+        assert!(
+            matches!(result, SmtpCommandResult::Continue),
+            "Expected Continue result for rejected STARTTLS, got {:?}",
+            result
+        );
+        assert_eq!(
+            protocol.get_state(),
+            SmtpState::RcptTo,
+            "State should remain RcptTo after rejected STARTTLS"
+        );
+    }
+
+    // Test STARTTLS command during DATA phase (should be treated as data)
+    #[tokio::test]
+    async fn test_data_starttls_is_data() {
+        let mut protocol = create_test_protocol();
+        protocol.state = SmtpState::Data; // Set state manually
+        assert_eq!(protocol.get_state(), SmtpState::Data);
+
+        let result = protocol.process_command("STARTTLS").await.unwrap();
+
+        // In DATA state, any line not "." is data
+        assert!(
+            matches!(result, SmtpCommandResult::DataLine(ref line) if line == b"STARTTLS"),
+            "Expected DataLine result, got {:?}",
+            result
+        );
+        assert_eq!(protocol.get_state(), SmtpState::Data);
+    }
+
+    // Test QUIT command works in Greeted state (important for STARTTLS flow)
+    #[tokio::test]
+    async fn test_greeted_quit() {
+        let mut protocol = create_test_protocol();
+        protocol.state = SmtpState::Greeted;
+        let result = protocol.process_command("QUIT").await.unwrap();
+        assert!(matches!(result, SmtpCommandResult::Quit));
+        // State doesn't technically matter after Quit, but it shouldn't change unexpectedly
+        assert_eq!(protocol.get_state(), SmtpState::Greeted);
+    }
+
+    // --- Require-TLS and secure sessions ---
+
+    #[tokio::test]
+    async fn test_require_tls_refuses_mail_from_in_plaintext() {
+        use std::io::Cursor;
+        let reader = BufReader::new(io::empty());
+        let output_buffer = Cursor::new(Vec::new());
+        let mut protocol =
+            SmtpProtocol::new(reader, output_buffer, 26_214_400).with_require_tls(true);
+        protocol.state = SmtpState::Greeted;
+
+        let result = protocol
+            .process_command("MAIL FROM:<user@example.com>")
+            .await
+            .unwrap();
+        assert!(matches!(result, SmtpCommandResult::Continue));
+        assert_eq!(protocol.get_state(), SmtpState::Greeted);
+
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert_eq!(written, "530 5.7.0 Must issue a STARTTLS command first\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_require_tls_allows_mail_from_over_tls() {
+        let mut protocol = create_test_protocol()
+            .with_tls_active(true)
+            .with_require_tls(true);
+        protocol.state = SmtpState::Greeted;
+
+        let result = protocol
+            .process_command("MAIL FROM:<user@example.com>")
+            .await
+            .unwrap();
+        assert!(
+            matches!(result, SmtpCommandResult::MailFrom(ref email) if email == "user@example.com")
+        );
+        assert_eq!(protocol.get_state(), SmtpState::MailFrom);
+    }
+
+    #[tokio::test]
+    async fn test_ehlo_over_tls_omits_starttls() {
+        use std::io::Cursor;
+        let reader = BufReader::new(io::empty());
+        let output_buffer = Cursor::new(Vec::new());
+        let mut protocol = SmtpProtocol::new(reader, output_buffer, 4242).with_tls_active(true);
+
+        protocol
+            .process_command("EHLO mail.example.org")
+            .await
+            .unwrap();
+
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert!(
+            !written.contains("STARTTLS"),
+            "EHLO over TLS must not advertise STARTTLS. Got: {}",
+            written
+        );
+        assert!(
+            written.ends_with("250-CHUNKING\r\n250 ENHANCEDSTATUSCODES\r\n"),
+            "ENHANCEDSTATUSCODES should be the final EHLO line over TLS. Got: {}",
+            written
+        );
+    }
+
+    #[tokio::test]
+    async fn test_starttls_over_tls_rejected() {
+        use std::io::Cursor;
+        let reader = BufReader::new(io::empty());
+        let output_buffer = Cursor::new(Vec::new());
+        let mut protocol =
+            SmtpProtocol::new(reader, output_buffer, 26_214_400).with_tls_active(true);
+        protocol.state = SmtpState::Greeted;
+
+        let result = protocol.process_command("STARTTLS").await.unwrap();
+        assert!(matches!(result, SmtpCommandResult::Continue));
+
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert!(written.starts_with("503"), "Got: {}", written);
+    }
+
+    // --- SMTP AUTH ---
+
+    fn create_auth_protocol(
+        input: &[u8],
+    ) -> SmtpProtocol<BufReader<std::io::Cursor<Vec<u8>>>, std::io::Cursor<Vec<u8>>> {
+        let reader = BufReader::new(std::io::Cursor::new(input.to_vec()));
+        let mut protocol = SmtpProtocol::new(reader, std::io::Cursor::new(Vec::new()), 26_214_400)
+            .with_tls_active(true)
+            .with_auth(true);
+        protocol.state = SmtpState::Greeted;
+        protocol
+    }
+
+    #[tokio::test]
+    async fn test_auth_plain_initial_response() {
+        let mut protocol = create_auth_protocol(b"");
+        // base64("\0scanner\0s3cret")
+        let result = protocol
+            .process_command("AUTH PLAIN AHNjYW5uZXIAczNjcmV0")
+            .await
+            .unwrap();
+        assert!(
+            matches!(result, SmtpCommandResult::Auth { ref username, ref password }
+                if username == "scanner" && password == "s3cret"),
+            "got {:?}",
+            result
+        );
+        assert_eq!(protocol.get_state(), SmtpState::Greeted);
+    }
+
+    #[tokio::test]
+    async fn test_auth_login_challenges() {
+        // base64("scanner"), base64("s3cret")
+        let mut protocol = create_auth_protocol(b"c2Nhbm5lcg==\r\nczNjcmV0\r\n");
+        let result = protocol.process_command("AUTH LOGIN").await.unwrap();
+        assert!(
+            matches!(result, SmtpCommandResult::Auth { ref username, ref password }
+                if username == "scanner" && password == "s3cret"),
+            "got {:?}",
+            result
+        );
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert_eq!(written, "334 VXNlcm5hbWU6\r\n334 UGFzc3dvcmQ6\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_auth_cancelled_by_client() {
+        let mut protocol = create_auth_protocol(b"*\r\n");
+        let result = protocol.process_command("AUTH PLAIN").await.unwrap();
+        assert!(matches!(result, SmtpCommandResult::Continue));
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert!(written.ends_with("501 5.0.0 Authentication cancelled\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_auth_refused_in_plaintext() {
+        let reader = BufReader::new(io::empty());
+        let mut protocol =
+            SmtpProtocol::new(reader, std::io::Cursor::new(Vec::new()), 26_214_400).with_auth(true);
+        protocol.state = SmtpState::Greeted;
+        let result = protocol
+            .process_command("AUTH PLAIN AHNjYW5uZXIAczNjcmV0")
+            .await
+            .unwrap();
+        assert!(matches!(result, SmtpCommandResult::Continue));
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert!(written.starts_with("538"), "Got: {}", written);
+    }
+
+    #[tokio::test]
+    async fn test_auth_rejected_after_success() {
+        let mut protocol = create_auth_protocol(b"");
+        protocol.mark_authenticated();
+        let result = protocol
+            .process_command("AUTH PLAIN AHNjYW5uZXIAczNjcmV0")
+            .await
+            .unwrap();
+        assert!(matches!(result, SmtpCommandResult::Continue));
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert!(written.starts_with("503"), "Got: {}", written);
+    }
+
+    #[tokio::test]
+    async fn test_ehlo_advertises_auth_only_over_tls() {
+        let mut protocol = create_auth_protocol(b"");
+        protocol.state = SmtpState::Initial;
+        protocol.process_command("EHLO client").await.unwrap();
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert!(
+            written.contains("250 AUTH PLAIN LOGIN\r\n"),
+            "Got: {}",
+            written
+        );
+
+        let reader = BufReader::new(io::empty());
+        let mut plaintext =
+            SmtpProtocol::new(reader, std::io::Cursor::new(Vec::new()), 26_214_400).with_auth(true);
+        plaintext.process_command("EHLO client").await.unwrap();
+        let written = String::from_utf8(plaintext.writer.get_ref().clone()).unwrap();
+        assert!(!written.contains("AUTH"), "Got: {}", written);
+    }
+
+    #[test]
+    fn test_parse_plain_rejects_foreign_authzid() {
+        assert_eq!(
+            parse_plain("\0user\0pw"),
+            Some(("user".to_string(), "pw".to_string()))
+        );
+        assert_eq!(
+            parse_plain("user\0user\0pw"),
+            Some(("user".to_string(), "pw".to_string()))
+        );
+        assert_eq!(parse_plain("admin\0user\0pw"), None);
+        assert_eq!(parse_plain("user-only"), None);
+    }
+
+    #[test]
+    fn test_redact_auth_masks_initial_response() {
+        assert_eq!(
+            redact_auth("AUTH PLAIN AHNjYW5uZXIAczNjcmV0"),
+            "AUTH PLAIN <redacted>"
+        );
+        assert_eq!(redact_auth("AUTH LOGIN"), "AUTH LOGIN");
+        assert_eq!(redact_auth("MAIL FROM:<a@b.c>"), "MAIL FROM:<a@b.c>");
+    }
+
+    // --- 8BITMIME / SMTPUTF8 and ESMTP parameters ---
+
+    #[tokio::test]
+    async fn test_ehlo_advertises_8bitmime_and_smtputf8() {
+        use std::io::Cursor;
+        let reader = BufReader::new(io::empty());
+        let mut protocol = SmtpProtocol::new(reader, Cursor::new(Vec::new()), 26_214_400);
+        protocol.process_command("EHLO client").await.unwrap();
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert!(written.contains("250-8BITMIME\r\n"), "Got: {}", written);
+        assert!(written.contains("250-SMTPUTF8\r\n"), "Got: {}", written);
+    }
+
+    #[tokio::test]
+    async fn test_mail_from_accepts_esmtp_parameters() {
+        let mut protocol = create_test_protocol();
+        protocol.state = SmtpState::Greeted;
+        let result = protocol
+            .process_command("MAIL FROM:<user@example.com> BODY=8BITMIME SIZE=1024 SMTPUTF8")
+            .await
+            .unwrap();
+        assert!(
+            matches!(result, SmtpCommandResult::MailFrom(ref email) if email == "user@example.com")
+        );
+        assert!(protocol.smtputf8);
+    }
+
+    #[tokio::test]
+    async fn test_mail_from_rejects_unknown_parameter() {
+        use std::io::Cursor;
+        let reader = BufReader::new(io::empty());
+        let mut protocol = SmtpProtocol::new(reader, Cursor::new(Vec::new()), 26_214_400);
+        protocol.state = SmtpState::Greeted;
+        for line in [
+            "MAIL FROM:<user@example.com> BODY=BINARYMIME",
+            "MAIL FROM:<user@example.com> FOO=bar",
+        ] {
+            let result = protocol.process_command(line).await.unwrap();
+            assert!(matches!(result, SmtpCommandResult::Continue), "{}", line);
+        }
+        assert_eq!(protocol.get_state(), SmtpState::Greeted);
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert_eq!(written.matches("555 5.5.4").count(), 2, "Got: {}", written);
+    }
+
+    #[tokio::test]
+    async fn test_non_ascii_address_requires_smtputf8() {
+        use std::io::Cursor;
+        let reader = BufReader::new(io::empty());
+        let mut protocol = SmtpProtocol::new(reader, Cursor::new(Vec::new()), 26_214_400);
+        protocol.state = SmtpState::Greeted;
+
+        let result = protocol
+            .process_command("MAIL FROM:<josé@example.com>")
+            .await
+            .unwrap();
+        assert!(matches!(result, SmtpCommandResult::Continue));
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert!(written.starts_with("553 5.6.7"), "Got: {}", written);
+
+        let result = protocol
+            .process_command("MAIL FROM:<josé@example.com> SMTPUTF8")
+            .await
+            .unwrap();
+        assert!(
+            matches!(result, SmtpCommandResult::MailFrom(ref email) if email == "josé@example.com")
+        );
+        let result = protocol
+            .process_command("RCPT TO:<用户@例子.广告>")
+            .await
+            .unwrap();
+        assert!(
+            matches!(result, SmtpCommandResult::RcptTo(ref email) if email == "用户@例子.广告")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_data_line_is_passed_through_byte_for_byte() {
+        let mut protocol = create_test_protocol();
+        protocol.state = SmtpState::Data;
+        let result = protocol.process_line(b"caf\xe9 \xff").await.unwrap();
+        assert!(matches!(result, SmtpCommandResult::DataLine(ref line) if line == b"caf\xe9 \xff"));
+        let result = protocol.process_line(b".").await.unwrap();
+        assert!(matches!(result, SmtpCommandResult::DataEnd));
+    }
+
+    #[test]
+    fn test_split_path_separates_parameters() {
+        assert_eq!(
+            split_path("MAIL FROM:<a@b.c> BODY=8BITMIME"),
+            Some(("<a@b.c>", "BODY=8BITMIME"))
+        );
+        assert_eq!(
+            split_path("MAIL FROM:<John <j@b.c>> SIZE=10"),
+            Some(("<John <j@b.c>>", "SIZE=10"))
+        );
+        assert_eq!(split_path("RCPT TO:a@b.c"), Some(("a@b.c", "")));
+        assert_eq!(split_path("RCPT TO"), None);
+    }
+
+    #[tokio::test]
+    async fn test_mail_from_size_over_limit_is_rejected_early() {
+        use std::io::Cursor;
+        let reader = BufReader::new(io::empty());
+        let mut protocol = SmtpProtocol::new(reader, Cursor::new(Vec::new()), 1_000);
+        protocol.state = SmtpState::Greeted;
+        for line in [
+            "MAIL FROM:<user@example.com> SIZE=1001",
+            "MAIL FROM:<user@example.com> SIZE=99999999999999999999999",
+        ] {
+            let result = protocol.process_command(line).await.unwrap();
+            assert!(matches!(result, SmtpCommandResult::Continue), "{}", line);
+            assert_eq!(protocol.get_state(), SmtpState::Greeted);
+        }
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert_eq!(
+            written
+                .matches("552 5.3.4 Message size exceeds fixed limit\r\n")
+                .count(),
+            2,
+            "Got: {}",
+            written
+        );
+
+        let result = protocol
+            .process_command("MAIL FROM:<user@example.com> SIZE=1000")
+            .await
+            .unwrap();
+        assert!(matches!(result, SmtpCommandResult::MailFrom(_)));
+        assert_eq!(protocol.declared_size(), Some(1000));
+    }
+
+    #[tokio::test]
+    async fn test_declared_size_resets_with_transaction() {
+        let mut protocol = create_test_protocol();
+        protocol.state = SmtpState::Greeted;
+        protocol
+            .process_command("MAIL FROM:<user@example.com> SIZE=10")
+            .await
+            .unwrap();
+        protocol.reset_transaction();
+        assert_eq!(protocol.get_state(), SmtpState::Greeted);
+        protocol
+            .process_command("MAIL FROM:<user@example.com>")
+            .await
+            .unwrap();
+        assert_eq!(protocol.declared_size(), None);
+    }
+
+    // --- DSN (RFC 3461) ---
+
+    #[tokio::test]
+    async fn test_ehlo_advertises_dsn() {
+        use std::io::Cursor;
+        let reader = BufReader::new(io::empty());
+        let mut protocol = SmtpProtocol::new(reader, Cursor::new(Vec::new()), 26_214_400);
+        protocol.process_command("EHLO client").await.unwrap();
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert!(written.contains("250-DSN\r\n"), "Got: {}", written);
+    }
+
+    #[tokio::test]
+    async fn test_dsn_parameters_are_captured() {
+        let mut protocol = create_test_protocol();
+        protocol.state = SmtpState::Greeted;
+        let result = protocol
+            .process_command("MAIL FROM:<user@example.com> RET=HDRS ENVID=QQ314159+2B1")
+            .await
+            .unwrap();
+        assert!(matches!(result, SmtpCommandResult::MailFrom(_)));
+        assert_eq!(protocol.mail_dsn().ret, Some(Ret::Hdrs));
+        assert_eq!(protocol.mail_dsn().envid.as_deref(), Some("QQ314159+1"));
+
+        let result = protocol
+            .process_command(
+                "RCPT TO:<rcpt@example.com> NOTIFY=FAILURE,DELAY ORCPT=rfc822;orig@example.com",
+            )
+            .await
+            .unwrap();
+        assert!(
+            matches!(result, SmtpCommandResult::RcptTo(ref email) if email == "rcpt@example.com")
+        );
+        let rcpt = protocol.rcpt_dsn();
+        assert_eq!(rcpt.recipient, "rcpt@example.com");
+        assert_eq!(rcpt.notify, vec![Notify::Failure, Notify::Delay]);
+        assert_eq!(rcpt.orcpt.as_deref(), Some("rfc822;orig@example.com"));
+
+        protocol
+            .process_command("RCPT TO:<plain@example.com>")
+            .await
+            .unwrap();
+        assert!(!protocol.rcpt_dsn().is_requested());
+    }
+
+    #[tokio::test]
+    async fn test_dsn_parameters_reset_with_transaction() {
+        let mut protocol = create_test_protocol();
+        protocol.state = SmtpState::Greeted;
+        protocol
+            .process_command("MAIL FROM:<user@example.com> RET=FULL")
+            .await
+            .unwrap();
+        protocol.process_command("RSET").await.unwrap();
+        protocol
+            .process_command("MAIL FROM:<user@example.com>")
+            .await
+            .unwrap();
+        assert!(protocol.mail_dsn().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_dsn_parameters_are_rejected() {
+        use std::io::Cursor;
+        let reader = BufReader::new(io::empty());
+        let mut protocol = SmtpProtocol::new(reader, Cursor::new(Vec::new()), 26_214_400);
+        protocol.state = SmtpState::Greeted;
+        for line in [
+            "MAIL FROM:<user@example.com> RET=BODY",
+            "MAIL FROM:<user@example.com> ENVID=a=b",
+        ] {
+            let result = protocol.process_command(line).await.unwrap();
+            assert!(matches!(result, SmtpCommandResult::Continue), "{}", line);
+        }
+        protocol
+            .process_command("MAIL FROM:<user@example.com>")
+            .await
+            .unwrap();
+        for line in [
+            "RCPT TO:<rcpt@example.com> NOTIFY=NEVER,FAILURE",
+            "RCPT TO:<rcpt@example.com> ORCPT=orig@example.com",
+        ] {
+            let result = protocol.process_command(line).await.unwrap();
+            assert!(matches!(result, SmtpCommandResult::Continue), "{}", line);
+        }
+        assert_eq!(protocol.get_state(), SmtpState::MailFrom);
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert_eq!(written.matches("555 5.5.4").count(), 4, "Got: {}", written);
+    }
+
+    // --- CHUNKING / BDAT (RFC 3030) ---
+
+    fn create_xclient_protocol(
+        xclient_enabled: bool,
+    ) -> SmtpProtocol<BufReader<io::Empty>, std::io::Cursor<Vec<u8>>> {
+        let reader = BufReader::new(io::empty());
+        let mut protocol = SmtpProtocol::new(reader, std::io::Cursor::new(Vec::new()), 26_214_400)
+            .with_xclient(xclient_enabled);
+        protocol.state = SmtpState::Greeted;
+        protocol
+    }
+
+    fn take_output(
+        protocol: &mut SmtpProtocol<BufReader<io::Empty>, std::io::Cursor<Vec<u8>>>,
+    ) -> String {
+        let output = std::mem::take(&mut protocol.writer).into_inner();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ehlo_advertises_xclient_only_to_trusted_relays() {
+        let mut trusted = create_xclient_protocol(true);
+        trusted.process_command("EHLO relay").await.unwrap();
+        let written = take_output(&mut trusted);
+        assert!(written.contains("250-XCLIENT ADDR NAME HELO PROTO\r\n"));
+        assert!(written.contains("250-XFORWARD ADDR NAME HELO PROTO\r\n"));
+
+        let mut untrusted = create_xclient_protocol(false);
+        untrusted.process_command("EHLO client").await.unwrap();
+        assert!(!take_output(&mut untrusted).contains("XCLIENT"));
+        let result = untrusted
+            .process_command("XCLIENT ADDR=192.0.2.1")
+            .await
+            .unwrap();
+        assert!(matches!(result, SmtpCommandResult::Continue));
+        assert!(take_output(&mut untrusted).starts_with("550 5.7.0"));
+    }
+
+    #[tokio::test]
+    async fn test_help_lists_only_commands_the_session_accepts() {
+        let mut plaintext = create_xclient_protocol(false).with_auth(true);
+        plaintext.process_command("HELP").await.unwrap();
+        assert_eq!(
+            take_output(&mut plaintext),
+            "214 2.0.0 Commands: EHLO HELO MAIL RCPT DATA BDAT RSET NOOP VRFY HELP QUIT STARTTLS\r\n"
+        );
+
+        let mut secure = create_xclient_protocol(true)
+            .with_tls_active(true)
+            .with_auth(true);
+        secure.process_command("HELP").await.unwrap();
+        let written = take_output(&mut secure);
+        assert!(
+            written.ends_with(" XCLIENT XFORWARD AUTH\r\n"),
+            "Got: {}",
+            written
+        );
+        assert!(!written.contains("STARTTLS"), "Got: {}", written);
+
+        let mut no_auth = create_xclient_protocol(false).with_tls_active(true);
+        no_auth.process_command("HELP").await.unwrap();
+        assert!(take_output(&mut no_auth).ends_with(" QUIT\r\n"));
     }
 
-    // Test existing HELO behavior for baseline
     #[tokio::test]
-    async fn test_initial_helo_sets_greeted() {
-        let mut protocol = create_test_protocol();
+    async fn test_xclient_restarts_session_with_forwarded_client() {
+        let mut protocol = create_xclient_protocol(true);
+        let result = protocol
+            .process_command(
+                "XCLIENT ADDR=IPV6:2001:db8::7 NAME=[UNAVAILABLE] HELO=origin.example PROTO=ESMTP",
+            )
+            .await
+            .unwrap();
+        let SmtpCommandResult::Forwarded(client) = result else {
+            panic!("expected Forwarded, got {:?}", result);
+        };
+        assert_eq!(client.addr, Some("2001:db8::7".parse().unwrap()));
+        assert_eq!(client.name, None);
+        assert_eq!(client.helo.as_deref(), Some("origin.example"));
+        assert_eq!(client.proto.as_deref(), Some("ESMTP"));
         assert_eq!(protocol.get_state(), SmtpState::Initial);
-        // We assume write_line succeeds internally for state tests
-        let result = protocol.process_command("HELO example.com").await.unwrap();
-        assert!(matches!(result, SmtpCommandResult::Helo(ref d) if d == "example.com"));
-        assert_eq!(protocol.get_state(), SmtpState::Greeted);
+        assert!(take_output(&mut protocol).starts_with("220 "));
     }
 
-    // Test existing EHLO behavior for baseline
     #[tokio::test]
-    async fn test_initial_ehlo_sets_greeted() {
-        let mut protocol = create_test_protocol();
-        assert_eq!(protocol.get_state(), SmtpState::Initial);
-        // The actual response lines for EHLO will be modified later to include STARTTLS
-        let result = protocol.process_command("EHLO example.com").await.unwrap();
-        assert!(matches!(result, SmtpCommandResult::Helo(ref d) if d == "example.com"));
+    async fn test_xforward_keeps_session_state() {
+        let mut protocol = create_xclient_protocol(true);
+        let result = protocol
+            .process_command("XFORWARD ADDR=198.51.100.4 HELO=mx+2Eorigin.example")
+            .await
+            .unwrap();
+        let SmtpCommandResult::Forwarded(client) = result else {
+            panic!("expected Forwarded, got {:?}", result);
+        };
+        assert_eq!(client.addr, Some("198.51.100.4".parse().unwrap()));
+        assert_eq!(client.helo.as_deref(), Some("mx.origin.example"));
         assert_eq!(protocol.get_state(), SmtpState::Greeted);
+        assert_eq!(take_output(&mut protocol), "250 2.0.0 OK\r\n");
     }
 
-    // Test STARTTLS command in the correct state (Greeted)
     #[tokio::test]
-    async fn test_greeted_starttls_accepted() {
-        let mut protocol = create_test_protocol();
-        // Manually set the state to Greeted for this test scenario
-        protocol.state = SmtpState::Greeted;
-        assert_eq!(protocol.get_state(), SmtpState::Greeted);
+    async fn test_xclient_rejects_bad_attributes_and_open_transactions() {
+        let mut protocol = create_xclient_protocol(true);
+        for bad in [
+            "XCLIENT",
+            "XCLIENT LOGIN=alice",
+            "XCLIENT ADDR=not-an-ip",
+            "XFORWARD HELO",
+        ] {
+            let result = protocol.process_command(bad).await.unwrap();
+            assert!(matches!(result, SmtpCommandResult::Continue), "{}", bad);
+            assert!(
+                take_output(&mut protocol).starts_with("501 5.5.4"),
+                "{}",
+                bad
+            );
+        }
 
-        let result = protocol.process_command("STARTTLS").await.unwrap();
+        protocol
+            .process_command("MAIL FROM:<a@example.com>")
+            .await
+            .unwrap();
+        take_output(&mut protocol);
+        let result = protocol
+            .process_command("XFORWARD ADDR=192.0.2.1")
+            .await
+            .unwrap();
+        assert!(matches!(result, SmtpCommandResult::Continue));
+        assert!(take_output(&mut protocol).starts_with("503 5.5.1"));
+        assert_eq!(protocol.get_state(), SmtpState::MailFrom);
+    }
 
-        // Expect the StartTls command result
+    fn create_bdat_protocol(
+        input: &[u8],
+        max_size: u64,
+    ) -> SmtpProtocol<BufReader<std::io::Cursor<Vec<u8>>>, std::io::Cursor<Vec<u8>>> {
+        let reader = BufReader::new(std::io::Cursor::new(input.to_vec()));
+        let mut protocol = SmtpProtocol::new(reader, std::io::Cursor::new(Vec::new()), max_size);
+        protocol.state = SmtpState::RcptTo;
+        protocol
+    }
+
+    #[tokio::test]
+    async fn test_ehlo_advertises_chunking() {
+        let mut protocol = create_bdat_protocol(b"", 26_214_400);
+        protocol.state = SmtpState::Initial;
+        protocol.process_command("EHLO client").await.unwrap();
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert!(written.contains("250-CHUNKING\r\n"), "Got: {}", written);
+    }
+
+    #[tokio::test]
+    async fn test_bdat_reads_chunks_by_byte_count() {
+        // The chunk payload contains CRLF and a lone "." line; neither is special.
+        let mut protocol = create_bdat_protocol(b"Sub\r\n.\r\nject\r\nQUIT\r\n", 26_214_400);
+
+        let result = protocol.process_command("BDAT 8").await.unwrap();
         assert!(
-            matches!(result, SmtpCommandResult::StartTls),
-            "Expected StartTls result, got {:?}",
+            matches!(result, SmtpCommandResult::BdatChunk { data: Some(ref d), last: false } if d == b"Sub\r\n.\r\n"),
+            "got {:?}",
             result
         );
-        // The state should remain Greeted, as the handshake happens *after* this command response.
-        // The connection handler will take over for the TLS part.
-        assert_eq!(
-            protocol.get_state(),
-            SmtpState::Greeted,
-            "State should remain Greeted after STARTTLS command"
+        assert_eq!(protocol.get_state(), SmtpState::Bdat);
+
+        let result = protocol.process_command("bdat 6 last").await.unwrap();
+        assert!(
+            matches!(result, SmtpCommandResult::BdatChunk { data: Some(ref d), last: true } if d == b"ject\r\n"),
+            "got {:?}",
+            result
         );
+        assert_eq!(protocol.get_state(), SmtpState::Greeted);
+        assert_eq!(protocol.read_raw_line().await.unwrap().unwrap(), b"QUIT");
     }
 
-    // Test STARTTLS command in an incorrect state (e.g., MailFrom)
     #[tokio::test]
-    async fn test_mailfrom_starttls_rejected() {
-        let mut protocol = create_test_protocol();
-        protocol.state = SmtpState::MailFrom; // Set state manually
-        assert_eq!(protocol.get_state(), SmtpState::MailFrom);
-
-        let result = protocol.process_command("STARTTLS").await.unwrap();
-
-        // Expect a rejection (Continue means an error response was sent, loop continues)
+    async fn test_bdat_over_size_limit_is_drained() {
+        let mut protocol = create_bdat_protocol(b"12345678QUIT\r\n", 6);
+
+        let result = protocol.process_command("BDAT 4").await.unwrap();
+        assert!(matches!(
+            result,
+            SmtpCommandResult::BdatChunk {
+                data: Some(_),
+                last: false
+            }
+        ));
+        let result = protocol.process_command("BDAT 4 LAST").await.unwrap();
         assert!(
-            matches!(result, SmtpCommandResult::Continue),
-            "Expected Continue result for rejected STARTTLS, got {:?}",
+            matches!(
+                result,
+                SmtpCommandResult::BdatChunk {
+                    data: None,
+                    last: true
+                }
+            ),
+            "got {:?}",
             result
         );
-        // State should not change due to the invalid command sequence
-        assert_eq!(
-            protocol.get_state(),
-            SmtpState::MailFrom,
-            "State should remain MailFrom after rejected STARTTLS"
-        );
+        assert_eq!(protocol.get_state(), SmtpState::Greeted);
+        assert_eq!(protocol.read_raw_line().await.unwrap().unwrap(), b"QUIT");
     }
 
-    // Test STARTTLS command in another incorrect state (e.g., RcptTo)
     #[tokio::test]
-    async fn test_rcptto_starttls_rejected() {
-        let mut protocol = create_test_protocol();
-        protocol.state = SmtpState::RcptTo; // Set state manually
-        assert_eq!(protocol.get_state(), SmtpState::RcptTo);
+    async fn test_bdat_out_of_sequence_is_drained_with_503() {
+        let mut protocol = create_bdat_protocol(b"abcQUIT\r\n", 26_214_400);
+        protocol.state = SmtpState::Greeted;
 
-        let result = protocol.process_command("STARTTLS").await.unwrap();
+        let result = protocol.process_command("BDAT 3 LAST").await.unwrap();
+        assert!(matches!(result, SmtpCommandResult::Continue));
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert!(written.starts_with("503"), "Got: {}", written);
+        assert_eq!(protocol.read_raw_line().await.unwrap().unwrap(), b"QUIT");
+    }
 
+    #[tokio::test]
+    async fn test_data_refused_after_bdat_chunk() {
+        let mut protocol = create_bdat_protocol(b"", 26_214_400);
+        protocol.state = SmtpState::Bdat;
+        let result = protocol.process_command("DATA").await.unwrap();
+        assert!(matches!(result, SmtpCommandResult::Continue));
+        assert_eq!(protocol.get_state(), SmtpState::Bdat);
+    }
+
+    #[tokio::test]
+    async fn test_bdat_syntax_errors() {
+        let mut protocol = create_bdat_protocol(b"", 26_214_400);
+        for line in ["BDAT", "BDAT x", "BDAT 5 FIRST", "BDAT 5 LAST extra"] {
+            let result = protocol.process_command(line).await.unwrap();
+            assert!(matches!(result, SmtpCommandResult::Continue), "{}", line);
+        }
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert_eq!(written.matches("501 5.5.4").count(), 4, "Got: {}", written);
+    }
+
+    // --- Pipelining (RFC 2920) ---
+
+    type PipelinedProtocol =
+        SmtpProtocol<BufReader<std::io::Cursor<Vec<u8>>>, BufWriter<std::io::Cursor<Vec<u8>>>>;
+
+    fn create_pipelined_protocol(input: &[u8]) -> PipelinedProtocol {
+        let reader = BufReader::new(std::io::Cursor::new(input.to_vec()));
+        let writer = BufWriter::new(std::io::Cursor::new(Vec::new()));
+        SmtpProtocol::new(reader, writer, 26_214_400)
+    }
+
+    /// What has actually reached the peer, excluding queued replies.
+    fn flushed_output(protocol: &PipelinedProtocol) -> String {
+        String::from_utf8(protocol.writer.get_ref().get_ref().clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ehlo_advertises_pipelining() {
+        let mut protocol = create_pipelined_protocol(b"");
+        protocol.process_command("EHLO client").await.unwrap();
+        protocol.flush().await.unwrap();
+        let written = flushed_output(&protocol);
+        assert!(written.contains("250-PIPELINING\r\n"), "Got: {}", written);
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_replies_are_flushed_together() {
+        let mut protocol = create_pipelined_protocol(b"RCPT TO:<b@example.com>\r\nDATA\r\n");
+        protocol.write_line("250 OK").await.unwrap();
+
+        // The next command is already buffered, so the reply stays queued.
+        assert_eq!(
+            protocol.read_raw_line().await.unwrap().unwrap(),
+            b"RCPT TO:<b@example.com>"
+        );
+        assert_eq!(flushed_output(&protocol), "");
+
+        protocol.write_line("250 OK").await.unwrap();
+        assert_eq!(protocol.read_raw_line().await.unwrap().unwrap(), b"DATA");
+        assert_eq!(flushed_output(&protocol), "");
+
+        // The input is drained, so both replies go out before reading blocks.
+        assert!(protocol.read_raw_line().await.unwrap().is_none());
+        assert_eq!(flushed_output(&protocol), "250 OK\r\n250 OK\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_data_with_pipelined_input_is_rejected() {
+        let mut protocol = create_pipelined_protocol(b"Subject: hi\r\n");
+        protocol.state = SmtpState::RcptTo;
+        let result = protocol.process_command("DATA").await.unwrap();
         assert!(
-            matches!(result, SmtpCommandResult::Continue),
-            "Expected Continue result for rejected STARTTLS, got {:?}",
+            matches!(result, SmtpCommandResult::Close),
+            "got {:?}",
             result
         );
+        assert_eq!(protocol.get_state(), SmtpState::RcptTo);
+        protocol.flush().await.unwrap();
         assert_eq!(
-            protocol.get_state(),
-            SmtpState::RcptTo,
-            "State should remain RcptTo after rejected STARTTLS"
+            flushed_output(&protocol),
+            "554 5.5.0 Improper use of SMTP command pipelining\r\n"
         );
     }
 
-    // Test STARTTLS command during DATA phase (should be treated as data)
     #[tokio::test]
-    async fn test_data_starttls_is_data() {
-        let mut protocol = create_test_protocol();
-        protocol.state = SmtpState::Data; // Set state manually
-        assert_eq!(protocol.get_state(), SmtpState::Data);
-
+    async fn test_starttls_with_pipelined_input_is_rejected() {
+        let mut protocol = create_pipelined_protocol(b"MAIL FROM:<a@example.com>\r\n");
+        protocol.state = SmtpState::Greeted;
         let result = protocol.process_command("STARTTLS").await.unwrap();
-
-        // In DATA state, any line not "." is data
         assert!(
-            matches!(result, SmtpCommandResult::DataLine(ref line) if line == "STARTTLS"),
-            "Expected DataLine result, got {:?}",
+            matches!(result, SmtpCommandResult::Close),
+            "got {:?}",
             result
         );
-        assert_eq!(protocol.get_state(), SmtpState::Data);
     }
 
-    // Test QUIT command works in Greeted state (important for STARTTLS flow)
     #[tokio::test]
-    async fn test_greeted_quit() {
-        let mut protocol = create_test_protocol();
+    async fn test_greeting_noop_and_vrfy_with_pipelined_input_are_rejected() {
+        for command in ["EHLO client", "HELO client", "NOOP", "VRFY user"] {
+            let mut protocol = create_pipelined_protocol(b"MAIL FROM:<a@example.com>\r\n");
+            protocol.state = SmtpState::Greeted;
+            let result = protocol.process_command(command).await.unwrap();
+            assert!(
+                matches!(result, SmtpCommandResult::Close),
+                "{}: got {:?}",
+                command,
+                result
+            );
+            protocol.flush().await.unwrap();
+            assert_eq!(
+                flushed_output(&protocol),
+                "554 5.5.0 Improper use of SMTP command pipelining\r\n",
+                "{}",
+                command
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_quit_with_pipelined_input_still_says_goodbye() {
+        let mut protocol = create_pipelined_protocol(b"NOOP\r\n");
         protocol.state = SmtpState::Greeted;
         let result = protocol.process_command("QUIT").await.unwrap();
-        assert!(matches!(result, SmtpCommandResult::Quit));
-        // State doesn't technically matter after Quit, but it shouldn't change unexpectedly
-        assert_eq!(protocol.get_state(), SmtpState::Greeted);
+        assert!(
+            matches!(result, SmtpCommandResult::Quit),
+            "got {:?}",
+            result
+        );
+    }
+
+    #[tokio::test]
+    async fn test_starttls_reply_is_flushed_before_handshake() {
+        let mut protocol = create_pipelined_protocol(b"");
+        protocol.state = SmtpState::Greeted;
+        let result = protocol.process_command("STARTTLS").await.unwrap();
+        assert!(matches!(result, SmtpCommandResult::StartTls));
+        assert_eq!(
+            flushed_output(&protocol),
+            "220 2.0.0 Ready to start TLS\r\n"
+        );
     }
 
     // Note: Testing that EHLO *advertises* STARTTLS requires checking the output buffer,
@@ -542,14 +2329,16 @@ mod tests {
         assert_eq!(protocol.get_state(), SmtpState::Data);
 
         let result = protocol.process_command("Subject: Test").await.unwrap();
-        assert!(matches!(result, SmtpCommandResult::DataLine(ref line) if line == "Subject: Test"));
+        assert!(
+            matches!(result, SmtpCommandResult::DataLine(ref line) if line == b"Subject: Test")
+        );
 
         let result = protocol.process_command("").await.unwrap();
         assert!(matches!(result, SmtpCommandResult::DataLine(ref line) if line.is_empty()));
 
         let result = protocol.process_command("Body of the email").await.unwrap();
         assert!(
-            matches!(result, SmtpCommandResult::DataLine(ref line) if line == "Body of the email")
+            matches!(result, SmtpCommandResult::DataLine(ref line) if line == b"Body of the email")
         );
 
         let result = protocol.process_command(".").await.unwrap();
@@ -678,7 +2467,7 @@ mod tests {
         protocol.state = SmtpState::Data;
         let result = protocol.process_command("QUIT").await.unwrap();
         assert!(
-            matches!(result, SmtpCommandResult::DataLine(ref line) if line == "QUIT"),
+            matches!(result, SmtpCommandResult::DataLine(ref line) if line == b"QUIT"),
             "QUIT in Data state should be treated as DataLine, got {:?}",
             result
         );
@@ -747,8 +2536,8 @@ mod tests {
         let writer = BufWriter::new(io::sink());
         let mut protocol = SmtpProtocol::new(reader, writer, 26_214_400);
 
-        let line = protocol.read_line().await.unwrap();
-        assert_eq!(line, "HELO example.com");
+        let line = protocol.read_raw_line().await.unwrap().unwrap();
+        assert_eq!(line, b"HELO example.com");
     }
 
     #[tokio::test]
@@ -759,18 +2548,31 @@ mod tests {
         let writer = BufWriter::new(io::sink());
         let mut protocol = SmtpProtocol::new(reader, writer, 26_214_400);
 
-        let line = protocol.read_line().await.unwrap();
-        assert_eq!(line, "HELO example.com");
+        let line = protocol.read_raw_line().await.unwrap().unwrap();
+        assert_eq!(line, b"HELO example.com");
     }
 
     #[tokio::test]
-    async fn test_read_line_eof_returns_empty() {
+    async fn test_read_line_eof_returns_none() {
         let reader = BufReader::new(io::empty());
         let writer = BufWriter::new(io::sink());
         let mut protocol = SmtpProtocol::new(reader, writer, 26_214_400);
 
-        let line = protocol.read_line().await.unwrap();
-        assert_eq!(line, "");
+        assert!(protocol.read_raw_line().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_line_keeps_8bit_bytes_and_blank_lines() {
+        use std::io::Cursor;
+        // Latin-1 "café" is not valid UTF-8.
+        let input = b"caf\xe9\r\n\r\n";
+        let reader = BufReader::new(Cursor::new(input.to_vec()));
+        let writer = BufWriter::new(io::sink());
+        let mut protocol = SmtpProtocol::new(reader, writer, 26_214_400);
+
+        assert_eq!(protocol.read_raw_line().await.unwrap().unwrap(), b"caf\xe9");
+        assert_eq!(protocol.read_raw_line().await.unwrap().unwrap(), b"");
+        assert!(protocol.read_raw_line().await.unwrap().is_none());
     }
 
     #[tokio::test]
@@ -781,11 +2583,11 @@ mod tests {
         let writer = BufWriter::new(io::sink());
         let mut protocol = SmtpProtocol::new(reader, writer, 26_214_400);
 
-        let line1 = protocol.read_line().await.unwrap();
-        assert_eq!(line1, "HELO example.com");
+        let line1 = protocol.read_raw_line().await.unwrap().unwrap();
+        assert_eq!(line1, b"HELO example.com");
 
-        let line2 = protocol.read_line().await.unwrap();
-        assert_eq!(line2, "MAIL FROM:<user@test.com>");
+        let line2 = protocol.read_raw_line().await.unwrap().unwrap();
+        assert_eq!(line2, b"MAIL FROM:<user@test.com>");
     }
 
     #[tokio::test]
@@ -919,4 +2721,186 @@ mod tests {
         assert!(written.contains("MailLaser"));
         assert!(written.ends_with("\r\n"));
     }
+
+    #[tokio::test]
+    async fn test_identity_names_host_in_greeting_and_ehlo() {
+        let reader = BufReader::new(io::empty());
+        let identity = Arc::new(ServerIdentity::new("mx.example.net", true));
+        let mut protocol = SmtpProtocol::new(reader, std::io::Cursor::new(Vec::new()), 26_214_400)
+            .with_identity(identity);
+
+        protocol.send_greeting().await.unwrap();
+        protocol
+            .process_command("EHLO client.example")
+            .await
+            .unwrap();
+
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert!(
+            written.starts_with("220 mx.example.net ESMTP ready\r\n"),
+            "Got: {}",
+            written
+        );
+        assert!(!written.contains("MailLaser"), "Got: {}", written);
+        assert!(written.contains("250-mx.example.net greets client.example\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_identity_names_host_in_helo_reply() {
+        let reader = BufReader::new(io::empty());
+        let identity = Arc::new(ServerIdentity::new("mx.example.net", true));
+        let mut protocol = SmtpProtocol::new(reader, std::io::Cursor::new(Vec::new()), 26_214_400)
+            .with_identity(identity);
+
+        protocol
+            .process_command("HELO client.example")
+            .await
+            .unwrap();
+
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert_eq!(written, "250 mx.example.net\r\n");
+    }
+
+    // --- LMTP (RFC 2033) ---
+
+    fn create_lmtp_protocol() -> SmtpProtocol<BufReader<io::Empty>, std::io::Cursor<Vec<u8>>> {
+        let reader = BufReader::new(io::empty());
+        SmtpProtocol::new(reader, std::io::Cursor::new(Vec::new()), 26_214_400).with_lmtp(true)
+    }
+
+    #[tokio::test]
+    async fn test_lmtp_greets_and_accepts_lhlo() {
+        let mut protocol = create_lmtp_protocol();
+        protocol.send_greeting().await.unwrap();
+        let result = protocol
+            .process_command("LHLO mx.example.com")
+            .await
+            .unwrap();
+        assert!(matches!(result, SmtpCommandResult::Helo(ref d) if d == "mx.example.com"));
+        assert_eq!(protocol.get_state(), SmtpState::Greeted);
+
+        let written = String::from_utf8(protocol.writer.get_ref().clone()).unwrap();
+        assert!(
+            written.starts_with("220 localhost LMTP MailLaser "),
+            "Got: {}",
+            written
+        );
+        assert!(written.contains("250-localhost greets mx.example.com\r\n"));
+        assert!(written.contains("250-CHUNKING\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_lmtp_refuses_helo_and_ehlo() {
+        let mut protocol = create_lmtp_protocol();
+        for line in ["HELO client", "EHLO client"] {
+            let result = protocol.process_command(line).await.unwrap();
+            assert!(matches!(result, SmtpCommandResult::Continue), "{}", line);
+            assert_eq!(protocol.get_state(), SmtpState::Initial);
+        }
+        protocol.process_command("MAIL FROM:<a@b.c>").await.unwrap();
+        let written = take_output(&mut protocol);
+        assert_eq!(written.matches("500 5.5.1").count(), 2, "Got: {}", written);
+        assert!(written.contains("expected LHLO"), "Got: {}", written);
+    }
+
+    #[tokio::test]
+    async fn test_smtp_does_not_accept_lhlo() {
+        let reader = BufReader::new(io::empty());
+        let mut protocol = SmtpProtocol::new(reader, std::io::Cursor::new(Vec::new()), 26_214_400);
+        let result = protocol.process_command("LHLO client").await.unwrap();
+        assert!(matches!(result, SmtpCommandResult::Continue));
+        assert_eq!(protocol.get_state(), SmtpState::Initial);
+        assert!(take_output(&mut protocol).starts_with("503 5.5.1"));
+    }
+
+    // --- Read timeouts (RFC 5321 §4.5.3.2) ---
+
+    type DuplexProtocol =
+        SmtpProtocol<BufReader<io::ReadHalf<io::DuplexStream>>, io::WriteHalf<io::DuplexStream>>;
+
+    /// A protocol on one end of an in-memory pipe, with the given greeting /
+    /// command / data limits and session allowance (milliseconds).
+    fn create_timed_protocol(
+        greeting: u64,
+        command: u64,
+        data: u64,
+        session: u64,
+    ) -> (DuplexProtocol, io::DuplexStream) {
+        let (server, client) = io::duplex(4096);
+        let (read_half, write_half) = io::split(server);
+        let protocol = SmtpProtocol::new(BufReader::new(read_half), write_half, 26_214_400)
+            .with_timeouts(ReadTimeouts {
+                greeting: Duration::from_millis(greeting),
+                command: Duration::from_millis(command),
+                data: Duration::from_millis(data),
+                session_deadline: Instant::now() + Duration::from_millis(session),
+            });
+        (protocol, client)
+    }
+
+    async fn expect_timeout(
+        protocol: &mut DuplexProtocol,
+        client: &mut io::DuplexStream,
+    ) -> TimeoutPhase {
+        let err = protocol.read_raw_line().await.unwrap_err();
+        let phase = err.downcast_ref::<ReadTimeout>().expect("a ReadTimeout").0;
+        let mut reply = vec![0u8; 128];
+        let n = client.read(&mut reply).await.unwrap();
+        let reply = String::from_utf8_lossy(&reply[..n]);
+        assert!(reply.starts_with("421 4.4.2 "), "Got: {}", reply);
+        phase
+    }
+
+    #[tokio::test]
+    async fn test_silent_client_hits_greeting_timeout() {
+        let (mut protocol, mut client) = create_timed_protocol(50, 10_000, 10_000, 10_000);
+        assert_eq!(
+            expect_timeout(&mut protocol, &mut client).await,
+            TimeoutPhase::Greeting
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trickled_command_hits_command_timeout() {
+        let (mut protocol, mut client) = create_timed_protocol(10_000, 50, 10_000, 10_000);
+        client.write_all(b"EHLO client\r\nMAIL FR").await.unwrap();
+        assert_eq!(
+            protocol.read_raw_line().await.unwrap().unwrap(),
+            b"EHLO client"
+        );
+        assert_eq!(
+            expect_timeout(&mut protocol, &mut client).await,
+            TimeoutPhase::Command
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stalled_data_and_bdat_hit_data_timeout() {
+        let (mut protocol, mut client) = create_timed_protocol(10_000, 10_000, 50, 10_000);
+        client.write_all(b"NOOP\r\n").await.unwrap();
+        protocol.read_raw_line().await.unwrap();
+        protocol.state = SmtpState::Data;
+        assert_eq!(
+            expect_timeout(&mut protocol, &mut client).await,
+            TimeoutPhase::Data
+        );
+
+        let (mut protocol, mut client) = create_timed_protocol(10_000, 10_000, 50, 10_000);
+        protocol.state = SmtpState::RcptTo;
+        client.write_all(b"partial chunk").await.unwrap();
+        let err = protocol.process_command("BDAT 100 LAST").await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ReadTimeout>().unwrap().0,
+            TimeoutPhase::Data
+        );
+    }
+
+    #[tokio::test]
+    async fn test_session_deadline_caps_every_phase() {
+        let (mut protocol, mut client) = create_timed_protocol(10_000, 10_000, 10_000, 50);
+        assert_eq!(
+            expect_timeout(&mut protocol, &mut client).await,
+            TimeoutPhase::Session
+        );
+    }
 }
@@ -0,0 +1,9 @@
+use super::*;
+
+#[test]
+fn address_literal_tags_ipv6() {
+    let literal = |s: &str| address_literal(s.parse().unwrap());
+    assert_eq!(literal("192.0.2.1"), "[192.0.2.1]");
+    assert_eq!(literal("::ffff:192.0.2.1"), "[192.0.2.1]");
+    assert_eq!(literal("2001:db8::1"), "[IPv6:2001:db8::1]");
+}
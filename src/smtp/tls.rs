@@ -0,0 +1,291 @@
+//! TLS server configuration for STARTTLS.
+//!
+//! With `MAIL_LASER_TLS_CERT`/`MAIL_LASER_TLS_KEY` set, the certificate chain
+//! and private key are loaded from PEM files; otherwise a self-signed
+//! certificate for the server's host name is generated once at startup.
+//! Either way the resulting `ServerConfig` is built once and shared by every
+//! session.
+//!
+//! File-backed certificates are reloaded when the files change (checked every
+//! [`CERT_POLL_INTERVAL`]) or when the process receives `SIGHUP`, so renewals
+//! by cert-manager or an ACME client apply without a restart. A reload that
+//! fails keeps serving the previous certificate.
+
+use super::file_watch::watch_reload;
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use rcgen::generate_simple_self_signed;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::ServerConfig as RustlsServerConfig;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+
+/// How often certificate files are checked for changes.
+pub(crate) const CERT_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Shared, reloadable TLS server configuration.
+pub(crate) struct ServerTls {
+    current: RwLock<Arc<RustlsServerConfig>>,
+    files: Option<CertFiles>,
+}
+
+struct CertFiles {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    /// Modification stamps of the cert and key at the last (re)load.
+    stamps: RwLock<(Option<SystemTime>, Option<SystemTime>)>,
+}
+
+impl ServerTls {
+    /// Loads the operator-supplied certificate when both paths are given,
+    /// otherwise generates a self-signed one for `hostname`.
+    pub(crate) fn load(
+        cert_path: Option<&Path>,
+        key_path: Option<&Path>,
+        hostname: &str,
+    ) -> Result<Arc<Self>> {
+        match (cert_path, key_path) {
+            (Some(cert_path), Some(key_path)) => {
+                let config = load_pem_config(cert_path, key_path)?;
+                info!(
+                    "TLS: loaded certificate {} and key {}",
+                    cert_path.display(),
+                    key_path.display()
+                );
+                Ok(Arc::new(Self {
+                    current: RwLock::new(Arc::new(config)),
+                    files: Some(CertFiles {
+                        cert_path: cert_path.to_path_buf(),
+                        key_path: key_path.to_path_buf(),
+                        stamps: RwLock::new(modified_stamps(cert_path, key_path)),
+                    }),
+                }))
+            }
+            (None, None) => {
+                let (cert, key) = generate_self_signed_cert(hostname)
+                    .context("Failed to generate self-signed certificate for STARTTLS")?;
+                let config = build_server_config(vec![cert], key)?;
+                info!(
+                    "TLS: no certificate configured, using a self-signed certificate for {}",
+                    hostname
+                );
+                Ok(Arc::new(Self {
+                    current: RwLock::new(Arc::new(config)),
+                    files: None,
+                }))
+            }
+            _ => Err(anyhow!(
+                "MAIL_LASER_TLS_CERT and MAIL_LASER_TLS_KEY must be set together"
+            )),
+        }
+    }
+
+    /// An acceptor for the configuration currently in effect.
+    pub(crate) fn acceptor(&self) -> TlsAcceptor {
+        let config = self
+            .current
+            .read()
+            .map(|c| c.clone())
+            .unwrap_or_else(|poisoned| poisoned.into_inner().clone());
+        TlsAcceptor::from(config)
+    }
+
+    /// Re-reads the certificate files and swaps in the new configuration.
+    /// A no-op for the self-signed certificate.
+    pub(crate) fn reload(&self) -> Result<()> {
+        let Some(files) = &self.files else {
+            return Ok(());
+        };
+        let stamps = modified_stamps(&files.cert_path, &files.key_path);
+        let config = load_pem_config(&files.cert_path, &files.key_path)?;
+        if let Ok(mut current) = self.current.write() {
+            *current = Arc::new(config);
+        }
+        if let Ok(mut last) = files.stamps.write() {
+            *last = stamps;
+        }
+        info!("TLS: reloaded certificate {}", files.cert_path.display());
+        Ok(())
+    }
+
+    /// Whether either file's modification time differs from the last load.
+    fn files_changed(&self) -> bool {
+        let Some(files) = &self.files else {
+            return false;
+        };
+        let now = modified_stamps(&files.cert_path, &files.key_path);
+        files.stamps.read().map(|last| *last != now).unwrap_or(true)
+    }
+
+    /// Reloads on file changes and `SIGHUP` until `cancel` fires. Returns
+    /// immediately for the self-signed certificate.
+    pub(crate) async fn watch(self: Arc<Self>, cancel: CancellationToken) {
+        if self.files.is_none() {
+            return;
+        }
+        watch_reload(
+            self,
+            "TLS",
+            "certificate",
+            CERT_POLL_INTERVAL,
+            Self::files_changed,
+            Self::reload,
+            cancel,
+        )
+        .await;
+    }
+}
+
+fn modified_stamps(cert_path: &Path, key_path: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    (modified(cert_path), modified(key_path))
+}
+
+fn load_pem_config(cert_path: &Path, key_path: &Path) -> Result<RustlsServerConfig> {
+    let cert_file = std::fs::File::open(cert_path)
+        .with_context(|| format!("Failed to open TLS certificate {}", cert_path.display()))?;
+    let certs: Vec<CertificateDer<'static>> = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .collect::<std::result::Result<_, _>>()
+        .with_context(|| format!("Failed to parse TLS certificate {}", cert_path.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in {}", cert_path.display()));
+    }
+
+    let key_file = std::fs::File::open(key_path)
+        .with_context(|| format!("Failed to open TLS key {}", key_path.display()))?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(key_file))
+        .with_context(|| format!("Failed to parse TLS key {}", key_path.display()))?
+        .ok_or_else(|| anyhow!("No private key found in {}", key_path.display()))?;
+
+    build_server_config(certs, key)
+}
+
+fn build_server_config(
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<RustlsServerConfig> {
+    RustlsServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| anyhow!("Failed to create rustls config: {}", e))
+}
+
+fn generate_self_signed_cert(
+    hostname: &str,
+) -> Result<(CertificateDer<'static>, PrivateKeyDer<'static>)> {
+    let subject_alt_names = vec![hostname.to_string()];
+
+    let certified_key = generate_simple_self_signed(subject_alt_names)
+        .context("Failed to generate self-signed certificate using rcgen")?;
+
+    let cert_der = certified_key.cert.der().to_vec();
+    let key_der = certified_key.signing_key.serialize_der();
+
+    Ok((
+        CertificateDer::from(cert_der),
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_der)),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn install_crypto() {
+        rustls::crypto::aws_lc_rs::default_provider()
+            .install_default()
+            .ok();
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mail-laser-tls-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes a freshly generated self-signed cert/key pair as PEM.
+    fn write_pem_pair(dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+        let certified = generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+        std::fs::write(&key_path, certified.signing_key.serialize_pem()).unwrap();
+        (cert_path, key_path)
+    }
+
+    fn served_cert(tls: &ServerTls) -> Arc<RustlsServerConfig> {
+        tls.current.read().unwrap().clone()
+    }
+
+    #[test]
+    fn self_signed_when_no_paths() {
+        install_crypto();
+        let tls = ServerTls::load(None, None, "localhost").expect("self-signed config");
+        assert!(tls.files.is_none());
+        assert!(!tls.files_changed());
+        tls.reload().expect("reload is a no-op without files");
+    }
+
+    #[test]
+    fn self_signed_cert_names_the_server() {
+        let (cert, _key) = generate_self_signed_cert("mx.example.net").unwrap();
+        let name = b"mx.example.net";
+        assert!(cert.windows(name.len()).any(|w| w == name));
+        assert!(!cert.windows(9).any(|w| w == b"localhost"));
+    }
+
+    #[test]
+    fn loads_pem_files() {
+        install_crypto();
+        let dir = temp_dir();
+        let (cert, key) = write_pem_pair(&dir, "mx.example.com");
+        let tls = ServerTls::load(Some(&cert), Some(&key), "localhost").expect("PEM config");
+        assert!(tls.files.is_some());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn only_one_path_is_an_error() {
+        install_crypto();
+        let err = ServerTls::load(Some(Path::new("/tmp/cert.pem")), None, "localhost")
+            .err()
+            .expect("must fail");
+        assert!(err.to_string().contains("MAIL_LASER_TLS_CERT"));
+    }
+
+    #[test]
+    fn missing_key_in_file_is_an_error() {
+        install_crypto();
+        let dir = temp_dir();
+        let (cert, key) = write_pem_pair(&dir, "mx.example.com");
+        std::fs::write(&key, "not a key\n").unwrap();
+        assert!(ServerTls::load(Some(&cert), Some(&key), "localhost").is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn reload_swaps_config_and_keeps_old_one_on_failure() {
+        install_crypto();
+        let dir = temp_dir();
+        let (cert, key) = write_pem_pair(&dir, "old.example.com");
+        let tls = ServerTls::load(Some(&cert), Some(&key), "localhost").unwrap();
+        let before = served_cert(&tls);
+
+        write_pem_pair(&dir, "new.example.com");
+        tls.reload().expect("reload with renewed files");
+        let after = served_cert(&tls);
+        assert!(!Arc::ptr_eq(&before, &after), "reload must swap the config");
+
+        std::fs::write(&cert, "garbage\n").unwrap();
+        assert!(tls.reload().is_err());
+        assert!(
+            Arc::ptr_eq(&after, &served_cert(&tls)),
+            "failed reload must keep serving the previous certificate"
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
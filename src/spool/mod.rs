@@ -0,0 +1,200 @@
+//! Durable on-disk queue between SMTP acceptance and webhook dispatch.
+//!
+//! When `MAIL_LASER_SPOOL_DIR` is set, every payload is written to the spool
+//! *before* the SMTP session replies `250`, and the webhook actor deletes the
+//! entry only after the webhook answered with a 2xx. Entries left behind by
+//! failed deliveries, an open circuit breaker, or a restart are re-sent by the
+//! actor's periodic drain.
+//!
+//! The layout is one JSON file per payload, named `<id>.json`. Ids start with
+//! the enqueue time in milliseconds so a lexical sort of the directory yields
+//! arrival order. Writes go to `<id>.json.tmp`, are fsynced, and then renamed
+//! into place, so a crash mid-write never leaves a truncated entry visible to
+//! the drain.
+//!
+//! An entry still undelivered after `MAIL_LASER_SPOOL_MAX_AGE` is given up
+//! by the drain: it is deleted and, when failure reports are enabled, a DSN
+//! is written for the sender. The age comes from the id's time prefix.
+
+use crate::config::Config;
+use crate::webhook::EmailPayload;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+
+#[cfg(test)]
+mod tests;
+
+const ENTRY_EXTENSION: &str = "json";
+const TMP_SUFFIX: &str = ".json.tmp";
+const CORRUPT_SUFFIX: &str = ".json.corrupt";
+
+/// A spooled payload as read back from disk.
+#[derive(Debug, Clone)]
+pub struct SpoolEntry {
+    pub id: String,
+    pub payload: EmailPayload,
+}
+
+impl SpoolEntry {
+    /// How long ago the entry was spooled, from the millisecond prefix of
+    /// its id. `None` for an id without one.
+    pub fn age(&self, now: SystemTime) -> Option<Duration> {
+        let (millis, _) = self.id.split_once('-')?;
+        let enqueued = UNIX_EPOCH + Duration::from_millis(millis.parse().ok()?);
+        Some(now.duration_since(enqueued).unwrap_or_default())
+    }
+}
+
+/// Handle to a spool directory. Cheap to share behind an `Arc`; every method
+/// goes straight to the filesystem, so several handles on the same directory
+/// stay consistent.
+#[derive(Debug)]
+pub struct Spool {
+    dir: PathBuf,
+}
+
+impl Spool {
+    /// Opens the spool configured by `MAIL_LASER_SPOOL_DIR`, or returns `None`
+    /// when spooling is disabled.
+    pub async fn from_config(config: &Config) -> Result<Option<Arc<Self>>> {
+        match &config.spool_dir {
+            Some(dir) => Ok(Some(Arc::new(Self::open(dir).await?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Creates the directory if needed and discards temp files left behind
+    /// by a write that never completed its rename.
+    pub async fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("Failed to create spool directory {}", dir.display()))?;
+
+        let mut entries = tokio::fs::read_dir(&dir)
+            .await
+            .with_context(|| format!("Failed to read spool directory {}", dir.display()))?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            if name.to_string_lossy().ends_with(TMP_SUFFIX) {
+                warn!("Removing incomplete spool write {}", entry.path().display());
+                let _ = tokio::fs::remove_file(entry.path()).await;
+            }
+        }
+
+        info!("Spool: using directory {}", dir.display());
+        Ok(Self { dir })
+    }
+
+    /// Durably writes `payload` and returns the id of the new entry. Once
+    /// this returns `Ok`, the payload survives a crash or restart.
+    pub async fn enqueue(&self, payload: &EmailPayload) -> Result<String> {
+        let id = new_id();
+        let json = serde_json::to_vec(payload).context("Failed to serialize spool entry")?;
+
+        let tmp_path = self.dir.join(format!("{id}{TMP_SUFFIX}"));
+        let mut file = tokio::fs::File::create(&tmp_path)
+            .await
+            .with_context(|| format!("Failed to create {}", tmp_path.display()))?;
+        file.write_all(&json).await?;
+        file.sync_all().await?;
+        drop(file);
+
+        tokio::fs::rename(&tmp_path, self.entry_path(&id))
+            .await
+            .with_context(|| format!("Failed to commit spool entry {id}"))?;
+        self.sync_dir().await;
+        Ok(id)
+    }
+
+    /// Deletes an entry after successful delivery. A missing entry is not an
+    /// error — another delivery attempt may already have removed it.
+    pub async fn remove(&self, id: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.entry_path(id)).await {
+            Ok(()) => {
+                self.sync_dir().await;
+                Ok(())
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => {
+                Err(anyhow::Error::new(e).context(format!("Failed to remove spool entry {id}")))
+            }
+        }
+    }
+
+    /// Whether the entry is still waiting for delivery.
+    pub async fn contains(&self, id: &str) -> bool {
+        tokio::fs::try_exists(self.entry_path(id))
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Every pending entry, oldest first. Entries that cannot be parsed are
+    /// renamed to `<id>.json.corrupt` so they are kept for inspection without
+    /// being retried forever.
+    pub async fn pending(&self) -> Result<Vec<SpoolEntry>> {
+        let mut ids = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir)
+            .await
+            .with_context(|| format!("Failed to read spool directory {}", self.dir.display()))?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(ENTRY_EXTENSION) {
+                continue;
+            }
+            if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                ids.push(stem.to_string());
+            }
+        }
+        ids.sort();
+
+        let mut pending = Vec::with_capacity(ids.len());
+        for id in ids {
+            let path = self.entry_path(&id);
+            let bytes = match tokio::fs::read(&path).await {
+                Ok(b) => b,
+                // Delivered and removed between the directory scan and now.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(anyhow::Error::new(e)
+                        .context(format!("Failed to read spool entry {}", path.display())))
+                }
+            };
+            match serde_json::from_slice::<EmailPayload>(&bytes) {
+                Ok(payload) => pending.push(SpoolEntry { id, payload }),
+                Err(e) => {
+                    warn!("Spool entry {} is unreadable ({}); setting it aside", id, e);
+                    let corrupt = self.dir.join(format!("{id}{CORRUPT_SUFFIX}"));
+                    let _ = tokio::fs::rename(&path, corrupt).await;
+                }
+            }
+        }
+        Ok(pending)
+    }
+
+    fn entry_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.{ENTRY_EXTENSION}"))
+    }
+
+    /// Persists the directory entry itself (the rename or unlink). Best
+    /// effort: not every platform allows opening a directory for sync.
+    async fn sync_dir(&self) {
+        if let Ok(dir) = tokio::fs::File::open(&self.dir).await {
+            let _ = dir.sync_all().await;
+        }
+    }
+}
+
+/// `<millis>-<uuid>`: zero-padded so ids sort by enqueue time, with a random
+/// suffix so concurrent sessions never collide.
+pub(crate) fn new_id() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    format!("{:016}-{}", millis, uuid::Uuid::new_v4().simple())
+}
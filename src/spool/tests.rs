@@ -0,0 +1,134 @@
+use super::*;
+
+fn temp_spool_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("mail-laser-spool-{}", uuid::Uuid::new_v4()))
+}
+
+fn sample_payload(subject: &str) -> EmailPayload {
+    EmailPayload {
+        sender: "sender@example.com".to_string(),
+        sender_name: None,
+        recipient: "target@example.com".to_string(),
+        recipients: vec!["target@example.com".to_string()],
+        subject: subject.to_string(),
+        body: "Body".to_string(),
+        html_body: None,
+        headers: None,
+        attachments: None,
+        dmarc_result: None,
+        authenticated_from: None,
+        authenticated: false,
+        auth_identity: None,
+        ptr_name: None,
+        fcrdns_ok: None,
+        helo_resolves: None,
+        dsn: None,
+    }
+}
+
+#[tokio::test]
+async fn enqueued_entries_are_pending_in_arrival_order() {
+    let dir = temp_spool_dir();
+    let spool = Spool::open(&dir).await.unwrap();
+
+    let first = spool.enqueue(&sample_payload("first")).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+    let second = spool.enqueue(&sample_payload("second")).await.unwrap();
+
+    let pending = spool.pending().await.unwrap();
+    let ids: Vec<_> = pending.iter().map(|e| e.id.clone()).collect();
+    assert_eq!(ids, vec![first, second]);
+    assert_eq!(pending[0].payload.subject, "first");
+    assert_eq!(pending[1].payload.subject, "second");
+
+    tokio::fs::remove_dir_all(&dir).await.ok();
+}
+
+#[tokio::test]
+async fn remove_deletes_entry_and_tolerates_missing() {
+    let dir = temp_spool_dir();
+    let spool = Spool::open(&dir).await.unwrap();
+
+    let id = spool.enqueue(&sample_payload("gone")).await.unwrap();
+    assert!(spool.contains(&id).await);
+
+    spool.remove(&id).await.unwrap();
+    assert!(!spool.contains(&id).await);
+    assert!(spool.pending().await.unwrap().is_empty());
+
+    // A second removal is a no-op, not an error.
+    spool.remove(&id).await.unwrap();
+
+    tokio::fs::remove_dir_all(&dir).await.ok();
+}
+
+#[tokio::test]
+async fn entries_survive_reopening_the_spool() {
+    let dir = temp_spool_dir();
+    let id = {
+        let spool = Spool::open(&dir).await.unwrap();
+        spool.enqueue(&sample_payload("durable")).await.unwrap()
+    };
+
+    let reopened = Spool::open(&dir).await.unwrap();
+    let pending = reopened.pending().await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].id, id);
+    assert_eq!(pending[0].payload.subject, "durable");
+
+    tokio::fs::remove_dir_all(&dir).await.ok();
+}
+
+#[tokio::test]
+async fn open_discards_incomplete_writes() {
+    let dir = temp_spool_dir();
+    tokio::fs::create_dir_all(&dir).await.unwrap();
+    let tmp = dir.join(format!("0000000000000001-abc{TMP_SUFFIX}"));
+    tokio::fs::write(&tmp, b"{\"sender\":").await.unwrap();
+
+    let spool = Spool::open(&dir).await.unwrap();
+    assert!(!tmp.exists(), "incomplete write must be removed on open");
+    assert!(spool.pending().await.unwrap().is_empty());
+
+    tokio::fs::remove_dir_all(&dir).await.ok();
+}
+
+#[tokio::test]
+async fn corrupt_entries_are_set_aside() {
+    let dir = temp_spool_dir();
+    let spool = Spool::open(&dir).await.unwrap();
+    let good = spool.enqueue(&sample_payload("good")).await.unwrap();
+    tokio::fs::write(dir.join("0000000000000001-bad.json"), b"not json")
+        .await
+        .unwrap();
+
+    let pending = spool.pending().await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].id, good);
+    assert!(dir
+        .join(format!("0000000000000001-bad{CORRUPT_SUFFIX}"))
+        .exists());
+
+    tokio::fs::remove_dir_all(&dir).await.ok();
+}
+
+#[test]
+fn entry_age_comes_from_the_id_prefix() {
+    let entry = |id: &str| SpoolEntry {
+        id: id.to_string(),
+        payload: sample_payload("age"),
+    };
+    let now = UNIX_EPOCH + Duration::from_secs(1_700_000_060);
+
+    assert_eq!(
+        entry("0001700000000000-abc").age(now),
+        Some(Duration::from_secs(60))
+    );
+    assert_eq!(
+        entry("0001700000090000-abc").age(now),
+        Some(Duration::ZERO),
+        "an entry from the future is not aged"
+    );
+    assert_eq!(entry("not-a-time").age(now), None);
+    assert!(entry(&new_id()).age(SystemTime::now()).unwrap() < Duration::from_secs(5));
+}
@@ -0,0 +1,74 @@
+//! A `HashMap` that drops dead entries as it grows.
+//!
+//! Used for per-address state that is only worth keeping for a while: the
+//! connection rate limiter's token buckets and the DNSBL result cache. Each
+//! caller decides what "dead" means; the map only decides when to look.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Map size that triggers the first sweep.
+pub(crate) const SWEEP_THRESHOLD: usize = 4096;
+
+pub(crate) struct SweptMap<K, V> {
+    map: HashMap<K, V>,
+    /// Sweeps run when the map reaches this size, which then doubles past
+    /// whatever survived, so sweeping stays amortized O(1) per insert.
+    sweep_at: usize,
+}
+
+impl<K: Eq + Hash, V> Default for SweptMap<K, V> {
+    fn default() -> Self {
+        Self {
+            map: HashMap::new(),
+            sweep_at: SWEEP_THRESHOLD,
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> SweptMap<K, V> {
+    pub(crate) fn get(&self, key: &K) -> Option<&V> {
+        self.map.get(key)
+    }
+
+    /// The entry for `key`. When the map has reached its watermark, every
+    /// entry `live` rejects is dropped first.
+    pub(crate) fn entry(
+        &mut self,
+        key: K,
+        live: impl FnMut(&K, &mut V) -> bool,
+    ) -> Entry<'_, K, V> {
+        if self.map.len() >= self.sweep_at {
+            self.map.retain(live);
+            self.sweep_at = SWEEP_THRESHOLD.max(self.map.len() * 2);
+        }
+        self.map.entry(key)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweeps_only_at_the_watermark_and_then_backs_off() {
+        let mut map = SweptMap::default();
+        for n in 0..SWEEP_THRESHOLD {
+            map.entry(n, |_, _: &mut bool| false).or_insert(n % 4 != 0);
+        }
+        assert_eq!(map.len(), SWEEP_THRESHOLD, "no sweep below the watermark");
+
+        // Three quarters survive, so the next sweep waits for twice that.
+        map.entry(SWEEP_THRESHOLD, |_, live| *live).or_insert(true);
+        assert_eq!(map.len(), SWEEP_THRESHOLD * 3 / 4 + 1);
+        assert_eq!(map.sweep_at, SWEEP_THRESHOLD * 3 / 2);
+        assert_eq!(map.get(&0), None);
+        assert_eq!(map.get(&1), Some(&true));
+    }
+}
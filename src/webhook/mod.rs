@@ -1,5 +1,7 @@
 use crate::attachment::SerializedAttachment;
 use crate::config::Config;
+use crate::dsn::{DsnRequest, DsnWriter};
+use crate::spool::{Spool, SpoolEntry};
 use acton_reactive::prelude::*;
 use anyhow::Result;
 use bytes::Bytes;
@@ -14,9 +16,11 @@ use hyper_util::{
 use log::{error, info};
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
 
 pub const SIGNATURE_HEADER: &str = "x-maillaser-signature-256";
 pub const TIMESTAMP_HEADER: &str = "x-maillaser-timestamp";
@@ -52,6 +56,35 @@ type WebhookHttpClient = Client<HttpsConn, Full<Bytes>>;
 #[acton_message]
 pub struct ForwardEmail {
     pub payload: EmailPayload,
+    /// Id of the spool entry holding this payload, when spooling is enabled.
+    /// The entry is deleted once the webhook accepts the payload.
+    pub spool_id: Option<String>,
+    /// Set in [`crate::config::DeliveryMode::Sync`]: completed with the final
+    /// delivery outcome once retries are exhausted or the webhook accepted.
+    pub notify: Option<DeliveryNotifier>,
+}
+
+/// One-shot channel through which the webhook actor reports whether a
+/// synchronous delivery succeeded. Wrapped so it can travel inside a
+/// (cloneable) actor message; only the first completion is delivered.
+#[derive(Debug, Clone)]
+pub struct DeliveryNotifier(Arc<Mutex<Option<oneshot::Sender<bool>>>>);
+
+impl DeliveryNotifier {
+    /// Returns the notifier to attach to [`ForwardEmail`] and the receiver
+    /// that resolves to `true` on a 2xx webhook response. The receiver errors
+    /// if the actor drops the message without answering (e.g. on shutdown).
+    pub fn new() -> (Self, oneshot::Receiver<bool>) {
+        let (tx, rx) = oneshot::channel();
+        (Self(Arc::new(Mutex::new(Some(tx)))), rx)
+    }
+
+    fn complete(&self, delivered: bool) {
+        let sender = self.0.lock().ok().and_then(|mut slot| slot.take());
+        if let Some(tx) = sender {
+            let _ = tx.send(delivered);
+        }
+    }
 }
 
 #[acton_message]
@@ -61,6 +94,12 @@ struct WebhookResult {
     sender_info: String,
 }
 
+/// Periodic tick asking the actor to re-send every spooled entry that is not
+/// already waiting in its mailbox, and to give up on entries past
+/// `spool_max_age`.
+#[acton_message]
+struct DrainSpool;
+
 // --- Public data structures ---
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -69,6 +108,12 @@ pub struct EmailPayload {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sender_name: Option<String>,
     pub recipient: String,
+    /// Every accepted `RCPT TO` recipient this payload is delivered for. With
+    /// [`crate::config::RecipientDelivery::Combined`] this lists all permitted
+    /// recipients of the message (`recipient` is the first of them); with
+    /// `PerRecipient` it holds just `recipient`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<String>,
     pub subject: String,
     pub body: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -86,6 +131,29 @@ pub struct EmailPayload {
     /// `None` in every other case.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub authenticated_from: Option<String>,
+    /// `true` when the SMTP session authenticated with AUTH before sending.
+    /// Omitted from the JSON when `false`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub authenticated: bool,
+    /// The SMTP AUTH username, present only when `authenticated`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_identity: Option<String>,
+    /// The peer's reverse DNS name, forward-confirmed when `fcrdns_ok`.
+    /// Present only when reverse DNS checks are on and the peer has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ptr_name: Option<String>,
+    /// Whether the peer's reverse DNS name resolves back to its address.
+    /// `None` when reverse DNS checks are off.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fcrdns_ok: Option<bool>,
+    /// Whether the HELO domain resolves to the peer's address. `None` when
+    /// reverse DNS checks are off.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub helo_resolves: Option<bool>,
+    /// DSN parameters (RFC 3461) the client sent on `MAIL FROM` and `RCPT
+    /// TO`, limited to this payload's recipients. `None` when it sent none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dsn: Option<DsnRequest>,
 }
 
 // --- WebhookClient (unchanged transport layer) ---
@@ -102,6 +170,7 @@ impl WebhookClient {
         // Allow plain HTTP only for loopback webhook URLs — the
         // sidecar-on-the-same-task deployment pattern. External hosts still
         // require HTTPS in release builds.
+        #[cfg(not(debug_assertions))]
         let allow_loopback_http = config.webhook_url.starts_with("http://127.0.0.1")
             || config.webhook_url.starts_with("http://localhost")
             || config.webhook_url.starts_with("http://[::1]");
@@ -205,6 +274,15 @@ pub struct WebhookState {
     max_retries: u32,
     circuit_threshold: u32,
     circuit_reset_secs: u64,
+    spool: Option<Arc<Spool>>,
+    /// Age past which a spooled entry is given up instead of re-sent.
+    spool_max_age: Duration,
+    /// Spool ids the drain has queued but the actor has not picked up yet, so
+    /// a slow round during an outage is not queued again by the next tick.
+    drain_queued: Arc<Mutex<HashSet<String>>>,
+    /// Writes RFC 3464 failure reports for asynchronous deliveries that are
+    /// given up; `None` when `MAIL_LASER_DSN_DIR` is unset.
+    dsn: Option<Arc<DsnWriter>>,
 }
 
 impl WebhookState {
@@ -222,16 +300,31 @@ impl WebhookState {
         builder.model.circuit_threshold = config.circuit_breaker_threshold;
         builder.model.circuit_reset_secs = config.circuit_breaker_reset_secs;
 
+        let spool = Spool::from_config(config).await?;
+        builder.model.spool = spool.clone();
+        builder.model.spool_max_age = Duration::from_secs(config.spool_max_age_secs);
+        builder.model.dsn = DsnWriter::from_config(config).await?;
+
         let client = Arc::new(WebhookClient::new(config.clone()));
 
         // ForwardEmail handler: circuit breaker check + async delivery with timeout + retry
         builder.mutate_on::<ForwardEmail>(move |actor, ctx| {
             let client = client.clone();
             let payload = ctx.message().payload.clone();
+            let spool_id = ctx.message().spool_id.clone();
+            let notify = ctx.message().notify.clone();
+            let spool = actor.model.spool.clone();
+            let dsn = actor.model.dsn.clone();
             let timeout_secs = actor.model.webhook_timeout_secs;
             let max_retries = actor.model.max_retries;
             let sender_info = payload.sender.clone();
 
+            if let Some(id) = &spool_id {
+                if let Ok(mut queued) = actor.model.drain_queued.lock() {
+                    queued.remove(id);
+                }
+            }
+
             // Circuit breaker check (synchronous — can mutate state)
             if actor.model.circuit_open {
                 let elapsed = current_time_ms() - actor.model.circuit_opened_at_ms;
@@ -239,9 +332,27 @@ impl WebhookState {
                     actor.model.circuit_open = false;
                     actor.model.consecutive_failures = 0;
                     tracing::info!("Circuit breaker half-open, allowing request");
+                } else if let Some(id) = &spool_id {
+                    // Spooled: nothing is lost, the next drain retries it.
+                    tracing::warn!(
+                        "Circuit breaker OPEN, leaving email from {} in spool ({})",
+                        sender_info,
+                        id
+                    );
+                    return Reply::ready();
                 } else {
                     tracing::warn!("Circuit breaker OPEN, dropping email from {}", sender_info);
                     actor.model.total_failed += 1;
+                    match (&notify, dsn) {
+                        (Some(notify), _) => notify.complete(false),
+                        (None, Some(dsn)) => {
+                            tokio::spawn(async move {
+                                report_failure(&dsn, &payload, "webhook circuit breaker open")
+                                    .await;
+                            });
+                        }
+                        (None, None) => {}
+                    }
                     return Reply::ready();
                 }
             }
@@ -249,7 +360,17 @@ impl WebhookState {
             let self_handle = actor.handle().clone();
 
             Reply::pending(async move {
+                // The drain may have queued an entry whose original delivery
+                // has since completed and removed it.
+                if let (Some(spool), Some(id)) = (&spool, &spool_id) {
+                    if !spool.contains(id).await {
+                        tracing::debug!("Spool entry {} already delivered, skipping", id);
+                        return;
+                    }
+                }
+
                 let mut success = false;
+                let mut last_error = String::new();
                 for attempt in 0..=max_retries {
                     if attempt > 0 {
                         let backoff_ms = 100 * 2u64.pow(attempt - 1);
@@ -270,6 +391,7 @@ impl WebhookState {
                         }
                         Ok(Err(e)) => {
                             tracing::warn!("Webhook attempt {} failed: {:#}", attempt + 1, e);
+                            last_error = format!("{:#}", e);
                         }
                         Err(_) => {
                             tracing::warn!(
@@ -277,6 +399,7 @@ impl WebhookState {
                                 attempt + 1,
                                 timeout_secs
                             );
+                            last_error = format!("timed out after {}s", timeout_secs);
                         }
                     }
                 }
@@ -289,6 +412,35 @@ impl WebhookState {
                     );
                 }
 
+                if let (Some(spool), Some(id)) = (&spool, &spool_id) {
+                    if success {
+                        if let Err(e) = spool.remove(id).await {
+                            tracing::error!(
+                                "Delivered spool entry {} could not be removed: {:#}",
+                                id,
+                                e
+                            );
+                        }
+                    } else {
+                        tracing::warn!("Spool entry {} kept for a later retry", id);
+                    }
+                }
+
+                if let Some(notify) = &notify {
+                    notify.complete(success);
+                }
+
+                // Asynchronous and unspooled: nothing will retry this payload,
+                // so the sender is told it was lost if it asked to be.
+                if let (false, None, None, Some(dsn)) = (success, &spool_id, &notify, &dsn) {
+                    let diagnostic = format!(
+                        "webhook delivery failed after {} attempt(s): {}",
+                        max_retries + 1,
+                        last_error
+                    );
+                    report_failure(dsn, &payload, &diagnostic).await;
+                }
+
                 self_handle
                     .send(WebhookResult {
                         success,
@@ -298,6 +450,55 @@ impl WebhookState {
             })
         });
 
+        // DrainSpool handler: re-send every entry left in the spool by failed
+        // deliveries or a previous run, and give up on those too old to
+        // retry. Runs detached — the handler must not wait on sends into its
+        // own (bounded) mailbox.
+        builder.mutate_on::<DrainSpool>(|actor, _ctx| {
+            let Some(spool) = actor.model.spool.clone() else {
+                return Reply::ready();
+            };
+            let queued = actor.model.drain_queued.clone();
+            let dsn = actor.model.dsn.clone();
+            let max_age = actor.model.spool_max_age;
+            let self_handle = actor.handle().clone();
+
+            tokio::spawn(async move {
+                let entries = match spool.pending().await {
+                    Ok(entries) => entries,
+                    Err(e) => {
+                        tracing::error!("Failed to scan spool: {:#}", e);
+                        return;
+                    }
+                };
+                if !entries.is_empty() {
+                    tracing::info!("Spool drain: {} entry(ies) pending", entries.len());
+                }
+                let now = SystemTime::now();
+                for entry in entries {
+                    if entry.age(now).is_some_and(|age| age > max_age) {
+                        expire_spooled(&spool, dsn.as_deref(), &entry, max_age).await;
+                        continue;
+                    }
+                    let newly_queued = queued
+                        .lock()
+                        .map(|mut q| q.insert(entry.id.clone()))
+                        .unwrap_or(false);
+                    if !newly_queued {
+                        continue;
+                    }
+                    self_handle
+                        .send(ForwardEmail {
+                            payload: entry.payload,
+                            spool_id: Some(entry.id),
+                            notify: None,
+                        })
+                        .await;
+                }
+            });
+            Reply::ready()
+        });
+
         // WebhookResult handler: update circuit breaker state
         builder.mutate_on::<WebhookResult>(|actor, ctx| {
             let result = ctx.message();
@@ -319,6 +520,34 @@ impl WebhookState {
             Reply::ready()
         });
 
+        let cancel = CancellationToken::new();
+        let cancel_for_stop = cancel.clone();
+        let retry_interval = Duration::from_secs(config.spool_retry_interval_secs);
+
+        builder.after_start(move |actor| {
+            if actor.model.spool.is_some() {
+                let self_handle = actor.handle().clone();
+                let cancel = cancel.clone();
+                // The first tick fires immediately, picking up anything a
+                // previous run left behind.
+                tokio::spawn(async move {
+                    let mut ticker = tokio::time::interval(retry_interval);
+                    loop {
+                        tokio::select! {
+                            _ = ticker.tick() => self_handle.send(DrainSpool).await,
+                            _ = cancel.cancelled() => break,
+                        }
+                    }
+                });
+            }
+            Reply::ready()
+        });
+
+        builder.before_stop(move |_| {
+            cancel_for_stop.cancel();
+            Reply::ready()
+        });
+
         builder.after_stop(|actor| {
             tracing::info!(
                 "WebhookActor stopped. Forwarded: {}, Failed: {}",
@@ -332,5 +561,52 @@ impl WebhookState {
     }
 }
 
+/// Gives up on a spooled entry older than `max_age`: writes the failure DSN
+/// when reports are enabled, then deletes the entry.
+async fn expire_spooled(
+    spool: &Spool,
+    dsn: Option<&DsnWriter>,
+    entry: &SpoolEntry,
+    max_age: Duration,
+) {
+    tracing::error!(
+        "Spool entry {} for {} undelivered after {}s, giving up",
+        entry.id,
+        entry.payload.sender,
+        max_age.as_secs()
+    );
+    if let Some(dsn) = dsn {
+        let diagnostic = format!(
+            "webhook delivery not completed within {}s",
+            max_age.as_secs()
+        );
+        report_failure(dsn, &entry.payload, &diagnostic).await;
+    }
+    if let Err(e) = spool.remove(&entry.id).await {
+        tracing::error!(
+            "Expired spool entry {} could not be removed: {:#}",
+            entry.id,
+            e
+        );
+    }
+}
+
+/// Writes the failure DSN for a payload whose delivery was given up.
+async fn report_failure(dsn: &DsnWriter, payload: &EmailPayload, diagnostic: &str) {
+    match dsn.report_failure(payload, diagnostic).await {
+        Ok(Some(path)) => tracing::info!(
+            "Wrote failure DSN for message from {} to {}",
+            payload.sender,
+            path.display()
+        ),
+        Ok(None) => {}
+        Err(e) => tracing::error!(
+            "Failed to write failure DSN for message from {}: {:#}",
+            payload.sender,
+            e
+        ),
+    }
+}
+
 #[cfg(test)]
 mod tests;
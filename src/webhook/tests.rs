@@ -1,5 +1,8 @@
 use super::*;
-use crate::config::{AttachmentDelivery, Config, DmarcMode, DmarcTempErrorAction};
+use crate::config::{
+    AttachmentDelivery, Config, DeliveryMode, DmarcMode, DmarcTempErrorAction, GreylistStage,
+    PeerDenyAction, RecipientDelivery,
+};
 use std::collections::HashMap;
 use std::path::PathBuf;
 
@@ -9,16 +12,28 @@ fn test_config() -> Config {
         target_emails: vec!["test@example.com".to_string()],
         smtp_bind_address: "127.0.0.1".to_string(),
         smtp_port: 2525,
+        smtps_port: None,
+        listeners: vec![],
         health_check_bind_address: "127.0.0.1".to_string(),
         health_check_port: 8080,
         header_prefixes: vec![],
+        recipient_delivery: RecipientDelivery::Combined,
+        delivery_mode: DeliveryMode::Async,
         webhook_timeout_secs: 30,
         webhook_max_retries: 3,
         circuit_breaker_threshold: 5,
         circuit_breaker_reset_secs: 60,
         webhook_signing_secret: None,
+        spool_dir: None,
+        spool_retry_interval_secs: 30,
+        spool_max_age_secs: 432_000,
+        dsn_dir: None,
         cedar_policies_path: PathBuf::from("/tmp/policies.cedar"),
         cedar_entities_path: None,
+        tls_cert_path: None,
+        tls_key_path: None,
+        require_tls: false,
+        auth_credentials_path: None,
         max_message_size_bytes: 26_214_400,
         max_attachment_size_bytes: 10_485_760,
         attachment_delivery: AttachmentDelivery::Inline,
@@ -27,7 +42,36 @@ fn test_config() -> Config {
         dmarc_dns_servers: vec![],
         dmarc_temperror_action: DmarcTempErrorAction::Reject,
         max_concurrent_per_ip: 0,
+        max_sessions: 0,
+        max_connections_per_minute_per_ip: 0,
+        ipv6_limit_prefix_len: 64,
         max_unknown_rcpts_per_session: 0,
+        proxy_protocol_trusted: vec![],
+        xclient_trusted: vec![],
+        greeting_timeout_secs: 60,
+        command_timeout_secs: 300,
+        data_timeout_secs: 180,
+        session_timeout_secs: 1800,
+        peer_allow: vec![],
+        peer_allow_file: None,
+        peer_deny: vec![],
+        peer_deny_file: None,
+        peer_deny_action: PeerDenyAction::Reject,
+        dnsbl_zones: Vec::new(),
+        dnsbl_reject_score: None,
+        dnsbl_timeout_secs: 2,
+        greylist_db: None,
+        greylist_stage: GreylistStage::Rcpt,
+        greylist_delay_secs: 300,
+        greylist_retry_window_secs: 172_800,
+        greylist_pass_ttl_secs: 3_024_000,
+        rdns_checks: false,
+        rdns_timeout_secs: 2,
+        greet_delay_secs: 0,
+        rcpt_tarpit_secs: 0,
+        rcpt_tarpit_max_secs: 30,
+        server_hostname: "localhost".to_string(),
+        hide_product: false,
     }
 }
 
@@ -117,6 +161,7 @@ fn test_email_payload_serialization_all_fields() {
         sender: "sender@example.com".to_string(),
         sender_name: Some("John Doe".to_string()),
         recipient: "recipient@example.com".to_string(),
+        recipients: vec![],
         subject: "Test Subject".to_string(),
         body: "Plain text body".to_string(),
         html_body: Some("<p>HTML body</p>".to_string()),
@@ -124,6 +169,12 @@ fn test_email_payload_serialization_all_fields() {
         attachments: None,
         dmarc_result: None,
         authenticated_from: None,
+        authenticated: false,
+        auth_identity: None,
+        ptr_name: None,
+        fcrdns_ok: None,
+        helo_resolves: None,
+        dsn: None,
     };
 
     let json = serde_json::to_value(&payload).expect("Serialization failed");
@@ -144,6 +195,7 @@ fn test_email_payload_serialization_required_only() {
         sender: "sender@example.com".to_string(),
         sender_name: None,
         recipient: "recipient@example.com".to_string(),
+        recipients: vec![],
         subject: "Test Subject".to_string(),
         body: "Plain text body".to_string(),
         html_body: None,
@@ -151,6 +203,12 @@ fn test_email_payload_serialization_required_only() {
         attachments: None,
         dmarc_result: None,
         authenticated_from: None,
+        authenticated: false,
+        auth_identity: None,
+        ptr_name: None,
+        fcrdns_ok: None,
+        helo_resolves: None,
+        dsn: None,
     };
 
     let json = serde_json::to_value(&payload).expect("Serialization failed");
@@ -175,6 +233,7 @@ fn test_email_payload_deserialization_roundtrip() {
         sender: "roundtrip@example.com".to_string(),
         sender_name: Some("Roundtrip User".to_string()),
         recipient: "dest@example.com".to_string(),
+        recipients: vec![],
         subject: "Roundtrip Test".to_string(),
         body: "This is the body text.".to_string(),
         html_body: Some("<b>Bold body</b>".to_string()),
@@ -182,6 +241,12 @@ fn test_email_payload_deserialization_roundtrip() {
         attachments: None,
         dmarc_result: None,
         authenticated_from: None,
+        authenticated: false,
+        auth_identity: None,
+        ptr_name: None,
+        fcrdns_ok: None,
+        helo_resolves: None,
+        dsn: None,
     };
 
     let json_string = serde_json::to_string(&original).expect("Serialization failed");
@@ -203,6 +268,7 @@ fn test_email_payload_deserialization_roundtrip_required_only() {
         sender: "minimal@example.com".to_string(),
         sender_name: None,
         recipient: "dest@example.com".to_string(),
+        recipients: vec![],
         subject: "Minimal".to_string(),
         body: "Body only.".to_string(),
         html_body: None,
@@ -210,6 +276,12 @@ fn test_email_payload_deserialization_roundtrip_required_only() {
         attachments: None,
         dmarc_result: None,
         authenticated_from: None,
+        authenticated: false,
+        auth_identity: None,
+        ptr_name: None,
+        fcrdns_ok: None,
+        helo_resolves: None,
+        dsn: None,
     };
 
     let json_string = serde_json::to_string(&original).expect("Serialization failed");
@@ -231,6 +303,7 @@ fn test_email_payload_skip_serializing_none_fields() {
         sender: "test@example.com".to_string(),
         sender_name: None,
         recipient: "dest@example.com".to_string(),
+        recipients: vec![],
         subject: "Skip Test".to_string(),
         body: "Body.".to_string(),
         html_body: None,
@@ -238,6 +311,12 @@ fn test_email_payload_skip_serializing_none_fields() {
         attachments: None,
         dmarc_result: None,
         authenticated_from: None,
+        authenticated: false,
+        auth_identity: None,
+        ptr_name: None,
+        fcrdns_ok: None,
+        helo_resolves: None,
+        dsn: None,
     };
 
     let json_string = serde_json::to_string(&payload).expect("Serialization failed");
@@ -259,6 +338,7 @@ fn test_email_payload_with_inline_attachment() {
         sender: "a@x.com".to_string(),
         sender_name: None,
         recipient: "b@x.com".to_string(),
+        recipients: vec![],
         subject: "with attachment".to_string(),
         body: "see attached".to_string(),
         html_body: None,
@@ -274,6 +354,12 @@ fn test_email_payload_with_inline_attachment() {
         }]),
         dmarc_result: None,
         authenticated_from: None,
+        authenticated: false,
+        auth_identity: None,
+        ptr_name: None,
+        fcrdns_ok: None,
+        helo_resolves: None,
+        dsn: None,
     };
 
     let json = serde_json::to_value(&payload).expect("serialize");
@@ -292,6 +378,7 @@ fn test_email_payload_with_s3_attachment_with_presigned() {
         sender: "a@x.com".to_string(),
         sender_name: None,
         recipient: "b@x.com".to_string(),
+        recipients: vec![],
         subject: "s3".to_string(),
         body: "b".to_string(),
         html_body: None,
@@ -308,6 +395,12 @@ fn test_email_payload_with_s3_attachment_with_presigned() {
         }]),
         dmarc_result: None,
         authenticated_from: None,
+        authenticated: false,
+        auth_identity: None,
+        ptr_name: None,
+        fcrdns_ok: None,
+        helo_resolves: None,
+        dsn: None,
     };
 
     let json = serde_json::to_value(&payload).expect("serialize");
@@ -323,6 +416,7 @@ fn test_email_payload_attachments_omitted_when_none() {
         sender: "a@x.com".to_string(),
         sender_name: None,
         recipient: "b@x.com".to_string(),
+        recipients: vec![],
         subject: "s".to_string(),
         body: "b".to_string(),
         html_body: None,
@@ -330,6 +424,12 @@ fn test_email_payload_attachments_omitted_when_none() {
         attachments: None,
         dmarc_result: None,
         authenticated_from: None,
+        authenticated: false,
+        auth_identity: None,
+        ptr_name: None,
+        fcrdns_ok: None,
+        helo_resolves: None,
+        dsn: None,
     };
     let s = serde_json::to_string(&payload).expect("serialize");
     assert!(!s.contains("attachments"));
@@ -341,6 +441,7 @@ fn test_email_payload_json_structure_matches_expected() {
         sender: "s@x.com".to_string(),
         sender_name: Some("S".to_string()),
         recipient: "r@x.com".to_string(),
+        recipients: vec![],
         subject: "Sub".to_string(),
         body: "B".to_string(),
         html_body: Some("<p>H</p>".to_string()),
@@ -348,6 +449,12 @@ fn test_email_payload_json_structure_matches_expected() {
         attachments: None,
         dmarc_result: None,
         authenticated_from: None,
+        authenticated: false,
+        auth_identity: None,
+        ptr_name: None,
+        fcrdns_ok: None,
+        helo_resolves: None,
+        dsn: None,
     };
 
     let json: serde_json::Value = serde_json::to_value(&payload).expect("Serialization failed");
@@ -361,3 +468,83 @@ fn test_email_payload_json_structure_matches_expected() {
     assert!(obj.contains_key("body"));
     assert!(obj.contains_key("html_body"));
 }
+
+#[test]
+fn test_email_payload_serializes_recipients_list() {
+    let payload = EmailPayload {
+        sender: "s@x.com".to_string(),
+        sender_name: None,
+        recipient: "a@x.com".to_string(),
+        recipients: vec!["a@x.com".to_string(), "b@x.com".to_string()],
+        subject: "Sub".to_string(),
+        body: "B".to_string(),
+        html_body: None,
+        headers: None,
+        attachments: None,
+        dmarc_result: None,
+        authenticated_from: None,
+        authenticated: false,
+        auth_identity: None,
+        ptr_name: None,
+        fcrdns_ok: None,
+        helo_resolves: None,
+        dsn: None,
+    };
+
+    let json = serde_json::to_value(&payload).expect("serialize");
+    assert_eq!(json["recipient"], "a@x.com");
+    assert_eq!(
+        json["recipients"],
+        serde_json::json!(["a@x.com", "b@x.com"])
+    );
+
+    let roundtrip: EmailPayload = serde_json::from_value(json).expect("deserialize");
+    assert_eq!(roundtrip.recipients, payload.recipients);
+}
+
+#[tokio::test]
+async fn test_delivery_notifier_reports_first_completion_only() {
+    let (notify, rx) = DeliveryNotifier::new();
+    let clone = notify.clone();
+    notify.complete(true);
+    clone.complete(false);
+    assert!(rx.await.expect("notifier must answer"));
+}
+
+#[tokio::test]
+async fn test_delivery_notifier_dropped_without_answer_errors() {
+    let (notify, rx) = DeliveryNotifier::new();
+    drop(notify);
+    assert!(rx.await.is_err());
+}
+
+#[test]
+fn test_email_payload_serializes_auth_identity_when_authenticated() {
+    let payload = EmailPayload {
+        sender: "noreply@scanner.internal".to_string(),
+        sender_name: None,
+        recipient: "r@x.com".to_string(),
+        recipients: vec![],
+        subject: "Scan".to_string(),
+        body: "B".to_string(),
+        html_body: None,
+        headers: None,
+        attachments: None,
+        dmarc_result: None,
+        authenticated_from: None,
+        authenticated: true,
+        auth_identity: Some("scanner".to_string()),
+        ptr_name: None,
+        fcrdns_ok: None,
+        helo_resolves: None,
+        dsn: None,
+    };
+
+    let json = serde_json::to_value(&payload).expect("serialize");
+    assert_eq!(json["authenticated"], true);
+    assert_eq!(json["auth_identity"], "scanner");
+
+    let roundtrip: EmailPayload = serde_json::from_value(json).expect("deserialize");
+    assert!(roundtrip.authenticated);
+    assert_eq!(roundtrip.auth_identity.as_deref(), Some("scanner"));
+}
@@ -12,12 +12,12 @@ use std::time::Duration;
 
 use acton_reactive::prelude::*;
 use hickory_server::authority::{AuthorityObject, Catalog, ZoneType};
-use hickory_server::proto::rr::rdata::{SOA, TXT};
+use hickory_server::proto::rr::rdata::{A, PTR, SOA, TXT};
 use hickory_server::proto::rr::{LowerName, Name, RData, Record};
 use hickory_server::store::in_memory::InMemoryAuthority;
 use hickory_server::ServerFuture;
 use mail_laser::attachment::{inline::InlineBackend, AttachmentBackend};
-use mail_laser::config::{Config, DmarcMode};
+use mail_laser::config::{Config, DmarcMode, ListenerConfig, ListenerProtocol, ListenerTls};
 use mail_laser::policy::PolicyEngine;
 use mail_laser::smtp::SmtpListenerState;
 use mail_laser::webhook::WebhookState;
@@ -68,6 +68,20 @@ async fn smtp_send_email(
     subject: &str,
     body: &str,
 ) -> anyhow::Result<()> {
+    let reply = smtp_send_email_reply(addr, sender, recipient, subject, body).await?;
+    assert!(reply.starts_with("250"), "DATA end failed: {}", reply);
+    Ok(())
+}
+
+/// Like [`smtp_send_email`], but returns the end-of-DATA reply instead of
+/// asserting it is a `250`.
+async fn smtp_send_email_reply(
+    addr: &str,
+    sender: &str,
+    recipient: &str,
+    subject: &str,
+    body: &str,
+) -> anyhow::Result<String> {
     let stream = TcpStream::connect(addr).await?;
     let (read_half, mut write_half) = tokio::io::split(stream);
     let mut reader = BufReader::new(read_half);
@@ -127,13 +141,12 @@ async fn smtp_send_email(
     write_half.flush().await?;
     line.clear();
     reader.read_line(&mut line).await?;
-    assert!(line.starts_with("250"), "DATA end failed: {}", line);
 
     // QUIT
     write_half.write_all(b"QUIT\r\n").await?;
     write_half.flush().await?;
 
-    Ok(())
+    Ok(line)
 }
 
 async fn start_mockserver() -> (ContainerAsync<GenericImage>, String) {
@@ -263,16 +276,28 @@ fn test_config(smtp_port: u16, webhook_url: &str) -> Config {
         webhook_url: webhook_url.to_string(),
         smtp_bind_address: "127.0.0.1".to_string(),
         smtp_port,
+        smtps_port: None,
+        listeners: vec![],
         health_check_bind_address: "127.0.0.1".to_string(),
         health_check_port: get_free_port(),
         header_prefixes: vec![],
+        recipient_delivery: mail_laser::config::RecipientDelivery::Combined,
+        delivery_mode: mail_laser::config::DeliveryMode::Async,
         webhook_timeout_secs: 10,
         webhook_max_retries: 3,
         circuit_breaker_threshold: 5,
         circuit_breaker_reset_secs: 60,
         webhook_signing_secret: None,
+        spool_dir: None,
+        spool_retry_interval_secs: 30,
+        spool_max_age_secs: 432_000,
+        dsn_dir: None,
         cedar_policies_path: std::path::PathBuf::from("tests/fixtures/integration.cedar"),
         cedar_entities_path: None,
+        tls_cert_path: None,
+        tls_key_path: None,
+        require_tls: false,
+        auth_credentials_path: None,
         max_message_size_bytes: 26_214_400,
         max_attachment_size_bytes: 10_485_760,
         attachment_delivery: mail_laser::config::AttachmentDelivery::Inline,
@@ -281,7 +306,36 @@ fn test_config(smtp_port: u16, webhook_url: &str) -> Config {
         dmarc_dns_servers: vec![],
         dmarc_temperror_action: mail_laser::config::DmarcTempErrorAction::Reject,
         max_concurrent_per_ip: 0,
+        max_sessions: 0,
+        max_connections_per_minute_per_ip: 0,
+        ipv6_limit_prefix_len: 64,
         max_unknown_rcpts_per_session: 0,
+        proxy_protocol_trusted: vec![],
+        xclient_trusted: vec![],
+        greeting_timeout_secs: 60,
+        command_timeout_secs: 300,
+        data_timeout_secs: 180,
+        session_timeout_secs: 1800,
+        peer_allow: vec![],
+        peer_allow_file: None,
+        peer_deny: vec![],
+        peer_deny_file: None,
+        peer_deny_action: mail_laser::config::PeerDenyAction::Reject,
+        dnsbl_zones: Vec::new(),
+        dnsbl_reject_score: None,
+        dnsbl_timeout_secs: 2,
+        greylist_db: None,
+        greylist_stage: mail_laser::config::GreylistStage::Rcpt,
+        greylist_delay_secs: 300,
+        greylist_retry_window_secs: 172_800,
+        greylist_pass_ttl_secs: 3_024_000,
+        rdns_checks: false,
+        rdns_timeout_secs: 2,
+        greet_delay_secs: 0,
+        rcpt_tarpit_secs: 0,
+        rcpt_tarpit_max_secs: 30,
+        server_hostname: "localhost".to_string(),
+        hide_product: false,
     }
 }
 
@@ -348,6 +402,123 @@ async fn start_dns_mock(domain: &str, spf_txt: &str, dmarc_txt: &str) -> String
     addr.to_string()
 }
 
+/// Spins up an in-process DNS authority on 127.0.0.1 serving one blocklist
+/// zone per `(zone, listed IPv4s)` entry, each listing answering
+/// `127.0.0.2`. Returns the bound `ip:port` for `Config.dmarc_dns_servers`.
+async fn start_dnsbl_mock(zones: &[(&str, &[&str])]) -> String {
+    let mut catalog = Catalog::new();
+    for (zone, listed) in zones {
+        let origin = Name::from_ascii(format!("{}.", zone)).expect("zone parses as DNS name");
+        let mut authority = InMemoryAuthority::empty(origin.clone(), ZoneType::Primary, false);
+        let soa_rdata = SOA::new(
+            Name::from_ascii(format!("ns.{}.", zone)).unwrap(),
+            Name::from_ascii(format!("admin.{}.", zone)).unwrap(),
+            1,
+            3600,
+            600,
+            604_800,
+            60,
+        );
+        authority.upsert_mut(
+            Record::from_rdata(origin.clone(), 60, RData::SOA(soa_rdata)),
+            0,
+        );
+        for ip in *listed {
+            let reversed: Vec<&str> = ip.split('.').rev().collect();
+            let name = Name::from_ascii(format!("{}.{}.", reversed.join("."), zone))
+                .expect("listing name parses");
+            authority.upsert_mut(
+                Record::from_rdata(name, 60, RData::A(A::new(127, 0, 0, 2))),
+                1,
+            );
+        }
+        catalog.upsert(
+            LowerName::new(&origin),
+            vec![Arc::new(authority) as Arc<dyn AuthorityObject>],
+        );
+    }
+
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0")
+        .await
+        .expect("DNS mock UDP bind");
+    let addr = socket.local_addr().expect("DNS mock local_addr");
+
+    let mut server = ServerFuture::new(catalog);
+    server.register_socket(socket);
+    tokio::spawn(async move {
+        let _ = server.block_until_done().await;
+    });
+
+    addr.to_string()
+}
+
+/// Spins up an in-process DNS authority on 127.0.0.1 serving `PTR` records
+/// for `(IPv4, name)` pairs under `in-addr.arpa` and `A` records for
+/// `(name, IPv4)` pairs under `zone`. Returns the bound `ip:port` for
+/// `Config.dmarc_dns_servers`.
+async fn start_rdns_mock(zone: &str, ptrs: &[(&str, &str)], addrs: &[(&str, &str)]) -> String {
+    let soa = |origin: &str| {
+        RData::SOA(SOA::new(
+            Name::from_ascii(format!("ns.{}", origin)).unwrap(),
+            Name::from_ascii(format!("admin.{}", origin)).unwrap(),
+            1,
+            3600,
+            600,
+            604_800,
+            60,
+        ))
+    };
+
+    let reverse_origin = Name::from_ascii("in-addr.arpa.").unwrap();
+    let mut reverse = InMemoryAuthority::empty(reverse_origin.clone(), ZoneType::Primary, false);
+    reverse.upsert_mut(
+        Record::from_rdata(reverse_origin.clone(), 60, soa("in-addr.arpa.")),
+        0,
+    );
+    for (ip, name) in ptrs {
+        let reversed: Vec<&str> = ip.split('.').rev().collect();
+        let owner = Name::from_ascii(format!("{}.in-addr.arpa.", reversed.join(".")))
+            .expect("PTR owner parses");
+        let target = Name::from_ascii(format!("{}.", name)).expect("PTR target parses");
+        reverse.upsert_mut(Record::from_rdata(owner, 60, RData::PTR(PTR(target))), 1);
+    }
+
+    let forward_origin = Name::from_ascii(format!("{}.", zone)).expect("zone parses");
+    let mut forward = InMemoryAuthority::empty(forward_origin.clone(), ZoneType::Primary, false);
+    forward.upsert_mut(
+        Record::from_rdata(forward_origin.clone(), 60, soa(&format!("{}.", zone))),
+        0,
+    );
+    for (name, ip) in addrs {
+        let owner = Name::from_ascii(format!("{}.", name)).expect("A owner parses");
+        let ip: std::net::Ipv4Addr = ip.parse().expect("A address parses");
+        forward.upsert_mut(Record::from_rdata(owner, 60, RData::A(A::from(ip))), 1);
+    }
+
+    let mut catalog = Catalog::new();
+    catalog.upsert(
+        LowerName::new(&reverse_origin),
+        vec![Arc::new(reverse) as Arc<dyn AuthorityObject>],
+    );
+    catalog.upsert(
+        LowerName::new(&forward_origin),
+        vec![Arc::new(forward) as Arc<dyn AuthorityObject>],
+    );
+
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0")
+        .await
+        .expect("DNS mock UDP bind");
+    let addr = socket.local_addr().expect("DNS mock local_addr");
+
+    let mut server = ServerFuture::new(catalog);
+    server.register_socket(socket);
+    tokio::spawn(async move {
+        let _ = server.block_until_done().await;
+    });
+
+    addr.to_string()
+}
+
 // --- Tests ---
 
 #[tokio::test]
@@ -680,6 +851,240 @@ async fn test_webhook_retry_on_failure() {
     runtime.shutdown_all().await.ok();
 }
 
+/// In sync mode the end-of-DATA reply reflects the webhook outcome: a failed
+/// delivery answers `451` so the sending MTA retries, a successful one `250`.
+#[tokio::test]
+async fn test_sync_delivery_mode_maps_webhook_failure_to_451() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+
+    // First request → 500, everything after → 200.
+    configure_mockserver(&mock_url, "/webhook", 500, Some(1), Some(10)).await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, Some(5)).await;
+
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(smtp_port, &webhook_url);
+    config.delivery_mode = mail_laser::config::DeliveryMode::Sync;
+    config.webhook_max_retries = 0;
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    let reply = smtp_send_email_reply(
+        &smtp_addr,
+        "sender@test.com",
+        "target@example.com",
+        "Sync Test 1",
+        "Webhook rejects this one",
+    )
+    .await
+    .unwrap();
+    assert!(
+        reply.starts_with("451 4.3.0"),
+        "failed sync delivery must defer with 451, got: {}",
+        reply
+    );
+
+    let reply = smtp_send_email_reply(
+        &smtp_addr,
+        "sender@test.com",
+        "target@example.com",
+        "Sync Test 2",
+        "Webhook accepts this one",
+    )
+    .await
+    .unwrap();
+    assert!(
+        reply.starts_with("250"),
+        "successful sync delivery must 250, got: {}",
+        reply
+    );
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// A message accepted while the webhook is unreachable stays in the spool
+/// across a restart and is delivered by the next run's drain.
+#[tokio::test]
+async fn test_spooled_message_survives_restart() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let spool_dir =
+        std::env::temp_dir().join(format!("mail-laser-it-spool-{}", uuid::Uuid::new_v4()));
+
+    // First run: nothing listens on the webhook port, so delivery fails.
+    let smtp_port = get_free_port();
+    let dead_webhook = format!("http://127.0.0.1:{}/webhook", get_free_port());
+    let mut config = test_config(smtp_port, &dead_webhook);
+    config.webhook_max_retries = 0;
+    config.webhook_timeout_secs = 1;
+    config.spool_dir = Some(spool_dir.clone());
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+    smtp_send_email(
+        &smtp_addr,
+        "sender@test.com",
+        "target@example.com",
+        "Spool Test",
+        "Must survive a restart",
+    )
+    .await
+    .unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    runtime.shutdown_all().await.ok();
+
+    let spooled = std::fs::read_dir(&spool_dir)
+        .unwrap()
+        .filter(|e| {
+            e.as_ref()
+                .map(|e| e.path().extension().is_some_and(|x| x == "json"))
+                .unwrap_or(false)
+        })
+        .count();
+    assert_eq!(spooled, 1, "undelivered message must remain in the spool");
+
+    // Second run: webhook is reachable, the startup drain delivers the entry.
+    let mut config = test_config(get_free_port(), &format!("{}/webhook", mock_url));
+    config.spool_dir = Some(spool_dir.clone());
+    let mut runtime = ActonApp::launch_async().await;
+    let _webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    let requests = loop {
+        let reqs = get_mockserver_requests(&mock_url, "/webhook").await;
+        if !reqs.is_empty() || std::time::Instant::now() > deadline {
+            break reqs;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    assert_eq!(requests.len(), 1, "spooled message must be delivered once");
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let remaining = std::fs::read_dir(&spool_dir).unwrap().count();
+    assert_eq!(
+        remaining, 0,
+        "delivered entry must be removed from the spool"
+    );
+
+    runtime.shutdown_all().await.ok();
+    std::fs::remove_dir_all(&spool_dir).ok();
+}
+
+/// A spooled entry that outlives `spool_max_age_secs` is given up: removed
+/// from the spool, with a failure report for a sender that asked for one.
+#[tokio::test]
+async fn test_expired_spool_entry_is_dropped_with_dsn() {
+    init_crypto();
+    let spool_dir =
+        std::env::temp_dir().join(format!("mail-laser-it-spool-{}", uuid::Uuid::new_v4()));
+    let dsn_dir = std::env::temp_dir().join(format!("mail-laser-it-dsn-{}", uuid::Uuid::new_v4()));
+
+    let smtp_port = get_free_port();
+    let dead_webhook = format!("http://127.0.0.1:{}/webhook", get_free_port());
+    let mut config = test_config(smtp_port, &dead_webhook);
+    config.webhook_max_retries = 0;
+    config.webhook_timeout_secs = 1;
+    config.spool_dir = Some(spool_dir.clone());
+    config.spool_retry_interval_secs = 1;
+    config.spool_max_age_secs = 1;
+    config.dsn_dir = Some(dsn_dir.clone());
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+    let stream = TcpStream::connect(&smtp_addr).await.expect("connect");
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    for (command, expected) in [
+        ("HELO expirer\r\n", "250"),
+        ("MAIL FROM:<sender@test.com>\r\n", "250"),
+        ("RCPT TO:<target@example.com> NOTIFY=FAILURE\r\n", "250"),
+        ("DATA\r\n", "354"),
+        (
+            "From: sender@test.com\r\nTo: target@example.com\r\nSubject: Stale\r\n\r\nOld\r\n.\r\n",
+            "250",
+        ),
+        ("QUIT\r\n", "221"),
+    ] {
+        reader
+            .get_mut()
+            .write_all(command.as_bytes())
+            .await
+            .unwrap();
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with(expected), "got: {}", line);
+    }
+
+    let count = |dir: &std::path::Path, ext: &str| {
+        std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .filter(|e| e.path().extension().is_some_and(|x| x == ext))
+                    .count()
+            })
+            .unwrap_or(0)
+    };
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    while count(&spool_dir, "json") > 0 && std::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(
+        count(&spool_dir, "json"),
+        0,
+        "expired entry must be removed"
+    );
+    assert_eq!(count(&dsn_dir, "eml"), 1, "expired entry must be reported");
+
+    runtime.shutdown_all().await.ok();
+    std::fs::remove_dir_all(&spool_dir).ok();
+    std::fs::remove_dir_all(&dsn_dir).ok();
+}
+
 /// Monitor-mode DMARC smoke test. Drives the full acton pipeline with a
 /// DmarcValidator configured, using the RFC 2606 `.invalid` TLD so the DMARC
 /// record lookup is deterministically NXDOMAIN — producing a `none` outcome
@@ -1162,10 +1567,11 @@ async fn test_per_ip_connection_cap_drops_over_cap_connection() {
     runtime.shutdown_all().await.ok();
 }
 
-/// On hitting the per-session unknown-RCPT cap, the server replies `421` and
-/// closes the connection. Bounds recipient enumeration within a session.
+/// Behind a trusted load balancer, the per-IP cap is keyed on the client
+/// address from the PROXY header, not on the balancer's socket address. A
+/// trusted peer that skips the header is dropped before the greeting.
 #[tokio::test]
-async fn test_unknown_rcpt_cap_closes_session() {
+async fn test_proxy_protocol_uses_client_address_for_per_ip_cap() {
     init_crypto();
     let (_container, mock_url) = start_mockserver().await;
     configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
@@ -1173,8 +1579,8 @@ async fn test_unknown_rcpt_cap_closes_session() {
     let smtp_port = get_free_port();
     let webhook_url = format!("{}/webhook", mock_url);
     let mut config = test_config(smtp_port, &webhook_url);
-    // Cap of 2 → 1st unknown is tolerated (550), 2nd trips the drop (421).
-    config.max_unknown_rcpts_per_session = 2;
+    config.max_concurrent_per_ip = 1;
+    config.proxy_protocol_trusted = vec!["127.0.0.0/8".parse().unwrap()];
 
     let mut runtime = ActonApp::launch_async().await;
     let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
@@ -1192,71 +1598,54 @@ async fn test_unknown_rcpt_cap_closes_session() {
     let smtp_addr = format!("127.0.0.1:{}", smtp_port);
     wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
 
-    let stream = TcpStream::connect(&smtp_addr).await.expect("connect");
-    let (read_half, write_half) = tokio::io::split(stream);
-    let mut reader = BufReader::new(read_half);
-    let mut writer = write_half;
-
-    async fn read_reply(reader: &mut BufReader<tokio::io::ReadHalf<TcpStream>>) -> String {
+    async fn first_line(addr: &str, header: &[u8]) -> (TcpStream, String) {
+        let mut stream = TcpStream::connect(addr).await.expect("connect");
+        stream.write_all(header).await.unwrap();
         let mut line = String::new();
-        tokio::time::timeout(Duration::from_secs(3), reader.read_line(&mut line))
+        let mut reader = BufReader::new(&mut stream);
+        // Closing with unread input (a rejected header) resets the socket
+        // rather than sending EOF; either way nothing was read.
+        let _ = tokio::time::timeout(Duration::from_secs(3), reader.read_line(&mut line))
             .await
-            .expect("server response timed out")
-            .expect("read ok");
-        line
+            .expect("server must answer or close in time");
+        (stream, line)
     }
 
-    let greeting = read_reply(&mut reader).await;
-    assert!(greeting.starts_with("220"), "greeting: {:?}", greeting);
-
-    writer.write_all(b"HELO tester\r\n").await.unwrap();
-    assert!(read_reply(&mut reader).await.starts_with("250"));
+    let (_no_header, line) = first_line(&smtp_addr, b"EHLO direct.example\r\n").await;
+    assert_eq!(
+        line, "",
+        "trusted peer without a PROXY header must be dropped"
+    );
 
-    writer
-        .write_all(b"MAIL FROM:<sender@probe.example>\r\n")
-        .await
-        .unwrap();
-    assert!(read_reply(&mut reader).await.starts_with("250"));
+    let (_held, line) =
+        first_line(&smtp_addr, b"PROXY TCP4 203.0.113.1 127.0.0.1 40000 25\r\n").await;
+    assert!(line.starts_with("220"), "got: {:?}", line);
 
-    // First unknown recipient — tolerated with 550.
-    writer
-        .write_all(b"RCPT TO:<nobody1@example.com>\r\n")
-        .await
-        .unwrap();
-    let first = read_reply(&mut reader).await;
-    assert!(first.starts_with("550"), "first unknown: {:?}", first);
+    let (_same_client, line) =
+        first_line(&smtp_addr, b"PROXY TCP4 203.0.113.1 127.0.0.1 40001 25\r\n").await;
+    assert_eq!(
+        line, "",
+        "second session from the same client exceeds the cap"
+    );
 
-    // Second unknown — hits cap, expect 421 and EOF.
-    writer
-        .write_all(b"RCPT TO:<nobody2@example.com>\r\n")
-        .await
-        .unwrap();
-    let second = read_reply(&mut reader).await;
+    let mut v2 = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+    v2.extend_from_slice(&[203, 0, 113, 2, 127, 0, 0, 1, 0x9c, 0x42, 0x00, 0x19]);
+    let (_other_client, line) = first_line(&smtp_addr, &v2).await;
     assert!(
-        second.starts_with("421"),
-        "cap-triggering unknown should return 421, got: {:?}",
-        second
-    );
-
-    // Server must close the socket after the 421.
-    let mut trailing = String::new();
-    let bytes = tokio::time::timeout(Duration::from_secs(3), reader.read_line(&mut trailing))
-        .await
-        .expect("post-421 read must not hang — server should close the socket")
-        .expect("read returns cleanly");
-    assert_eq!(
-        bytes, 0,
-        "server must close connection after 421, extra bytes: {:?}",
-        trailing
+        line.starts_with("220"),
+        "a different client behind the same proxy has its own slot, got: {:?}",
+        line
     );
 
     runtime.shutdown_all().await.ok();
 }
 
-/// Below the cap, unknown recipients get a standard `550` and the session
-/// continues normally (SMTP conformance: the 550 response is preserved).
+/// The connection rate limit groups IPv6 clients by `/64`, so rotating
+/// addresses within one block does not earn fresh allowance; once every
+/// session slot is taken, further clients are told `421 4.3.2` instead of
+/// being greeted. Clients are distinguished through PROXY headers.
 #[tokio::test]
-async fn test_unknown_rcpt_under_cap_returns_550_without_drop() {
+async fn test_global_session_cap_and_ipv6_prefix_rate_limit() {
     init_crypto();
     let (_container, mock_url) = start_mockserver().await;
     configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
@@ -1264,7 +1653,10 @@ async fn test_unknown_rcpt_under_cap_returns_550_without_drop() {
     let smtp_port = get_free_port();
     let webhook_url = format!("{}/webhook", mock_url);
     let mut config = test_config(smtp_port, &webhook_url);
-    config.max_unknown_rcpts_per_session = 5;
+    config.max_sessions = 2;
+    config.max_connections_per_minute_per_ip = 2;
+    config.ipv6_limit_prefix_len = 64;
+    config.proxy_protocol_trusted = vec!["127.0.0.0/8".parse().unwrap()];
 
     let mut runtime = ActonApp::launch_async().await;
     let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
@@ -1280,90 +1672,74 @@ async fn test_unknown_rcpt_under_cap_returns_550_without_drop() {
     .unwrap();
 
     let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    // The probe sends no PROXY header, so it never reaches admission.
     wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
 
-    let stream = TcpStream::connect(&smtp_addr).await.expect("connect");
-    let (read_half, write_half) = tokio::io::split(stream);
-    let mut reader = BufReader::new(read_half);
-    let mut writer = write_half;
-
-    async fn read_reply(reader: &mut BufReader<tokio::io::ReadHalf<TcpStream>>) -> String {
+    async fn first_line(addr: &str, client: &str) -> (TcpStream, String) {
+        let mut stream = TcpStream::connect(addr).await.expect("connect");
+        let (family, local) = if client.contains(':') {
+            ("TCP6", "::1")
+        } else {
+            ("TCP4", "127.0.0.1")
+        };
+        let header = format!("PROXY {} {} {} 40000 25\r\n", family, client, local);
+        stream.write_all(header.as_bytes()).await.unwrap();
         let mut line = String::new();
-        tokio::time::timeout(Duration::from_secs(3), reader.read_line(&mut line))
+        let mut reader = BufReader::new(&mut stream);
+        let _ = tokio::time::timeout(Duration::from_secs(3), reader.read_line(&mut line))
             .await
-            .expect("server response timed out")
-            .expect("read ok");
-        line
+            .expect("server must answer or close in time");
+        (stream, line)
     }
 
-    assert!(read_reply(&mut reader).await.starts_with("220"));
-    writer.write_all(b"HELO tester\r\n").await.unwrap();
-    assert!(read_reply(&mut reader).await.starts_with("250"));
-    writer
-        .write_all(b"MAIL FROM:<sender@probe.example>\r\n")
-        .await
-        .unwrap();
-    assert!(read_reply(&mut reader).await.starts_with("250"));
+    let (held_one, line) = first_line(&smtp_addr, "2001:db8:1:1::1").await;
+    assert!(line.starts_with("220"), "got: {:?}", line);
+    let (_held_two, line) = first_line(&smtp_addr, "2001:db8:1:1::2").await;
+    assert!(line.starts_with("220"), "got: {:?}", line);
 
-    // Two unknowns — both should get 550 and the session should stay open.
-    for addr in ["<nobody1@example.com>", "<nobody2@example.com>"] {
-        writer
-            .write_all(format!("RCPT TO:{}\r\n", addr).as_bytes())
-            .await
-            .unwrap();
-        let reply = read_reply(&mut reader).await;
-        assert!(reply.starts_with("550"), "expected 550, got: {:?}", reply);
-    }
+    let (_rotated, line) = first_line(&smtp_addr, "2001:db8:1:1::3").await;
+    assert_eq!(
+        line, "",
+        "a third address in the same /64 exceeds the shared rate limit"
+    );
 
-    // A known recipient still works (session not closed).
-    writer
-        .write_all(b"RCPT TO:<target@example.com>\r\n")
-        .await
-        .unwrap();
-    let known = read_reply(&mut reader).await;
+    let (_busy, line) = first_line(&smtp_addr, "203.0.113.5").await;
     assert!(
-        known.starts_with("250"),
-        "known recipient must be accepted after sub-cap 550s, got: {:?}",
-        known
+        line.starts_with("421 4.3.2"),
+        "a new client is refused while both session slots are held, got: {:?}",
+        line
     );
 
-    writer.write_all(b"QUIT\r\n").await.unwrap();
-    let quit = read_reply(&mut reader).await;
-    assert!(quit.starts_with("221"), "expected 221, got: {:?}", quit);
+    drop(held_one);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let (_after, line) = first_line(&smtp_addr, "203.0.113.6").await;
+    assert!(
+        line.starts_with("220"),
+        "closing a session frees its slot, got: {:?}",
+        line
+    );
 
     runtime.shutdown_all().await.ok();
 }
 
-/// Under `DmarcMode::Enforce`, a message whose From-domain publishes a DMARC
-/// record but has neither SPF nor DKIM aligned must be rejected at end-of-DATA
-/// with `550 5.7.1 DMARC policy violation`. Uses an in-process DNS authority
-/// to make the outcome deterministic (system DNS would give `none`/`temperror`
-/// for `.invalid` TLD, which this test cannot exercise).
+/// Peers outside the allowlist, or inside the denylist, are answered with
+/// `554 5.7.1` in place of the greeting. Clients are distinguished through
+/// PROXY headers, so the lists apply to the forwarded client address.
 #[tokio::test]
-async fn test_dmarc_enforce_rejects_fail_with_550() {
+async fn test_peer_allow_and_deny_lists_refuse_before_greeting() {
     init_crypto();
     let (_container, mock_url) = start_mockserver().await;
     configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
 
-    // DNS mock: SPF `-all` forces SPF Fail; no DKIM signature → DKIM None;
-    // DMARC record present → outcome Fail (not NoPolicy).
-    let dns_addr = start_dns_mock(
-        "dmarcfail.example",
-        "v=spf1 -all",
-        "v=DMARC1; p=reject;",
-    )
-    .await;
+    let deny_file = std::env::temp_dir().join(format!("mail-laser-deny-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&deny_file, "# abusive host\n203.0.113.66\n").unwrap();
 
     let smtp_port = get_free_port();
     let webhook_url = format!("{}/webhook", mock_url);
     let mut config = test_config(smtp_port, &webhook_url);
-    config.dmarc_mode = DmarcMode::Enforce;
-    config.dmarc_dns_servers = vec![dns_addr];
-    config.dmarc_dns_timeout_secs = 5;
-
-    let dmarc = mail_laser::dmarc::DmarcValidator::load(&config)
-        .expect("DMARC validator builds in enforce mode")
-        .expect("validator must be Some when mode != Off");
+    config.proxy_protocol_trusted = vec!["127.0.0.0/8".parse().unwrap()];
+    config.peer_allow = vec!["203.0.113.0/24".parse().unwrap()];
+    config.peer_deny_file = Some(deny_file.clone());
 
     let mut runtime = ActonApp::launch_async().await;
     let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
@@ -1373,7 +1749,7 @@ async fn test_dmarc_enforce_rejects_fail_with_550() {
         webhook_handle,
         test_policy(),
         test_backend(),
-        Some(dmarc),
+        None,
     )
     .await
     .unwrap();
@@ -1381,122 +1757,76 @@ async fn test_dmarc_enforce_rejects_fail_with_550() {
     let smtp_addr = format!("127.0.0.1:{}", smtp_port);
     wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
 
-    let stream = TcpStream::connect(&smtp_addr).await.unwrap();
-    let (read_half, mut write_half) = tokio::io::split(stream);
-    let mut reader = BufReader::new(read_half);
-    let mut line = String::new();
-
-    reader.read_line(&mut line).await.unwrap();
-    assert!(line.starts_with("220"));
-
-    write_half
-        .write_all(b"EHLO sender.dmarcfail.example\r\n")
-        .await
-        .unwrap();
-    write_half.flush().await.unwrap();
-    loop {
-        line.clear();
-        reader.read_line(&mut line).await.unwrap();
-        if line.starts_with("250 ") {
-            break;
-        }
+    async fn first_line(addr: &str, client: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.expect("connect");
+        let header = format!("PROXY TCP4 {} 127.0.0.1 40000 25\r\n", client);
+        stream.write_all(header.as_bytes()).await.unwrap();
+        let mut line = String::new();
+        let mut reader = BufReader::new(&mut stream);
+        tokio::time::timeout(Duration::from_secs(3), reader.read_line(&mut line))
+            .await
+            .expect("server must answer in time")
+            .expect("read ok");
+        line
     }
 
-    write_half
-        .write_all(b"MAIL FROM:<sender@dmarcfail.example>\r\n")
-        .await
-        .unwrap();
-    write_half.flush().await.unwrap();
-    line.clear();
-    reader.read_line(&mut line).await.unwrap();
-    assert!(line.starts_with("250"), "MAIL FROM: {}", line);
-
-    write_half
-        .write_all(b"RCPT TO:<target@example.com>\r\n")
-        .await
-        .unwrap();
-    write_half.flush().await.unwrap();
-    line.clear();
-    reader.read_line(&mut line).await.unwrap();
-    assert!(line.starts_with("250"), "RCPT TO: {}", line);
-
-    write_half.write_all(b"DATA\r\n").await.unwrap();
-    write_half.flush().await.unwrap();
-    line.clear();
-    reader.read_line(&mut line).await.unwrap();
-    assert!(line.starts_with("354"), "DATA: {}", line);
-
-    let email_content = "From: sender@dmarcfail.example\r\n\
-                         To: target@example.com\r\n\
-                         Subject: DMARC enforce smoke\r\n\
-                         \r\n\
-                         Body that should be rejected by DMARC enforce.\r\n\
-                         .\r\n";
-    write_half
-        .write_all(email_content.as_bytes())
-        .await
-        .unwrap();
-    write_half.flush().await.unwrap();
+    let line = first_line(&smtp_addr, "203.0.113.5").await;
+    assert!(line.starts_with("220"), "allowed client, got: {:?}", line);
 
-    line.clear();
-    reader.read_line(&mut line).await.unwrap();
+    let line = first_line(&smtp_addr, "192.0.2.1").await;
     assert!(
-        line.starts_with("550 5.7.1"),
-        "enforce-mode DMARC fail must 550 5.7.1 at end-of-DATA, got: {}",
+        line.starts_with("554 5.7.1"),
+        "client outside the allowlist, got: {:?}",
         line
     );
+
+    let line = first_line(&smtp_addr, "203.0.113.66").await;
     assert!(
-        line.to_ascii_lowercase().contains("dmarc"),
-        "reply should mention DMARC, got: {}",
+        line.starts_with("554 5.7.1"),
+        "client in the deny file, got: {:?}",
         line
     );
 
-    write_half.write_all(b"QUIT\r\n").await.unwrap();
-    write_half.flush().await.unwrap();
-
-    tokio::time::sleep(Duration::from_secs(1)).await;
-
-    let requests = get_mockserver_requests(&mock_url, "/webhook").await;
-    assert_eq!(
-        requests.len(),
-        0,
-        "rejected message must not hit the webhook (got {})",
-        requests.len()
-    );
-
     runtime.shutdown_all().await.ok();
+    std::fs::remove_file(&deny_file).ok();
 }
 
-/// Positive counterpart to `test_cedar_denies_at_end_of_data_when_dmarc_context_missing`:
-/// with DMARC fully passing and the `dmarc_pass_required_policy` in force,
-/// Cedar must allow SendMail, the message must be forwarded, and the webhook
-/// payload must carry `dmarc_result=pass` plus a populated `authenticated_from`.
+/// A peer whose DNSBL score reaches `dnsbl_reject_score` gets `554 5.7.1`
+/// in place of the greeting; a lower score is only exposed to Cedar, here
+/// refusing `MAIL FROM` for any listed peer. Clients are distinguished
+/// through PROXY headers, since loopback peers are never looked up.
 #[tokio::test]
-async fn test_cedar_allows_when_dmarc_passes() {
+async fn test_dnsbl_score_rejects_or_feeds_policy() {
     init_crypto();
     let (_container, mock_url) = start_mockserver().await;
     configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
 
-    // SPF explicitly authorizes the loopback IP → SPF Pass → DMARC Pass
-    // (DKIM can remain absent because SPF alone is enough for alignment when
-    // the envelope-from domain matches the From-header domain).
-    let dns_addr = start_dns_mock(
-        "dmarcpass.example",
-        "v=spf1 ip4:127.0.0.1 -all",
-        "v=DMARC1; p=reject;",
-    )
+    let dns_addr = start_dnsbl_mock(&[
+        ("heavy.bl.test", &["192.0.2.10"]),
+        ("light.bl.test", &["192.0.2.10", "192.0.2.11"]),
+    ])
     .await;
 
+    let policy = Arc::new(
+        PolicyEngine::from_strings(
+            r#"
+            permit(principal, action == Action::"SendMail", resource);
+            permit(principal, action == Action::"MailFrom", resource)
+              when { context.dnsbl_score == 0 && context.dnsbl_listed_on.isEmpty() };
+            "#,
+            None,
+        )
+        .expect("dnsbl policy parses"),
+    );
+
     let smtp_port = get_free_port();
     let webhook_url = format!("{}/webhook", mock_url);
     let mut config = test_config(smtp_port, &webhook_url);
-    config.dmarc_mode = DmarcMode::Enforce;
+    config.proxy_protocol_trusted = vec!["127.0.0.0/8".parse().unwrap()];
     config.dmarc_dns_servers = vec![dns_addr];
-    config.dmarc_dns_timeout_secs = 5;
-
-    let dmarc = mail_laser::dmarc::DmarcValidator::load(&config)
-        .expect("DMARC validator builds")
-        .expect("validator must be Some when mode != Off");
+    config.dnsbl_zones =
+        mail_laser::config::DnsblZone::parse_list("heavy.bl.test=3,light.bl.test").unwrap();
+    config.dnsbl_reject_score = Some(4);
 
     let mut runtime = ActonApp::launch_async().await;
     let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
@@ -1504,9 +1834,9 @@ async fn test_cedar_allows_when_dmarc_passes() {
         &mut runtime,
         &config,
         webhook_handle,
-        dmarc_pass_required_policy(),
+        policy,
         test_backend(),
-        Some(dmarc),
+        None,
     )
     .await
     .unwrap();
@@ -1514,71 +1844,772 @@ async fn test_cedar_allows_when_dmarc_passes() {
     let smtp_addr = format!("127.0.0.1:{}", smtp_port);
     wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
 
-    smtp_send_email(
-        &smtp_addr,
-        "sender@dmarcpass.example",
-        "target@example.com",
-        "DMARC pass + Cedar allow",
-        "Hello from an aligned, SPF-passing sender.",
-    )
-    .await
-    .expect("DMARC-passing message must be accepted end-to-end");
+    async fn read_reply(reader: &mut BufReader<tokio::io::ReadHalf<TcpStream>>) -> String {
+        let mut line = String::new();
+        tokio::time::timeout(Duration::from_secs(5), reader.read_line(&mut line))
+            .await
+            .expect("server must answer in time")
+            .expect("read ok");
+        line
+    }
 
-    // Poll the mockserver — webhook delivery is async relative to DATA ack.
-    let deadline = std::time::Instant::now() + Duration::from_secs(5);
-    let requests = loop {
-        let reqs = get_mockserver_requests(&mock_url, "/webhook").await;
-        if !reqs.is_empty() || std::time::Instant::now() > deadline {
-            break reqs;
+    // Returns the first reply, then the reply to MAIL FROM when greeted.
+    async fn converse(addr: &str, client: &str) -> (String, Option<String>) {
+        let stream = TcpStream::connect(addr).await.expect("connect");
+        let (read_half, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(read_half);
+        let header = format!("PROXY TCP4 {} 127.0.0.1 40000 25\r\n", client);
+        writer.write_all(header.as_bytes()).await.unwrap();
+        let first = read_reply(&mut reader).await;
+        if !first.starts_with("220") {
+            return (first, None);
         }
-        tokio::time::sleep(Duration::from_millis(100)).await;
-    };
+        writer.write_all(b"HELO tester\r\n").await.unwrap();
+        read_reply(&mut reader).await;
+        writer
+            .write_all(b"MAIL FROM:<sender@example.com>\r\n")
+            .await
+            .unwrap();
+        (first, Some(read_reply(&mut reader).await))
+    }
 
-    assert_eq!(
-        requests.len(),
-        1,
-        "Cedar-allowed message must be forwarded exactly once, got {}",
-        requests.len()
+    let (first, _) = converse(&smtp_addr, "192.0.2.10").await;
+    assert!(
+        first.starts_with("554 5.7.1") && first.contains("heavy.bl.test"),
+        "score 4 reaches the reject score, got: {:?}",
+        first
     );
 
-    let req = &requests[0];
-    let body_json: serde_json::Value = if let Some(json_val) = req["body"]["json"].as_object() {
-        serde_json::Value::Object(json_val.clone())
-    } else if let Some(s) = req["body"]["string"].as_str() {
-        serde_json::from_str(s).expect("Webhook body should be valid JSON")
-    } else if let Some(s) = req["body"].as_str() {
-        serde_json::from_str(s).expect("Webhook body should be valid JSON")
-    } else {
-        panic!(
-            "Could not extract body: {}",
-            serde_json::to_string_pretty(&req["body"]).unwrap_or_default()
-        );
-    };
-    assert_eq!(
-        body_json["dmarc_result"].as_str(),
-        Some("pass"),
-        "dmarc_result must be pass in payload: {}",
-        body_json
+    let (first, mail_from) = converse(&smtp_addr, "192.0.2.11").await;
+    assert!(first.starts_with("220"), "score 1 is greeted, got: {:?}", first);
+    let mail_from = mail_from.unwrap();
+    assert!(
+        mail_from.starts_with("550"),
+        "Cedar sees the listing, got: {:?}",
+        mail_from
     );
-    assert_eq!(
-        body_json["authenticated_from"].as_str(),
-        Some("sender@dmarcpass.example"),
-        "authenticated_from must be the aligned From address: {}",
-        body_json
+
+    let (first, mail_from) = converse(&smtp_addr, "192.0.2.12").await;
+    assert!(first.starts_with("220"), "clean client, got: {:?}", first);
+    let mail_from = mail_from.unwrap();
+    assert!(
+        mail_from.starts_with("250"),
+        "clean client may send, got: {:?}",
+        mail_from
     );
 
     runtime.shutdown_all().await.ok();
 }
 
-/// rustls `ServerCertVerifier` that accepts any certificate. Test-only — the
-/// server generates a self-signed cert on every STARTTLS upgrade, so there is
-/// nothing stable to pin to and no CA anchor is meaningful for this harness.
-#[derive(Debug)]
-struct AcceptAnyServerCert;
+/// Greylisting defers a new triplet with `451 4.7.1` until a retry after the
+/// delay, lets Cedar exempt senders via `SkipGreylist`, and keeps passed
+/// triplets across a restart, where it then runs at end-of-DATA.
+#[tokio::test]
+async fn test_greylisting_defers_first_attempt_and_persists_passes() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
 
-impl ServerCertVerifier for AcceptAnyServerCert {
-    fn verify_server_cert(
-        &self,
+    let greylist_dir =
+        std::env::temp_dir().join(format!("mail-laser-it-greylist-{}", uuid::Uuid::new_v4()));
+    let policy = Arc::new(
+        PolicyEngine::from_strings(
+            r#"
+            permit(principal, action == Action::"SendMail", resource);
+            permit(
+              principal == User::"trusted@partner.example",
+              action == Action::"SkipGreylist",
+              resource
+            );
+            "#,
+            None,
+        )
+        .expect("greylist policy parses"),
+    );
+
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(smtp_port, &webhook_url);
+    config.greylist_db = Some(greylist_dir.join("greylist.jsonl"));
+    config.greylist_delay_secs = 1;
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        policy.clone(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    // Returns the reply to RCPT TO.
+    async fn rcpt_reply(addr: &str, sender: &str) -> String {
+        let stream = TcpStream::connect(addr).await.expect("connect");
+        let (read_half, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(read_half);
+        let mut line = String::new();
+        for command in [
+            "HELO tester".to_string(),
+            format!("MAIL FROM:<{}>", sender),
+            "RCPT TO:<target@example.com>".to_string(),
+        ] {
+            line.clear();
+            reader.read_line(&mut line).await.expect("read ok");
+            writer
+                .write_all(format!("{}\r\n", command).as_bytes())
+                .await
+                .unwrap();
+        }
+        line.clear();
+        reader.read_line(&mut line).await.expect("read ok");
+        writer.write_all(b"QUIT\r\n").await.ok();
+        line
+    }
+
+    let reply = rcpt_reply(&smtp_addr, "sender@example.com").await;
+    assert!(
+        reply.starts_with("451 4.7.1"),
+        "first attempt is greylisted, got: {:?}",
+        reply
+    );
+    let reply = rcpt_reply(&smtp_addr, "trusted@partner.example").await;
+    assert!(
+        reply.starts_with("250"),
+        "SkipGreylist exempts the sender, got: {:?}",
+        reply
+    );
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let reply = rcpt_reply(&smtp_addr, "sender@example.com").await;
+    assert!(
+        reply.starts_with("250"),
+        "a retry after the delay passes, got: {:?}",
+        reply
+    );
+    runtime.shutdown_all().await.ok();
+
+    // Second run, greylisting at end-of-DATA over the same store.
+    let smtp_port = get_free_port();
+    let mut config = test_config(smtp_port, &webhook_url);
+    config.greylist_db = Some(greylist_dir.join("greylist.jsonl"));
+    config.greylist_stage = mail_laser::config::GreylistStage::Data;
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        policy,
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    let reply = smtp_send_email_reply(
+        &smtp_addr,
+        "sender@example.com",
+        "target@example.com",
+        "Greylist",
+        "Passed before the restart",
+    )
+    .await
+    .unwrap();
+    assert!(
+        reply.starts_with("250"),
+        "the passed triplet survives a restart, got: {:?}",
+        reply
+    );
+    let reply = smtp_send_email_reply(
+        &smtp_addr,
+        "other@example.com",
+        "target@example.com",
+        "Greylist",
+        "Never seen before",
+    )
+    .await
+    .unwrap();
+    assert!(
+        reply.starts_with("451 4.7.1"),
+        "a new triplet is greylisted at end-of-DATA, got: {:?}",
+        reply
+    );
+
+    runtime.shutdown_all().await.ok();
+    std::fs::remove_dir_all(&greylist_dir).ok();
+}
+
+/// With reverse DNS checks on, Cedar sees whether the peer's PTR name is
+/// forward-confirmed and whether its HELO resolves to it, and the webhook
+/// payload carries the same facts.
+#[tokio::test]
+async fn test_fcrdns_and_helo_checks_feed_policy_and_payload() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let dns_addr = start_rdns_mock(
+        "rdns.test",
+        &[
+            ("192.0.2.10", "mail.rdns.test"),
+            ("192.0.2.11", "forged.rdns.test"),
+        ],
+        &[
+            ("mail.rdns.test", "192.0.2.10"),
+            ("forged.rdns.test", "198.51.100.1"),
+            ("helo.rdns.test", "192.0.2.11"),
+        ],
+    )
+    .await;
+
+    let policy = Arc::new(
+        PolicyEngine::from_strings(
+            r#"
+            permit(principal, action == Action::"SendMail", resource)
+              when { context.fcrdns_ok && context.ptr_name == "mail.rdns.test" };
+            permit(principal, action == Action::"SendMail", resource)
+              when { context.helo_resolves };
+            "#,
+            None,
+        )
+        .expect("rdns policy parses"),
+    );
+
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(smtp_port, &webhook_url);
+    config.proxy_protocol_trusted = vec!["127.0.0.0/8".parse().unwrap()];
+    config.dmarc_dns_servers = vec![dns_addr];
+    config.rdns_checks = true;
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        policy,
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    // Sends one message as `client` (via a PROXY header) announcing `helo`;
+    // returns the end-of-DATA reply.
+    async fn send_as(addr: &str, client: &str, helo: &str) -> String {
+        let stream = TcpStream::connect(addr).await.expect("connect");
+        let (read_half, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(read_half);
+        let header = format!("PROXY TCP4 {} 127.0.0.1 40000 25\r\n", client);
+        writer.write_all(header.as_bytes()).await.unwrap();
+        let mut line = String::new();
+        for command in [
+            format!("HELO {}", helo),
+            "MAIL FROM:<sender@example.com>".to_string(),
+            "RCPT TO:<target@example.com>".to_string(),
+            "DATA".to_string(),
+            "Subject: rdns\r\n\r\nBody\r\n.".to_string(),
+        ] {
+            line.clear();
+            reader.read_line(&mut line).await.expect("read ok");
+            writer
+                .write_all(format!("{}\r\n", command).as_bytes())
+                .await
+                .unwrap();
+        }
+        line.clear();
+        tokio::time::timeout(Duration::from_secs(10), reader.read_line(&mut line))
+            .await
+            .expect("server must answer in time")
+            .expect("read ok");
+        writer.write_all(b"QUIT\r\n").await.ok();
+        line
+    }
+
+    let reply = send_as(&smtp_addr, "192.0.2.10", "tester").await;
+    assert!(
+        reply.starts_with("250"),
+        "a forward-confirmed PTR name is permitted, got: {:?}",
+        reply
+    );
+    let reply = send_as(&smtp_addr, "192.0.2.11", "helo.rdns.test").await;
+    assert!(
+        reply.starts_with("250"),
+        "a HELO resolving to the peer is permitted, got: {:?}",
+        reply
+    );
+    let reply = send_as(&smtp_addr, "192.0.2.12", "helo.rdns.test").await;
+    assert!(
+        reply.starts_with("550 5.7.1"),
+        "a HELO resolving elsewhere proves nothing, got: {:?}",
+        reply
+    );
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    let requests = loop {
+        let reqs = get_mockserver_requests(&mock_url, "/webhook").await;
+        if reqs.len() >= 2 || std::time::Instant::now() > deadline {
+            break reqs;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    assert_eq!(requests.len(), 2, "both permitted messages are delivered");
+    let mut payloads: Vec<serde_json::Value> = requests
+        .iter()
+        .map(|req| {
+            if let Some(json_val) = req["body"]["json"].as_object() {
+                serde_json::Value::Object(json_val.clone())
+            } else if let Some(s) = req["body"]["string"].as_str() {
+                serde_json::from_str(s).expect("Webhook body should be valid JSON")
+            } else {
+                panic!("Could not extract body: {}", req["body"]);
+            }
+        })
+        .collect();
+    payloads.sort_by_key(|p| p["ptr_name"].as_str().unwrap_or_default().to_string());
+
+    assert_eq!(payloads[0]["ptr_name"], "forged.rdns.test");
+    assert_eq!(payloads[0]["fcrdns_ok"], false);
+    assert_eq!(payloads[0]["helo_resolves"], true);
+    assert_eq!(payloads[1]["ptr_name"], "mail.rdns.test");
+    assert_eq!(payloads[1]["fcrdns_ok"], true);
+    assert_eq!(payloads[1]["helo_resolves"], false);
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// On hitting the per-session unknown-RCPT cap, the server replies `421` and
+/// closes the connection. Bounds recipient enumeration within a session.
+#[tokio::test]
+async fn test_unknown_rcpt_cap_closes_session() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(smtp_port, &webhook_url);
+    // Cap of 2 → 1st unknown is tolerated (550), 2nd trips the drop (421).
+    config.max_unknown_rcpts_per_session = 2;
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    let stream = TcpStream::connect(&smtp_addr).await.expect("connect");
+    let (read_half, write_half) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+    let mut writer = write_half;
+
+    async fn read_reply(reader: &mut BufReader<tokio::io::ReadHalf<TcpStream>>) -> String {
+        let mut line = String::new();
+        tokio::time::timeout(Duration::from_secs(3), reader.read_line(&mut line))
+            .await
+            .expect("server response timed out")
+            .expect("read ok");
+        line
+    }
+
+    let greeting = read_reply(&mut reader).await;
+    assert!(greeting.starts_with("220"), "greeting: {:?}", greeting);
+
+    writer.write_all(b"HELO tester\r\n").await.unwrap();
+    assert!(read_reply(&mut reader).await.starts_with("250"));
+
+    writer
+        .write_all(b"MAIL FROM:<sender@probe.example>\r\n")
+        .await
+        .unwrap();
+    assert!(read_reply(&mut reader).await.starts_with("250"));
+
+    // First unknown recipient — tolerated with 550.
+    writer
+        .write_all(b"RCPT TO:<nobody1@example.com>\r\n")
+        .await
+        .unwrap();
+    let first = read_reply(&mut reader).await;
+    assert!(first.starts_with("550"), "first unknown: {:?}", first);
+
+    // Second unknown — hits cap, expect 421 and EOF.
+    writer
+        .write_all(b"RCPT TO:<nobody2@example.com>\r\n")
+        .await
+        .unwrap();
+    let second = read_reply(&mut reader).await;
+    assert!(
+        second.starts_with("421"),
+        "cap-triggering unknown should return 421, got: {:?}",
+        second
+    );
+
+    // Server must close the socket after the 421.
+    let mut trailing = String::new();
+    let bytes = tokio::time::timeout(Duration::from_secs(3), reader.read_line(&mut trailing))
+        .await
+        .expect("post-421 read must not hang — server should close the socket")
+        .expect("read returns cleanly");
+    assert_eq!(
+        bytes, 0,
+        "server must close connection after 421, extra bytes: {:?}",
+        trailing
+    );
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// Below the cap, unknown recipients get a standard `550` and the session
+/// continues normally (SMTP conformance: the 550 response is preserved).
+#[tokio::test]
+async fn test_unknown_rcpt_under_cap_returns_550_without_drop() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(smtp_port, &webhook_url);
+    config.max_unknown_rcpts_per_session = 5;
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    let stream = TcpStream::connect(&smtp_addr).await.expect("connect");
+    let (read_half, write_half) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+    let mut writer = write_half;
+
+    async fn read_reply(reader: &mut BufReader<tokio::io::ReadHalf<TcpStream>>) -> String {
+        let mut line = String::new();
+        tokio::time::timeout(Duration::from_secs(3), reader.read_line(&mut line))
+            .await
+            .expect("server response timed out")
+            .expect("read ok");
+        line
+    }
+
+    assert!(read_reply(&mut reader).await.starts_with("220"));
+    writer.write_all(b"HELO tester\r\n").await.unwrap();
+    assert!(read_reply(&mut reader).await.starts_with("250"));
+    writer
+        .write_all(b"MAIL FROM:<sender@probe.example>\r\n")
+        .await
+        .unwrap();
+    assert!(read_reply(&mut reader).await.starts_with("250"));
+
+    // Two unknowns — both should get 550 and the session should stay open.
+    for addr in ["<nobody1@example.com>", "<nobody2@example.com>"] {
+        writer
+            .write_all(format!("RCPT TO:{}\r\n", addr).as_bytes())
+            .await
+            .unwrap();
+        let reply = read_reply(&mut reader).await;
+        assert!(reply.starts_with("550"), "expected 550, got: {:?}", reply);
+    }
+
+    // A known recipient still works (session not closed).
+    writer
+        .write_all(b"RCPT TO:<target@example.com>\r\n")
+        .await
+        .unwrap();
+    let known = read_reply(&mut reader).await;
+    assert!(
+        known.starts_with("250"),
+        "known recipient must be accepted after sub-cap 550s, got: {:?}",
+        known
+    );
+
+    writer.write_all(b"QUIT\r\n").await.unwrap();
+    let quit = read_reply(&mut reader).await;
+    assert!(quit.starts_with("221"), "expected 221, got: {:?}", quit);
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// Under `DmarcMode::Enforce`, a message whose From-domain publishes a DMARC
+/// record but has neither SPF nor DKIM aligned must be rejected at end-of-DATA
+/// with `550 5.7.1 DMARC policy violation`. Uses an in-process DNS authority
+/// to make the outcome deterministic (system DNS would give `none`/`temperror`
+/// for `.invalid` TLD, which this test cannot exercise).
+#[tokio::test]
+async fn test_dmarc_enforce_rejects_fail_with_550() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    // DNS mock: SPF `-all` forces SPF Fail; no DKIM signature → DKIM None;
+    // DMARC record present → outcome Fail (not NoPolicy).
+    let dns_addr = start_dns_mock(
+        "dmarcfail.example",
+        "v=spf1 -all",
+        "v=DMARC1; p=reject;",
+    )
+    .await;
+
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(smtp_port, &webhook_url);
+    config.dmarc_mode = DmarcMode::Enforce;
+    config.dmarc_dns_servers = vec![dns_addr];
+    config.dmarc_dns_timeout_secs = 5;
+
+    let dmarc = mail_laser::dmarc::DmarcValidator::load(&config)
+        .expect("DMARC validator builds in enforce mode")
+        .expect("validator must be Some when mode != Off");
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        Some(dmarc),
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    let stream = TcpStream::connect(&smtp_addr).await.unwrap();
+    let (read_half, mut write_half) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+    let mut line = String::new();
+
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("220"));
+
+    write_half
+        .write_all(b"EHLO sender.dmarcfail.example\r\n")
+        .await
+        .unwrap();
+    write_half.flush().await.unwrap();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        if line.starts_with("250 ") {
+            break;
+        }
+    }
+
+    write_half
+        .write_all(b"MAIL FROM:<sender@dmarcfail.example>\r\n")
+        .await
+        .unwrap();
+    write_half.flush().await.unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("250"), "MAIL FROM: {}", line);
+
+    write_half
+        .write_all(b"RCPT TO:<target@example.com>\r\n")
+        .await
+        .unwrap();
+    write_half.flush().await.unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("250"), "RCPT TO: {}", line);
+
+    write_half.write_all(b"DATA\r\n").await.unwrap();
+    write_half.flush().await.unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("354"), "DATA: {}", line);
+
+    let email_content = "From: sender@dmarcfail.example\r\n\
+                         To: target@example.com\r\n\
+                         Subject: DMARC enforce smoke\r\n\
+                         \r\n\
+                         Body that should be rejected by DMARC enforce.\r\n\
+                         .\r\n";
+    write_half
+        .write_all(email_content.as_bytes())
+        .await
+        .unwrap();
+    write_half.flush().await.unwrap();
+
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(
+        line.starts_with("550 5.7.1"),
+        "enforce-mode DMARC fail must 550 5.7.1 at end-of-DATA, got: {}",
+        line
+    );
+    assert!(
+        line.to_ascii_lowercase().contains("dmarc"),
+        "reply should mention DMARC, got: {}",
+        line
+    );
+
+    write_half.write_all(b"QUIT\r\n").await.unwrap();
+    write_half.flush().await.unwrap();
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let requests = get_mockserver_requests(&mock_url, "/webhook").await;
+    assert_eq!(
+        requests.len(),
+        0,
+        "rejected message must not hit the webhook (got {})",
+        requests.len()
+    );
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// Positive counterpart to `test_cedar_denies_at_end_of_data_when_dmarc_context_missing`:
+/// with DMARC fully passing and the `dmarc_pass_required_policy` in force,
+/// Cedar must allow SendMail, the message must be forwarded, and the webhook
+/// payload must carry `dmarc_result=pass` plus a populated `authenticated_from`.
+#[tokio::test]
+async fn test_cedar_allows_when_dmarc_passes() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    // SPF explicitly authorizes the loopback IP → SPF Pass → DMARC Pass
+    // (DKIM can remain absent because SPF alone is enough for alignment when
+    // the envelope-from domain matches the From-header domain).
+    let dns_addr = start_dns_mock(
+        "dmarcpass.example",
+        "v=spf1 ip4:127.0.0.1 -all",
+        "v=DMARC1; p=reject;",
+    )
+    .await;
+
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(smtp_port, &webhook_url);
+    config.dmarc_mode = DmarcMode::Enforce;
+    config.dmarc_dns_servers = vec![dns_addr];
+    config.dmarc_dns_timeout_secs = 5;
+
+    let dmarc = mail_laser::dmarc::DmarcValidator::load(&config)
+        .expect("DMARC validator builds")
+        .expect("validator must be Some when mode != Off");
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        dmarc_pass_required_policy(),
+        test_backend(),
+        Some(dmarc),
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    smtp_send_email(
+        &smtp_addr,
+        "sender@dmarcpass.example",
+        "target@example.com",
+        "DMARC pass + Cedar allow",
+        "Hello from an aligned, SPF-passing sender.",
+    )
+    .await
+    .expect("DMARC-passing message must be accepted end-to-end");
+
+    // Poll the mockserver — webhook delivery is async relative to DATA ack.
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    let requests = loop {
+        let reqs = get_mockserver_requests(&mock_url, "/webhook").await;
+        if !reqs.is_empty() || std::time::Instant::now() > deadline {
+            break reqs;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+
+    assert_eq!(
+        requests.len(),
+        1,
+        "Cedar-allowed message must be forwarded exactly once, got {}",
+        requests.len()
+    );
+
+    let req = &requests[0];
+    let body_json: serde_json::Value = if let Some(json_val) = req["body"]["json"].as_object() {
+        serde_json::Value::Object(json_val.clone())
+    } else if let Some(s) = req["body"]["string"].as_str() {
+        serde_json::from_str(s).expect("Webhook body should be valid JSON")
+    } else if let Some(s) = req["body"].as_str() {
+        serde_json::from_str(s).expect("Webhook body should be valid JSON")
+    } else {
+        panic!(
+            "Could not extract body: {}",
+            serde_json::to_string_pretty(&req["body"]).unwrap_or_default()
+        );
+    };
+    assert_eq!(
+        body_json["dmarc_result"].as_str(),
+        Some("pass"),
+        "dmarc_result must be pass in payload: {}",
+        body_json
+    );
+    assert_eq!(
+        body_json["authenticated_from"].as_str(),
+        Some("sender@dmarcpass.example"),
+        "authenticated_from must be the aligned From address: {}",
+        body_json
+    );
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// rustls `ServerCertVerifier` that accepts any certificate. Test-only — the
+/// server generates a self-signed cert on every STARTTLS upgrade, so there is
+/// nothing stable to pin to and no CA anchor is meaningful for this harness.
+#[derive(Debug)]
+struct AcceptAnyServerCert;
+
+impl ServerCertVerifier for AcceptAnyServerCert {
+    fn verify_server_cert(
+        &self,
         _end_entity: &CertificateDer<'_>,
         _intermediates: &[CertificateDer<'_>],
         _server_name: &ServerName<'_>,
@@ -1588,54 +2619,1416 @@ impl ServerCertVerifier for AcceptAnyServerCert {
         Ok(ServerCertVerified::assertion())
     }
 
-    fn verify_tls12_signature(
-        &self,
-        _message: &[u8],
-        _cert: &CertificateDer<'_>,
-        _dss: &DigitallySignedStruct,
-    ) -> Result<HandshakeSignatureValid, rustls::Error> {
-        Ok(HandshakeSignatureValid::assertion())
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![
+            SignatureScheme::RSA_PKCS1_SHA256,
+            SignatureScheme::RSA_PKCS1_SHA384,
+            SignatureScheme::RSA_PKCS1_SHA512,
+            SignatureScheme::RSA_PSS_SHA256,
+            SignatureScheme::RSA_PSS_SHA384,
+            SignatureScheme::RSA_PSS_SHA512,
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignatureScheme::ECDSA_NISTP521_SHA512,
+            SignatureScheme::ED25519,
+        ]
+    }
+}
+
+/// A real TLS client completes the STARTTLS handshake and delivers a message
+/// over the encrypted channel. Exercises the server's on-the-fly self-signed
+/// cert generation (`generate_self_signed_cert` in src/smtp/mod.rs) and the
+/// full plaintext → secure session transition (`handle_starttls` →
+/// `handle_secure_session`).
+#[tokio::test]
+async fn test_starttls_end_to_end() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let config = test_config(smtp_port, &webhook_url);
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    let plaintext = TcpStream::connect(&smtp_addr).await.expect("connect");
+    let (read_half, mut write_half) = tokio::io::split(plaintext);
+    let mut reader = BufReader::new(read_half);
+    let mut line = String::new();
+
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("220"), "greeting: {}", line);
+
+    // EHLO must advertise STARTTLS.
+    write_half.write_all(b"EHLO tls-test\r\n").await.unwrap();
+    write_half.flush().await.unwrap();
+    let mut saw_starttls = false;
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        if line.to_ascii_uppercase().contains("STARTTLS") {
+            saw_starttls = true;
+        }
+        if line.starts_with("250 ") {
+            break;
+        }
+        assert!(line.starts_with("250"), "EHLO line: {}", line);
+    }
+    assert!(saw_starttls, "EHLO must advertise STARTTLS");
+
+    write_half.write_all(b"STARTTLS\r\n").await.unwrap();
+    write_half.flush().await.unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("220"), "STARTTLS reply: {}", line);
+
+    // Re-assemble the underlying TcpStream for the TLS handshake.
+    let read_half = reader.into_inner();
+    let plaintext = read_half.unsplit(write_half);
+
+    let client_config = rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyServerCert))
+        .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(client_config));
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let tls_stream = connector
+        .connect(server_name, plaintext)
+        .await
+        .expect("TLS handshake must succeed");
+
+    let (tls_read, mut tls_write) = tokio::io::split(tls_stream);
+    let mut reader = BufReader::new(tls_read);
+
+    // Post-handshake: server does NOT send a fresh greeting (see
+    // handle_secure_session in src/smtp/mod.rs:240). Client must drive.
+    tls_write.write_all(b"EHLO tls-test\r\n").await.unwrap();
+    tls_write.flush().await.unwrap();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        if line.starts_with("250 ") {
+            break;
+        }
+        assert!(line.starts_with("250"), "post-TLS EHLO: {}", line);
+    }
+
+    tls_write
+        .write_all(b"MAIL FROM:<sender@tls.example>\r\n")
+        .await
+        .unwrap();
+    tls_write.flush().await.unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("250"), "MAIL FROM: {}", line);
+
+    tls_write
+        .write_all(b"RCPT TO:<target@example.com>\r\n")
+        .await
+        .unwrap();
+    tls_write.flush().await.unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("250"), "RCPT TO: {}", line);
+
+    tls_write.write_all(b"DATA\r\n").await.unwrap();
+    tls_write.flush().await.unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("354"), "DATA: {}", line);
+
+    let body = "From: sender@tls.example\r\n\
+                To: target@example.com\r\n\
+                Subject: STARTTLS smoke\r\n\
+                \r\n\
+                Delivered over TLS.\r\n\
+                .\r\n";
+    tls_write.write_all(body.as_bytes()).await.unwrap();
+    tls_write.flush().await.unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(
+        line.starts_with("250"),
+        "end-of-DATA over TLS must 250, got: {}",
+        line
+    );
+
+    tls_write.write_all(b"QUIT\r\n").await.unwrap();
+    tls_write.flush().await.unwrap();
+
+    // Webhook delivery is async — poll briefly.
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    let requests = loop {
+        let reqs = get_mockserver_requests(&mock_url, "/webhook").await;
+        if !reqs.is_empty() || std::time::Instant::now() > deadline {
+            break reqs;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    assert_eq!(
+        requests.len(),
+        1,
+        "message delivered over TLS must hit the webhook exactly once, got {}",
+        requests.len()
+    );
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// SMTPS listener: the TLS handshake runs right after accept, the greeting
+/// arrives over TLS, and the session delivers through the same pipeline as the
+/// plaintext port.
+#[tokio::test]
+async fn test_implicit_tls_end_to_end() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let smtp_port = get_free_port();
+    let smtps_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(smtp_port, &webhook_url);
+    config.smtps_port = Some(smtps_port);
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtps_addr = format!("127.0.0.1:{}", smtps_port);
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    let tcp = loop {
+        match TcpStream::connect(&smtps_addr).await {
+            Ok(s) => break s,
+            Err(_) if std::time::Instant::now() < deadline => {
+                tokio::time::sleep(Duration::from_millis(100)).await
+            }
+            Err(e) => panic!("SMTPS listener never came up: {}", e),
+        }
+    };
+
+    let client_config = rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyServerCert))
+        .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(client_config));
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let tls_stream = connector
+        .connect(server_name, tcp)
+        .await
+        .expect("implicit TLS handshake must succeed");
+
+    let (tls_read, mut tls_write) = tokio::io::split(tls_stream);
+    let mut reader = BufReader::new(tls_read);
+    let mut line = String::new();
+
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("220"), "greeting over TLS: {}", line);
+
+    tls_write.write_all(b"EHLO smtps-test\r\n").await.unwrap();
+    tls_write.flush().await.unwrap();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        if line.starts_with("250 ") {
+            break;
+        }
+        assert!(line.starts_with("250"), "EHLO: {}", line);
+    }
+
+    for (command, expected) in [
+        ("MAIL FROM:<sender@smtps.example>\r\n", "250"),
+        ("RCPT TO:<target@example.com>\r\n", "250"),
+        ("DATA\r\n", "354"),
+        (
+            "From: sender@smtps.example\r\nTo: target@example.com\r\nSubject: SMTPS smoke\r\n\r\nDelivered over implicit TLS.\r\n.\r\n",
+            "250",
+        ),
+    ] {
+        tls_write.write_all(command.as_bytes()).await.unwrap();
+        tls_write.flush().await.unwrap();
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert!(
+            line.starts_with(expected),
+            "expected {} after {:?}, got: {}",
+            expected,
+            command,
+            line
+        );
+    }
+
+    tls_write.write_all(b"QUIT\r\n").await.unwrap();
+    tls_write.flush().await.unwrap();
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    let requests = loop {
+        let reqs = get_mockserver_requests(&mock_url, "/webhook").await;
+        if !reqs.is_empty() || std::time::Instant::now() > deadline {
+            break reqs;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    assert_eq!(
+        requests.len(),
+        1,
+        "message delivered over SMTPS must hit the webhook exactly once, got {}",
+        requests.len()
+    );
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// With `require_tls`, a plaintext `MAIL FROM` is refused with `530` and the
+/// same transaction is accepted once the client upgrades via STARTTLS. The
+/// post-upgrade EHLO no longer advertises STARTTLS.
+#[tokio::test]
+async fn test_require_tls_refuses_plaintext_mail_from() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(smtp_port, &webhook_url);
+    config.require_tls = true;
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    let plaintext = TcpStream::connect(&smtp_addr).await.expect("connect");
+    let (read_half, mut write_half) = tokio::io::split(plaintext);
+    let mut reader = BufReader::new(read_half);
+    let mut line = String::new();
+
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("220"), "greeting: {}", line);
+
+    write_half.write_all(b"EHLO tls-test\r\n").await.unwrap();
+    write_half.flush().await.unwrap();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        if line.starts_with("250 ") {
+            break;
+        }
+    }
+
+    write_half
+        .write_all(b"MAIL FROM:<sender@cleartext.example>\r\n")
+        .await
+        .unwrap();
+    write_half.flush().await.unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(
+        line.starts_with("530 5.7.0"),
+        "plaintext MAIL FROM must be refused, got: {}",
+        line
+    );
+
+    write_half.write_all(b"STARTTLS\r\n").await.unwrap();
+    write_half.flush().await.unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("220"), "STARTTLS reply: {}", line);
+
+    let plaintext = reader.into_inner().unsplit(write_half);
+    let client_config = rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyServerCert))
+        .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(client_config));
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let tls_stream = connector
+        .connect(server_name, plaintext)
+        .await
+        .expect("TLS handshake must succeed");
+
+    let (tls_read, mut tls_write) = tokio::io::split(tls_stream);
+    let mut reader = BufReader::new(tls_read);
+
+    tls_write.write_all(b"EHLO tls-test\r\n").await.unwrap();
+    tls_write.flush().await.unwrap();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert!(
+            !line.to_ascii_uppercase().contains("STARTTLS"),
+            "EHLO over TLS must not advertise STARTTLS: {}",
+            line
+        );
+        if line.starts_with("250 ") {
+            break;
+        }
+    }
+
+    tls_write
+        .write_all(b"MAIL FROM:<sender@tls.example>\r\n")
+        .await
+        .unwrap();
+    tls_write.flush().await.unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("250"), "MAIL FROM over TLS: {}", line);
+
+    tls_write.write_all(b"QUIT\r\n").await.unwrap();
+    tls_write.flush().await.unwrap();
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// Policy for a submission endpoint: only sessions authenticated as
+/// `scanner` may send.
+const AUTH_REQUIRED_POLICY: &str = r#"
+    permit(principal == User::"scanner", action == Action::"SendMail", resource)
+      when { context.authenticated };
+    permit(principal, action == Action::"Attach", resource);
+"#;
+
+/// SMTP AUTH over implicit TLS: a bad password is refused with `535`, the
+/// right one with `235`, and the authenticated username becomes the Cedar
+/// principal and appears in the webhook payload.
+#[tokio::test]
+async fn test_smtp_auth_identity_is_principal_and_in_payload() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let creds_path =
+        std::env::temp_dir().join(format!("mail-laser-it-auth-{}", uuid::Uuid::new_v4()));
+    std::fs::write(
+        &creds_path,
+        format!("scanner:{}\n", bcrypt::hash("s3cret", 4).unwrap()),
+    )
+    .unwrap();
+
+    let smtp_port = get_free_port();
+    let smtps_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(smtp_port, &webhook_url);
+    config.smtps_port = Some(smtps_port);
+    config.auth_credentials_path = Some(creds_path.clone());
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        Arc::new(PolicyEngine::from_strings(AUTH_REQUIRED_POLICY, None).unwrap()),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtps_addr = format!("127.0.0.1:{}", smtps_port);
+    wait_for_smtp(&smtps_addr, Duration::from_secs(5)).await;
+    let tcp = TcpStream::connect(&smtps_addr).await.expect("connect");
+
+    let client_config = rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyServerCert))
+        .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(client_config));
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let tls_stream = connector
+        .connect(server_name, tcp)
+        .await
+        .expect("implicit TLS handshake must succeed");
+
+    let (tls_read, mut tls_write) = tokio::io::split(tls_stream);
+    let mut reader = BufReader::new(tls_read);
+    let mut line = String::new();
+
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("220"), "greeting over TLS: {}", line);
+
+    tls_write.write_all(b"EHLO auth-test\r\n").await.unwrap();
+    tls_write.flush().await.unwrap();
+    let mut saw_auth = false;
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        if line.contains("AUTH PLAIN LOGIN") {
+            saw_auth = true;
+        }
+        if line.starts_with("250 ") {
+            break;
+        }
+    }
+    assert!(saw_auth, "EHLO over TLS must advertise AUTH");
+
+    for (command, expected) in [
+        // base64("\0scanner\0wrong")
+        ("AUTH PLAIN AHNjYW5uZXIAd3Jvbmc=\r\n", "535"),
+        // base64("\0scanner\0s3cret")
+        ("AUTH PLAIN AHNjYW5uZXIAczNjcmV0\r\n", "235"),
+        ("MAIL FROM:<noreply@scanner.internal>\r\n", "250"),
+        ("RCPT TO:<target@example.com>\r\n", "250"),
+        ("DATA\r\n", "354"),
+        (
+            "From: noreply@scanner.internal\r\nTo: target@example.com\r\nSubject: Scan\r\n\r\nScan complete.\r\n.\r\n",
+            "250",
+        ),
+    ] {
+        tls_write.write_all(command.as_bytes()).await.unwrap();
+        tls_write.flush().await.unwrap();
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert!(
+            line.starts_with(expected),
+            "expected {} after {:?}, got: {}",
+            expected,
+            command,
+            line
+        );
+    }
+
+    tls_write.write_all(b"QUIT\r\n").await.unwrap();
+    tls_write.flush().await.unwrap();
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    let requests = loop {
+        let reqs = get_mockserver_requests(&mock_url, "/webhook").await;
+        if !reqs.is_empty() || std::time::Instant::now() > deadline {
+            break reqs;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    assert_eq!(requests.len(), 1, "authenticated message must be delivered");
+
+    let req = &requests[0];
+    let body_json: serde_json::Value = if let Some(json_val) = req["body"]["json"].as_object() {
+        serde_json::Value::Object(json_val.clone())
+    } else if let Some(s) = req["body"]["string"].as_str() {
+        serde_json::from_str(s).expect("Webhook body should be valid JSON")
+    } else {
+        panic!("Could not extract body: {}", req["body"]);
+    };
+    assert_eq!(body_json["authenticated"], true, "{}", body_json);
+    assert_eq!(body_json["auth_identity"], "scanner", "{}", body_json);
+
+    let _ = std::fs::remove_file(&creds_path);
+    runtime.shutdown_all().await.ok();
+}
+
+/// Repeated AUTH failures close the session with `421 4.7.0`, whether the
+/// username exists or not.
+#[tokio::test]
+async fn test_repeated_auth_failures_close_the_session() {
+    init_crypto();
+    let creds_path =
+        std::env::temp_dir().join(format!("mail-laser-it-auth-{}", uuid::Uuid::new_v4()));
+    std::fs::write(
+        &creds_path,
+        format!("scanner:{}\n", bcrypt::hash("s3cret", 4).unwrap()),
+    )
+    .unwrap();
+
+    let smtp_port = get_free_port();
+    let smtps_port = get_free_port();
+    let mut config = test_config(smtp_port, "http://127.0.0.1:9/webhook");
+    config.smtps_port = Some(smtps_port);
+    config.auth_credentials_path = Some(creds_path.clone());
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtps_addr = format!("127.0.0.1:{}", smtps_port);
+    wait_for_smtp(&smtps_addr, Duration::from_secs(5)).await;
+    let tcp = TcpStream::connect(&smtps_addr).await.expect("connect");
+    let client_config = rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyServerCert))
+        .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(client_config));
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let tls_stream = connector
+        .connect(server_name, tcp)
+        .await
+        .expect("implicit TLS handshake must succeed");
+
+    let (tls_read, mut tls_write) = tokio::io::split(tls_stream);
+    let mut reader = BufReader::new(tls_read);
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("220"), "greeting over TLS: {}", line);
+
+    tls_write.write_all(b"EHLO auth-test\r\n").await.unwrap();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        if line.starts_with("250 ") {
+            break;
+        }
+    }
+
+    for (command, expected) in [
+        // base64("\0scanner\0wrong")
+        ("AUTH PLAIN AHNjYW5uZXIAd3Jvbmc=\r\n", "535 5.7.8"),
+        // base64("\0nobody\0s3cret")
+        ("AUTH PLAIN AG5vYm9keQBzM2NyZXQ=\r\n", "535 5.7.8"),
+        ("AUTH PLAIN AHNjYW5uZXIAd3Jvbmc=\r\n", "421 4.7.0"),
+    ] {
+        tls_write.write_all(command.as_bytes()).await.unwrap();
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert!(
+            line.starts_with(expected),
+            "expected {} after {:?}, got: {}",
+            expected,
+            command,
+            line
+        );
+    }
+
+    line.clear();
+    let n = tokio::time::timeout(Duration::from_secs(5), reader.read_line(&mut line))
+        .await
+        .expect("server must close the session")
+        .unwrap_or(0);
+    assert_eq!(n, 0, "session must be closed, got: {}", line);
+
+    let _ = std::fs::remove_file(&creds_path);
+    runtime.shutdown_all().await.ok();
+}
+
+/// RFC 2920: a client that saw `PIPELINING` may send the whole envelope in
+/// one write. Replies come back in order, and DATA with content queued
+/// behind it is refused because the client must wait for the 354.
+#[tokio::test]
+async fn test_pipelined_envelope_is_answered_in_order() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let config = test_config(smtp_port, &webhook_url);
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    async fn read_reply(reader: &mut BufReader<tokio::io::ReadHalf<TcpStream>>) -> String {
+        let mut line = String::new();
+        tokio::time::timeout(Duration::from_secs(3), reader.read_line(&mut line))
+            .await
+            .expect("server response timed out")
+            .expect("read ok");
+        line
+    }
+
+    let stream = TcpStream::connect(&smtp_addr).await.expect("connect");
+    let (read_half, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+    assert!(read_reply(&mut reader).await.starts_with("220"));
+
+    writer.write_all(b"EHLO pipeliner\r\n").await.unwrap();
+    let mut advertised = false;
+    loop {
+        let line = read_reply(&mut reader).await;
+        advertised |= line.trim_end() == "250-PIPELINING" || line.trim_end() == "250 PIPELINING";
+        if line.starts_with("250 ") {
+            break;
+        }
+    }
+    assert!(advertised, "EHLO must advertise PIPELINING");
+
+    writer
+        .write_all(
+            b"MAIL FROM:<sender@test.com>\r\nRCPT TO:<nobody@example.com>\r\n\
+              RCPT TO:<target@example.com>\r\nDATA\r\n",
+        )
+        .await
+        .unwrap();
+    assert!(read_reply(&mut reader).await.starts_with("250"));
+    assert!(read_reply(&mut reader).await.starts_with("550"));
+    assert!(read_reply(&mut reader).await.starts_with("250"));
+    assert!(read_reply(&mut reader).await.starts_with("354"));
+
+    writer
+        .write_all(
+            b"From: sender@test.com\r\nTo: target@example.com\r\nSubject: Pipelined\r\n\r\nhi\r\n.\r\n",
+        )
+        .await
+        .unwrap();
+    let end = read_reply(&mut reader).await;
+    assert!(end.starts_with("250"), "end of DATA: {}", end);
+
+    // A second transaction that does not wait for the 354 is refused and
+    // the connection closed.
+    writer
+        .write_all(
+            b"MAIL FROM:<sender@test.com>\r\nRCPT TO:<target@example.com>\r\nDATA\r\n\
+              Subject: injected\r\n\r\n.\r\n",
+        )
+        .await
+        .unwrap();
+    assert!(read_reply(&mut reader).await.starts_with("250"));
+    assert!(read_reply(&mut reader).await.starts_with("250"));
+    let refused = read_reply(&mut reader).await;
+    assert!(refused.starts_with("554 5.5.0"), "got: {}", refused);
+    assert_eq!(read_reply(&mut reader).await, "", "server must close");
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let requests = get_mockserver_requests(&mock_url, "/webhook").await;
+    assert_eq!(requests.len(), 1, "only the first message is delivered");
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// 8BITMIME: raw Latin-1 bytes in DATA must not break the session, and the
+/// webhook must see the body decoded from the declared charset.
+#[tokio::test]
+async fn test_8bit_latin1_message_is_delivered() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let config = test_config(smtp_port, &webhook_url);
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    let stream = TcpStream::connect(&smtp_addr).await.expect("connect");
+    let (read_half, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("220"), "greeting: {}", line);
+
+    writer.write_all(b"EHLO legacy\r\n").await.unwrap();
+    let mut advertised = false;
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        advertised |= line[4..].trim_end() == "8BITMIME";
+        if line.starts_with("250 ") {
+            break;
+        }
+    }
+    assert!(advertised, "EHLO must advertise 8BITMIME");
+
+    for (command, expected) in [
+        (&b"MAIL FROM:<sender@test.com> BODY=8BITMIME\r\n"[..], "250"),
+        (b"RCPT TO:<target@example.com>\r\n", "250"),
+        (b"DATA\r\n", "354"),
+        (
+            b"From: sender@test.com\r\nTo: target@example.com\r\nSubject: Legacy\r\n\
+              Content-Type: text/plain; charset=iso-8859-1\r\n\
+              Content-Transfer-Encoding: 8bit\r\n\r\n\
+              Un caf\xe9, s'il vous pla\xeet.\r\n.\r\n",
+            "250",
+        ),
+    ] {
+        writer.write_all(command).await.unwrap();
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with(expected), "got: {}", line);
+    }
+    writer.write_all(b"QUIT\r\n").await.unwrap();
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let requests = get_mockserver_requests(&mock_url, "/webhook").await;
+    assert_eq!(requests.len(), 1);
+    let body = serde_json::to_string(&requests[0]["body"]).unwrap();
+    assert!(body.contains("Un café, s'il vous plaît."), "body: {}", body);
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// CHUNKING: a message sent as two BDAT chunks is delivered byte for byte,
+/// with no dot-unstuffing applied to a body line starting with `.`.
+#[tokio::test]
+async fn test_bdat_chunked_message_is_delivered() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let config = test_config(smtp_port, &webhook_url);
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    let stream = TcpStream::connect(&smtp_addr).await.expect("connect");
+    let (read_half, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("220"), "greeting: {}", line);
+
+    writer.write_all(b"EHLO chunker\r\n").await.unwrap();
+    let mut advertised = false;
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        advertised |= line[4..].trim_end() == "CHUNKING";
+        if line.starts_with("250 ") {
+            break;
+        }
+    }
+    assert!(advertised, "EHLO must advertise CHUNKING");
+
+    let head: &[u8] =
+        b"From: sender@test.com\r\nTo: target@example.com\r\nSubject: Chunked\r\n\r\n";
+    let tail: &[u8] = b"..leading dots kept\r\n";
+    let bdat_head = format!("BDAT {}\r\n", head.len());
+    let bdat_tail = format!("BDAT {} LAST\r\n", tail.len());
+    for (command, payload, expected) in [
+        (&b"MAIL FROM:<sender@test.com>\r\n"[..], &b""[..], "250"),
+        (b"RCPT TO:<target@example.com>\r\n", b"", "250"),
+        (bdat_head.as_bytes(), head, "250"),
+        (bdat_tail.as_bytes(), tail, "250"),
+    ] {
+        writer.write_all(command).await.unwrap();
+        writer.write_all(payload).await.unwrap();
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with(expected), "got: {}", line);
+    }
+    writer.write_all(b"QUIT\r\n").await.unwrap();
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let requests = get_mockserver_requests(&mock_url, "/webhook").await;
+    assert_eq!(requests.len(), 1);
+    let body = serde_json::to_string(&requests[0]["body"]).unwrap();
+    assert!(body.contains("..leading dots kept"), "body: {}", body);
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// DSN parameters travel into the webhook payload, and when asynchronous
+/// delivery is given up a failure report is written for the sender.
+#[tokio::test]
+async fn test_failed_async_delivery_writes_dsn() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 500, None, None).await;
+
+    let dsn_dir = std::env::temp_dir().join(format!("mail-laser-it-dsn-{}", uuid::Uuid::new_v4()));
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(smtp_port, &webhook_url);
+    config.webhook_max_retries = 0;
+    config.dsn_dir = Some(dsn_dir.clone());
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    let stream = TcpStream::connect(&smtp_addr).await.expect("connect");
+    let (read_half, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("220"), "greeting: {}", line);
+
+    writer.write_all(b"EHLO bouncer\r\n").await.unwrap();
+    let mut advertised = false;
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        advertised |= line[4..].trim_end() == "DSN";
+        if line.starts_with("250 ") {
+            break;
+        }
+    }
+    assert!(advertised, "EHLO must advertise DSN");
+
+    for (command, expected) in [
+        (
+            "MAIL FROM:<sender@test.com> RET=HDRS ENVID=QQ314159\r\n",
+            "250",
+        ),
+        (
+            "RCPT TO:<target@example.com> NOTIFY=FAILURE ORCPT=rfc822;target@example.com\r\n",
+            "250",
+        ),
+        ("DATA\r\n", "354"),
+        (
+            "From: sender@test.com\r\nTo: target@example.com\r\nSubject: Lost\r\n\r\nGone\r\n.\r\n",
+            "250",
+        ),
+    ] {
+        writer.write_all(command.as_bytes()).await.unwrap();
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with(expected), "got: {}", line);
+    }
+    writer.write_all(b"QUIT\r\n").await.unwrap();
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let requests = get_mockserver_requests(&mock_url, "/webhook").await;
+    assert_eq!(requests.len(), 1);
+    let body = serde_json::to_string(&requests[0]["body"]).unwrap();
+    assert!(body.contains("QQ314159"), "body: {}", body);
+
+    let mut reports = Vec::new();
+    let mut entries = tokio::fs::read_dir(&dsn_dir).await.unwrap();
+    while let Some(entry) = entries.next_entry().await.unwrap() {
+        reports.push(tokio::fs::read_to_string(entry.path()).await.unwrap());
     }
+    assert_eq!(reports.len(), 1, "expected one failure report");
+    assert!(reports[0].contains("To: <sender@test.com>\r\n"));
+    assert!(reports[0].contains("Original-Envelope-Id: QQ314159\r\n"));
+    assert!(reports[0].contains("Final-Recipient: rfc822; target@example.com\r\n"));
+    assert!(reports[0].contains("Subject: Lost\r\n"));
 
-    fn verify_tls13_signature(
-        &self,
-        _message: &[u8],
-        _cert: &CertificateDer<'_>,
-        _dss: &DigitallySignedStruct,
-    ) -> Result<HandshakeSignatureValid, rustls::Error> {
-        Ok(HandshakeSignatureValid::assertion())
+    tokio::fs::remove_dir_all(&dsn_dir).await.ok();
+    runtime.shutdown_all().await.ok();
+}
+
+/// A `SIZE=` declaration over the global limit is refused at `MAIL FROM`,
+/// and a Cedar `MailFrom` policy can apply a tighter per-sender limit.
+#[tokio::test]
+async fn test_declared_size_is_rejected_at_mail_from() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let policy = Arc::new(
+        PolicyEngine::from_strings(
+            r#"
+            permit(principal, action == Action::"SendMail", resource);
+            permit(principal, action == Action::"MailFrom", resource);
+            forbid(principal == User::"small@test.com", action == Action::"MailFrom", resource)
+              when { context.declared_size > 1000 };
+            "#,
+            None,
+        )
+        .expect("size policy parses"),
+    );
+
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(smtp_port, &webhook_url);
+    config.max_message_size_bytes = 1_000_000;
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        policy,
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    let stream = TcpStream::connect(&smtp_addr).await.expect("connect");
+    let (read_half, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("220"), "greeting: {}", line);
+
+    writer.write_all(b"EHLO sizer\r\n").await.unwrap();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        if line.starts_with("250 ") {
+            break;
+        }
     }
 
-    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
-        vec![
-            SignatureScheme::RSA_PKCS1_SHA256,
-            SignatureScheme::RSA_PKCS1_SHA384,
-            SignatureScheme::RSA_PKCS1_SHA512,
-            SignatureScheme::RSA_PSS_SHA256,
-            SignatureScheme::RSA_PSS_SHA384,
-            SignatureScheme::RSA_PSS_SHA512,
-            SignatureScheme::ECDSA_NISTP256_SHA256,
-            SignatureScheme::ECDSA_NISTP384_SHA384,
-            SignatureScheme::ECDSA_NISTP521_SHA512,
-            SignatureScheme::ED25519,
-        ]
+    for (command, expected) in [
+        ("MAIL FROM:<big@test.com> SIZE=50000000\r\n", "552 5.3.4"),
+        ("MAIL FROM:<small@test.com> SIZE=5000\r\n", "550 5.7.1"),
+        ("MAIL FROM:<small@test.com> SIZE=500\r\n", "250"),
+    ] {
+        writer.write_all(command.as_bytes()).await.unwrap();
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with(expected), "{} got: {}", command, line);
+    }
+    writer.write_all(b"QUIT\r\n").await.unwrap();
+
+    let requests = get_mockserver_requests(&mock_url, "/webhook").await;
+    assert!(requests.is_empty());
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// A trusted relay's `XCLIENT` replaces the peer address and HELO that Cedar
+/// sees, and the relay's own EHLO afterwards does not undo the override.
+#[tokio::test]
+async fn test_xclient_overrides_peer_and_helo_for_policy() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let policy = Arc::new(
+        PolicyEngine::from_strings(
+            r#"
+            permit(principal, action == Action::"SendMail", resource);
+            permit(principal, action == Action::"MailFrom", resource)
+              when { context.peer_ip == "203.0.113.7" && context.helo == "origin.example" };
+            "#,
+            None,
+        )
+        .expect("origin policy parses"),
+    );
+
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(smtp_port, &webhook_url);
+    config.xclient_trusted = vec!["127.0.0.0/8".parse().unwrap()];
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        policy,
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    let stream = TcpStream::connect(&smtp_addr).await.expect("connect");
+    let (read_half, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("220"), "greeting: {}", line);
+
+    async fn ehlo(
+        reader: &mut BufReader<tokio::io::ReadHalf<TcpStream>>,
+        writer: &mut tokio::io::WriteHalf<TcpStream>,
+    ) -> String {
+        writer.write_all(b"EHLO relay.local\r\n").await.unwrap();
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            reply.push_str(&line);
+            if line.starts_with("250 ") {
+                return reply;
+            }
+        }
+    }
+
+    let capabilities = ehlo(&mut reader, &mut writer).await;
+    assert!(
+        capabilities.contains("250-XCLIENT ADDR NAME HELO PROTO\r\n"),
+        "capabilities: {}",
+        capabilities
+    );
+
+    for (command, expected) in [
+        ("MAIL FROM:<sender@test.com>\r\n", "550 5.7.1"),
+        (
+            "XCLIENT ADDR=203.0.113.7 HELO=origin.example PROTO=ESMTP\r\n",
+            "220",
+        ),
+    ] {
+        writer.write_all(command.as_bytes()).await.unwrap();
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with(expected), "{} got: {}", command, line);
+    }
+
+    ehlo(&mut reader, &mut writer).await;
+    writer
+        .write_all(b"MAIL FROM:<sender@test.com>\r\n")
+        .await
+        .unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(
+        line.starts_with("250"),
+        "forwarded client must be authorized, got: {}",
+        line
+    );
+    writer.write_all(b"QUIT\r\n").await.unwrap();
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// Several listeners run side by side: IPv4 and IPv6 wildcards share a port
+/// (the IPv6 socket is bound v6-only), and a third listener requires TLS
+/// while the others do not.
+#[tokio::test]
+async fn test_multiple_listeners_with_per_listener_settings() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let shared_port = get_free_port();
+    let tls_only_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(shared_port, &webhook_url);
+    let listener = |address: &str, require_tls: bool| ListenerConfig {
+        address: address.parse().unwrap(),
+        protocol: ListenerProtocol::Smtp,
+        tls: ListenerTls::Starttls,
+        require_tls,
+        proxy_protocol_trusted: vec![],
+        xclient_trusted: vec![],
+        socket_mode: None,
+    };
+    config.listeners = vec![
+        listener(&format!("0.0.0.0:{}", shared_port), false),
+        listener(&format!("[::]:{}", shared_port), false),
+        listener(&format!("127.0.0.1:{}", tls_only_port), true),
+    ];
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let tls_only_addr = format!("127.0.0.1:{}", tls_only_port);
+    wait_for_smtp(&tls_only_addr, Duration::from_secs(5)).await;
+
+    async fn mail_from_reply(addr: &str) -> String {
+        let stream = TcpStream::connect(addr).await.expect("connect");
+        let (read_half, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(read_half);
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with("220"), "{} greeting: {}", addr, line);
+        writer
+            .write_all(b"EHLO client\r\nMAIL FROM:<sender@test.com>\r\n")
+            .await
+            .unwrap();
+        loop {
+            line.clear();
+            reader.read_line(&mut line).await.unwrap();
+            if line.starts_with("250 ") {
+                break;
+            }
+        }
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        writer.write_all(b"QUIT\r\n").await.unwrap();
+        line
+    }
+
+    let ipv4 = mail_from_reply(&format!("127.0.0.1:{}", shared_port)).await;
+    assert!(ipv4.starts_with("250"), "IPv4 listener: {}", ipv4);
+    let ipv6 = mail_from_reply(&format!("[::1]:{}", shared_port)).await;
+    assert!(ipv6.starts_with("250"), "IPv6 listener: {}", ipv6);
+    let tls_only = mail_from_reply(&tls_only_addr).await;
+    assert!(
+        tls_only.starts_with("530 5.7.0"),
+        "require_tls listener: {}",
+        tls_only
+    );
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// An LMTP listener on a Unix socket answers each successful `RCPT TO`
+/// separately after `DATA`: a recipient Cedar denies gets its own `550`
+/// while the others are accepted and delivered.
+#[cfg(unix)]
+#[tokio::test]
+async fn test_lmtp_over_unix_socket_replies_per_recipient() {
+    use std::os::unix::fs::PermissionsExt;
+
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let policy = Arc::new(
+        PolicyEngine::from_strings(
+            r#"
+            permit(principal, action == Action::"SendMail", resource);
+            forbid(principal, action == Action::"SendMail", resource == Recipient::"denied@example.com");
+            "#,
+            None,
+        )
+        .expect("recipient policy parses"),
+    );
+
+    let socket_dir =
+        std::env::temp_dir().join(format!("mail-laser-it-lmtp-{}", uuid::Uuid::new_v4()));
+    tokio::fs::create_dir_all(&socket_dir).await.unwrap();
+    let socket_path = socket_dir.join("lmtp.sock");
+
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(get_free_port(), &webhook_url);
+    config.target_emails = vec![
+        "target@example.com".to_string(),
+        "denied@example.com".to_string(),
+    ];
+    config.listeners = vec![ListenerConfig {
+        address: format!("unix:{}", socket_path.display()).parse().unwrap(),
+        protocol: ListenerProtocol::Lmtp,
+        tls: ListenerTls::Starttls,
+        require_tls: false,
+        proxy_protocol_trusted: vec![],
+        xclient_trusted: vec![],
+        socket_mode: Some(0o660),
+    }];
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        policy,
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let start = tokio::time::Instant::now();
+    let stream = loop {
+        match tokio::net::UnixStream::connect(&socket_path).await {
+            Ok(stream) => break stream,
+            Err(_) if start.elapsed() < Duration::from_secs(5) => {
+                tokio::time::sleep(Duration::from_millis(50)).await
+            }
+            Err(e) => panic!("LMTP socket never came up: {}", e),
+        }
+    };
+    let mode = std::fs::metadata(&socket_path)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o660);
+
+    let (read_half, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("220 localhost LMTP"), "greeting: {}", line);
+
+    writer.write_all(b"EHLO client\r\n").await.unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("500"), "EHLO over LMTP: {}", line);
+
+    writer.write_all(b"LHLO client\r\n").await.unwrap();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        if line.starts_with("250 ") {
+            break;
+        }
+    }
+
+    writer
+        .write_all(
+            b"MAIL FROM:<sender@test.com>\r\n\
+              RCPT TO:<target@example.com>\r\n\
+              RCPT TO:<denied@example.com>\r\n\
+              RCPT TO:<nobody@example.com>\r\n\
+              RCPT TO:<target@example.com>\r\n\
+              DATA\r\n",
+        )
+        .await
+        .unwrap();
+    let mut replies = Vec::new();
+    for _ in 0..6 {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        replies.push(line.trim_end().to_string());
+    }
+    assert!(replies[3].starts_with("550 5.1.1"), "{:?}", replies);
+    assert!(replies[5].starts_with("354"), "{:?}", replies);
+
+    writer
+        .write_all(b"Subject: LMTP\r\n\r\nPer-recipient replies.\r\n.\r\n")
+        .await
+        .unwrap();
+    let mut data_replies = Vec::new();
+    for _ in 0..3 {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        data_replies.push(line.trim_end().to_string());
     }
+    assert!(data_replies[0].starts_with("250 "), "{:?}", data_replies);
+    assert!(
+        data_replies[1].starts_with("550 5.7.1"),
+        "{:?}",
+        data_replies
+    );
+    assert!(data_replies[2].starts_with("250 "), "{:?}", data_replies);
+    writer.write_all(b"QUIT\r\n").await.unwrap();
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let requests = get_mockserver_requests(&mock_url, "/webhook").await;
+    assert_eq!(
+        requests.len(),
+        1,
+        "one delivery for the permitted recipient"
+    );
+    let body = requests[0]["body"].to_string();
+    assert!(body.contains("target@example.com"), "{}", body);
+    assert!(!body.contains("denied@example.com"), "{}", body);
+
+    runtime.shutdown_all().await.ok();
+    tokio::fs::remove_dir_all(&socket_dir).await.ok();
 }
 
-/// A real TLS client completes the STARTTLS handshake and delivers a message
-/// over the encrypted channel. Exercises the server's on-the-fly self-signed
-/// cert generation (`generate_self_signed_cert` in src/smtp/mod.rs) and the
-/// full plaintext → secure session transition (`handle_starttls` →
-/// `handle_secure_session`).
+/// Over SMTP there is only one end-of-DATA reply, so a recipient Cedar
+/// denies rejects the whole message with `550 5.7.1` instead of being
+/// silently left out of a `250`.
 #[tokio::test]
-async fn test_starttls_end_to_end() {
+async fn test_smtp_rejects_message_when_any_recipient_is_denied() {
     init_crypto();
-    let (_container, mock_url) = start_mockserver().await;
-    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+    let policy = Arc::new(
+        PolicyEngine::from_strings(
+            r#"
+            permit(principal, action == Action::"SendMail", resource);
+            forbid(principal, action == Action::"SendMail", resource == Recipient::"denied@example.com");
+            "#,
+            None,
+        )
+        .expect("recipient policy parses"),
+    );
 
     let smtp_port = get_free_port();
-    let webhook_url = format!("{}/webhook", mock_url);
-    let config = test_config(smtp_port, &webhook_url);
+    let mut config = test_config(smtp_port, "http://127.0.0.1:9/webhook");
+    config.target_emails = vec![
+        "target@example.com".to_string(),
+        "denied@example.com".to_string(),
+    ];
 
     let mut runtime = ActonApp::launch_async().await;
     let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
@@ -1643,7 +4036,7 @@ async fn test_starttls_end_to_end() {
         &mut runtime,
         &config,
         webhook_handle,
-        test_policy(),
+        policy,
         test_backend(),
         None,
     )
@@ -1653,126 +4046,341 @@ async fn test_starttls_end_to_end() {
     let smtp_addr = format!("127.0.0.1:{}", smtp_port);
     wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
 
-    let plaintext = TcpStream::connect(&smtp_addr).await.expect("connect");
-    let (read_half, mut write_half) = tokio::io::split(plaintext);
+    let stream = TcpStream::connect(&smtp_addr).await.unwrap();
+    let (read_half, mut writer) = tokio::io::split(stream);
     let mut reader = BufReader::new(read_half);
     let mut line = String::new();
-
     reader.read_line(&mut line).await.unwrap();
     assert!(line.starts_with("220"), "greeting: {}", line);
 
-    // EHLO must advertise STARTTLS.
-    write_half.write_all(b"EHLO tls-test\r\n").await.unwrap();
-    write_half.flush().await.unwrap();
-    let mut saw_starttls = false;
+    writer.write_all(b"EHLO client\r\n").await.unwrap();
     loop {
         line.clear();
         reader.read_line(&mut line).await.unwrap();
-        if line.to_ascii_uppercase().contains("STARTTLS") {
-            saw_starttls = true;
-        }
         if line.starts_with("250 ") {
             break;
         }
-        assert!(line.starts_with("250"), "EHLO line: {}", line);
     }
-    assert!(saw_starttls, "EHLO must advertise STARTTLS");
 
-    write_half.write_all(b"STARTTLS\r\n").await.unwrap();
-    write_half.flush().await.unwrap();
+    writer
+        .write_all(
+            b"MAIL FROM:<sender@test.com>\r\n\
+              RCPT TO:<target@example.com>\r\n\
+              RCPT TO:<denied@example.com>\r\n\
+              DATA\r\n",
+        )
+        .await
+        .unwrap();
+    let mut replies = Vec::new();
+    for _ in 0..4 {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        replies.push(line.trim_end().to_string());
+    }
+    assert!(replies[2].starts_with("250"), "{:?}", replies);
+    assert!(replies[3].starts_with("354"), "{:?}", replies);
+
+    writer
+        .write_all(b"Subject: Mixed\r\n\r\nOne recipient is denied.\r\n.\r\n")
+        .await
+        .unwrap();
     line.clear();
     reader.read_line(&mut line).await.unwrap();
-    assert!(line.starts_with("220"), "STARTTLS reply: {}", line);
+    assert!(
+        line.starts_with("550 5.7.1"),
+        "a denied recipient rejects the message: {}",
+        line
+    );
+    writer.write_all(b"QUIT\r\n").await.unwrap();
 
-    // Re-assemble the underlying TcpStream for the TLS handshake.
-    let read_half = reader.into_inner();
-    let plaintext = read_half.unsplit(write_half);
+    // The permitted recipient alone is still accepted.
+    let reply = smtp_send_email_reply(
+        &smtp_addr,
+        "sender@test.com",
+        "target@example.com",
+        "Permitted",
+        "Only the permitted recipient.",
+    )
+    .await
+    .unwrap();
+    assert!(reply.starts_with("250"), "permitted recipient: {}", reply);
 
-    let client_config = rustls::ClientConfig::builder()
-        .dangerous()
-        .with_custom_certificate_verifier(Arc::new(AcceptAnyServerCert))
-        .with_no_client_auth();
-    let connector = TlsConnector::from(Arc::new(client_config));
-    let server_name = ServerName::try_from("localhost").unwrap();
-    let tls_stream = connector
-        .connect(server_name, plaintext)
-        .await
-        .expect("TLS handshake must succeed");
+    runtime.shutdown_all().await.ok();
+}
 
-    let (tls_read, mut tls_write) = tokio::io::split(tls_stream);
-    let mut reader = BufReader::new(tls_read);
+/// Clients that connect and never send a command are dropped with
+/// `421 4.4.2` once the greeting timeout passes, freeing their per-IP slots,
+/// and the timeouts are counted in the metrics.
+#[tokio::test]
+async fn test_idle_clients_are_closed_by_greeting_timeout() {
+    init_crypto();
+    let smtp_port = get_free_port();
+    let mut config = test_config(smtp_port, "http://127.0.0.1:9/webhook");
+    config.greeting_timeout_secs = 1;
+    // Filled by the two idle clients below.
+    config.max_concurrent_per_ip = 2;
 
-    // Post-handshake: server does NOT send a fresh greeting (see
-    // handle_secure_session in src/smtp/mod.rs:240). Client must drive.
-    tls_write.write_all(b"EHLO tls-test\r\n").await.unwrap();
-    tls_write.flush().await.unwrap();
-    loop {
-        line.clear();
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+    // Let the readiness probe's session end and release its slot.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let greeting_timeouts = mail_laser::metrics::SMTP_TIMEOUTS.get("greeting");
+
+    async fn greeted(addr: &str) -> BufReader<TcpStream> {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
         reader.read_line(&mut line).await.unwrap();
-        if line.starts_with("250 ") {
-            break;
-        }
-        assert!(line.starts_with("250"), "post-TLS EHLO: {}", line);
+        assert!(line.starts_with("220"), "greeting: {}", line);
+        reader
     }
 
-    tls_write
-        .write_all(b"MAIL FROM:<sender@tls.example>\r\n")
+    let idle = [greeted(&smtp_addr).await, greeted(&smtp_addr).await];
+    for mut reader in idle {
+        let mut line = String::new();
+        tokio::time::timeout(Duration::from_secs(5), reader.read_line(&mut line))
+            .await
+            .expect("server must give up on the idle client")
+            .unwrap();
+        assert!(line.starts_with("421 4.4.2"), "timeout reply: {}", line);
+        line.clear();
+        assert_eq!(reader.read_line(&mut line).await.unwrap(), 0, "then close");
+    }
+    assert!(mail_laser::metrics::SMTP_TIMEOUTS.get("greeting") >= greeting_timeouts + 2);
+
+    // Both slots are free again for new connections from this IP.
+    greeted(&smtp_addr).await;
+
+    runtime.shutdown_all().await.ok();
+}
+
+/// With a greet delay, a client that speaks before the `220` is refused with
+/// `554` and counted, while a patient one is greeted once the delay passes.
+/// Unknown recipients are answered ever more slowly, up to the tarpit cap.
+#[tokio::test]
+async fn test_early_talkers_are_refused_and_unknown_rcpts_tarpitted() {
+    init_crypto();
+    let smtp_port = get_free_port();
+    let mut config = test_config(smtp_port, "http://127.0.0.1:9/webhook");
+    config.greet_delay_secs = 1;
+    config.rcpt_tarpit_secs = 1;
+    config.rcpt_tarpit_max_secs = 2;
+    config.max_unknown_rcpts_per_session = 0;
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+    let early_talkers = mail_laser::metrics::SMTP_EARLY_TALKERS.get();
+
+    let stream = TcpStream::connect(&smtp_addr).await.unwrap();
+    let mut reader = BufReader::new(stream);
+    reader.get_mut().write_all(b"EHLO bot\r\n").await.unwrap();
+    let mut line = String::new();
+    tokio::time::timeout(Duration::from_secs(5), reader.read_line(&mut line))
         .await
+        .expect("server must answer the early talker")
         .unwrap();
-    tls_write.flush().await.unwrap();
+    assert!(
+        line.starts_with("554 5.5.0"),
+        "early talker reply: {}",
+        line
+    );
     line.clear();
-    reader.read_line(&mut line).await.unwrap();
-    assert!(line.starts_with("250"), "MAIL FROM: {}", line);
+    assert_eq!(reader.read_line(&mut line).await.unwrap(), 0, "then close");
+    assert!(mail_laser::metrics::SMTP_EARLY_TALKERS.get() > early_talkers);
 
-    tls_write
+    let started = std::time::Instant::now();
+    let stream = TcpStream::connect(&smtp_addr).await.unwrap();
+    let mut reader = BufReader::new(stream);
+    async fn reply(reader: &mut BufReader<TcpStream>) -> String {
+        let mut line = String::new();
+        tokio::time::timeout(Duration::from_secs(5), reader.read_line(&mut line))
+            .await
+            .expect("server response timed out")
+            .unwrap();
+        line
+    }
+    let greeting = reply(&mut reader).await;
+    assert!(greeting.starts_with("220"), "greeting: {}", greeting);
+    assert!(
+        started.elapsed() >= Duration::from_secs(1),
+        "greeting held back"
+    );
+
+    for command in ["HELO tester", "MAIL FROM:<sender@probe.example>"] {
+        let command = format!("{}\r\n", command);
+        reader
+            .get_mut()
+            .write_all(command.as_bytes())
+            .await
+            .unwrap();
+        assert!(reply(&mut reader).await.starts_with("250"));
+    }
+    for (n, expected_secs) in [(1, 1), (2, 2), (3, 2)] {
+        let command = format!("RCPT TO:<nobody{}@example.com>\r\n", n);
+        let sent = std::time::Instant::now();
+        reader
+            .get_mut()
+            .write_all(command.as_bytes())
+            .await
+            .unwrap();
+        let line = reply(&mut reader).await;
+        let waited = sent.elapsed();
+        assert!(line.starts_with("550 5.1.1"), "unknown #{}: {}", n, line);
+        assert!(
+            waited >= Duration::from_secs(expected_secs)
+                && waited < Duration::from_secs(expected_secs + 1),
+            "unknown #{} answered after {:?}",
+            n,
+            waited
+        );
+    }
+
+    // Known recipients are not delayed.
+    let sent = std::time::Instant::now();
+    reader
+        .get_mut()
         .write_all(b"RCPT TO:<target@example.com>\r\n")
         .await
         .unwrap();
-    tls_write.flush().await.unwrap();
-    line.clear();
-    reader.read_line(&mut line).await.unwrap();
-    assert!(line.starts_with("250"), "RCPT TO: {}", line);
+    assert!(reply(&mut reader).await.starts_with("250"));
+    assert!(sent.elapsed() < Duration::from_secs(1));
 
-    tls_write.write_all(b"DATA\r\n").await.unwrap();
-    tls_write.flush().await.unwrap();
-    line.clear();
-    reader.read_line(&mut line).await.unwrap();
-    assert!(line.starts_with("354"), "DATA: {}", line);
+    runtime.shutdown_all().await.ok();
+}
 
-    let body = "From: sender@tls.example\r\n\
-                To: target@example.com\r\n\
-                Subject: STARTTLS smoke\r\n\
-                \r\n\
-                Delivered over TLS.\r\n\
-                .\r\n";
-    tls_write.write_all(body.as_bytes()).await.unwrap();
-    tls_write.flush().await.unwrap();
-    line.clear();
-    reader.read_line(&mut line).await.unwrap();
-    assert!(
-        line.starts_with("250"),
-        "end-of-DATA over TLS must 250, got: {}",
+/// The configured host name leads the greeting and EHLO reply and names this
+/// server in the `Received:` header it forwards in place of the message's
+/// own; with the product hidden, neither the greeting nor the header
+/// mentions MailLaser.
+#[tokio::test]
+async fn test_server_identity_in_banner_ehlo_and_received_header() {
+    init_crypto();
+    let (_container, mock_url) = start_mockserver().await;
+    configure_mockserver(&mock_url, "/webhook", 200, None, None).await;
+
+    let smtp_port = get_free_port();
+    let webhook_url = format!("{}/webhook", mock_url);
+    let mut config = test_config(smtp_port, &webhook_url);
+    config.server_hostname = "mx.identity.test".to_string();
+    config.hide_product = true;
+    config.header_prefixes = vec!["Received".to_string()];
+
+    let mut runtime = ActonApp::launch_async().await;
+    let webhook_handle = WebhookState::create(&mut runtime, &config).await.unwrap();
+    let _smtp_handle = SmtpListenerState::create(
+        &mut runtime,
+        &config,
+        webhook_handle,
+        test_policy(),
+        test_backend(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let smtp_addr = format!("127.0.0.1:{}", smtp_port);
+    wait_for_smtp(&smtp_addr, Duration::from_secs(5)).await;
+
+    let stream = TcpStream::connect(&smtp_addr).await.unwrap();
+    let mut reader = BufReader::new(stream);
+    async fn reply(reader: &mut BufReader<TcpStream>) -> String {
+        let mut line = String::new();
+        tokio::time::timeout(Duration::from_secs(5), reader.read_line(&mut line))
+            .await
+            .expect("server response timed out")
+            .unwrap();
         line
+    }
+    assert_eq!(
+        reply(&mut reader).await,
+        "220 mx.identity.test ESMTP ready\r\n"
     );
 
-    tls_write.write_all(b"QUIT\r\n").await.unwrap();
-    tls_write.flush().await.unwrap();
+    reader
+        .get_mut()
+        .write_all(b"EHLO tester\r\n")
+        .await
+        .unwrap();
+    assert_eq!(
+        reply(&mut reader).await,
+        "250-mx.identity.test greets tester\r\n"
+    );
+    while !reply(&mut reader).await.starts_with("250 ") {}
+
+    for command in [
+        "MAIL FROM:<sender@example.com>",
+        "RCPT TO:<target@example.com>",
+        "DATA",
+        "Received: from upstream.test by relay.test; Mon, 12 Oct 2026 09:00:00 +0000\r\n\
+         Subject: identity\r\n\r\nBody\r\n.",
+    ] {
+        let command = format!("{}\r\n", command);
+        reader
+            .get_mut()
+            .write_all(command.as_bytes())
+            .await
+            .unwrap();
+        let line = reply(&mut reader).await;
+        assert!(
+            line.starts_with("250") || line.starts_with("354"),
+            "{}",
+            line
+        );
+    }
+    reader.get_mut().write_all(b"QUIT\r\n").await.ok();
 
-    // Webhook delivery is async — poll briefly.
-    let deadline = std::time::Instant::now() + Duration::from_secs(5);
-    let requests = loop {
-        let reqs = get_mockserver_requests(&mock_url, "/webhook").await;
-        if !reqs.is_empty() || std::time::Instant::now() > deadline {
-            break reqs;
-        }
-        tokio::time::sleep(Duration::from_millis(100)).await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let requests = get_mockserver_requests(&mock_url, "/webhook").await;
+    assert_eq!(requests.len(), 1, "one delivery");
+    let req = &requests[0];
+    let body_json: serde_json::Value = if let Some(json_val) = req["body"]["json"].as_object() {
+        serde_json::Value::Object(json_val.clone())
+    } else if let Some(s) = req["body"]["string"].as_str() {
+        serde_json::from_str(s).expect("Webhook body should be valid JSON")
+    } else {
+        panic!("Could not extract body: {}", req["body"]);
     };
-    assert_eq!(
-        requests.len(),
-        1,
-        "message delivered over TLS must hit the webhook exactly once, got {}",
-        requests.len()
+    let received = body_json["headers"]["Received"]
+        .as_str()
+        .expect("Received header forwarded");
+    assert!(
+        received.contains("from tester ([127.0.0.1])"),
+        "{}",
+        received
+    );
+    assert!(
+        received.contains("by mx.identity.test with ESMTP for <target@example.com>;"),
+        "{}",
+        received
     );
+    assert!(!received.contains("MailLaser"), "{}", received);
+    assert!(!received.contains("upstream.test"), "{}", received);
 
     runtime.shutdown_all().await.ok();
 }
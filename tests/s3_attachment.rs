@@ -336,16 +336,28 @@ fn s3_config(
         webhook_url: webhook_url.to_string(),
         smtp_bind_address: "127.0.0.1".to_string(),
         smtp_port,
+        smtps_port: None,
+        listeners: vec![],
         health_check_bind_address: "127.0.0.1".to_string(),
         health_check_port: get_free_port(),
         header_prefixes: vec![],
+        recipient_delivery: mail_laser::config::RecipientDelivery::Combined,
+        delivery_mode: mail_laser::config::DeliveryMode::Async,
         webhook_timeout_secs: 10,
         webhook_max_retries: 3,
         circuit_breaker_threshold: 5,
         circuit_breaker_reset_secs: 60,
         webhook_signing_secret: None,
+        spool_dir: None,
+        spool_retry_interval_secs: 30,
+        spool_max_age_secs: 432_000,
+        dsn_dir: None,
         cedar_policies_path: std::path::PathBuf::from("tests/fixtures/integration.cedar"),
         cedar_entities_path: None,
+        tls_cert_path: None,
+        tls_key_path: None,
+        require_tls: false,
+        auth_credentials_path: None,
         max_message_size_bytes: 26_214_400,
         max_attachment_size_bytes: 10_485_760,
         attachment_delivery: AttachmentDelivery::S3(S3Settings {
@@ -360,7 +372,36 @@ fn s3_config(
         dmarc_dns_servers: vec![],
         dmarc_temperror_action: mail_laser::config::DmarcTempErrorAction::Reject,
         max_concurrent_per_ip: 0,
+        max_sessions: 0,
+        max_connections_per_minute_per_ip: 0,
+        ipv6_limit_prefix_len: 64,
         max_unknown_rcpts_per_session: 0,
+        proxy_protocol_trusted: vec![],
+        xclient_trusted: vec![],
+        greeting_timeout_secs: 60,
+        command_timeout_secs: 300,
+        data_timeout_secs: 180,
+        session_timeout_secs: 1800,
+        peer_allow: vec![],
+        peer_allow_file: None,
+        peer_deny: vec![],
+        peer_deny_file: None,
+        peer_deny_action: mail_laser::config::PeerDenyAction::Reject,
+        dnsbl_zones: Vec::new(),
+        dnsbl_reject_score: None,
+        dnsbl_timeout_secs: 2,
+        greylist_db: None,
+        greylist_stage: mail_laser::config::GreylistStage::Rcpt,
+        greylist_delay_secs: 300,
+        greylist_retry_window_secs: 172_800,
+        greylist_pass_ttl_secs: 3_024_000,
+        rdns_checks: false,
+        rdns_timeout_secs: 2,
+        greet_delay_secs: 0,
+        rcpt_tarpit_secs: 0,
+        rcpt_tarpit_max_secs: 30,
+        server_hostname: "localhost".to_string(),
+        hide_product: false,
     }
 }
 